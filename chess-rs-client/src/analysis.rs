@@ -0,0 +1,155 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use chess_rs_core::engine::{Engine, Score, SearchInfo, SearchLimits};
+use chess_rs_core::tablebase::Tablebase;
+use chess_rs_core::{Board, ChessTeam, Move, Tile};
+
+const ANALYSIS_LINES: usize = 3;
+
+// a search running on its own thread
+struct Worker {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+    receiver: Receiver<SearchInfo>,
+}
+
+// Runs the engine in the background on whatever position the board is showing.
+//   The search never ends by itself, it's stopped when the position changes.
+pub struct Analysis {
+    tablebase: Option<Arc<dyn Tablebase>>,
+    current: Option<Worker>,
+    // searches that were told to stop but haven't finished yet
+    stopping: Vec<Worker>,
+    // the analyzed position
+    whose_turn: ChessTeam,
+    move_number: u32,
+    // latest report of the current search
+    info: Option<SearchInfo>,
+}
+
+impl Analysis {
+    pub fn init(tablebase: Option<Arc<dyn Tablebase>>) -> Analysis {
+        Analysis {
+            tablebase,
+            current: None,
+            stopping: vec![],
+            whose_turn: ChessTeam::White,
+            move_number: 1,
+            info: None,
+        }
+    }
+
+    // starts analyzing a new position
+    pub fn set_position(&mut self, board: &Board, ep_square: Option<Tile>, move_number: u32) {
+        if let Some(worker) = self.current.take() {
+            worker.stop.store(true, Ordering::Relaxed);
+            self.stopping.push(worker);
+        }
+
+        self.whose_turn = board.whose_turn;
+        self.move_number = move_number;
+        self.info = None;
+
+        let mut engine = Engine::new();
+        engine.set_tablebase(self.tablebase.clone());
+        let stop = engine.stop_flag();
+        let (sender, receiver) = mpsc::channel();
+        let board = board.clone();
+
+        let handle = std::thread::spawn(move || {
+            let limits = SearchLimits {
+                multi_pv: ANALYSIS_LINES,
+                ..SearchLimits::default()
+            };
+            engine.search(&board, ep_square, &limits, |info| {
+                let _ = sender.send(info.clone());
+            });
+        });
+
+        self.current = Some(Worker {
+            stop,
+            handle,
+            receiver,
+        });
+    }
+
+    // picks up what the search reported since the last frame
+    pub fn poll(&mut self) {
+        if let Some(worker) = &self.current {
+            while let Ok(info) = worker.receiver.try_recv() {
+                self.info = Some(info);
+            }
+        }
+
+        // the search clears its stop flag when it starts, so a search that was
+        //   stopped right away has to be told again
+        self.stopping.retain(|worker| {
+            worker.stop.store(true, Ordering::Relaxed);
+            !worker.handle.is_finished()
+        });
+    }
+
+    pub fn info(&self) -> Option<&SearchInfo> {
+        self.info.as_ref()
+    }
+
+    // whose turn it is in the analyzed position
+    pub fn whose_turn(&self) -> ChessTeam {
+        self.whose_turn
+    }
+
+    pub fn best_move(&self) -> Option<Move> {
+        self.info.as_ref().and_then(|info| info.best_move())
+    }
+
+    // evaluation of the best line from White's point of view
+    pub fn white_score(&self) -> Option<Score> {
+        let score = self.info.as_ref()?.lines.first()?.score;
+        match self.whose_turn {
+            ChessTeam::White => Some(score),
+            ChessTeam::Black => Some(score.flip()),
+        }
+    }
+
+    // "+0.35 1.e4 e5 2.Nf3", with the score from White's point of view
+    pub fn lines_text(&self) -> Vec<String> {
+        let info = match &self.info {
+            Some(info) => info,
+            None => return vec![],
+        };
+
+        info.lines
+            .iter()
+            .map(|line| {
+                let score = match self.whose_turn {
+                    ChessTeam::White => line.score,
+                    ChessTeam::Black => line.score.flip(),
+                };
+                format!(
+                    "{} {}",
+                    score,
+                    line.format_san(self.move_number, self.whose_turn)
+                )
+            })
+            .collect()
+    }
+}
+
+impl Drop for Analysis {
+    fn drop(&mut self) {
+        if let Some(worker) = self.current.take() {
+            self.stopping.push(worker);
+        }
+
+        for worker in self.stopping.drain(..) {
+            while !worker.handle.is_finished() {
+                worker.stop.store(true, Ordering::Relaxed);
+                std::thread::sleep(std::time::Duration::from_millis(1));
+            }
+            let _ = worker.handle.join();
+        }
+    }
+}
@@ -0,0 +1,126 @@
+// The chat window: the lines of the game or the lobby, and private messages.
+//  A line goes to the room, or to the player named in "To" as a private
+//  message. The server sends back everything we say, so lines only show up
+//  once they went through.
+
+use chess_rs_protocol::{ChatLine, ChatRoom, Message, MAX_CHAT_LEN};
+use egui::CtxRef;
+
+use crate::Audio;
+
+// older lines are dropped
+const MAX_LINES: usize = 100;
+const LINES_HEIGHT: f32 = 150.;
+
+pub struct ChatPanel {
+    //ours, to not play sounds for our own lines
+    name: String,
+    lines: Vec<ChatLine>,
+    text: String,
+    //empty for the room
+    to: String,
+    //spectators only read
+    can_write: bool,
+    //a line came in, the list scrolls down to it
+    scroll_down: bool,
+}
+
+fn line_text(line: &ChatLine, name: &str) -> String {
+    match &line.room {
+        ChatRoom::Private(to) if line.from == name => format!("to {}: {}", to, line.text),
+        ChatRoom::Private(_) => format!("{} (private): {}", line.from, line.text),
+        ChatRoom::Game | ChatRoom::Lobby => format!("{}: {}", line.from, line.text),
+    }
+}
+
+impl ChatPanel {
+    pub fn new(name: String, can_write: bool) -> ChatPanel {
+        ChatPanel {
+            name,
+            lines: vec![],
+            text: String::new(),
+            to: String::new(),
+            can_write,
+            scroll_down: false,
+        }
+    }
+
+    pub fn receive(&mut self, line: ChatLine, audio: &Audio) {
+        if line.from != self.name {
+            match line.room {
+                ChatRoom::Private(_) => audio.play_sound("NewPM"),
+                ChatRoom::Game | ChatRoom::Lobby => audio.play_sound("SocialNotify"),
+            }
+        }
+
+        self.lines.push(line);
+        if self.lines.len() > MAX_LINES {
+            self.lines.remove(0);
+        }
+        self.scroll_down = true;
+    }
+
+    fn message(&self) -> Message {
+        let text = self.text.trim().to_string();
+        match self.to.trim() {
+            "" => Message::Chat(text),
+            to => Message::PrivateChat(to.to_string(), text),
+        }
+    }
+
+    // the message to send, if one was written
+    pub fn draw(&mut self, egui_ctx: &CtxRef, pos: egui::Pos2) -> Option<Message> {
+        let mut to_send = None;
+
+        egui::Window::new("Chat")
+            .default_pos(pos)
+            .resizable(false)
+            .show(egui_ctx, |ui| {
+                egui::ScrollArea::from_max_height(LINES_HEIGHT).show(ui, |ui| {
+                    if self.lines.is_empty() {
+                        ui.label("No one said anything yet.");
+                    }
+                    for line in self.lines.iter() {
+                        ui.label(line_text(line, &self.name));
+                    }
+                    if self.scroll_down {
+                        ui.scroll_to_cursor(egui::Align::BOTTOM);
+                        self.scroll_down = false;
+                    }
+                });
+
+                if !self.can_write {
+                    return;
+                }
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.label("To:");
+                    let to = egui::TextEdit::singleline(&mut self.to)
+                        .hint_text("everyone")
+                        .desired_width(100.);
+                    ui.add(to);
+                });
+                ui.horizontal(|ui| {
+                    let text = egui::TextEdit::singleline(&mut self.text).desired_width(180.);
+                    let response = ui.add(text);
+                    let is_entered =
+                        response.lost_focus() && ui.input().key_pressed(egui::Key::Enter);
+                    let is_sent = ui.button("Send").clicked() || is_entered;
+
+                    if is_sent && !self.text.trim().is_empty() {
+                        to_send = Some(self.message());
+                        self.text.clear();
+                    }
+                    //keep typing
+                    if is_entered {
+                        response.request_focus();
+                    }
+                });
+                if self.text.chars().count() > MAX_CHAT_LEN {
+                    ui.label(format!("Lines are up to {} characters.", MAX_CHAT_LEN));
+                }
+            });
+
+        to_send
+    }
+}
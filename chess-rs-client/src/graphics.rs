@@ -5,6 +5,13 @@ use chess_rs_core::{
     self, Board, ChessPiece, ChessTeam, Coord, GameEndState, GameState, Move, MoveError, Tile,
 };
 use chess_rs_core as chess;
+use chess_rs_core::clock::{self, Clock, TimeControl};
+use chess_rs_core::engine::Score;
+use chess_rs_core::move_tree::{Mark, MarkColor};
+use chess_rs_core::pgn::{self, MovetextToken};
+use chess_rs_core::retrograde::DtmTables;
+use chess_rs_core::tablebase::{self, Adjudication, SyzygyTablebase, Tablebase};
+use chess_rs_protocol::{ChatLine, Message};
 
 
 use crate::MenuChange;
@@ -13,6 +20,8 @@ use futures::executor::block_on;
 use macroquad::input;
 use macroquad::prelude::*;
 use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
 
 use clipboard::ClipboardContext;
 use clipboard::ClipboardProvider;
@@ -22,7 +31,12 @@ use macroquad::ui::{
     Skin, // Drag, Ui,
 };
 
-use crate::multiplayer::MPState;
+use crate::analysis::Analysis;
+use crate::chat::ChatPanel;
+use crate::review::{self, ReviewJob};
+use crate::lobby::LobbyState;
+use crate::multiplayer;
+use crate::spectator::SpectatorState;
 use crate::Audio;
 use crate::MainMenuState;
 
@@ -34,6 +48,20 @@ use crate::MainMenuState;
 pub enum PlayerInput {
     GoBack,
     Move(Move, Result<bool, MoveError>),
+    //take back the last move (in multiplayer, ask the opponent)
+    Takeback,
+    //answer to the opponent asking for a takeback
+    TakebackAnswer(bool),
+    //ending a multiplayer game
+    Resign,
+    OfferDraw,
+    //answer to the opponent offering a draw
+    DrawAnswer(bool),
+    Abort,
+    //half the time, for more points in an arena
+    Berserk,
+    //something to say in the chat
+    Chat(Message),
 }
 
 // use crate::GameState as ProgramState;
@@ -41,6 +69,10 @@ pub enum PlayerInput {
 const PIECE_DISPLAY_SIZE: u32 = 80;
 const BOARD_PADDING: u32 = 30;
 const MOVES_LIST_WIDTH: u32 = 150;
+const EVAL_BAR_WIDTH: f32 = 14.;
+const CLOCK_HEIGHT: f32 = 40.;
+//the LowTime sound plays under this, and the countdown under 10 seconds
+const LOW_TIME: Duration = Duration::from_secs(20);
 
 const WINDOW_WIDTH: i32 =
     (PIECE_DISPLAY_SIZE * 8 + BOARD_PADDING * 2 + MOVES_LIST_WIDTH + BOARD_PADDING * 2) as i32;
@@ -49,7 +81,7 @@ const WINDOW_HEIGHT: i32 = (PIECE_DISPLAY_SIZE * 8 + BOARD_PADDING * 2) as i32;
 // Color used for legal move indicators, arrows, tile where the piece will end up, etc...
 
 const HIGHLIGHT_COLOR_RGB: (f32, f32, f32) = (0.89, 0.596, 0.850); //pinki
-const BACKGROUND_COLOR: Color = Color {
+pub const BACKGROUND_COLOR: Color = Color {
     r: 1.0,
     g: 1.0,
     b: 1.0,
@@ -163,6 +195,35 @@ enum Arrow {
     Circle(Coord), //x,y
 }
 
+impl Arrow {
+    fn from_mark(mark: Mark) -> Arrow {
+        match mark {
+            Mark::Arrow { from, to, .. } => {
+                let distance = Coord::from(from).distance(Coord::from(to));
+                match (distance.x.abs(), distance.y.abs()) {
+                    (1, 2) | (2, 1) => Arrow::KnightArrow(Coord::from(from), Coord::from(to)),
+                    _ => Arrow::Arrow(Coord::from(from), Coord::from(to)),
+                }
+            }
+            Mark::Square { tile, .. } => Arrow::Circle(Coord::from(tile)),
+        }
+    }
+
+    fn to_mark(&self, color: MarkColor) -> Mark {
+        match self {
+            Arrow::Arrow(from, to) | Arrow::KnightArrow(from, to) => Mark::Arrow {
+                from: Tile::try_from(*from).unwrap(),
+                to: Tile::try_from(*to).unwrap(),
+                color,
+            },
+            Arrow::Circle(coord) => Mark::Square {
+                tile: Tile::try_from(*coord).unwrap(),
+                color,
+            },
+        }
+    }
+}
+
 pub struct GfxState {
     dragged_piece_i: usize,
     is_dragged: bool,
@@ -181,18 +242,80 @@ pub struct GfxState {
     viewed_move: usize,
     //promotion
     is_promotion_ui_shown: bool,
+    //the opponent asked for a takeback
+    is_takeback_requested: bool,
+    //the opponent offered a draw
+    is_draw_offered: bool,
     promotion_move: Move,
     //ui styles
     skin1: Skin,
-    //cache moves as strings
-    moves_str: Vec<String>,
+    //cache moves as strings, with the variations
+    movetext: Vec<MovetextToken>,
     //is board flipped (white or black perspective)
     is_board_flipped: bool,
     locked_team: Option<ChessTeam>,
     player_input_buffer: Option<PlayerInput>,
     is_board_locked: bool,
+    //watching someone else's game. No piece can be moved
+    is_spectating: bool,
+    //a game of an arena
+    is_berserk_allowed: bool,
     audio: Rc<Audio>,
     options_visible: bool,
+    //endgame tablebase and what it says about the viewed position
+    tablebase: Option<Arc<dyn Tablebase>>,
+    endgame_info: Option<String>,
+    //the position endgame_info is about, so it is only probed once
+    endgame_position: Option<(Board, Option<Tile>)>,
+    //engine running on the viewed position, if analysis mode is on
+    analysis: Option<Analysis>,
+    is_game_over: bool,
+    //how the game ended, shown in a window
+    result: Option<String>,
+    //clock sounds already played for the running clock
+    is_low_time_warned: bool,
+    countdown_second: Option<u64>,
+    //post-game review, running or done
+    review: Option<ReviewJob>,
+    //only online
+    chat: Option<ChatPanel>,
+    //a text field has the keyboard, so the keys aren't shortcuts
+    is_typing: bool,
+}
+
+// Syzygy tables from SYZYGY_PATH, or else chess-rs-tbgen tables from DTM_PATH
+fn load_tablebase() -> Option<Arc<dyn Tablebase>> {
+    let paths = match std::env::var_os("SYZYGY_PATH") {
+        Some(paths) => paths,
+        None => return load_dtm_tables(),
+    };
+
+    let mut tb: Option<SyzygyTablebase> = None;
+
+    for path in std::env::split_paths(&paths) {
+        let res = match tb.as_mut() {
+            Some(tb) => tb.add_directory(&path),
+            None => SyzygyTablebase::open(&path).map(|new_tb| tb = Some(new_tb)),
+        };
+
+        if let Err(e) = res {
+            println!("could not load tablebases: {}", e);
+        }
+    }
+
+    tb.map(|tb| Arc::new(tb) as Arc<dyn Tablebase>)
+}
+
+fn load_dtm_tables() -> Option<Arc<dyn Tablebase>> {
+    let path = std::env::var_os("DTM_PATH")?;
+
+    match DtmTables::open(&path) {
+        Ok(tables) => Some(Arc::new(tables)),
+        Err(e) => {
+            println!("could not load tablebases: {}", e);
+            None
+        }
+    }
 }
 
 fn get_board_coord(tile: Tile) -> Coord {
@@ -288,17 +411,33 @@ impl GfxState {
             viewed_move,
             promotion_move: Move::CastleLong,
             is_promotion_ui_shown: false,
+            is_takeback_requested: false,
+            is_draw_offered: false,
             skin1,
-            moves_str: vec![],
+            movetext: pgn::movetext_tokens(game),
             is_board_flipped,
             locked_team,
             player_input_buffer: None,
             is_board_locked: false,
+            is_spectating: false,
+            is_berserk_allowed: false,
             audio,
             options_visible: false,
+            tablebase: load_tablebase(),
+            endgame_info: None,
+            endgame_position: None,
+            analysis: None,
+            is_game_over: false,
+            result: None,
+            is_low_time_warned: false,
+            countdown_second: None,
+            review: None,
+            chat: None,
+            is_typing: false,
         };
 
         state.sync_board(&mut game.get_board());
+        state.update_endgame_info(game);
 
         state
     }
@@ -307,6 +446,29 @@ impl GfxState {
         self.locked_team = team;
     }
 
+    // locks the pieces of both teams
+    pub fn set_spectating(&mut self) {
+        self.is_spectating = true;
+    }
+
+    pub fn set_berserk_allowed(&mut self) {
+        self.is_berserk_allowed = true;
+    }
+
+    pub fn set_chat(&mut self, chat: ChatPanel) {
+        self.chat = Some(chat);
+    }
+
+    pub fn take_chat(&mut self) -> Option<ChatPanel> {
+        self.chat.take()
+    }
+
+    pub fn chat_line_received(&mut self, line: ChatLine) {
+        if let Some(chat) = &mut self.chat {
+            chat.receive(line, &self.audio);
+        }
+    }
+
     //display board position at move [move_i]
     fn show_move(&mut self, game: &GameState, move_i: usize) {
         assert!(move_i <= game.move_count());
@@ -317,11 +479,258 @@ impl GfxState {
 
         self.sync_board(&board);
 
-        self.clear_arrows();
+        self.load_arrows(game);
+        self.update_endgame_info(game);
+        self.update_analysis(game);
 
         // if it is not the last move, lock the board (can't make any move)
     }
 
+    //asks the tablebase about the viewed position, unless it already did
+    fn update_endgame_info(&mut self, game: &GameState) {
+        let board = game.get_board_at(self.viewed_move);
+        let ep_square = game.get_en_passant_square_at(self.viewed_move);
+        let position = (board.clone(), ep_square);
+        if self.endgame_position.as_ref() == Some(&position) {
+            return;
+        }
+        self.endgame_position = Some(position);
+        self.endgame_info = None;
+
+        let tb = match &self.tablebase {
+            Some(tb) => tb,
+            None => return,
+        };
+
+        if board.piece_locations.len() > tb.max_pieces() {
+            return;
+        }
+
+        self.endgame_info = match tablebase::best_move(tb.as_ref(), &board, ep_square) {
+            Ok(Some(best)) => Some(format!(
+                "{} to move: {}\nBest move: {} (DTZ {})",
+                board.whose_turn,
+                best.wdl,
+                board.get_move_in_chess_notation(best.the_move),
+                best.dtz
+            )),
+            Ok(None) => None,
+            Err(e) => Some(format!("{}", e)),
+        };
+    }
+
+    fn draw_endgame_ui(&self, egui_ctx: &CtxRef) {
+        if let Some(info) = &self.endgame_info {
+            egui::Window::new("Endgame")
+                .resizable(false)
+                .collapsible(true)
+                .default_pos(egui::pos2(
+                    PIECE_DISPLAY_SIZE as f32 * 8. + BOARD_PADDING as f32 * 2.,
+                    BOARD_PADDING as f32 + PIECE_DISPLAY_SIZE as f32 * 5.,
+                ))
+                .show(egui_ctx, |ui| {
+                    ui.label(info.as_str());
+                });
+        }
+    }
+
+    // no engine help while playing someone online
+    fn toggle_analysis(&mut self, game: &GameState) {
+        if self.analysis.is_some() {
+            self.analysis = None;
+        } else if self.locked_team.is_none() {
+            self.analysis = Some(Analysis::init(self.tablebase.clone()));
+            self.update_analysis(game);
+        }
+    }
+
+    //restarts the engine on the viewed position
+    fn update_analysis(&mut self, game: &GameState) {
+        if let Some(analysis) = &mut self.analysis {
+            let board = game.get_board_at(self.viewed_move);
+            let ep_square = game.get_en_passant_square_at(self.viewed_move);
+            let move_number = game.get_full_move_count_at(self.viewed_move);
+            analysis.set_position(&board, ep_square, move_number);
+        }
+    }
+
+    fn draw_analysis_ui(&self, egui_ctx: &CtxRef) {
+        if let Some(analysis) = &self.analysis {
+            egui::Window::new("Analysis")
+                .resizable(false)
+                .collapsible(true)
+                .default_pos(egui::pos2(
+                    PIECE_DISPLAY_SIZE as f32 * 8. + BOARD_PADDING as f32 * 2.,
+                    BOARD_PADDING as f32 + PIECE_DISPLAY_SIZE as f32 * 3.,
+                ))
+                .show(egui_ctx, |ui| {
+                    ui.set_max_width(300.);
+                    match analysis.info() {
+                        Some(info) => {
+                            ui.label(format!("Depth {}  Nodes {}", info.depth, info.nodes));
+                            for line in analysis.lines_text() {
+                                ui.label(line);
+                            }
+                        }
+                        None => {
+                            ui.label("Thinking...");
+                        }
+                    }
+                });
+        }
+    }
+
+    fn draw_review_ui(&mut self, egui_ctx: &CtxRef) {
+        let job = match &self.review {
+            Some(job) => job,
+            None => return,
+        };
+
+        let mut open = true;
+
+        egui::Window::new("Review")
+            .resizable(false)
+            .collapsible(true)
+            .open(&mut open)
+            .default_pos(egui::pos2(
+                BOARD_PADDING as f32 + PIECE_DISPLAY_SIZE as f32 * 2.,
+                BOARD_PADDING as f32 + PIECE_DISPLAY_SIZE as f32 * 2.,
+            ))
+            .show(egui_ctx, |ui| {
+                let review = match &job.review {
+                    Some(review) => review,
+                    None => {
+                        ui.label(format!(
+                            "Reviewing the game... {}/{}",
+                            job.progress.0, job.progress.1
+                        ));
+                        return;
+                    }
+                };
+
+                egui::Grid::new("review_grid").striped(true).show(ui, |ui| {
+                    ui.label("");
+                    ui.label("White");
+                    ui.label("Black");
+                    ui.end_row();
+
+                    ui.label("Accuracy");
+                    for team in [ChessTeam::White, ChessTeam::Black] {
+                        match review.accuracy(team) {
+                            Some(accuracy) => ui.label(format!("{:.1}%", accuracy)),
+                            None => ui.label("-"),
+                        };
+                    }
+                    ui.end_row();
+
+                    for class in [
+                        chess::review::MoveClass::Inaccuracy,
+                        chess::review::MoveClass::Mistake,
+                        chess::review::MoveClass::Blunder,
+                    ] {
+                        ui.label(format!("{}s", class));
+                        for team in [ChessTeam::White, ChessTeam::Black] {
+                            ui.label(format!("{}", review.count(team, class)));
+                        }
+                        ui.end_row();
+                    }
+                });
+
+                //evaluation graph, in pawns for White
+                let evals = review
+                    .eval_graph()
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, cp)| egui::plot::Value::new(i as f64, cp as f64 / 100.));
+                let line = egui::plot::Line::new(egui::plot::Values::from_values_iter(evals))
+                    .fill(0.)
+                    .color(egui::Color32::from_rgb(120, 120, 120));
+                ui.add(
+                    egui::plot::Plot::new("eval_graph")
+                        .line(line)
+                        .hline(egui::plot::HLine::new(0.))
+                        .include_y(-3.)
+                        .include_y(3.)
+                        .allow_zoom(false)
+                        .allow_drag(false)
+                        .width(300.)
+                        .height(120.),
+                );
+
+                if ui
+                    .add(egui::Button::new("Copy annotated PGN"))
+                    .clicked()
+                {
+                    let mut ctx: ClipboardContext = ClipboardProvider::new().unwrap();
+                    ctx.set_contents(review.to_pgn()).unwrap();
+                }
+            });
+
+        if !open {
+            self.review = None;
+        }
+    }
+
+    // White's share of the bar, from the bottom (or the top if the board is flipped)
+    fn draw_eval_bar(&self) {
+        let score = match &self.analysis {
+            Some(analysis) => analysis.white_score(),
+            None => return,
+        };
+
+        let white_share = match score {
+            Some(Score::Cp(cp)) => 1. / (1. + (-cp as f32 / 400.).exp()),
+            Some(Score::Mate(n)) if n > 0 => 1.,
+            Some(Score::Mate(_)) => 0.,
+            Some(Score::Tablebase(wdl)) => (wdl.value().signum() + 1) as f32 / 2.,
+            None => 0.5,
+        };
+
+        let x = (BOARD_PADDING as f32 - EVAL_BAR_WIDTH) / 2.;
+        let white_h = self.board_col.h * white_share;
+
+        draw_rectangle(x, self.board_col.y, EVAL_BAR_WIDTH, self.board_col.h, DARKGRAY);
+        let white_y = if self.is_board_flipped {
+            self.board_col.y
+        } else {
+            self.board_col.y + self.board_col.h - white_h
+        };
+        draw_rectangle(x, white_y, EVAL_BAR_WIDTH, white_h, WHITE);
+        draw_rectangle_lines(x, self.board_col.y, EVAL_BAR_WIDTH, self.board_col.h, 2., GRAY);
+    }
+
+    //the engine's best move, drawn like the player's arrows
+    fn get_engine_arrow(&self) -> Option<Arrow> {
+        let analysis = self.analysis.as_ref()?;
+        let the_move = analysis.best_move()?;
+
+        let (coord_from, coord_to) = match the_move {
+            Move::PieceMove {
+                tile_from, tile_to, ..
+            }
+            | Move::PieceMoveWithPromotion {
+                tile_from, tile_to, ..
+            } => (Coord::from(tile_from), Coord::from(tile_to)),
+            Move::CastleShort | Move::CastleLong => {
+                let y = match analysis.whose_turn() {
+                    ChessTeam::White => 0,
+                    ChessTeam::Black => 7,
+                };
+                let x = if the_move == Move::CastleShort { 6 } else { 2 };
+                (Coord { x: 4, y }, Coord { x, y })
+            }
+        };
+
+        let distance = coord_from.distance(coord_to);
+        if (distance.x.abs() == 2 && distance.y.abs() == 1)
+            || (distance.x.abs() == 1 && distance.y.abs() == 2)
+        {
+            Some(Arrow::KnightArrow(coord_from, coord_to))
+        } else {
+            Some(Arrow::Arrow(coord_from, coord_to))
+        }
+    }
+
     fn get_coord_col(&self, coord: Coord) -> ColBox {
         if self.is_board_flipped {
             ColBox {
@@ -494,24 +903,142 @@ impl GfxState {
         }
     }
 
-    fn handle_end_state(&self, game: &mut GameState) {
-        match game.get_end_state() {
-            GameEndState::Checkmate => {
-                println!(
-                    "It's checkmate! {} has won!",
-                    game.whose_turn().the_other_one()
-                );
-                self.audio.play_sound("GenericNotify");
+    // Local games also end when the tablebase knows the result. Online the
+    //   server says when the game is over
+    fn handle_end_state(&mut self, game: &mut GameState) {
+        let end_state = game.get_end_state();
+        let adjudication = match &self.tablebase {
+            Some(tb) if self.locked_team.is_none() => tablebase::adjudicate(tb.as_ref(), game),
+            _ => None,
+        };
+        self.is_game_over = end_state != GameEndState::Running || adjudication.is_some();
+
+        self.result = match end_state {
+            GameEndState::Checkmate => Some(format!(
+                "It's checkmate! {} has won!",
+                game.whose_turn().the_other_one()
+            )),
+            GameEndState::Draw => Some("It's a draw!".to_string()),
+            GameEndState::Timeout => game.get_clock().and_then(|c| c.flagged()).map(|team| {
+                format!(
+                    "{} ran out of time! {} has won!",
+                    team,
+                    team.the_other_one()
+                )
+            }),
+            GameEndState::Running => adjudication.map(|adjudication| match adjudication {
+                Adjudication::Win(team) => format!("The tablebase says {} wins!", team),
+                Adjudication::Draw => "The tablebase says it's a draw!".to_string(),
+            }),
+        };
+
+        if let Some(result) = &self.result {
+            println!("{}", result);
+            self.audio.play_sound("GenericNotify");
+
+            //the clocks stop with the game
+            if let Some(clock) = game.get_clock_mut() {
+                clock.stop();
             }
-            GameEndState::Draw => {
-                println!("It's a draw!");
-                self.audio.play_sound("GenericNotify");
+        }
+    }
+
+    fn draw_result_ui(&self, egui_ctx: &CtxRef) {
+        let result = match &self.result {
+            Some(result) => result,
+            None => return,
+        };
+
+        egui::Window::new("Game over")
+            .resizable(false)
+            .collapsible(false)
+            .anchor(egui::Align2::CENTER_TOP, egui::vec2(0., 5.))
+            .show(egui_ctx, |ui| {
+                ui.label(result);
+            });
+    }
+
+    //where the moves list goes. It makes room for the clocks if there are any
+    fn get_moves_list_rect(&self, game: &GameState) -> (f32, f32) {
+        let height = PIECE_DISPLAY_SIZE as f32 * 7.;
+        match game.get_clock() {
+            Some(_) => (
+                BOARD_PADDING as f32 + CLOCK_HEIGHT + 10.,
+                height - (CLOCK_HEIGHT + 10.) * 2.,
+            ),
+            None => (BOARD_PADDING as f32, height),
+        }
+    }
+
+    // The clock of the team on top of the board goes above the moves list, the
+    //   other one below it. The running one is highlighted.
+    fn draw_clocks(&mut self, game: &mut GameState) {
+        let clock = match game.get_clock() {
+            Some(clock) => clock,
+            None => return,
+        };
+
+        let x = PIECE_DISPLAY_SIZE as f32 * 8. + BOARD_PADDING as f32 * 2.;
+        let (moves_y, moves_h) = self.get_moves_list_rect(game);
+        let top_team = match self.is_board_flipped {
+            true => ChessTeam::White,
+            false => ChessTeam::Black,
+        };
+
+        for (team, y) in [
+            (top_team, BOARD_PADDING as f32),
+            (top_team.the_other_one(), moves_y + moves_h + 10.),
+        ] {
+            let remaining = clock.remaining(team);
+            let (back_color, text_color) = match clock.running() == Some(team) {
+                true if remaining < LOW_TIME => (Color::from_rgba(200, 60, 60, 255), WHITE),
+                true => (DARKGRAY, WHITE),
+                false => (LIGHTGRAY, DARKGRAY),
+            };
+
+            draw_rectangle(x, y, MOVES_LIST_WIDTH as f32, CLOCK_HEIGHT, back_color);
+            draw_text(
+                &clock_text(remaining),
+                x + 10.,
+                y + CLOCK_HEIGHT * 0.75,
+                CLOCK_HEIGHT,
+                text_color,
+            );
+        }
+
+        //sounds for the player whose clock is running
+        let running = clock.running();
+        let my_time = running
+            .filter(|team| self.locked_team != Some(*team))
+            .map(|team| clock.remaining(team));
+        let is_flagged = clock.flagged().is_some();
+
+        match my_time {
+            Some(time) if time < LOW_TIME => {
+                if !self.is_low_time_warned {
+                    self.is_low_time_warned = true;
+                    self.audio.play_sound("LowTime");
+                }
+
+                let second = time.as_secs();
+                if second <= 10 && self.countdown_second != Some(second) && !is_flagged {
+                    self.countdown_second = Some(second);
+                    self.audio.play_sound(&format!("CountDown{}", second));
+                }
             }
-            GameEndState::Running => {}
+            _ => {
+                self.is_low_time_warned = false;
+                self.countdown_second = None;
+            }
+        }
+
+        //the flag can fall while nobody moves
+        if is_flagged && !self.is_game_over {
+            self.handle_end_state(game);
         }
     }
 
-    fn draw_options_button(&mut self, egui_ctx: &CtxRef) {
+    fn draw_options_button(&mut self, game: &GameState, egui_ctx: &CtxRef) {
         const PIECE_HEIGHT: f32 = 7.;
 
         egui::Window::new("Options_button")
@@ -526,9 +1053,22 @@ impl GfxState {
             .resizable(false)
             .title_bar(false)
             .show(egui_ctx, |ui| {
-                if ui.add(egui::Button::new("Options")).clicked() {
-                    self.options_visible = true;
-                }
+                ui.horizontal(|ui| {
+                    if ui.add(egui::Button::new("Options")).clicked() {
+                        self.options_visible = true;
+                    }
+                    if self.locked_team.is_none()
+                        && ui.add(egui::Button::new("Analysis")).clicked()
+                    {
+                        self.toggle_analysis(game);
+                    }
+                    if self.is_game_over
+                        && self.review.is_none()
+                        && ui.add(egui::Button::new("Review")).clicked()
+                    {
+                        self.review = Some(ReviewJob::start(game, self.tablebase.clone()));
+                    }
+                });
             });
     }
 
@@ -570,60 +1110,202 @@ impl GfxState {
     }
 
     fn draw_moves_ui(&mut self, game: &mut GameState, egui_ctx: &CtxRef) {
-        const MOVE_NO_W: f32 = 30.;
-        const PIECE_HEIGHT: f32 = 7.;
-
-        let group_count = (game.move_count() + 1) / 2;
+        let (moves_y, moves_h) = self.get_moves_list_rect(game);
         let move_count = game.move_count();
+        let line = game.get_line_path().to_vec();
+        let viewed_path = &line[..self.viewed_move];
+
+        //reviewed moves get their mark and colour
+        let move_reviews: Vec<_> = self
+            .review
+            .as_ref()
+            .and_then(|r| r.review.as_ref())
+            .filter(|r| r.moves.len() == move_count)
+            .map(|r| r.moves.iter().map(|m| m.class).collect())
+            .unwrap_or_default();
+
+        let mut clicked_path = None;
+        let mut game_input = None;
+        let mut promote = false;
+        let mut delete = false;
+        let mut annotated = false;
 
         egui::Window::new("Moves")
-            // .anchor(egui::Align2::LEFT_TOP,
-            //     egui::vec2(
-            //         PIECE_DISPLAY_SIZE as f32 * 8. + BOARD_PADDING as f32 * 2.,
-            //         BOARD_PADDING as f32,
-            //     ))
-            .fixed_size(egui::vec2(
-                MOVES_LIST_WIDTH as f32,
-                PIECE_DISPLAY_SIZE as f32 * PIECE_HEIGHT,
-            ))
+            .fixed_size(egui::vec2(MOVES_LIST_WIDTH as f32, moves_h))
             .fixed_pos(egui::pos2(
                 PIECE_DISPLAY_SIZE as f32 * 8. + BOARD_PADDING as f32 * 2.,
-                BOARD_PADDING as f32,
+                moves_y,
             ))
-            // .fixed_size(egui::pos2(
-            //     PIECE_DISPLAY_SIZE as f32 * 8. + BOARD_PADDING as f32 * 2.,
-            //     BOARD_PADDING as f32,
-            // ))
             .resizable(false)
             .title_bar(false)
             .scroll(true)
             .show(egui_ctx, |ui| {
-                ui.set_min_size(egui::vec2(
-                    MOVES_LIST_WIDTH as f32,
-                    PIECE_DISPLAY_SIZE as f32 * PIECE_HEIGHT,
-                ));
-
-                egui::Grid::new("my_grid")
-                    .striped(true)
-                    // .min_col_width(self.min_col_width)
-                    // .max_col_width(self.max_col_width)
-                    .show(ui, |ui| {
-                        for i in 1..=group_count {
-                            ui.label(&format!("{}", i));
-                            for j in 0..=1 {
-                                let move_i = (i as i32 * 2 + (j as i32 - 1)) as usize - 1;
-                                if move_count > move_i
-                                    && ui
-                                        .add(egui::Button::new(self.moves_str[move_i].as_str()))
-                                        .clicked()
+                ui.set_min_size(egui::vec2(MOVES_LIST_WIDTH as f32, moves_h));
+
+                //the moves in a paragraph, variations in parentheses
+                ui.horizontal_wrapped(|ui| {
+                    for token in &self.movetext {
+                        let (path, text, nags) = match token {
+                            MovetextToken::Move { path, text, nags } => (path, text, nags),
+                            MovetextToken::Comment { text, .. } => {
+                                if !text.is_empty() {
+                                    ui.add(egui::Label::new(text).weak().italics());
+                                }
+                                continue;
+                            }
+                            MovetextToken::VariationStart => {
+                                ui.label("(");
+                                continue;
+                            }
+                            MovetextToken::VariationEnd => {
+                                ui.label(")");
+                                continue;
+                            }
+                        };
+
+                        let on_line = path.len() <= line.len() && line[..path.len()] == path[..];
+                        let class = match on_line {
+                            true => move_reviews.get(path.len() - 1).copied(),
+                            false => None,
+                        };
+
+                        let mut text = text.clone();
+                        for nag in nags {
+                            match pgn::nag_glyph(*nag) {
+                                Some(glyph) => text += glyph,
+                                None => text += &format!(" ${}", nag),
+                            }
+                        }
+
+                        let mut button = match class {
+                            Some(class) => {
+                                egui::Button::new(format!("{}{}", text, review::class_symbol(class)))
+                                    .text_color_opt(review::class_color(class))
+                            }
+                            None => egui::Button::new(text),
+                        };
+                        if path[..] == *viewed_path {
+                            button = button.fill(ui.visuals().selection.bg_fill);
+                        }
+
+                        if ui.add(button).clicked() {
+                            clicked_path = Some(path.clone());
+                        }
+                    }
+                });
+
+                //ending the game, only for the players of a multiplayer game
+                if self.locked_team.is_some() {
+                    if !self.is_spectating && !self.is_game_over {
+                        ui.separator();
+                        ui.horizontal_wrapped(|ui| {
+                            if ui.button("Resign").clicked() {
+                                game_input = Some(PlayerInput::Resign);
+                            }
+                            if ui.button("Offer draw").clicked() {
+                                game_input = Some(PlayerInput::OfferDraw);
+                            }
+                            //before both sides moved
+                            let can_abort = game.move_count() < 2;
+                            if ui
+                                .add(egui::Button::new("Abort").enabled(can_abort))
+                                .clicked()
+                            {
+                                game_input = Some(PlayerInput::Abort);
+                            }
+                            //before our first move
+                            if let Some(team) = self.locked_team.filter(|_| self.is_berserk_allowed)
+                            {
+                                let has_moved = match game.move_count() {
+                                    0 => false,
+                                    1 => game.whose_turn() != team,
+                                    _ => true,
+                                };
+                                let is_berserked =
+                                    game.get_clock().is_none_or(|c| c.is_berserked(team));
+                                if ui
+                                    .add(
+                                        egui::Button::new("Berserk")
+                                            .enabled(!has_moved && !is_berserked),
+                                    )
+                                    .clicked()
                                 {
-                                    self.show_move(game, move_i + 1);
+                                    game_input = Some(PlayerInput::Berserk);
                                 }
                             }
-                            ui.end_row();
+                        });
+                    }
+                    return;
+                }
+
+                //editing the variations and annotations, only in single player
+                ui.separator();
+
+                if !viewed_path.is_empty() {
+                    ui.horizontal(|ui| {
+                        let is_variation = viewed_path.iter().any(|i| *i != 0);
+                        if ui
+                            .add(egui::Button::new("Promote variation").enabled(is_variation))
+                            .clicked()
+                        {
+                            promote = true;
+                        }
+                        if ui.button("Delete from here").clicked() {
+                            delete = true;
                         }
                     });
+                }
+
+                if let Some(annotations) = game.get_annotations_mut(viewed_path) {
+                    //glyphs: ! ? !! ?? !? ?!
+                    if !viewed_path.is_empty() {
+                        ui.horizontal(|ui| {
+                            for nag in 1..=6 {
+                                let has_nag = annotations.nags.contains(&nag);
+                                let glyph = pgn::nag_glyph(nag).unwrap();
+                                if ui.selectable_label(has_nag, glyph).clicked() {
+                                    if has_nag {
+                                        annotations.nags.retain(|n| *n != nag);
+                                    } else {
+                                        annotations.nags.push(nag);
+                                    }
+                                    annotated = true;
+                                }
+                            }
+                        });
+                    }
+
+                    let comment = egui::TextEdit::multiline(&mut annotations.comment)
+                        .hint_text("Comment")
+                        .desired_rows(2);
+                    if ui.add(comment).changed() {
+                        annotated = true;
+                    }
+                }
             });
+
+        if game_input.is_some() {
+            self.player_input_buffer = game_input;
+        }
+
+        if let Some(path) = clicked_path {
+            //moves of another line switch the game to it
+            if !(path.len() <= line.len() && line[..path.len()] == path[..]) {
+                game.go_to_path(&path);
+            }
+            self.show_move(game, path.len());
+        } else if promote {
+            let path = line[..self.viewed_move].to_vec();
+            game.promote_variation(&path);
+            self.movetext = pgn::movetext_tokens(game);
+        } else if delete {
+            let path = line[..self.viewed_move].to_vec();
+            game.delete_variation(&path);
+            self.movetext = pgn::movetext_tokens(game);
+            self.show_move(game, path.len() - 1);
+        } else if annotated {
+            self.movetext = pgn::movetext_tokens(game);
+        }
     }
 
     fn draw_legal_move_tiles_at(&self, coords: &Vec<Coord>) {
@@ -703,13 +1385,101 @@ impl GfxState {
     }
 
     fn move_was_made(&mut self, game: &mut GameState) {
-        self.viewed_move = game.move_count();
-        self.moves_str
-            .push(game.get_move_in_chess_notation(self.viewed_move - 1));
-        self.clear_arrows();
+        //a move cancels any takeback request
+        self.is_takeback_requested = false;
+        self.viewed_move = game.cursor();
+        self.movetext = pgn::movetext_tokens(game);
+        self.load_arrows(game);
+        self.update_endgame_info(game);
+        self.update_analysis(game);
         self.handle_end_state(game);
     }
 
+    pub fn moves_were_taken_back(&mut self, game: &mut GameState) {
+        self.is_dragged = false;
+        self.is_promotion_ui_shown = false;
+        self.is_takeback_requested = false;
+        self.movetext = pgn::movetext_tokens(game);
+        self.show_move(game, game.cursor());
+        self.handle_end_state(game);
+    }
+
+    pub fn takeback_was_requested(&mut self) {
+        self.is_takeback_requested = true;
+        self.audio.play_sound("GenericNotify");
+    }
+
+    fn draw_takeback_request(&mut self, egui_ctx: &CtxRef) {
+        egui::Window::new("Takeback")
+            .resizable(false)
+            .collapsible(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0., 0.))
+            .show(egui_ctx, |ui| {
+                ui.label("Your opponent asks to take back their last move.");
+                ui.horizontal(|ui| {
+                    if ui.button("Accept").clicked() {
+                        self.player_input_buffer = Some(PlayerInput::TakebackAnswer(true));
+                        self.is_takeback_requested = false;
+                    }
+                    if ui.button("Decline").clicked() {
+                        self.player_input_buffer = Some(PlayerInput::TakebackAnswer(false));
+                        self.is_takeback_requested = false;
+                    }
+                });
+            });
+    }
+
+    // the team gave up half its time. The server sends the clocks next
+    pub fn player_berserked(&mut self, game: &mut GameState, team: ChessTeam) {
+        if let Some(clock) = game.get_clock_mut() {
+            clock.berserk(team);
+        }
+        self.audio.play_sound("Berserk");
+    }
+
+    pub fn draw_was_offered(&mut self) {
+        self.is_draw_offered = true;
+        self.audio.play_sound("GenericNotify");
+    }
+
+    fn draw_draw_offer(&mut self, egui_ctx: &CtxRef) {
+        egui::Window::new("Draw offer")
+            .resizable(false)
+            .collapsible(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0., 0.))
+            .show(egui_ctx, |ui| {
+                ui.label("Your opponent offers a draw.");
+                ui.horizontal(|ui| {
+                    if ui.button("Accept").clicked() {
+                        self.player_input_buffer = Some(PlayerInput::DrawAnswer(true));
+                        self.is_draw_offered = false;
+                    }
+                    if ui.button("Decline").clicked() {
+                        self.player_input_buffer = Some(PlayerInput::DrawAnswer(false));
+                        self.is_draw_offered = false;
+                    }
+                });
+            });
+    }
+
+    // The server ended the game. The board can't tell a resignation or an agreed
+    //   draw, so the result comes from the server
+    pub fn game_was_ended(&mut self, game: &mut GameState, result: String) {
+        self.is_takeback_requested = false;
+        self.is_draw_offered = false;
+        //the board saw the checkmate already
+        if self.is_game_over {
+            return;
+        }
+
+        self.is_game_over = true;
+        self.result = Some(result);
+        self.audio.play_sound("GenericNotify");
+        if let Some(clock) = game.get_clock_mut() {
+            clock.stop();
+        }
+    }
+
     pub fn move_was_made_from_other_client(&mut self, game: &mut GameState, res: bool) {
         self.move_was_made(game);
         self.sync_board(&game.get_board());
@@ -720,8 +1490,42 @@ impl GfxState {
         }
     }
 
-    fn clear_arrows(&mut self) {
-        self.arrows.clear();
+    //the arrows of every position are kept with the move in the game
+    fn load_arrows(&mut self, game: &GameState) {
+        let path = &game.get_line_path()[..self.viewed_move];
+        self.arrows = match game.get_annotations(path) {
+            Some(annotations) => annotations
+                .marks
+                .iter()
+                .map(|mark| Arrow::from_mark(*mark))
+                .collect(),
+            None => vec![],
+        };
+    }
+
+    fn save_arrows(&mut self, game: &mut GameState) {
+        let path = game.get_line_path()[..self.viewed_move].to_vec();
+        if let Some(annotations) = game.get_annotations_mut(&path) {
+            let old_marks = std::mem::take(&mut annotations.marks);
+            annotations.marks = self
+                .arrows
+                .iter()
+                .map(|arrow| {
+                    //arrows that were there keep their colour, new ones are green
+                    old_marks
+                        .iter()
+                        .copied()
+                        .find(|mark| {
+                            let color = match mark {
+                                Mark::Arrow { color, .. } | Mark::Square { color, .. } => *color,
+                            };
+                            *mark == arrow.to_mark(color)
+                        })
+                        .unwrap_or_else(|| arrow.to_mark(MarkColor::Green))
+                })
+                .collect();
+        }
+        self.movetext = pgn::movetext_tokens(game);
     }
 
     fn draw_arrows(&mut self) {
@@ -832,7 +1636,8 @@ impl GfxState {
         }
 
         //draw arrows
-        for arrow in &self.arrows {
+        let engine_arrow = self.get_engine_arrow();
+        for arrow in self.arrows.iter().chain(engine_arrow.iter()) {
             match arrow {
                 Arrow::Arrow(coord_from_orig, coord_to_orig) => {
                     let coord_from: Coord;
@@ -904,7 +1709,9 @@ impl GfxState {
         }
     }
 
-    fn handle_arrows_input(&mut self) {
+    fn handle_arrows_input(&mut self, game: &mut GameState) {
+        let arrow_count = self.arrows.len();
+
         // start arrow registration when pressing right mouse button
         if input::is_mouse_button_pressed(MouseButton::Right) && !self.is_dragged {
             let mouse_vec = input::mouse_position();
@@ -985,6 +1792,10 @@ impl GfxState {
             }
         }
 
+        if self.arrows.len() != arrow_count {
+            self.save_arrows(game);
+        }
+
         self.draw_arrows();
     }
 
@@ -996,28 +1807,67 @@ impl GfxState {
             println!("pgn output: {}", game.get_pgn());
         }
 
-        if input::is_key_pressed(KeyCode::Backspace) {
+        if input::is_key_pressed(KeyCode::Backspace) && !self.is_typing {
             self.player_input_buffer = Some(PlayerInput::GoBack);
         }
 
-        if input::is_key_pressed(KeyCode::T) {
+        if input::is_key_pressed(KeyCode::T) && !self.is_typing {
             self.flip_board();
         }
 
+        if input::is_key_pressed(KeyCode::A) && !self.is_typing {
+            self.toggle_analysis(game);
+        }
+
+        if input::is_key_pressed(KeyCode::U) && !self.is_typing {
+            self.player_input_buffer = Some(PlayerInput::Takeback);
+        }
+
+        if let Some(analysis) = &mut self.analysis {
+            analysis.poll();
+        }
+
+        if let Some(review) = &mut self.review {
+            review.poll();
+        }
+
         egui_macroquad::ui(|egui_ctx| {
             self.draw_moves_ui(game, egui_ctx);
-            self.draw_options_button(egui_ctx);
+            self.draw_options_button(game, egui_ctx);
+            self.draw_endgame_ui(egui_ctx);
+            self.draw_analysis_ui(egui_ctx);
+            self.draw_review_ui(egui_ctx);
+            self.draw_result_ui(egui_ctx);
 
             if self.is_promotion_ui_shown {
                 self.draw_promotion(game, egui_ctx);
             }
 
+            if self.is_takeback_requested {
+                self.draw_takeback_request(egui_ctx);
+            }
+
+            if self.is_draw_offered {
+                self.draw_draw_offer(egui_ctx);
+            }
+
+            if let Some(chat) = &mut self.chat {
+                let pos = egui::pos2(BOARD_PADDING as f32, WINDOW_HEIGHT as f32 - 260.);
+                if let Some(message) = chat.draw(egui_ctx, pos) {
+                    self.player_input_buffer = Some(PlayerInput::Chat(message));
+                }
+            }
+
+            self.is_typing = egui_ctx.wants_keyboard_input();
+
             if self.options_visible {
                 self.draw_options_ui(game, egui_ctx);
             }
         });
 
         self.draw_board();
+        self.draw_eval_bar();
+        self.draw_clocks(game);
 
         //draw tiles of last move
 
@@ -1044,7 +1894,7 @@ impl GfxState {
                     coord_from = Coord::from(tile_from);
                     coord_to = Coord::from(tile_to);
                 }
-                Move::CastleShort => match game.whose_turn().the_other_one() {
+                Move::CastleShort => match game.get_board_at(self.viewed_move - 1).whose_turn {
                     ChessTeam::Black => {
                         coord_from = Coord { x: 4, y: 7 };
                         coord_to = Coord { x: 6, y: 7 };
@@ -1054,7 +1904,7 @@ impl GfxState {
                         coord_to = Coord { x: 6, y: 0 };
                     }
                 },
-                Move::CastleLong => match game.whose_turn().the_other_one() {
+                Move::CastleLong => match game.get_board_at(self.viewed_move - 1).whose_turn {
                     ChessTeam::Black => {
                         coord_from = Coord { x: 4, y: 7 };
                         coord_to = Coord { x: 2, y: 7 };
@@ -1079,8 +1929,12 @@ impl GfxState {
             draw_rectangle(col_to.x, col_to.y, col_to.w, col_to.h, LAST_MOVE_COLOR);
         }
 
-        // if you click the board and you are watching a previous move, jump to last move
-        if input::is_mouse_button_down(MouseButton::Left) && self.viewed_move != game.move_count() {
+        // if you click the board and you are watching a previous move, jump to last move.
+        //  In single player you can play from there instead, making a variation
+        if input::is_mouse_button_down(MouseButton::Left)
+            && self.viewed_move != game.move_count()
+            && self.locked_team.is_some()
+        {
             let mouse_vec = input::mouse_position();
             let mouse_vec = vec2(mouse_vec.0, mouse_vec.1);
 
@@ -1094,8 +1948,9 @@ impl GfxState {
             let mouse_vec = input::mouse_position();
             let mouse_vec = vec2(mouse_vec.0, mouse_vec.1);
 
-            if self.board_col.is_in_box(mouse_vec) {
-                self.clear_arrows();
+            if self.board_col.is_in_box(mouse_vec) && !self.arrows.is_empty() {
+                self.arrows.clear();
+                self.save_arrows(game);
             }
         }
 
@@ -1177,8 +2032,13 @@ impl GfxState {
         // When you try to grab a piece with the mouse and mouse click
         if input::is_mouse_button_pressed(MouseButton::Left)
             && !self.is_dragged
-            && self.viewed_move == game.move_count()
+            && (self.viewed_move == game.move_count() || self.locked_team.is_none())
         {
+            //moves are played from the position on the board
+            if game.cursor() != self.viewed_move {
+                game.go_to(self.viewed_move);
+            }
+
             // println!("mouse click! at {:?}", input::mouse_position());
             //check if u clicked the box
             let mouse_vec = input::mouse_position();
@@ -1187,7 +2047,8 @@ impl GfxState {
             for (i, piece) in self.pieces.iter().enumerate() {
                 if piece.col.is_in_box(mouse_vec) {
                     // println!("clicked on box! dragged = true");
-                    if game.whose_turn() != piece.team || self.is_board_locked {
+                    if game.whose_turn() != piece.team || self.is_board_locked || self.is_spectating
+                    {
                         continue;
                     }
 
@@ -1232,7 +2093,7 @@ impl GfxState {
             let mouse_vec = input::mouse_position();
             let mouse_vec = vec2(mouse_vec.0, mouse_vec.1);
 
-            let mut pi = &mut self.pieces[self.dragged_piece_i];
+            let pi = &mut self.pieces[self.dragged_piece_i];
 
             pi.col.x = mouse_vec.x - pi.col.w / 2.0;
             pi.col.y = mouse_vec.y - pi.col.h / 2.0;
@@ -1275,7 +2136,7 @@ impl GfxState {
             self.pieces[self.dragged_piece_i].draw(&self.pieces_tex);
         }
 
-        self.handle_arrows_input();
+        self.handle_arrows_input(game);
 
         egui_macroquad::draw();
     }
@@ -1448,7 +2309,10 @@ pub fn draw_main_menu(mm_state: &mut MainMenuState, audio: Rc<Audio>) -> MenuCha
     let mut play_button_clicked = false;
     let mut play_fen_clicked = false;
 
-    let mut play_client_clicked = false;
+    //the message to log in with, to join the lobby
+    let mut login: Option<Message> = None;
+    let mut watch_clicked = false;
+    let mut watched_game = None;
 
     let mut res = MenuChange::None;
 
@@ -1463,6 +2327,7 @@ pub fn draw_main_menu(mm_state: &mut MainMenuState, audio: Rc<Audio>) -> MenuCha
                 if ui.add(egui::Button::new("Play against yourself")).clicked() {
                     res = MenuChange::Menu(MainMenuState::PlayMenu {
                         fen_string: String::new(),
+                        time_control: String::new(),
                     });
                 }
                 // ui.add(egui::TextEdit::singleline(ip_string));
@@ -1470,12 +2335,104 @@ pub fn draw_main_menu(mm_state: &mut MainMenuState, audio: Rc<Audio>) -> MenuCha
                     .add(egui::Button::new("Look for a player online"))
                     .clicked()
                 {
-                    play_client_clicked = true;
+                    res = MenuChange::Menu(MainMenuState::OnlineMenu {
+                        name: String::new(),
+                        password: String::new(),
+                        error: None,
+                    });
+                }
+                if ui.add(egui::Button::new("Watch a game online")).clicked() {
+                    watch_clicked = true;
                 }
             });
         }
-        MainMenuState::PlayMenu { fen_string } => {
+        MainMenuState::OnlineMenu {
+            name,
+            password,
+            error,
+        } => {
+            egui::Window::new("Play online").show(egui_ctx, |ui| {
+                if let Some(error) = error {
+                    ui.label(error.as_str());
+                }
+                if let Some(token) = multiplayer::saved_login() {
+                    ui.horizontal(|ui| {
+                        if ui.add(egui::Button::new("Log in as last time")).clicked() {
+                            login = Some(Message::LoginWithToken(token));
+                        }
+                        if ui.add(egui::Button::new("Forget it")).clicked() {
+                            multiplayer::forget_login();
+                        }
+                    });
+                    ui.separator();
+                }
+                ui.horizontal(|ui| {
+                    ui.label("Name:");
+                    ui.add(egui::TextEdit::singleline(name));
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Password:");
+                    ui.add(egui::TextEdit::singleline(password).password(true));
+                });
+                ui.horizontal(|ui| {
+                    let name = name.trim().to_string();
+                    if ui.add(egui::Button::new("Log in")).clicked() {
+                        login = Some(Message::Login(name.clone(), password.clone()));
+                    }
+                    if ui.add(egui::Button::new("Register")).clicked() {
+                        login = Some(Message::Register(name, password.clone()));
+                    }
+                    if ui.add(egui::Button::new("Play as guest")).clicked() {
+                        login = Some(Message::PlayAsGuest);
+                    }
+                    if ui.add(egui::Button::new("Back")).clicked() {
+                        res = MenuChange::Menu(MainMenuState::Main {});
+                    }
+                });
+            });
+        }
+        MainMenuState::WatchMenu { games, error } => {
+            egui::Window::new("Games being played").show(egui_ctx, |ui| {
+                if let Some(error) = error {
+                    ui.label(error.as_str());
+                }
+                if games.is_empty() {
+                    ui.label("No one is playing right now.");
+                }
+                for game in games.iter() {
+                    ui.horizontal(|ui| {
+                        let clock = match &game.settings.time_control {
+                            Some(control) => time_control_text(control),
+                            None => "no clock".to_string(),
+                        };
+                        ui.label(format!(
+                            "{} - {}: {} moves, {}",
+                            game.players[0], game.players[1], game.moves, clock
+                        ));
+                        if ui.add(egui::Button::new("Watch")).clicked() {
+                            watched_game = Some(game.id);
+                        }
+                    });
+                }
+                ui.horizontal(|ui| {
+                    if ui.add(egui::Button::new("Refresh")).clicked() {
+                        watch_clicked = true;
+                    }
+                    if ui.add(egui::Button::new("Back")).clicked() {
+                        res = MenuChange::Menu(MainMenuState::Main {});
+                    }
+                });
+            });
+        }
+        MainMenuState::PlayMenu {
+            fen_string,
+            time_control,
+        } => {
             egui::Window::new("Play").show(egui_ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Clock (minutes+increment):");
+                    ui.add(egui::TextEdit::singleline(time_control).desired_width(60.));
+                });
                 if ui.add(egui::Button::new("Play normal game")).clicked() {
                     play_button_clicked = true;
                 }
@@ -1535,22 +2492,96 @@ pub fn draw_main_menu(mm_state: &mut MainMenuState, audio: Rc<Audio>) -> MenuCha
     egui_macroquad::draw();
 
     if play_button_clicked {
-        res = MenuChange::Game(GameState::init());
+        res = MenuChange::Game(Box::new(GameState::init()));
     } else if play_fen_clicked {
-        if let MainMenuState::PlayMenu { fen_string } = mm_state {
+        if let MainMenuState::PlayMenu { fen_string, .. } = mm_state {
             let game = chess::parse_fen(fen_string.to_string());
             if let Some(game) = game {
-                res = MenuChange::Game(game);
+                res = MenuChange::Game(Box::new(game));
+            }
+        }
+    } else if let Some(login) = login {
+        if let MainMenuState::OnlineMenu { error, .. } = mm_state {
+            match LobbyState::join(multiplayer::SERVER_ADDR, login, audio) {
+                Ok(lobby) => res = MenuChange::Lobby(Box::new(lobby)),
+                Err(e) => *error = Some(format!("Couldn't join the lobby: {}", e)),
             }
         }
-    } else if play_client_clicked {
-        if let MainMenuState::Main {} = mm_state {
-            let mp_state = MPState::init("193.200.238.76:3333".to_string(), audio);
-            res = MenuChange::MultiplayerGame(mp_state);
+    } else if watch_clicked {
+        res = MenuChange::Menu(match multiplayer::list_games(multiplayer::SERVER_ADDR) {
+            Ok(games) => MainMenuState::WatchMenu {
+                games,
+                error: None,
+            },
+            Err(e) => MainMenuState::WatchMenu {
+                games: vec![],
+                error: Some(format!("Couldn't get the games: {}", e)),
+            },
+        });
+    } else if let Some(game_id) = watched_game {
+        match SpectatorState::init(multiplayer::SERVER_ADDR, game_id, audio) {
+            Ok(spectator) => res = MenuChange::Spectate(Box::new(spectator)),
+            Err(e) => {
+                if let MainMenuState::WatchMenu { error, .. } = mm_state {
+                    *error = Some(format!("Couldn't watch the game: {}", e));
+                }
+            }
         }
     } else if preset_position.is_some() {
-        res = MenuChange::Game(preset_position.unwrap());
+        res = MenuChange::Game(Box::new(preset_position.unwrap()));
+    }
+
+    //games from the play menu get the clock that was asked for
+    if let (MenuChange::Game(game), MainMenuState::PlayMenu { time_control, .. }) =
+        (&mut res, mm_state)
+    {
+        if let Some(control) = parse_time_control(time_control) {
+            let source = Arc::new(clock::SystemTime::new());
+            game.set_clock(Some(Clock::new(control, source)));
+        }
     }
 
     res
 }
+
+// "5+3" is 5 minutes plus 3 seconds a move. "10" has no increment
+pub fn parse_time_control(text: &str) -> Option<TimeControl> {
+    let mut parts = text.trim().splitn(2, '+');
+    let minutes: f32 = parts.next()?.trim().parse().ok()?;
+    let increment: u64 = match parts.next() {
+        Some(increment) => increment.trim().parse().ok()?,
+        None => 0,
+    };
+
+    //nan, inf or too many minutes for a Duration
+    let time = Duration::try_from_secs_f32(minutes * 60.).ok()?;
+    let control = TimeControl::fischer(time, Duration::from_secs(increment));
+    match control.is_valid() {
+        true => Some(control),
+        false => None,
+    }
+}
+
+// the other way around: 5+3 for 5 minutes plus 3 seconds a move
+pub fn time_control_text(control: &TimeControl) -> String {
+    let minutes = control.starting_time().as_secs_f32() / 60.;
+    match control {
+        TimeControl::Periods {
+            bonus: clock::TimeBonus::Fischer(increment),
+            ..
+        } => format!("{}+{}", minutes, increment.as_secs()),
+        _ => format!("{}", minutes),
+    }
+}
+
+// m:ss, h:mm:ss, and tenths of a second under 10 seconds
+fn clock_text(time: Duration) -> String {
+    let secs = time.as_secs();
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else if time < Duration::from_secs(10) {
+        format!("0:{:02}.{}", secs, time.subsec_millis() / 100)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
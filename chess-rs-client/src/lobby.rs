@@ -0,0 +1,497 @@
+// The lobby of the server: the seeks of the other players, the form to post
+//  our own, and the challenges we get. It ends when the server starts a game.
+//
+// It also shows our ratings, how they went, and the leaderboards. And the
+//  past games on the server, to look at them again on the board, the lobby's
+//  chat and the tournaments. After a game of a tournament the server puts us
+//  back here, on the same connection.
+
+use std::rc::Rc;
+use std::sync::mpsc;
+
+use chess_rs_core::{self as chess, pgn, ChessTeam};
+use chess_rs_protocol::{
+    self as protocol, Challenge, GameQuery, GameSettings, GameStart, Message, PastGame,
+    PlayerRating, RatingCategory, RatingPoint, Seek, SeekRequest, Variant,
+};
+use macroquad::prelude::*;
+
+use crate::chat::ChatPanel;
+use crate::graphics::{self, BACKGROUND_COLOR};
+use crate::multiplayer::{self, Connection, MPState};
+use crate::tournaments::TournamentPanel;
+use crate::Audio;
+
+pub enum LobbyChange {
+    None,
+    GoBack,
+    Game(GameStart),
+    //a past game, to look at on the board
+    Review(Box<chess::GameState>),
+}
+
+pub struct LobbyState {
+    connection: Connection,
+    ip: String,
+    name: String,
+    is_guest: bool,
+    audio: Rc<Audio>,
+    seeks: Vec<Seek>,
+    //challenges we didn't answer yet
+    challenges: Vec<Challenge>,
+    //the last thing the server said, like an error
+    status: Option<String>,
+    //the seek form. "5+3", or empty for no clock
+    time_control: String,
+    //empty for the starting position
+    fen: String,
+    color: Option<ChessTeam>,
+    min_rating: String,
+    max_rating: String,
+    //who to challenge
+    opponent: String,
+    //ours, empty for guests
+    ratings: Vec<(RatingCategory, PlayerRating)>,
+    //the category the leaderboard and history are of
+    category: RatingCategory,
+    leaderboard: Option<Vec<(String, PlayerRating)>>,
+    //the name it's of, and the ratings
+    history: Option<(String, Vec<RatingPoint>)>,
+    //whose history to get
+    history_name: String,
+    //the search for past games. Dates are "2020.09.13"
+    games_player: String,
+    games_opening: String,
+    games_since: String,
+    games_until: String,
+    past_games: Option<Vec<PastGame>>,
+    chat: ChatPanel,
+    tournaments: TournamentPanel,
+}
+
+fn seek_text(request: &SeekRequest) -> String {
+    let clock = match &request.settings.time_control {
+        Some(control) => graphics::time_control_text(control),
+        None => "no clock".to_string(),
+    };
+    let variant = match request.settings.variant {
+        Variant::Standard => "standard",
+        Variant::FromPosition(_) => "from position",
+    };
+    let color = match request.color {
+        Some(team) => format!(", plays {}", team),
+        None => String::new(),
+    };
+    let range = match request.rating_range {
+        Some((min, max)) => format!(", {}-{}", min, max),
+        None => String::new(),
+    };
+    format!("{} {}{}{}", clock, variant, color, range)
+}
+
+impl LobbyState {
+    // connects to the server, logs in with the message and joins the lobby
+    pub fn join(ip: &str, login: Message, audio: Rc<Audio>) -> Result<LobbyState, String> {
+        let is_guest = login == Message::PlayAsGuest;
+        let mut stream = multiplayer::connect(ip)?;
+        let name = multiplayer::log_in(&mut stream, login)?;
+        protocol::write_message(&mut stream, &Message::JoinLobby).map_err(|e| e.to_string())?;
+
+        let seeks = match protocol::read_message(&mut stream) {
+            Ok(Message::Lobby(seeks)) => seeks,
+            Ok(Message::Error(e)) => return Err(e.to_string()),
+            Ok(message) => return Err(format!("expected the lobby, got {:?}", message)),
+            Err(e) => return Err(e.to_string()),
+        };
+
+        let connection = multiplayer::spawn_connection_threads(stream);
+        Ok(LobbyState::new(
+            connection,
+            ip.to_string(),
+            name,
+            is_guest,
+            audio,
+            seeks,
+        ))
+    }
+
+    // Back from a game of a tournament, on its connection. With what the server
+    //   sent since it put us back in the lobby
+    pub fn back_from_game(
+        connection: Connection,
+        ip: String,
+        name: String,
+        is_guest: bool,
+        audio: Rc<Audio>,
+        seeks: Vec<Seek>,
+        messages: Vec<Message>,
+    ) -> LobbyState {
+        let mut lobby = LobbyState::new(connection, ip, name, is_guest, audio, seeks);
+        for message in messages {
+            lobby.handle_message(message);
+        }
+        lobby
+    }
+
+    fn new(
+        connection: Connection,
+        ip: String,
+        name: String,
+        is_guest: bool,
+        audio: Rc<Audio>,
+        seeks: Vec<Seek>,
+    ) -> LobbyState {
+        if !is_guest {
+            let _ = connection.tx_send.send(Message::GetRatings(name.clone()));
+        }
+        let _ = connection.tx_send.send(Message::ListTournaments);
+
+        LobbyState {
+            connection,
+            ip,
+            history_name: name.clone(),
+            name: name.clone(),
+            is_guest,
+            audio,
+            seeks,
+            challenges: vec![],
+            status: None,
+            time_control: String::new(),
+            fen: String::new(),
+            color: None,
+            min_rating: String::new(),
+            max_rating: String::new(),
+            opponent: String::new(),
+            ratings: vec![],
+            category: RatingCategory::Blitz,
+            leaderboard: None,
+            history: None,
+            games_player: String::new(),
+            games_opening: String::new(),
+            games_since: String::new(),
+            games_until: String::new(),
+            past_games: None,
+            chat: ChatPanel::new(name.clone(), true),
+            tournaments: TournamentPanel::new(name),
+        }
+    }
+
+    // the game the server started, on the lobby's connection
+    pub fn into_game(self, start: GameStart) -> MPState {
+        MPState::start(self.connection, start, self.ip, self.is_guest, self.audio)
+    }
+
+    fn send_message(&mut self, message: Message) {
+        let _ = self.connection.tx_send.send(message);
+    }
+
+    // the seek of the form. None if something in it isn't valid
+    fn request(&self) -> Option<SeekRequest> {
+        let time_control = match self.time_control.trim() {
+            "" => None,
+            text => Some(graphics::parse_time_control(text)?),
+        };
+        let variant = match self.fen.trim() {
+            "" => Variant::Standard,
+            fen => Variant::FromPosition(fen.to_string()),
+        };
+        let rating_range = match (self.min_rating.trim(), self.max_rating.trim()) {
+            ("", "") => None,
+            (min, max) => Some((min.parse().unwrap_or(0), max.parse().unwrap_or(u32::MAX))),
+        };
+
+        Some(SeekRequest {
+            settings: GameSettings {
+                variant,
+                time_control,
+            },
+            color: self.color,
+            rating_range,
+        })
+    }
+
+    // the search of the past games form. None if a date isn't valid
+    fn game_query(&self) -> Option<GameQuery> {
+        let text = |text: &str| match text.trim() {
+            "" => None,
+            text => Some(text.to_string()),
+        };
+        let date = |date: &str| match date.trim() {
+            "" => Some(None),
+            date => pgn::parse_pgn_date(date).map(Some),
+        };
+        Some(GameQuery {
+            player: text(&self.games_player),
+            since: date(&self.games_since)?,
+            //the whole day
+            until: date(&self.games_until)?.map(|until| until + 86400),
+            opening: text(&self.games_opening),
+        })
+    }
+
+    fn handle_message(&mut self, message: Message) -> LobbyChange {
+        match message {
+            Message::Lobby(seeks) => self.seeks = seeks,
+            Message::SeekAdded(seek) => self.seeks.push(seek),
+            Message::SeekRemoved(id) => self.seeks.retain(|seek| seek.id != id),
+            Message::Challenged(challenge) => {
+                self.audio.play_sound("NewChallenge");
+                self.challenges.push(challenge);
+            }
+            Message::ChallengeDeclined(name) => {
+                self.status = Some(format!("{} declined the challenge.", name));
+            }
+            Message::GameStart(start) => return LobbyChange::Game(start),
+            Message::Ratings(name, ratings) if name == self.name => self.ratings = ratings,
+            Message::Leaderboard(category, best) if category == self.category => {
+                self.leaderboard = Some(best);
+            }
+            Message::RatingHistory(name, category, points) if category == self.category => {
+                self.history = Some((name, points));
+            }
+            Message::FoundGames(games) => self.past_games = Some(games),
+            Message::ChatLine(line) => self.chat.receive(line, &self.audio),
+            message @ Message::Tournaments(_)
+            | message @ Message::TournamentUpdated(_)
+            | message @ Message::Standings(..)
+            | message @ Message::TournamentOver(..) => {
+                self.tournaments.receive(message, &self.audio)
+            }
+            Message::Pgn(_, pgn) => match pgn::parse_pgn(&pgn) {
+                Ok(game) => return LobbyChange::Review(Box::new(game)),
+                Err(e) => self.status = Some(format!("The game can't be read: {:?}", e)),
+            },
+            Message::Error(e) => {
+                self.audio.play_sound("Error");
+                self.status = Some(e.to_string());
+            }
+            message => println!("recieved {:?} in the lobby", message),
+        }
+        LobbyChange::None
+    }
+
+    pub fn lobby_loop(&mut self) -> LobbyChange {
+        match self.connection.rx_recv.try_recv() {
+            Ok(message) => {
+                let change = self.handle_message(message);
+                if !matches!(change, LobbyChange::None) {
+                    return change;
+                }
+            }
+            Err(mpsc::TryRecvError::Empty) => {}
+            Err(_) => {
+                self.status = Some("Lost the connection to the server.".to_string());
+            }
+        }
+
+        let mut res = LobbyChange::None;
+        let mut to_send = vec![];
+
+        clear_background(BACKGROUND_COLOR);
+        egui_macroquad::ui(|egui_ctx| {
+            egui::Window::new(format!("Lobby ({})", self.name)).show(egui_ctx, |ui| {
+                if let Some(status) = &self.status {
+                    ui.label(status.as_str());
+                    ui.separator();
+                }
+
+                if self.seeks.is_empty() {
+                    ui.label("No one is looking for a game.");
+                }
+                for seek in self.seeks.iter() {
+                    ui.horizontal(|ui| {
+                        ui.label(format!(
+                            "{} ({}): {}",
+                            seek.player,
+                            seek.rating,
+                            seek_text(&seek.request)
+                        ));
+                        if seek.player == self.name {
+                            if ui.add(egui::Button::new("Cancel")).clicked() {
+                                to_send.push(Message::CancelSeek(seek.id));
+                            }
+                        } else if ui.add(egui::Button::new("Play")).clicked() {
+                            to_send.push(Message::AcceptSeek(seek.id));
+                        }
+                    });
+                }
+
+                if !self.challenges.is_empty() {
+                    ui.separator();
+                }
+                let mut answered = None;
+                for challenge in self.challenges.iter() {
+                    ui.horizontal(|ui| {
+                        ui.label(format!(
+                            "{} challenges you: {}",
+                            challenge.from,
+                            seek_text(&challenge.request)
+                        ));
+                        if ui.add(egui::Button::new("Accept")).clicked() {
+                            to_send.push(Message::AcceptChallenge(challenge.id));
+                            answered = Some(challenge.id);
+                        }
+                        if ui.add(egui::Button::new("Decline")).clicked() {
+                            to_send.push(Message::DeclineChallenge(challenge.id));
+                            answered = Some(challenge.id);
+                        }
+                    });
+                }
+                if let Some(id) = answered {
+                    self.challenges.retain(|challenge| challenge.id != id);
+                }
+
+                ui.separator();
+                if ui.add(egui::Button::new("Back")).clicked() {
+                    res = LobbyChange::GoBack;
+                }
+            });
+
+            egui::Window::new("New game").show(egui_ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Clock (minutes+increment):");
+                    ui.add(egui::TextEdit::singleline(&mut self.time_control).desired_width(60.));
+                });
+                ui.horizontal(|ui| {
+                    ui.label("FEN:");
+                    ui.add(egui::TextEdit::singleline(&mut self.fen));
+                });
+                ui.horizontal(|ui| {
+                    ui.radio_value(&mut self.color, None, "Either color");
+                    ui.radio_value(&mut self.color, Some(ChessTeam::White), "White");
+                    ui.radio_value(&mut self.color, Some(ChessTeam::Black), "Black");
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Opponent rating:");
+                    ui.add(egui::TextEdit::singleline(&mut self.min_rating).desired_width(50.));
+                    ui.label("to");
+                    ui.add(egui::TextEdit::singleline(&mut self.max_rating).desired_width(50.));
+                });
+
+                let request = self.request();
+                if ui.add(egui::Button::new("Look for an opponent")).clicked() {
+                    match &request {
+                        Some(request) => to_send.push(Message::Seek(request.clone())),
+                        None => self.status = Some("The clock isn't valid.".to_string()),
+                    }
+                }
+                ui.horizontal(|ui| {
+                    ui.add(egui::TextEdit::singleline(&mut self.opponent).desired_width(100.));
+                    if ui.add(egui::Button::new("Challenge")).clicked() {
+                        if let Some(request) = &request {
+                            let opponent = self.opponent.trim().to_string();
+                            to_send.push(Message::Challenge(opponent, request.clone()));
+                        }
+                    }
+                });
+            });
+
+            egui::Window::new("Ratings").show(egui_ctx, |ui| {
+                if self.ratings.is_empty() {
+                    ui.label("Rated games are between players with accounts, with a clock.");
+                }
+                for (category, rating) in self.ratings.iter() {
+                    ui.label(format!("{}: {}", category, rating));
+                }
+                ui.separator();
+
+                ui.horizontal(|ui| {
+                    for category in RatingCategory::ALL.iter() {
+                        if ui
+                            .radio_value(&mut self.category, *category, category.name())
+                            .clicked()
+                        {
+                            self.leaderboard = None;
+                            self.history = None;
+                        }
+                    }
+                });
+                ui.horizontal(|ui| {
+                    if ui.add(egui::Button::new("Leaderboard")).clicked() {
+                        to_send.push(Message::GetLeaderboard(self.category));
+                    }
+                    if ui.add(egui::Button::new("History of")).clicked() {
+                        let name = self.history_name.trim().to_string();
+                        to_send.push(Message::GetRatingHistory(name, self.category));
+                    }
+                    ui.add(egui::TextEdit::singleline(&mut self.history_name).desired_width(100.));
+                });
+
+                if let Some(best) = &self.leaderboard {
+                    if best.is_empty() {
+                        ui.label("No one is on this leaderboard yet.");
+                    }
+                    for (place, (name, rating)) in best.iter().enumerate() {
+                        ui.label(format!("{}. {} {}", place + 1, name, rating));
+                    }
+                }
+                if let Some((name, points)) = &self.history {
+                    let ratings: Vec<String> = points
+                        .iter()
+                        .map(|point| point.rating.to_string())
+                        .collect();
+                    match ratings.is_empty() {
+                        true => ui.label(format!("{} has no {} games.", name, self.category)),
+                        false => ui.label(format!("{}: {}", name, ratings.join(", "))),
+                    };
+                }
+            });
+
+            egui::Window::new("Past games").show(egui_ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Player:");
+                    ui.add(egui::TextEdit::singleline(&mut self.games_player).desired_width(100.));
+                    ui.label("Opening:");
+                    ui.add(egui::TextEdit::singleline(&mut self.games_opening).desired_width(100.));
+                });
+                ui.horizontal(|ui| {
+                    ui.label("From:");
+                    ui.add(egui::TextEdit::singleline(&mut self.games_since).desired_width(80.));
+                    ui.label("to:");
+                    ui.add(egui::TextEdit::singleline(&mut self.games_until).desired_width(80.));
+                    if ui.add(egui::Button::new("Find")).clicked() {
+                        match self.game_query() {
+                            Some(query) => to_send.push(Message::FindGames(query)),
+                            None => self.status = Some("Dates are like 2020.09.13.".to_string()),
+                        }
+                    }
+                });
+
+                if let Some(games) = &self.past_games {
+                    if games.is_empty() {
+                        ui.label("No games found.");
+                    }
+                    for game in games.iter() {
+                        ui.horizontal(|ui| {
+                            ui.label(format!(
+                                "{} {} - {}, {} moves. {}",
+                                pgn::pgn_date(game.started),
+                                game.players[0],
+                                game.players[1],
+                                game.moves,
+                                game.result
+                            ));
+                            if ui.add(egui::Button::new("Review")).clicked() {
+                                to_send.push(Message::GetPgn(game.id));
+                            }
+                        });
+                    }
+                }
+            });
+
+            if let Some(message) = self.tournaments.draw(egui_ctx) {
+                to_send.push(message);
+            }
+            if let Some(message) = self.chat.draw(egui_ctx, egui::pos2(10., 400.)) {
+                to_send.push(message);
+            }
+        });
+        egui_macroquad::draw();
+
+        for message in to_send {
+            self.send_message(message);
+        }
+
+        res
+    }
+}
@@ -2,12 +2,21 @@
 #![allow(dead_code)]
 //#![windows_subsystem = "windows"]
 
+mod analysis;
+mod chat;
 mod graphics;
+mod lobby;
 mod multiplayer;
+mod review;
+mod spectator;
+mod tournaments;
 
 use chess_rs_core as chess;
 
-use crate::multiplayer::MPState;
+use crate::lobby::{LobbyChange, LobbyState};
+use crate::multiplayer::{MPChange, MPState};
+use crate::spectator::SpectatorState;
+use chess_rs_protocol::GameInfo;
 use std::collections::HashMap;
 use std::rc::Rc;
 use std::sync::Arc;
@@ -116,14 +125,31 @@ fn parse_args(args: Vec<String>) -> Args {
 
 pub enum MainMenuState {
     Main,
-    PlayMenu { fen_string: String },
+    PlayMenu {
+        fen_string: String,
+        //"5+3", or empty for no clock
+        time_control: String,
+    },
+    //logging in to play online
+    OnlineMenu {
+        name: String,
+        password: String,
+        error: Option<String>,
+    },
+    //the games on the server, to pick one to watch
+    WatchMenu {
+        games: Vec<GameInfo>,
+        error: Option<String>,
+    },
     OptionsMenu,
 }
 
 pub enum GameState {
     MainMenu(MainMenuState),
     SinglePlayer(chess::GameState, graphics::GfxState),
+    Lobby(LobbyState),
     MultiplayerSession(MPState),
+    Spectating(SpectatorState),
 }
 
 impl GameState {
@@ -185,8 +211,9 @@ async fn main() {
 
 pub enum MenuChange {
     Menu(MainMenuState),
-    Game(chess::GameState),
-    MultiplayerGame(MPState),
+    Game(Box<chess::GameState>),
+    Lobby(Box<LobbyState>),
+    Spectate(Box<SpectatorState>),
     None,
 }
 
@@ -198,10 +225,13 @@ fn game_loop(game_state: &mut GameState, audio: Rc<Audio>) {
                 *game_state = GameState::MainMenu(menu);
             }
             MenuChange::Game(gs) => {
-                game_state.swap_to_in_game(gs, audio.clone());
+                game_state.swap_to_in_game(*gs, audio.clone());
             }
-            MenuChange::MultiplayerGame(mp_state) => {
-                game_state.swap_to_multiplayer(mp_state);
+            MenuChange::Lobby(lobby) => {
+                *game_state = GameState::Lobby(*lobby);
+            }
+            MenuChange::Spectate(spectator) => {
+                *game_state = GameState::Spectating(*spectator);
             }
             MenuChange::None => {}
         },
@@ -214,6 +244,20 @@ fn game_loop(game_state: &mut GameState, audio: Rc<Audio>) {
                     graphics::PlayerInput::GoBack => {
                         game_state.swap_to_mm();
                     }
+                    graphics::PlayerInput::Takeback => {
+                        //no one to ask in single player
+                        if game.undo_move().is_some() {
+                            gfx_state.moves_were_taken_back(game);
+                        }
+                    }
+                    //no one to play against
+                    graphics::PlayerInput::TakebackAnswer(_)
+                    | graphics::PlayerInput::Resign
+                    | graphics::PlayerInput::OfferDraw
+                    | graphics::PlayerInput::DrawAnswer(_)
+                    | graphics::PlayerInput::Abort
+                    | graphics::PlayerInput::Berserk
+                    | graphics::PlayerInput::Chat(_) => {}
                     graphics::PlayerInput::Move(_chess_move, _move_res) => {
                         //ok so here u do stuff with the move
                         // if u are the client u send the move to the server and stuff
@@ -225,9 +269,37 @@ fn game_loop(game_state: &mut GameState, audio: Rc<Audio>) {
                 }
             }
         }
-        GameState::MultiplayerSession(mp_state) => {
-            let go_back = mp_state.mp_loop();
-            if go_back {
+        GameState::Lobby(lobby) => match lobby.lobby_loop() {
+            LobbyChange::GoBack => game_state.swap_to_mm(),
+            LobbyChange::Game(start) => {
+                //the game takes the lobby's connection
+                let state = std::mem::replace(game_state, GameState::init_mm());
+                if let GameState::Lobby(lobby) = state {
+                    game_state.swap_to_multiplayer(lobby.into_game(start));
+                }
+            }
+            LobbyChange::Review(game) => game_state.swap_to_in_game(*game, audio.clone()),
+            LobbyChange::None => {}
+        },
+        GameState::MultiplayerSession(mp_state) => match mp_state.mp_loop() {
+            MPChange::GoBack => game_state.swap_to_mm(),
+            //a game of a tournament ended, the connection goes on
+            MPChange::Lobby => {
+                let state = std::mem::replace(game_state, GameState::init_mm());
+                if let GameState::MultiplayerSession(mp_state) = state {
+                    *game_state = GameState::Lobby(mp_state.into_lobby());
+                }
+            }
+            MPChange::Game(start) => {
+                let state = std::mem::replace(game_state, GameState::init_mm());
+                if let GameState::MultiplayerSession(mp_state) = state {
+                    game_state.swap_to_multiplayer(mp_state.into_game(start));
+                }
+            }
+            MPChange::None => {}
+        },
+        GameState::Spectating(spectator) => {
+            if spectator.spectate_loop() {
                 game_state.swap_to_mm();
             }
         }
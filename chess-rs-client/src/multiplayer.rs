@@ -1,178 +1,526 @@
-use bincode::Options;
-use serde::{Deserialize, Serialize};
-use std::io::{Read, Write};
+use std::convert::TryInto;
+use std::fs;
 use std::net::TcpStream;
 use std::rc::Rc;
 use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use chess_rs_core::{ChessPiece, ChessTeam, GameState, Move, Tile};
-use chess_rs_core as chess;
+use chess_rs_core::clock::{self, Clock};
+use chess_rs_core::{ChessTeam, GameState};
+use chess_rs_protocol::{
+    self as protocol, ClockTimes, GameInfo, GameSettings, GameSnapshot, GameStart, LoginToken,
+    Message, ProtocolError, Seek, SessionToken,
+};
 
+use crate::chat::ChatPanel;
 use crate::graphics::{GfxState, PlayerInput};
+use crate::lobby::LobbyState;
 use crate::Audio;
 
+pub const SERVER_ADDR: &str = "193.200.238.76:3333";
+
+// where the login token is kept, to log in without the password next time
+const LOGIN_TOKEN_FILE: &str = "login_token";
+
+// how long to keep trying to get back in the game after losing the connection
+const RECONNECT_TIME: Duration = Duration::from_secs(60);
+
+// the channels to the threads that talk to the server
+pub struct Connection {
+    pub rx_recv: Receiver<Message>,
+    pub tx_send: Sender<Message>,
+}
+
+// what a successful reconnection brings back
+struct Resumed {
+    connection: Connection,
+    team: ChessTeam,
+    snapshot: GameSnapshot,
+}
+
+pub enum MPChange {
+    None,
+    //to the menu
+    GoBack,
+    //the game of the tournament is over, and we are in the lobby again
+    Lobby,
+    //the next game of the tournament starts
+    Game(GameStart),
+}
+
 pub struct MPState {
     team: ChessTeam,
+    //the name we play with
+    name: String,
+    is_guest: bool,
     game: GameState,
     gfx_state: GfxState,
-    rx_recv: Receiver<Message>,
-    tx_send: Sender<Message>,
+    connection: Connection,
+    ip: String,
+    session: SessionToken,
+    audio: Rc<Audio>,
+    //the connection was lost and a thread is trying to get it back
+    reconnecting: Option<Receiver<Resumed>>,
+    is_game_over: bool,
+    //the seeks of the lobby, once the server put us back there after a game
+    //  of a tournament. And what else it sent since, for the lobby
+    lobby: Option<Vec<Seek>>,
+    lobby_messages: Vec<Message>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub enum Message {
-    GameStart(chess::ChessTeam),
-    Move(Move),
+// connects to the server and makes sure it speaks our protocol
+pub fn connect(ip: &str) -> Result<TcpStream, String> {
+    let mut stream = TcpStream::connect(ip).map_err(|e| e.to_string())?;
+
+    protocol::write_message(&mut stream, &Message::Hello(protocol::PROTOCOL_VERSION))
+        .map_err(|e| e.to_string())?;
+    match protocol::read_message(&mut stream) {
+        Ok(Message::Welcome(_)) => Ok(stream),
+        Ok(Message::Error(e)) => Err(format!("the server refused the connection: {}", e)),
+        Ok(message) => Err(format!(
+            "expected a welcome from the server, got {:?}",
+            message
+        )),
+        Err(e) => Err(e.to_string()),
+    }
 }
 
-impl MPState {
-    //connect to server and wait for game start
-    pub fn init(ip: String, audio: Rc<Audio>) -> MPState {
-        let mut game = GameState::init();
-        println!("ip {}", ip);
+pub fn saved_login() -> Option<LoginToken> {
+    let bytes = fs::read(LOGIN_TOKEN_FILE).ok()?;
+    Some(LoginToken(bytes.as_slice().try_into().ok()?))
+}
 
-        let tcp_stream_op;
+fn save_login(token: &LoginToken) {
+    if let Err(e) = fs::write(LOGIN_TOKEN_FILE, token.0) {
+        println!("couldn't save the login: {}", e);
+    }
+}
 
-        match TcpStream::connect(ip) {
-            Ok(stream) => {
-                println!("Successfully connected to server. Waiting for another player...");
-                tcp_stream_op = stream;
-            }
-            Err(e) => {
-                panic!("error at connecting {}", e);
+pub fn forget_login() {
+    let _ = fs::remove_file(LOGIN_TOKEN_FILE);
+}
+
+// Logs in with the message: Login, Register, LoginWithToken or PlayAsGuest.
+//   Returns the name we play with
+pub fn log_in(stream: &mut TcpStream, login: Message) -> Result<String, String> {
+    protocol::write_message(stream, &login).map_err(|e| e.to_string())?;
+    match protocol::read_message(stream) {
+        Ok(Message::LoggedIn(name, token)) => {
+            //every token works once, so the new one replaces the old one
+            if let Some(token) = token {
+                save_login(&token);
             }
+            Ok(name)
         }
+        Ok(Message::Error(ProtocolError::UnknownLogin)) => {
+            forget_login();
+            Err(ProtocolError::UnknownLogin.to_string())
+        }
+        Ok(Message::Error(e)) => Err(e.to_string()),
+        Ok(message) => Err(format!("expected to log in, got {:?}", message)),
+        Err(e) => Err(e.to_string()),
+    }
+}
 
-        let mut stream1 = tcp_stream_op;
-        let mut stream2 = stream1.try_clone().unwrap();
-
-        let (tx_send, rx_send): (Sender<Message>, Receiver<Message>) = mpsc::channel();
+// The threads that send and recieve the messages. When the connection is
+//   gone they stop, and the channels say so.
+pub fn spawn_connection_threads(stream: TcpStream) -> Connection {
+    let mut stream1 = stream;
+    let mut stream2 = stream1.try_clone().unwrap();
 
-        //thread that sends the messages
-        thread::spawn(move || {
-            let my_options = bincode::DefaultOptions::new()
-                .with_fixint_encoding()
-                .allow_trailing_bytes();
+    let (tx_send, rx_send): (Sender<Message>, Receiver<Message>) = mpsc::channel();
 
-            loop {
-                let msg_1 = rx_send.recv().unwrap();
-                let msg_1_encoded: Vec<u8> = my_options.serialize(&msg_1).unwrap();
+    //thread that sends the messages
+    thread::spawn(move || {
+        for msg_1 in rx_send {
+            if let Err(e) = protocol::write_message(&mut stream1, &msg_1) {
+                println!("error while writing to the socket: {}", e);
+                break;
+            }
+        }
+    });
 
-                stream1.write(&msg_1_encoded).unwrap();
-                // match stream1.write(&msg_1_encoded) {
-                //     Ok(_) => { }
-                //     Err(e) => {
-                //         println!("broken pipe... attempting to reconnect..., {}", e);
-                //     }
-                // }
+    //thread that recvs the messages
+    let (tx_recv, rx_recv): (Sender<Message>, Receiver<Message>) = mpsc::channel();
+    thread::spawn(move || {
+        //recv value from socket and send it thru channel
+        loop {
+            match protocol::read_message(&mut stream2) {
+                Ok(msg_decoded) => {
+                    if tx_recv.send(msg_decoded).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    println!("error while reading socket: {}", e);
+                    break;
+                }
             }
-        });
+        }
+    });
 
-        //thread that recvs the messages
-        let (tx_recv, rx_recv): (Sender<Message>, Receiver<Message>) = mpsc::channel();
-        thread::spawn(move || {
-            let message_size;
-            //this just calculates a move size
-            {
-                let the_move = Move::PieceMove {
-                    piece: ChessPiece::Queen,
-                    tile_from: Tile::A1,
-                    tile_to: Tile::A2,
-                    is_en_passant: false,
-                };
+    Connection { rx_recv, tx_send }
+}
 
-                let message = Message::Move(the_move);
+// the game before the first move, with its clock and the players for the PGN
+pub fn new_game(settings: &GameSettings, players: &[String; 2]) -> Option<GameState> {
+    let mut game = settings.variant.starting_game()?;
+    game.set_tag("White", players[0].clone());
+    game.set_tag("Black", players[1].clone());
+    if let Some(control) = &settings.time_control {
+        let source = Arc::new(clock::SystemTime::new());
+        game.set_clock(Some(Clock::new(control.clone(), source)));
+    }
+    Some(game)
+}
 
-                let my_options = bincode::DefaultOptions::new()
-                    .with_fixint_encoding()
-                    .allow_trailing_bytes();
+// the server's game, up to now
+pub fn snapshot_game(snapshot: &GameSnapshot) -> Result<GameState, String> {
+    let mut game = new_game(&snapshot.settings, &snapshot.players)
+        .ok_or("the server sent an invalid position")?;
+    for the_move in snapshot.moves.iter() {
+        if game.perform_move(*the_move).is_err() {
+            return Err("the server sent an illegal move".to_string());
+        }
+    }
+    if let Some(times) = snapshot.clocks {
+        sync_clock(&mut game, times);
+    }
+    Ok(game)
+}
 
-                let move_encoded: Vec<u8> = my_options.serialize(&message).unwrap();
-                message_size = move_encoded.len();
-            }
+pub fn sync_clock(game: &mut GameState, times: ClockTimes) {
+    if let Some(clock) = game.get_clock_mut() {
+        clock.sync(times.remaining, times.running);
+    }
+}
 
-            //recv value from socket and send it thru channel
-            loop {
-                let mut msg_buffer: Vec<u8> = vec![0; message_size];
-                stream2
-                    .read(&mut msg_buffer)
-                    .expect("error while reading socket");
+// The server's clocks are the ones that count, ours can run out a bit
+//   before. Then the server's move would be out of time here
+pub fn unflag_clock(game: &mut GameState) {
+    if let Some(clock) = game.get_clock_mut() {
+        if clock.flagged().is_some() {
+            let remaining = [
+                clock.remaining(ChessTeam::White),
+                clock.remaining(ChessTeam::Black),
+            ];
+            clock.sync(remaining, None);
+        }
+    }
+}
 
-                let msg_decoded: Message = bincode::deserialize(&msg_buffer).unwrap();
-                tx_recv.send(msg_decoded).unwrap();
-            }
-        });
+// the games on the server that can be watched
+pub fn list_games(ip: &str) -> Result<Vec<GameInfo>, String> {
+    let mut stream = connect(ip)?;
+    protocol::write_message(&mut stream, &Message::ListGames).map_err(|e| e.to_string())?;
+    match protocol::read_message(&mut stream) {
+        Ok(Message::GameList(games)) => Ok(games),
+        Ok(message) => Err(format!("expected the list of games, got {:?}", message)),
+        Err(e) => Err(e.to_string()),
+    }
+}
 
-        let team;
+// Tries to get back in the game of the session until it works, the server
+//   says the game is gone, or it's too late.
+fn spawn_reconnection_thread(ip: String, session: SessionToken) -> Receiver<Resumed> {
+    let (tx, rx) = mpsc::channel();
 
-        loop {
-            match rx_recv.try_recv() {
-                Ok(message) => match message {
-                    Message::GameStart(the_team) => {
-                        team = the_team;
-                        println!("Game started!!! team is {:?}", team);
-                        break;
-                    }
-                    Message::Move(the_move) => {
-                        println!("recieved move! but game didn's start yet!?? {:?}", the_move);
-                    }
-                },
-                Err(mpsc::TryRecvError::Empty) => {}
+    thread::spawn(move || {
+        let start = Instant::now();
+
+        while start.elapsed() < RECONNECT_TIME {
+            let mut stream = match connect(&ip) {
+                Ok(stream) => stream,
                 Err(_) => {
-                    panic!("recv error");
+                    thread::sleep(Duration::from_secs(1));
+                    continue;
                 }
-            }
+            };
 
-            thread::sleep(Duration::from_millis(200));
+            let res = protocol::write_message(&mut stream, &Message::Resume(session))
+                .map_err(protocol::FrameError::Io)
+                .and_then(|_| protocol::read_message(&mut stream));
+
+            match res {
+                Ok(Message::Resumed(team, snapshot)) => {
+                    let connection = spawn_connection_threads(stream);
+                    let _ = tx.send(Resumed {
+                        connection,
+                        team,
+                        snapshot,
+                    });
+                    return;
+                }
+                Ok(Message::Error(e)) => {
+                    println!("Couldn't get back in the game: {}", e);
+                    return;
+                }
+                _ => thread::sleep(Duration::from_secs(1)),
+            }
         }
+    });
 
-        let gfx_state = GfxState::init(&mut game, Some(team), audio);
+    rx
+}
 
-        let game = chess::GameState::init();
+impl MPState {
+    // the game found in the lobby starts
+    pub fn start(
+        connection: Connection,
+        start: GameStart,
+        ip: String,
+        is_guest: bool,
+        audio: Rc<Audio>,
+    ) -> MPState {
+        let opponent = match start.team {
+            ChessTeam::White => &start.players[1],
+            ChessTeam::Black => &start.players[0],
+        };
+        println!(
+            "Game started against {}! team is {:?}",
+            opponent, start.team
+        );
+        if let Some(tournament) = &start.tournament {
+            println!("It's a game of {}.", tournament.name);
+        }
+        //the server checked the position
+        let mut game = new_game(&start.settings, &start.players).unwrap_or_else(GameState::init);
+        let mut gfx_state = GfxState::init(&mut game, Some(start.team), audio.clone());
+        let name = match start.team {
+            ChessTeam::White => &start.players[0],
+            ChessTeam::Black => &start.players[1],
+        };
+        gfx_state.set_chat(ChatPanel::new(name.clone(), true));
+        if start.tournament.as_ref().is_some_and(|t| t.can_berserk) {
+            gfx_state.set_berserk_allowed();
+        }
 
         MPState {
+            team: start.team,
+            name: name.clone(),
+            is_guest,
             game,
             gfx_state,
-            rx_recv,
-            tx_send,
-            team,
+            connection,
+            ip,
+            session: start.session,
+            audio,
+            reconnecting: None,
+            is_game_over: false,
+            lobby: None,
+            lobby_messages: vec![],
         }
     }
 
-    fn send_move(&mut self, the_move: Move) {
-        let message = Message::Move(the_move);
-        self.tx_send.send(message).unwrap();
+    // back to the lobby the server put us in
+    pub fn into_lobby(self) -> LobbyState {
+        LobbyState::back_from_game(
+            self.connection,
+            self.ip,
+            self.name,
+            self.is_guest,
+            self.audio,
+            self.lobby.unwrap_or_default(),
+            self.lobby_messages,
+        )
     }
 
-    fn recieve_move_maybe(&mut self) -> Option<Move> {
-        //read from channel
-        match self.rx_recv.try_recv() {
-            Ok(message) => match message {
-                Message::GameStart(_) => {
-                    println!("games start recieved.. this shouldn't happen");
-                    return None;
+    // the next game of the tournament, on the same connection
+    pub fn into_game(self, start: GameStart) -> MPState {
+        MPState::start(self.connection, start, self.ip, self.is_guest, self.audio)
+    }
+
+    // messages sent while the connection is lost are dropped. The game is
+    //   rebuilt from the server's when it comes back.
+    fn send_message(&mut self, message: Message) {
+        let _ = self.connection.tx_send.send(message);
+    }
+
+    // back in the game: the server's moves replace ours
+    fn resume(&mut self, resumed: Resumed) {
+        println!("Back in the game!");
+        self.connection = resumed.connection;
+        self.team = resumed.team;
+
+        match snapshot_game(&resumed.snapshot) {
+            Ok(game) => self.game = game,
+            Err(e) => {
+                println!("{}. this shouldn't happen", e);
+                return;
+            }
+        }
+        //the chat stays as it was
+        let chat = self.gfx_state.take_chat();
+        self.gfx_state = GfxState::init(&mut self.game, Some(self.team), self.audio.clone());
+        if let Some(chat) = chat {
+            self.gfx_state.set_chat(chat);
+        }
+    }
+
+    fn recieve_message_maybe(&mut self) -> Option<Message> {
+        if let Some(reconnecting) = &self.reconnecting {
+            match reconnecting.try_recv() {
+                Ok(resumed) => {
+                    self.reconnecting = None;
+                    self.resume(resumed);
+                }
+                Err(mpsc::TryRecvError::Empty) => {}
+                Err(_) => {
+                    println!("The game is lost.");
+                    self.reconnecting = None;
+                    self.is_game_over = true;
                 }
-                Message::Move(the_move) => return Some(the_move),
-            },
-            Err(mpsc::TryRecvError::Empty) => return None,
+            }
+            return None;
+        }
+
+        //read from channel
+        match self.connection.rx_recv.try_recv() {
+            Ok(message) => Some(message),
+            Err(mpsc::TryRecvError::Empty) => None,
             Err(_) => {
-                panic!();
+                //the server closes the connection when the game ends
+                if !self.is_game_over {
+                    println!("Lost the connection to the server. Reconnecting...");
+                    let reconnecting = spawn_reconnection_thread(self.ip.clone(), self.session);
+                    self.reconnecting = Some(reconnecting);
+                }
+                None
             }
         }
     }
 
-    //true to go back to menu
-    pub fn mp_loop(&mut self) -> bool {
-        let mut res = false;
+    pub fn mp_loop(&mut self) -> MPChange {
+        let mut res = MPChange::None;
+
+        let message = match self.recieve_message_maybe() {
+            //back in the lobby: it gets everything, unless the next game starts
+            Some(Message::GameStart(start)) if self.lobby.is_some() => {
+                return MPChange::Game(start)
+            }
+            Some(message) if self.lobby.is_some() => {
+                self.lobby_messages.push(message);
+                None
+            }
+            message => message,
+        };
 
-        let the_move = self.recieve_move_maybe();
-        if let Some(some_move) = the_move {
-            if let Ok(res) = self.game.perform_move(some_move) {
+        match message {
+            Some(Message::GameStart(..)) | Some(Message::Resumed(..)) => {
+                println!("games start recieved.. this shouldn't happen");
+            }
+            Some(Message::OpponentLeft(grace)) => {
+                println!(
+                    "The opponent lost the connection. Waiting {} seconds for them...",
+                    grace
+                );
+            }
+            Some(Message::OpponentBack) => {
+                println!("The opponent is back.");
+            }
+            Some(Message::Move(some_move)) => {
+                unflag_clock(&mut self.game);
+                if let Ok(res) = self.game.perform_move(some_move) {
+                    self.gfx_state
+                        .move_was_made_from_other_client(&mut self.game, res);
+                }
+            }
+            Some(Message::TakebackRequest) => {
+                self.gfx_state.takeback_was_requested();
+            }
+            Some(Message::TakebackAnswer(_)) => {
+                println!("The opponent declined the takeback.");
+            }
+            Some(Message::Takeback(move_count)) => {
+                for _ in 0..move_count {
+                    self.game.undo_move();
+                }
+                self.gfx_state.moves_were_taken_back(&mut self.game);
+            }
+            Some(Message::MoveRejected(e)) => {
+                //the move was only made here
+                println!("The server rejected the move: {}", e);
+                self.game.undo_move();
+                self.gfx_state.moves_were_taken_back(&mut self.game);
+            }
+            Some(Message::GameOver(result)) => {
+                println!("The server ended the game. {}", result);
+                self.is_game_over = true;
                 self.gfx_state
-                    .move_was_made_from_other_client(&mut self.game, res);
+                    .game_was_ended(&mut self.game, result.to_string());
+            }
+            Some(Message::OfferDraw) => {
+                self.gfx_state.draw_was_offered();
+            }
+            Some(Message::DeclineDraw) => {
+                println!("The opponent declined the draw.");
+            }
+            Some(Message::RatingChanged(category, rating, change)) => {
+                println!("Your {} rating is now {} ({:+}).", category, rating, change);
             }
+            Some(Message::Error(e)) => {
+                println!("Error from the server: {}", e);
+            }
+            Some(Message::ChatLine(line)) => self.gfx_state.chat_line_received(line),
+            Some(Message::ClockUpdate(times)) => sync_clock(&mut self.game, times),
+            Some(Message::Berserked(team)) => {
+                self.gfx_state.player_berserked(&mut self.game, team);
+            }
+            //the game of the tournament is over
+            Some(Message::Lobby(seeks)) => self.lobby = Some(seeks),
+            //only for spectators, or the lobby
+            Some(Message::LoggedIn(..))
+            | Some(Message::Ratings(..))
+            | Some(Message::RatingHistory(..))
+            | Some(Message::Leaderboard(..))
+            | Some(Message::FoundGames(_))
+            | Some(Message::Pgn(..))
+            | Some(Message::GameList(_))
+            | Some(Message::Spectating(_))
+            | Some(Message::Tournaments(_))
+            | Some(Message::TournamentUpdated(_))
+            | Some(Message::Standings(..))
+            | Some(Message::TournamentOver(..))
+            | Some(Message::SeekAdded(_))
+            | Some(Message::SeekRemoved(_))
+            | Some(Message::Challenged(_))
+            | Some(Message::ChallengeDeclined(_)) => {}
+            //only the client sends these
+            Some(Message::Hello(_))
+            | Some(Message::Welcome(_))
+            | Some(Message::Register(..))
+            | Some(Message::Login(..))
+            | Some(Message::LoginWithToken(_))
+            | Some(Message::PlayAsGuest)
+            | Some(Message::JoinLobby)
+            | Some(Message::Resign)
+            | Some(Message::AcceptDraw)
+            | Some(Message::Abort)
+            | Some(Message::Berserk)
+            | Some(Message::ListTournaments)
+            | Some(Message::CreateTournament(_))
+            | Some(Message::JoinTournament(_))
+            | Some(Message::LeaveTournament(_))
+            | Some(Message::StartTournament(_))
+            | Some(Message::Chat(_))
+            | Some(Message::PrivateChat(..))
+            | Some(Message::GetRatings(_))
+            | Some(Message::GetRatingHistory(..))
+            | Some(Message::GetLeaderboard(_))
+            | Some(Message::FindGames(_))
+            | Some(Message::GetPgn(_))
+            | Some(Message::Seek(_))
+            | Some(Message::CancelSeek(_))
+            | Some(Message::AcceptSeek(_))
+            | Some(Message::Challenge(..))
+            | Some(Message::AcceptChallenge(_))
+            | Some(Message::DeclineChallenge(_))
+            | Some(Message::Resume(_))
+            | Some(Message::ListGames)
+            | Some(Message::Spectate(_)) => {}
+            None => {}
         }
 
         self.gfx_state.draw(&mut self.game);
@@ -181,13 +529,35 @@ impl MPState {
         if let Some(input) = player_input {
             match input {
                 PlayerInput::GoBack => {
-                    res = true;
+                    res = match self.lobby {
+                        Some(_) => MPChange::Lobby,
+                        None => MPChange::GoBack,
+                    };
                 }
                 PlayerInput::Move(chess_move, move_res) => {
                     if let Ok(_res) = move_res {
-                        self.send_move(chess_move);
+                        self.send_message(Message::Move(chess_move));
+                    }
+                }
+                PlayerInput::Takeback => {
+                    if self.game.takeback_len(self.team) > 0 {
+                        println!("Asking the opponent for a takeback...");
+                        self.send_message(Message::TakebackRequest);
                     }
                 }
+                PlayerInput::TakebackAnswer(accepted) => {
+                    self.send_message(Message::TakebackAnswer(accepted));
+                }
+                PlayerInput::Resign => self.send_message(Message::Resign),
+                PlayerInput::OfferDraw => {
+                    println!("Offering a draw...");
+                    self.send_message(Message::OfferDraw);
+                }
+                PlayerInput::DrawAnswer(true) => self.send_message(Message::AcceptDraw),
+                PlayerInput::DrawAnswer(false) => self.send_message(Message::DeclineDraw),
+                PlayerInput::Abort => self.send_message(Message::Abort),
+                PlayerInput::Berserk => self.send_message(Message::Berserk),
+                PlayerInput::Chat(message) => self.send_message(message),
             }
         }
 
@@ -0,0 +1,94 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chess_rs_core::engine::{Engine, SearchLimits};
+use chess_rs_core::review::{self, GameReview, MoveClass};
+use chess_rs_core::tablebase::Tablebase;
+use chess_rs_core::GameState;
+
+enum ReviewUpdate {
+    Progress(usize, usize),
+    Done(GameReview),
+}
+
+// Reviews a finished game on another thread
+pub struct ReviewJob {
+    receiver: Receiver<ReviewUpdate>,
+    cancel: Arc<AtomicBool>,
+    // positions searched, out of how many
+    pub progress: (usize, usize),
+    pub review: Option<GameReview>,
+}
+
+impl ReviewJob {
+    pub fn start(game: &GameState, tablebase: Option<Arc<dyn Tablebase>>) -> ReviewJob {
+        let (sender, receiver) = mpsc::channel();
+        let cancel = Arc::new(AtomicBool::new(false));
+
+        let progress = (0, game.move_count() + 1);
+        let game = game.clone();
+        let thread_cancel = cancel.clone();
+        std::thread::spawn(move || {
+            let mut engine = Engine::new();
+            engine.set_tablebase(tablebase);
+            let limits = SearchLimits {
+                depth: Some(6),
+                time: Some(Duration::from_millis(300)),
+                ..SearchLimits::default()
+            };
+
+            let review = review::review_game(&game, &engine, &limits, |done, total| {
+                let _ = sender.send(ReviewUpdate::Progress(done, total));
+                !thread_cancel.load(Ordering::Relaxed)
+            });
+
+            if let Some(review) = review {
+                let _ = sender.send(ReviewUpdate::Done(review));
+            }
+        });
+
+        ReviewJob {
+            receiver,
+            cancel,
+            progress,
+            review: None,
+        }
+    }
+
+    pub fn poll(&mut self) {
+        while let Ok(update) = self.receiver.try_recv() {
+            match update {
+                ReviewUpdate::Progress(done, total) => self.progress = (done, total),
+                ReviewUpdate::Done(review) => self.review = Some(review),
+            }
+        }
+    }
+}
+
+impl Drop for ReviewJob {
+    fn drop(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+}
+
+// "?!", "?" or "??"
+pub fn class_symbol(class: MoveClass) -> &'static str {
+    match class {
+        MoveClass::Best | MoveClass::Good => "",
+        MoveClass::Inaccuracy => "?!",
+        MoveClass::Mistake => "?",
+        MoveClass::Blunder => "??",
+    }
+}
+
+pub fn class_color(class: MoveClass) -> Option<egui::Color32> {
+    match class {
+        MoveClass::Best => Some(egui::Color32::from_rgb(60, 160, 60)),
+        MoveClass::Good => None,
+        MoveClass::Inaccuracy => Some(egui::Color32::from_rgb(200, 170, 0)),
+        MoveClass::Mistake => Some(egui::Color32::from_rgb(230, 120, 20)),
+        MoveClass::Blunder => Some(egui::Color32::from_rgb(210, 30, 30)),
+    }
+}
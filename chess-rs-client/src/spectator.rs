@@ -0,0 +1,108 @@
+// Watching a game on the server. The board shows the server's game and no
+//  piece can be moved.
+
+use std::rc::Rc;
+use std::sync::mpsc;
+
+use chess_rs_core::{ChessTeam, GameState};
+use chess_rs_protocol::{self as protocol, Message};
+
+use crate::chat::ChatPanel;
+use crate::graphics::{GfxState, PlayerInput};
+use crate::multiplayer::{self, sync_clock, unflag_clock, Connection};
+use crate::Audio;
+
+pub struct SpectatorState {
+    game: GameState,
+    gfx_state: GfxState,
+    connection: Connection,
+    is_game_over: bool,
+}
+
+impl SpectatorState {
+    // connects to the server and catches up with the game
+    pub fn init(ip: &str, game_id: u64, audio: Rc<Audio>) -> Result<SpectatorState, String> {
+        let mut stream = multiplayer::connect(ip)?;
+        protocol::write_message(&mut stream, &Message::Spectate(game_id))
+            .map_err(|e| e.to_string())?;
+
+        let snapshot = match protocol::read_message(&mut stream) {
+            Ok(Message::Spectating(snapshot)) => snapshot,
+            Ok(Message::Error(e)) => return Err(e.to_string()),
+            Ok(message) => return Err(format!("expected the game, got {:?}", message)),
+            Err(e) => return Err(e.to_string()),
+        };
+        println!("{} - {}", snapshot.players[0], snapshot.players[1]);
+        let mut game = multiplayer::snapshot_game(&snapshot)?;
+
+        //seen from White's side
+        let mut gfx_state = GfxState::init(&mut game, Some(ChessTeam::White), audio);
+        gfx_state.set_spectating();
+        gfx_state.set_chat(ChatPanel::new(String::new(), false));
+
+        Ok(SpectatorState {
+            game,
+            gfx_state,
+            connection: multiplayer::spawn_connection_threads(stream),
+            is_game_over: false,
+        })
+    }
+
+    //true to go back to menu
+    pub fn spectate_loop(&mut self) -> bool {
+        let message = match self.connection.rx_recv.try_recv() {
+            Ok(message) => Some(message),
+            Err(mpsc::TryRecvError::Empty) => None,
+            Err(_) => {
+                //the server closes the connection when the game ends
+                if !self.is_game_over {
+                    println!("Lost the connection to the server.");
+                    self.is_game_over = true;
+                }
+                None
+            }
+        };
+
+        match message {
+            Some(Message::Move(the_move)) => {
+                unflag_clock(&mut self.game);
+                if let Ok(res) = self.game.perform_move(the_move) {
+                    self.gfx_state
+                        .move_was_made_from_other_client(&mut self.game, res);
+                }
+            }
+            Some(Message::Takeback(move_count)) => {
+                for _ in 0..move_count {
+                    self.game.undo_move();
+                }
+                self.gfx_state.moves_were_taken_back(&mut self.game);
+            }
+            Some(Message::ClockUpdate(times)) => sync_clock(&mut self.game, times),
+            Some(Message::Berserked(team)) => {
+                self.gfx_state.player_berserked(&mut self.game, team);
+            }
+            Some(Message::GameOver(result)) => {
+                println!("The game is over. {}", result);
+                self.is_game_over = true;
+                if let Some(clock) = self.game.get_clock_mut() {
+                    clock.stop();
+                }
+            }
+            Some(Message::ChatLine(line)) => self.gfx_state.chat_line_received(line),
+            Some(Message::Error(e)) => {
+                println!("Error from the server: {}", e);
+            }
+            //for the players
+            Some(_) => {}
+            None => {}
+        }
+
+        self.gfx_state.draw(&mut self.game);
+
+        //nothing to do but leave
+        matches!(
+            self.gfx_state.consume_player_input_buffer(),
+            Some(PlayerInput::GoBack)
+        )
+    }
+}
@@ -0,0 +1,282 @@
+// The tournaments window of the lobby: the tournaments on the server, the
+//  form to make one, and the standings of the ones we play in. The server
+//  pairs the players itself, so a game of a tournament just starts like any
+//  other game. The standings come whenever they change.
+
+use chess_rs_protocol::{
+    GameSettings, Message, Standing, TournamentInfo, TournamentKind, TournamentSettings,
+    TournamentStatus, Variant,
+};
+use egui::CtxRef;
+
+use crate::graphics;
+use crate::Audio;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Kind {
+    Arena,
+    Swiss,
+    RoundRobin,
+}
+
+pub struct TournamentPanel {
+    //ours
+    name: String,
+    tournaments: Vec<TournamentInfo>,
+    //of the tournaments we are in, by their id
+    standings: Vec<(u64, Vec<Standing>)>,
+    //the name of the last one that ended, and how
+    result: Option<(String, Vec<Standing>)>,
+    error: Option<String>,
+    //the form. The clock is "3+2", or empty for no clock
+    tournament_name: String,
+    kind: Kind,
+    minutes: String,
+    rounds: String,
+    time_control: String,
+}
+
+fn status_text(status: TournamentStatus) -> &'static str {
+    match status {
+        TournamentStatus::Open => "open",
+        TournamentStatus::Running => "running",
+        TournamentStatus::Finished => "over",
+    }
+}
+
+fn standing_text(place: usize, standing: &Standing, kind: &TournamentKind) -> String {
+    let mut text = match kind {
+        TournamentKind::Arena { .. } => format!(
+            "{}. {} {} ({} games)",
+            place + 1,
+            standing.name,
+            standing.score,
+            standing.games
+        ),
+        _ => format!(
+            "{}. {} {} (Buchholz {}, SB {})",
+            place + 1,
+            standing.name,
+            standing.score,
+            standing.buchholz,
+            standing.sonneborn_berger
+        ),
+    };
+    if standing.on_fire {
+        text.push_str(", on fire");
+    }
+    if standing.withdrawn {
+        text.push_str(", withdrawn");
+    }
+    text
+}
+
+impl TournamentPanel {
+    pub fn new(name: String) -> TournamentPanel {
+        TournamentPanel {
+            name,
+            tournaments: vec![],
+            standings: vec![],
+            result: None,
+            error: None,
+            tournament_name: String::new(),
+            kind: Kind::Arena,
+            minutes: "30".to_string(),
+            rounds: "5".to_string(),
+            time_control: "3+2".to_string(),
+        }
+    }
+
+    // the tournament messages of the server
+    pub fn receive(&mut self, message: Message, audio: &Audio) {
+        match message {
+            Message::Tournaments(tournaments) => self.tournaments = tournaments,
+            Message::TournamentUpdated(info) => {
+                self.tournaments
+                    .retain(|tournament| tournament.id != info.id);
+                if info.status != TournamentStatus::Finished {
+                    self.tournaments.push(info);
+                    self.tournaments.sort_by_key(|tournament| tournament.id);
+                }
+            }
+            Message::Standings(id, standings) => {
+                self.standings.retain(|(other, _)| *other != id);
+                self.standings.push((id, standings));
+            }
+            Message::TournamentOver(id, standings) => {
+                let place = standings.iter().position(|s| s.name == self.name);
+                audio.play_sound(match place {
+                    Some(0) => "Tournament1st",
+                    Some(1) => "Tournament2nd",
+                    Some(2) => "Tournament3rd",
+                    _ => "TournamentOther",
+                });
+
+                let name = match self.tournaments.iter().find(|t| t.id == id) {
+                    Some(tournament) => tournament.settings.name.clone(),
+                    None => "The tournament".to_string(),
+                };
+                self.standings.retain(|(other, _)| *other != id);
+                self.result = Some((name, standings));
+            }
+            _ => {}
+        }
+    }
+
+    // the tournament of the form. None if something in it isn't valid
+    fn settings(&self) -> Option<TournamentSettings> {
+        let kind = match self.kind {
+            Kind::Arena => TournamentKind::Arena {
+                minutes: self.minutes.trim().parse().ok()?,
+            },
+            Kind::Swiss => TournamentKind::Swiss {
+                rounds: self.rounds.trim().parse().ok()?,
+            },
+            Kind::RoundRobin => TournamentKind::RoundRobin,
+        };
+        let time_control = match self.time_control.trim() {
+            "" => None,
+            text => Some(graphics::parse_time_control(text)?),
+        };
+
+        Some(TournamentSettings {
+            name: self.tournament_name.trim().to_string(),
+            kind,
+            game: GameSettings {
+                variant: Variant::Standard,
+                time_control,
+            },
+        })
+    }
+
+    fn is_playing_in(&self, id: u64) -> bool {
+        self.standings.iter().any(|(other, standings)| {
+            *other == id
+                && standings
+                    .iter()
+                    .any(|standing| standing.name == self.name && !standing.withdrawn)
+        })
+    }
+
+    // the message to send, if a button was clicked
+    pub fn draw(&mut self, egui_ctx: &CtxRef) -> Option<Message> {
+        let mut to_send = None;
+
+        egui::Window::new("Tournaments").show(egui_ctx, |ui| {
+            if let Some(error) = &self.error {
+                ui.label(error.as_str());
+                ui.separator();
+            }
+
+            if self.tournaments.is_empty() {
+                ui.label("There are no tournaments right now.");
+            }
+            let mut left = None;
+            for tournament in self.tournaments.iter() {
+                let clock = match &tournament.settings.game.time_control {
+                    Some(control) => graphics::time_control_text(control),
+                    None => "no clock".to_string(),
+                };
+                let is_in = self.is_playing_in(tournament.id);
+                let can_join = match tournament.status {
+                    TournamentStatus::Open => true,
+                    TournamentStatus::Running => {
+                        matches!(tournament.settings.kind, TournamentKind::Arena { .. })
+                    }
+                    TournamentStatus::Finished => false,
+                };
+
+                ui.horizontal(|ui| {
+                    ui.label(format!(
+                        "{} ({}, {}) by {}: {} players, {}",
+                        tournament.settings.name,
+                        tournament.settings.kind,
+                        clock,
+                        tournament.creator,
+                        tournament.players,
+                        status_text(tournament.status)
+                    ));
+                    if is_in && ui.button("Leave").clicked() {
+                        to_send = Some(Message::LeaveTournament(tournament.id));
+                        //the server doesn't tell the ones that left before the start
+                        if tournament.status == TournamentStatus::Open {
+                            left = Some(tournament.id);
+                        }
+                    }
+                    if !is_in && can_join && ui.button("Join").clicked() {
+                        to_send = Some(Message::JoinTournament(tournament.id));
+                    }
+                    let can_start = tournament.creator == self.name
+                        && tournament.status == TournamentStatus::Open;
+                    if can_start && ui.button("Start").clicked() {
+                        to_send = Some(Message::StartTournament(tournament.id));
+                    }
+                });
+
+                if let Some((_, standings)) =
+                    self.standings.iter().find(|(id, _)| *id == tournament.id)
+                {
+                    ui.collapsing(format!("Standings of {}", tournament.settings.name), |ui| {
+                        for (place, standing) in standings.iter().enumerate() {
+                            ui.label(standing_text(place, standing, &tournament.settings.kind));
+                        }
+                    });
+                }
+            }
+            if let Some(id) = left {
+                self.standings.retain(|(other, _)| *other != id);
+            }
+
+            if let Some((name, standings)) = &self.result {
+                ui.separator();
+                ui.label(format!("{} is over:", name));
+                for (place, standing) in standings.iter().enumerate().take(3) {
+                    ui.label(format!(
+                        "{}. {} {}",
+                        place + 1,
+                        standing.name,
+                        standing.score
+                    ));
+                }
+                if let Some(place) = standings.iter().position(|s| s.name == self.name) {
+                    ui.label(format!("You are number {}.", place + 1));
+                }
+            }
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.label("Name:");
+                ui.add(egui::TextEdit::singleline(&mut self.tournament_name).desired_width(120.));
+                ui.label("Clock:");
+                ui.add(egui::TextEdit::singleline(&mut self.time_control).desired_width(50.));
+            });
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut self.kind, Kind::Arena, "Arena");
+                ui.radio_value(&mut self.kind, Kind::Swiss, "Swiss");
+                ui.radio_value(&mut self.kind, Kind::RoundRobin, "Round robin");
+                match self.kind {
+                    Kind::Arena => {
+                        ui.add(egui::TextEdit::singleline(&mut self.minutes).desired_width(40.));
+                        ui.label("minutes");
+                    }
+                    Kind::Swiss => {
+                        ui.add(egui::TextEdit::singleline(&mut self.rounds).desired_width(40.));
+                        ui.label("rounds");
+                    }
+                    Kind::RoundRobin => {}
+                }
+            });
+            if ui.button("Make a tournament").clicked() {
+                match self.settings() {
+                    Some(settings) => {
+                        self.error = None;
+                        to_send = Some(Message::CreateTournament(settings));
+                    }
+                    None => self.error = Some("The clock or the length isn't valid.".to_string()),
+                }
+            }
+        });
+
+        to_send
+    }
+}
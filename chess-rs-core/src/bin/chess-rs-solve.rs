@@ -0,0 +1,52 @@
+// Solves chess problems
+//
+// usage: chess-rs-solve "<FEN>" <stipulation>
+//   e.g. chess-rs-solve "k7/8/2K5/8/8/8/8/1R6 w - - 0 1" #2
+//
+// stipulations: #n (direct mate), s#n (selfmate), h#n (helpmate)
+
+use chess_rs_core::problem::{self, Stipulation};
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.len() != 2 {
+        eprintln!("usage: chess-rs-solve \"<FEN>\" <stipulation>");
+        eprintln!("  e.g. chess-rs-solve \"k7/8/2K5/8/8/8/8/1R6 w - - 0 1\" #2");
+        eprintln!("stipulations: #n (direct mate), s#n (selfmate), h#n (helpmate)");
+        std::process::exit(1);
+    }
+
+    let stipulation = match Stipulation::parse(&args[1]) {
+        Some(stipulation) => stipulation,
+        None => {
+            eprintln!("Invalid stipulation: {}", args[1]);
+            std::process::exit(1);
+        }
+    };
+
+    let solution = match problem::solve_fen(&args[0], stipulation) {
+        Some(solution) => solution,
+        None => {
+            eprintln!("Invalid position: {}", args[0]);
+            std::process::exit(1);
+        }
+    };
+
+    println!("{}", solution);
+    println!();
+
+    if solution.is_cooked() {
+        match stipulation {
+            Stipulation::Helpmate(_) => println!("Cooked: {} solutions.", solution.lines().len()),
+            _ => println!("Cooked: {} keys.", solution.keys.len()),
+        }
+    }
+
+    for dual in solution.duals() {
+        println!("Dual after {}", dual.join(" "));
+    }
+
+    if solution.is_sound() {
+        println!("Sound.");
+    }
+}
@@ -0,0 +1,37 @@
+// Generates distance to mate tables
+//
+// usage: chess-rs-tbgen <output directory> <material>...
+//   e.g. chess-rs-tbgen tables KQvK KRvK KBNvK KPvK
+
+use chess_rs_core::retrograde::DtmTables;
+use std::time::Instant;
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.len() < 2 {
+        eprintln!("usage: chess-rs-tbgen <output directory> <material>...");
+        eprintln!("  e.g. chess-rs-tbgen tables KQvK KRvK KBNvK KPvK");
+        std::process::exit(1);
+    }
+
+    let mut tables = DtmTables::new();
+
+    for key in &args[1..] {
+        let start = Instant::now();
+        if let Err(e) = tables.generate(key) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        println!("{} done in {:.1}s", key, start.elapsed().as_secs_f32());
+    }
+
+    for key in tables.keys() {
+        let table = tables.get(&key).unwrap();
+        println!("{}: longest mate {} halfmoves", key, table.longest_mate());
+    }
+
+    if let Err(e) = tables.save(&args[0]) {
+        eprintln!("Could not save the tables: {}", e);
+        std::process::exit(1);
+    }
+}
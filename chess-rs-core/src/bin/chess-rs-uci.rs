@@ -0,0 +1,246 @@
+// Runs the engine as a UCI engine, for use with chess GUIs
+//
+// usage: chess-rs-uci
+//
+// options: MultiPV (number of lines to report),
+//   SyzygyPath (directories with Syzygy tables, separated like PATH),
+//   DtmPath (directory with tables made by chess-rs-tbgen)
+
+use chess_rs_core::engine::{self, Engine, SearchInfo, SearchLimits};
+use chess_rs_core::retrograde::DtmTables;
+use chess_rs_core::tablebase::{SyzygyTablebase, TablebaseError};
+use chess_rs_core::{parse_fen, Board, ChessTeam, GameState, Tile};
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+fn send(line: &str) {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    let _ = writeln!(stdout, "{}", line);
+    let _ = stdout.flush();
+}
+
+fn print_info(info: &SearchInfo, board: &Board) {
+    for (i, line) in info.lines.iter().enumerate() {
+        let score = match line.score {
+            engine::Score::Cp(cp) => format!("cp {}", cp),
+            engine::Score::Mate(n) => format!("mate {}", n),
+            engine::Score::Tablebase(wdl) => {
+                format!("cp {}", wdl.value().signum() * engine::TABLEBASE_CP)
+            }
+        };
+
+        let mut pv_board = board.clone();
+        let mut pv = vec![];
+        for the_move in &line.moves {
+            pv.push(engine::move_to_uci(&pv_board, *the_move));
+            pv_board.apply_move(*the_move);
+        }
+
+        send(&format!(
+            "info depth {} seldepth {} multipv {} score {} nodes {} nps {} time {} pv {}",
+            info.depth,
+            info.seldepth,
+            i + 1,
+            score,
+            info.nodes,
+            info.nps,
+            info.time.as_millis(),
+            pv.join(" ")
+        ));
+    }
+}
+
+// All the tables in the directories of paths, separated like PATH
+fn open_syzygy(paths: &str) -> Result<SyzygyTablebase, TablebaseError> {
+    let mut dirs = std::env::split_paths(paths);
+    let mut tb = SyzygyTablebase::open(dirs.next().unwrap_or_default())?;
+    for dir in dirs {
+        tb.add_directory(dir)?;
+    }
+    Ok(tb)
+}
+
+// "position startpos moves e2e4 e7e5" or "position fen <fen> moves ..."
+fn parse_position(args: &[&str]) -> Option<GameState> {
+    let moves_at = args
+        .iter()
+        .position(|a| *a == "moves")
+        .unwrap_or(args.len());
+    let mut game = match args.first() {
+        Some(&"startpos") => GameState::init(),
+        Some(&"fen") => parse_fen(args[1..moves_at].join(" "))?,
+        _ => return None,
+    };
+
+    for uci in args.iter().skip(moves_at + 1) {
+        let ep_square = game.en_passant_square;
+        let the_move = engine::move_from_uci(game.get_board(), ep_square, uci)?;
+        game.perform_move(the_move).ok()?;
+    }
+
+    Some(game)
+}
+
+fn parse_go(
+    args: &[&str],
+    board: &Board,
+    ep_square: Option<Tile>,
+    multi_pv: usize,
+) -> SearchLimits {
+    let mut limits = SearchLimits {
+        multi_pv,
+        ..SearchLimits::default()
+    };
+    let white_to_move = board.whose_turn == ChessTeam::White;
+
+    let mut time_left = None;
+    let mut increment = 0;
+    let mut moves_to_go = 30;
+
+    let value = |i: usize| args.get(i + 1).and_then(|v| v.parse::<u64>().ok());
+    for (i, arg) in args.iter().enumerate() {
+        match *arg {
+            "depth" => limits.depth = value(i).map(|d| d as u32),
+            "nodes" => limits.nodes = value(i),
+            "movetime" => limits.time = value(i).map(Duration::from_millis),
+            "wtime" if white_to_move => time_left = value(i),
+            "btime" if !white_to_move => time_left = value(i),
+            "winc" if white_to_move => increment = value(i).unwrap_or(0),
+            "binc" if !white_to_move => increment = value(i).unwrap_or(0),
+            "movestogo" => moves_to_go = value(i).unwrap_or(30).max(1),
+            // the moves go until the next word that isn't a move
+            "searchmoves" => {
+                let moves = args[i + 1..]
+                    .iter()
+                    .map_while(|uci| engine::move_from_uci(board, ep_square, uci))
+                    .collect();
+                limits.search_moves = Some(moves);
+            }
+            _ => {}
+        }
+    }
+
+    // spend an even share of the clock, plus most of the increment
+    if limits.time.is_none() && !args.contains(&"infinite") {
+        if let Some(time_left) = time_left {
+            let budget = time_left / moves_to_go + increment * 3 / 4;
+            let budget = budget.min(time_left.saturating_sub(50)).max(10);
+            limits.time = Some(Duration::from_millis(budget));
+        }
+    }
+
+    limits
+}
+
+// The search clears the flag when it starts, so keep setting it until the
+//   thread is done
+fn stop_search(engine: &Engine, searching: Option<JoinHandle<()>>) {
+    if let Some(handle) = searching {
+        while !handle.is_finished() {
+            engine.stop_flag().store(true, Ordering::Relaxed);
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        let _ = handle.join();
+    }
+}
+
+fn main() {
+    let mut engine = Engine::new();
+    let mut game = GameState::init();
+    let mut multi_pv = 1;
+    let mut searching: Option<JoinHandle<()>> = None;
+
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (command, args) = match words.split_first() {
+            Some((command, args)) => (*command, args),
+            None => continue,
+        };
+
+        match command {
+            "uci" => {
+                send("id name chess-rs");
+                send("id author chess-rs");
+                send("option name MultiPV type spin default 1 min 1 max 64");
+                send("option name SyzygyPath type string default <empty>");
+                send("option name DtmPath type string default <empty>");
+                send("uciok");
+            }
+            "isready" => send("readyok"),
+            "ucinewgame" => game = GameState::init(),
+            "setoption" => {
+                // setoption name <name> value <value>
+                let value_at = args.iter().position(|a| *a == "value");
+                let name_end = value_at.unwrap_or(args.len()).max(1);
+                let name = args.get(1..name_end).unwrap_or(&[]).join(" ");
+                let value = value_at
+                    .map(|i| args[i + 1..].join(" "))
+                    .unwrap_or_default();
+
+                match name.to_lowercase().as_str() {
+                    "multipv" => multi_pv = value.parse().unwrap_or(1).clamp(1, 64),
+                    "syzygypath" if value.is_empty() || value == "<empty>" => {
+                        engine.set_tablebase(None)
+                    }
+                    "syzygypath" => match open_syzygy(&value) {
+                        Ok(tb) => engine.set_tablebase(Some(Arc::new(tb))),
+                        Err(e) => send(&format!("info string can't open {}: {}", value, e)),
+                    },
+                    "dtmpath" => match DtmTables::open(&value) {
+                        Ok(tables) => engine.set_tablebase(Some(Arc::new(tables))),
+                        Err(e) => send(&format!("info string can't open {}: {}", value, e)),
+                    },
+                    _ => send(&format!("info string unknown option {}", name)),
+                }
+            }
+            "position" => match parse_position(args) {
+                Some(new_game) => game = new_game,
+                None => send("info string invalid position"),
+            },
+            "go" => {
+                stop_search(&engine, searching.take());
+
+                let board = game.get_board().clone();
+                let ep_square = game.en_passant_square;
+                let limits = parse_go(args, &board, ep_square, multi_pv);
+                let infinite = args.contains(&"infinite");
+                let engine = engine.clone();
+
+                searching = Some(std::thread::spawn(move || {
+                    let info =
+                        engine.search(&board, ep_square, &limits, |info| print_info(info, &board));
+                    // in infinite mode bestmove may only be sent after "stop"
+                    if infinite {
+                        let stop = engine.stop_flag();
+                        while !stop.load(Ordering::Relaxed) {
+                            std::thread::sleep(Duration::from_millis(1));
+                        }
+                    }
+                    match info.best_move() {
+                        Some(the_move) => send(&format!(
+                            "bestmove {}",
+                            engine::move_to_uci(&board, the_move)
+                        )),
+                        None => send("bestmove 0000"),
+                    }
+                }));
+            }
+            "stop" => {
+                stop_search(&engine, searching.take());
+            }
+            "quit" => break,
+            _ => send(&format!("info string unknown command {}", command)),
+        }
+    }
+
+    stop_search(&engine, searching.take());
+}
@@ -0,0 +1,378 @@
+// Chess clocks
+//
+// A Clock counts down the time of both teams following a TimeControl. Only the
+//  clock of the team to move runs. Pressing it after a move stops it, gives
+//  the time bonus and starts the other team's clock.
+//
+// The clock asks a TimeSource what time it is, so tests can move time by hand.
+
+use super::*;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub trait TimeSource: Send + Sync {
+    // time since some fixed point. It never goes back
+    fn now(&self) -> Duration;
+}
+
+// the time of the computer
+pub struct SystemTime {
+    start: Instant,
+}
+
+impl SystemTime {
+    pub fn new() -> SystemTime {
+        SystemTime {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemTime {
+    fn default() -> Self {
+        SystemTime::new()
+    }
+}
+
+impl TimeSource for SystemTime {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+// time that only moves when told to
+#[derive(Default)]
+pub struct ManualTime {
+    now: Mutex<Duration>,
+}
+
+impl ManualTime {
+    pub fn new() -> ManualTime {
+        ManualTime::default()
+    }
+
+    pub fn advance(&self, time: Duration) {
+        *self.now.lock().unwrap() += time;
+    }
+}
+
+impl TimeSource for ManualTime {
+    fn now(&self) -> Duration {
+        *self.now.lock().unwrap()
+    }
+}
+
+// the longest time a period, a bonus or an hourglass can have
+pub const MAX_TIME: Duration = Duration::from_secs(24 * 60 * 60);
+
+// What a team gets for every move
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub enum TimeBonus {
+    None,
+    // added after the move
+    Fischer(Duration),
+    // after the move, gives back the time used, up to this much
+    Bronstein(Duration),
+    // the clock waits this much before it starts counting down (simple delay)
+    Delay(Duration),
+}
+
+impl TimeBonus {
+    // the most the bonus is worth in a move
+    fn time(&self) -> Duration {
+        match self {
+            TimeBonus::None => Duration::from_secs(0),
+            TimeBonus::Fischer(time) | TimeBonus::Bronstein(time) | TimeBonus::Delay(time) => *time,
+        }
+    }
+}
+
+// Part of the game with its own time. "40 moves in 90 minutes"
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub struct Period {
+    // None for the rest of the game. If the last period has a number of
+    //   moves, it starts again after them.
+    pub moves: Option<u32>,
+    pub time: Duration,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum TimeControl {
+    // The time of each period is added when the previous one ends
+    Periods {
+        periods: Vec<Period>,
+        bonus: TimeBonus,
+    },
+    // the time one team uses is given to the other
+    Hourglass(Duration),
+}
+
+impl TimeControl {
+    pub fn sudden_death(time: Duration) -> TimeControl {
+        TimeControl::with_bonus(time, TimeBonus::None)
+    }
+
+    // 5+3 is fischer(5 minutes, 3 seconds)
+    pub fn fischer(time: Duration, increment: Duration) -> TimeControl {
+        TimeControl::with_bonus(time, TimeBonus::Fischer(increment))
+    }
+
+    pub fn bronstein(time: Duration, delay: Duration) -> TimeControl {
+        TimeControl::with_bonus(time, TimeBonus::Bronstein(delay))
+    }
+
+    pub fn delay(time: Duration, delay: Duration) -> TimeControl {
+        TimeControl::with_bonus(time, TimeBonus::Delay(delay))
+    }
+
+    fn with_bonus(time: Duration, bonus: TimeBonus) -> TimeControl {
+        TimeControl::Periods {
+            periods: vec![Period { moves: None, time }],
+            bonus,
+        }
+    }
+
+    // If a clock can run with it: there is a period, the game doesn't start
+    //   without time, no period is over after zero moves and no time is
+    //   longer than MAX_TIME
+    pub fn is_valid(&self) -> bool {
+        match self {
+            TimeControl::Periods { periods, bonus } => {
+                self.starting_time() > Duration::from_secs(0)
+                    && periods.iter().all(|p| p.time <= MAX_TIME && p.moves != Some(0))
+                    && bonus.time() <= MAX_TIME
+            }
+            TimeControl::Hourglass(time) => *time > Duration::from_secs(0) && *time <= MAX_TIME,
+        }
+    }
+
+    // the time of each team when the game starts. Zero without periods
+    pub fn starting_time(&self) -> Duration {
+        match self {
+            TimeControl::Periods { periods, .. } => {
+                periods.first().map_or(Duration::from_secs(0), |p| p.time)
+            }
+            TimeControl::Hourglass(time) => *time,
+        }
+    }
+
+    // How long the time of a team lasts in a game of 40 moves. It's what
+    //   makes a game bullet, blitz or rapid. None if there are no periods or
+    //   the time doesn't fit in a Duration
+    pub fn estimated_duration(&self) -> Option<Duration> {
+        match self {
+            TimeControl::Periods { periods, bonus } => {
+                let bonus = bonus.time();
+
+                // the periods that start in the first 40 moves
+                let last = periods.len().checked_sub(1)?;
+                let mut time = Duration::from_secs(0);
+                let mut moves: u32 = 0;
+                let mut i = 0;
+                while moves < 40 {
+                    //the last period starts again
+                    let period = periods[i.min(last)];
+                    time = time.checked_add(period.time)?;
+                    match period.moves {
+                        Some(period_moves) if period_moves > 0 => {
+                            moves = moves.saturating_add(period_moves)
+                        }
+                        _ => break,
+                    }
+                    i += 1;
+                }
+                time.checked_add(bonus.checked_mul(40)?)
+            }
+            TimeControl::Hourglass(time) => Some(*time),
+        }
+    }
+}
+
+// How long a move took and the time left after it
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MoveTime {
+    pub spent: Duration,
+    pub remaining: Duration,
+}
+
+fn team_i(team: ChessTeam) -> usize {
+    match team {
+        ChessTeam::White => 0,
+        ChessTeam::Black => 1,
+    }
+}
+
+#[derive(Clone)]
+pub struct Clock {
+    control: TimeControl,
+    source: Arc<dyn TimeSource>,
+    // time left when the team's clock was last stopped. White first
+    remaining: [Duration; 2],
+    // the period each team is in, and the moves made in it
+    period: [usize; 2],
+    period_moves: [u32; 2],
+    // the team whose clock runs, and when it started
+    running: Option<(ChessTeam, Duration)>,
+    flagged: Option<ChessTeam>,
+    // teams that gave up half their time and their bonus
+    berserked: [bool; 2],
+}
+
+impl Clock {
+    pub fn new(control: TimeControl, source: Arc<dyn TimeSource>) -> Clock {
+        let time = control.starting_time();
+        Clock {
+            control,
+            source,
+            remaining: [time; 2],
+            period: [0; 2],
+            period_moves: [0; 2],
+            running: None,
+            flagged: None,
+            berserked: [false; 2],
+        }
+    }
+
+    pub fn get_time_control(&self) -> &TimeControl {
+        &self.control
+    }
+
+    // the team whose clock is running
+    pub fn running(&self) -> Option<ChessTeam> {
+        self.running.map(|(team, _)| team)
+    }
+
+    // starts the team's clock, stopping the other one
+    pub fn start(&mut self, team: ChessTeam) {
+        self.stop();
+        self.running = Some((team, self.source.now()));
+    }
+
+    // stops the running clock without a bonus, like pausing the game
+    pub fn stop(&mut self) {
+        if self.running.is_some() {
+            self.check_flag();
+            for t in [ChessTeam::White, ChessTeam::Black] {
+                self.remaining[team_i(t)] = self.remaining(t);
+            }
+            self.running = None;
+        }
+    }
+
+    fn elapsed(&self) -> Duration {
+        match self.running {
+            Some((_, start)) => self.source.now().saturating_sub(start),
+            None => Duration::from_secs(0),
+        }
+    }
+
+    // the time the team has left right now
+    pub fn remaining(&self, team: ChessTeam) -> Duration {
+        let stored = self.remaining[team_i(team)];
+        let running = match self.running {
+            Some((running, _)) => running,
+            None => return stored,
+        };
+        let elapsed = self.elapsed();
+
+        match &self.control {
+            TimeControl::Hourglass(_) if team != running => stored + elapsed,
+            _ if team != running => stored,
+            TimeControl::Periods {
+                bonus: TimeBonus::Delay(delay),
+                ..
+            } => stored.saturating_sub(elapsed.saturating_sub(*delay)),
+            _ => stored.saturating_sub(elapsed),
+        }
+    }
+
+    // The team gives up half of its time, and gets no bonus for its moves
+    //   from now on. Like berserking in an arena tournament
+    pub fn berserk(&mut self, team: ChessTeam) {
+        let running = self.running();
+        self.stop();
+        let i = team_i(team);
+        self.remaining[i] /= 2;
+        self.berserked[i] = true;
+        if let Some(running) = running {
+            self.start(running);
+        }
+    }
+
+    pub fn is_berserked(&self, team: ChessTeam) -> bool {
+        self.berserked[team_i(team)]
+    }
+
+    // Sets the times to the ones of another clock, like the server's. The
+    //   running clock starts counting from now
+    pub fn sync(&mut self, remaining: [Duration; 2], running: Option<ChessTeam>) {
+        self.remaining = remaining;
+        self.running = running.map(|team| (team, self.source.now()));
+        self.flagged = None;
+    }
+
+    // the team that ran out of time
+    pub fn flagged(&self) -> Option<ChessTeam> {
+        match self.running {
+            Some((team, _)) if self.flagged.is_none() && self.remaining(team).is_zero() => {
+                Some(team)
+            }
+            _ => self.flagged,
+        }
+    }
+
+    fn check_flag(&mut self) {
+        self.flagged = self.flagged();
+    }
+
+    // The team finished its move: stops its clock and starts the other one.
+    //   Returns how long the move took, or the team that ran out of time.
+    //   If the team's clock wasn't running the move took no time and gets no
+    //   bonus, so the first move of the game starts the clocks without an
+    //   increment. Black's first move gets one like any other.
+    pub fn press(&mut self, team: ChessTeam) -> Result<MoveTime, ChessTeam> {
+        self.check_flag();
+        if let Some(team) = self.flagged {
+            return Err(team);
+        }
+
+        let was_running = self.running() == Some(team);
+        let spent = match was_running {
+            true => self.elapsed(),
+            false => Duration::from_secs(0),
+        };
+        self.stop();
+
+        let i = team_i(team);
+        if let TimeControl::Periods { periods, bonus } = &self.control {
+            if was_running && !self.berserked[i] {
+                self.remaining[i] += match bonus {
+                    TimeBonus::None | TimeBonus::Delay(_) => Duration::from_secs(0),
+                    TimeBonus::Fischer(increment) => *increment,
+                    TimeBonus::Bronstein(delay) => std::cmp::min(spent, *delay),
+                };
+            }
+
+            //next period. A control without periods never gets one
+            if let Some(moves) = periods.get(self.period[i]).and_then(|p| p.moves) {
+                self.period_moves[i] += 1;
+                if self.period_moves[i] == moves {
+                    self.period_moves[i] = 0;
+                    self.period[i] = std::cmp::min(self.period[i] + 1, periods.len() - 1);
+                    self.remaining[i] += periods[self.period[i]].time;
+                }
+            }
+        }
+
+        self.start(team.the_other_one());
+
+        Ok(MoveTime {
+            spent,
+            remaining: self.remaining[i],
+        })
+    }
+}
+
+#[cfg(test)]
+#[path = "./tests/clock_tests.rs"]
+mod clock_tests;
@@ -0,0 +1,764 @@
+// Chess engine
+//
+// Alpha-beta search with iterative deepening and a quiescence search for
+//  captures. It can report the best K lines at once (multi-PV): after the best
+//  line of an iteration is found, the root is searched again without its
+//  first move, and so on.
+//
+// Every finished iteration is sent to a callback as a SearchInfo, so the client
+//  and the UCI binary can show the progress as it happens.
+//
+// Positions that repeat inside the search are scored as draws. The search
+//  doesn't know about the moves played before the root or the 50 move rule.
+
+use super::*;
+use crate::tablebase::{Tablebase, Wdl};
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const INFINITY: i32 = 1_000_000;
+const MATE: i32 = 100_000;
+// scores above this are mates
+const MATE_BOUND: i32 = MATE - 1000;
+// a won position according to the tablebase, but no mate found yet
+const TABLEBASE_WIN: i32 = 50_000;
+// scores above this are tablebase wins, no evaluation gets this high
+const TABLEBASE_BOUND: i32 = TABLEBASE_WIN - 1000;
+// what UCI gets for a tablebase win, between the evaluations and the mates
+pub const TABLEBASE_CP: i32 = 20_000;
+
+pub const MAX_DEPTH: u32 = 64;
+
+// A score for the team whose turn it is
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Score {
+    // in centipawns
+    Cp(i32),
+    // moves until mate. Positive if the team to move mates, negative if it gets mated
+    Mate(i32),
+    // won or lost for the team to move according to the tablebase, without
+    //   a mate found yet
+    Tablebase(Wdl),
+}
+
+impl Score {
+    fn from_internal(score: i32) -> Score {
+        if score > MATE_BOUND {
+            Score::Mate((MATE - score + 1) / 2)
+        } else if score < -MATE_BOUND {
+            Score::Mate(-(MATE + score) / 2)
+        } else if score > TABLEBASE_BOUND {
+            Score::Tablebase(Wdl::Win)
+        } else if score < -TABLEBASE_BOUND {
+            Score::Tablebase(Wdl::Loss)
+        } else {
+            Score::Cp(score)
+        }
+    }
+
+    // the same score for the other team
+    pub fn flip(self) -> Score {
+        match self {
+            Score::Cp(cp) => Score::Cp(-cp),
+            Score::Mate(n) => Score::Mate(-n),
+            Score::Tablebase(wdl) => Score::Tablebase(wdl.flip()),
+        }
+    }
+}
+
+// "+0.35", "-1.20", "#3", "#-2", "+TB"
+impl fmt::Display for Score {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Score::Cp(cp) => write!(f, "{:+.2}", *cp as f32 / 100.0),
+            Score::Mate(n) => write!(f, "#{}", n),
+            Score::Tablebase(Wdl::Win) => write!(f, "+TB"),
+            Score::Tablebase(Wdl::Loss) => write!(f, "-TB"),
+            Score::Tablebase(_) => write!(f, "0.00"),
+        }
+    }
+}
+
+// When to stop searching. With no limits at all, the search goes on until
+//   MAX_DEPTH or until it is stopped.
+#[derive(Clone, Debug)]
+pub struct SearchLimits {
+    pub depth: Option<u32>,
+    pub nodes: Option<u64>,
+    pub time: Option<Duration>,
+    // number of lines to report
+    pub multi_pv: usize,
+    // only consider these moves at the root, like UCI's searchmoves
+    pub search_moves: Option<Vec<Move>>,
+}
+
+impl Default for SearchLimits {
+    fn default() -> Self {
+        SearchLimits {
+            depth: None,
+            nodes: None,
+            time: None,
+            multi_pv: 1,
+            search_moves: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PvLine {
+    pub score: Score,
+    pub moves: Vec<Move>,
+    // the moves in algebraic notation
+    pub san: Vec<String>,
+}
+
+impl PvLine {
+    // "1.e4 e5 2.Nf3", or "1...e5 2.Nf3" if Black moves first
+    pub fn format_san(&self, move_number: u32, whose_turn: ChessTeam) -> String {
+        let mut res = String::new();
+        let mut move_number = move_number;
+        let mut team = whose_turn;
+
+        for (i, san) in self.san.iter().enumerate() {
+            if i > 0 {
+                res.push(' ');
+            }
+            match team {
+                ChessTeam::White => res.push_str(&format!("{}.", move_number)),
+                ChessTeam::Black => {
+                    if i == 0 {
+                        res.push_str(&format!("{}...", move_number));
+                    }
+                    move_number += 1;
+                }
+            }
+            res.push_str(san);
+            team = team.the_other_one();
+        }
+
+        res
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchInfo {
+    pub depth: u32,
+    // deepest ply reached, counting captures at the end of the lines
+    pub seldepth: u32,
+    pub nodes: u64,
+    // nodes per second
+    pub nps: u64,
+    pub time: Duration,
+    // best line first
+    pub lines: Vec<PvLine>,
+}
+
+impl SearchInfo {
+    pub fn best_move(&self) -> Option<Move> {
+        self.lines.first().and_then(|l| l.moves.first().copied())
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Engine {
+    tablebase: Option<Arc<dyn Tablebase>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Engine {
+    pub fn new() -> Engine {
+        Engine {
+            tablebase: None,
+            stop: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    // Positions with few enough pieces get their result from the tablebase
+    pub fn set_tablebase(&mut self, tablebase: Option<Arc<dyn Tablebase>>) {
+        self.tablebase = tablebase;
+    }
+
+    // Setting it to true stops the search. It's set back to false when a new
+    //   search starts.
+    pub fn stop_flag(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    // Searches the position, calling on_info after every iteration.
+    //   Returns the last complete iteration.
+    pub fn search<F: FnMut(&SearchInfo)>(
+        &self,
+        board: &Board,
+        ep_square: Option<Tile>,
+        limits: &SearchLimits,
+        mut on_info: F,
+    ) -> SearchInfo {
+        self.stop.store(false, Ordering::Relaxed);
+
+        let mut search = Search {
+            engine: self,
+            limits,
+            start: Instant::now(),
+            nodes: 0,
+            seldepth: 0,
+            aborted: false,
+            iteration: 0,
+            killers: vec![[None; 2]; MAX_DEPTH as usize * 2 + 1],
+            path: vec![],
+        };
+
+        let mut root_moves = board.get_all_legal_moves(ep_square);
+        if let Some(search_moves) = &limits.search_moves {
+            root_moves.retain(|m| search_moves.contains(m));
+        }
+        let multi_pv = std::cmp::max(limits.multi_pv, 1);
+        let max_depth = limits.depth.unwrap_or(MAX_DEPTH).clamp(1, MAX_DEPTH);
+
+        let mut result = SearchInfo {
+            depth: 0,
+            seldepth: 0,
+            nodes: 0,
+            nps: 0,
+            time: Duration::from_secs(0),
+            lines: vec![],
+        };
+
+        if root_moves.is_empty() {
+            return result;
+        }
+
+        for depth in 1..=max_depth {
+            let mut lines: Vec<(i32, Vec<Move>)> = vec![];
+
+            for _ in 0..std::cmp::min(multi_pv, root_moves.len()) {
+                let excluded: Vec<Move> = lines.iter().map(|(_, pv)| pv[0]).collect();
+                let (score, pv) = search.root(board, &root_moves, &excluded, depth);
+
+                // the first iteration always finishes, so there's always a move
+                if search.aborted && depth > 1 {
+                    break;
+                }
+                lines.push((score, pv));
+            }
+
+            if search.aborted && depth > 1 {
+                break;
+            }
+
+            // next iteration, search the best lines first
+            let best: Vec<Move> = lines.iter().map(|(_, pv)| pv[0]).collect();
+            root_moves.sort_by_key(|m| best.iter().position(|b| b == m).unwrap_or(best.len()));
+
+            let time = search.start.elapsed();
+            result = SearchInfo {
+                depth,
+                seldepth: search.seldepth,
+                nodes: search.nodes,
+                nps: (search.nodes as f64 / time.as_secs_f64().max(0.001)) as u64,
+                time,
+                lines: lines
+                    .into_iter()
+                    .map(|(score, moves)| PvLine {
+                        score: Score::from_internal(score),
+                        san: pv_notation(board, &moves),
+                        moves,
+                    })
+                    .collect(),
+            };
+            on_info(&result);
+
+            // no point in looking deeper than a forced mate
+            let is_mate = |l: &PvLine| matches!(l.score, Score::Mate(_));
+            if result.lines.iter().all(is_mate) {
+                break;
+            }
+
+            if search.should_stop() {
+                break;
+            }
+        }
+
+        result
+    }
+}
+
+// the moves of a line in algebraic notation
+fn pv_notation(board: &Board, moves: &[Move]) -> Vec<String> {
+    let mut board = board.clone();
+    let mut res = vec![];
+    for the_move in moves {
+        res.push(board.get_move_in_chess_notation(*the_move));
+        board.apply_move(*the_move);
+    }
+    res
+}
+
+fn piece_value(piece: ChessPiece) -> i32 {
+    match piece {
+        ChessPiece::Pawn => 100,
+        ChessPiece::Knight => 320,
+        ChessPiece::Bishop => 330,
+        ChessPiece::Rook => 500,
+        ChessPiece::Queen => 900,
+        ChessPiece::King => 0,
+    }
+}
+
+// Piece square tables, from White's side. a1 first, rank by rank.
+#[rustfmt::skip]
+const PAWN_TABLE: [i32; 64] = [
+     0,   0,   0,   0,   0,   0,   0,   0,
+     5,  10,  10, -20, -20,  10,  10,   5,
+     5,  -5, -10,   0,   0, -10,  -5,   5,
+     0,   0,   0,  20,  20,   0,   0,   0,
+     5,   5,  10,  25,  25,  10,   5,   5,
+    10,  10,  20,  30,  30,  20,  10,  10,
+    50,  50,  50,  50,  50,  50,  50,  50,
+     0,   0,   0,   0,   0,   0,   0,   0,
+];
+#[rustfmt::skip]
+const KNIGHT_TABLE: [i32; 64] = [
+    -50, -40, -30, -30, -30, -30, -40, -50,
+    -40, -20,   0,   5,   5,   0, -20, -40,
+    -30,   5,  10,  15,  15,  10,   5, -30,
+    -30,   0,  15,  20,  20,  15,   0, -30,
+    -30,   5,  15,  20,  20,  15,   5, -30,
+    -30,   0,  10,  15,  15,  10,   0, -30,
+    -40, -20,   0,   0,   0,   0, -20, -40,
+    -50, -40, -30, -30, -30, -30, -40, -50,
+];
+#[rustfmt::skip]
+const BISHOP_TABLE: [i32; 64] = [
+    -20, -10, -10, -10, -10, -10, -10, -20,
+    -10,   5,   0,   0,   0,   0,   5, -10,
+    -10,  10,  10,  10,  10,  10,  10, -10,
+    -10,   0,  10,  10,  10,  10,   0, -10,
+    -10,   5,   5,  10,  10,   5,   5, -10,
+    -10,   0,   5,  10,  10,   5,   0, -10,
+    -10,   0,   0,   0,   0,   0,   0, -10,
+    -20, -10, -10, -10, -10, -10, -10, -20,
+];
+#[rustfmt::skip]
+const ROOK_TABLE: [i32; 64] = [
+     0,   0,   0,   5,   5,   0,   0,   0,
+    -5,   0,   0,   0,   0,   0,   0,  -5,
+    -5,   0,   0,   0,   0,   0,   0,  -5,
+    -5,   0,   0,   0,   0,   0,   0,  -5,
+    -5,   0,   0,   0,   0,   0,   0,  -5,
+    -5,   0,   0,   0,   0,   0,   0,  -5,
+     5,  10,  10,  10,  10,  10,  10,   5,
+     0,   0,   0,   0,   0,   0,   0,   0,
+];
+#[rustfmt::skip]
+const KING_TABLE: [i32; 64] = [
+     20,  30,  10,   0,   0,  10,  30,  20,
+     20,  20,   0,   0,   0,   0,  20,  20,
+    -10, -20, -20, -20, -20, -20, -20, -10,
+    -20, -30, -30, -40, -40, -30, -30, -20,
+    -30, -40, -40, -50, -50, -40, -40, -30,
+    -30, -40, -40, -50, -50, -40, -40, -30,
+    -30, -40, -40, -50, -50, -40, -40, -30,
+    -30, -40, -40, -50, -50, -40, -40, -30,
+];
+// in the endgame the king should come to the center
+#[rustfmt::skip]
+const KING_ENDGAME_TABLE: [i32; 64] = [
+    -50, -30, -30, -30, -30, -30, -30, -50,
+    -30, -30,   0,   0,   0,   0, -30, -30,
+    -30, -10,  20,  30,  30,  20, -10, -30,
+    -30, -10,  30,  40,  40,  30, -10, -30,
+    -30, -10,  30,  40,  40,  30, -10, -30,
+    -30, -10,  20,  30,  30,  20, -10, -30,
+    -30, -20, -10,   0,   0, -10, -20, -30,
+    -50, -40, -30, -20, -20, -30, -40, -50,
+];
+
+// Static evaluation, in centipawns for the team whose turn it is
+pub fn evaluate(board: &Board) -> i32 {
+    let pieces = board.find_all_pieces();
+
+    // endgame: no queens, or very little material
+    let material: i32 = pieces
+        .iter()
+        .map(|(p, _)| piece_value(p.1))
+        .filter(|v| *v != 100)
+        .sum();
+    let is_endgame = material <= 2 * (500 + 330);
+
+    let mut score = 0;
+    for (piece, tile) in pieces {
+        let coord = Coord::from(tile);
+        // the tables are from White's side
+        let y = match piece.0 {
+            ChessTeam::White => coord.y,
+            ChessTeam::Black => 7 - coord.y,
+        };
+        let square = (y * 8 + coord.x) as usize;
+
+        let positional = match piece.1 {
+            ChessPiece::Pawn => PAWN_TABLE[square],
+            ChessPiece::Knight => KNIGHT_TABLE[square],
+            ChessPiece::Bishop => BISHOP_TABLE[square],
+            ChessPiece::Rook => ROOK_TABLE[square],
+            ChessPiece::Queen => 0,
+            ChessPiece::King => {
+                if is_endgame {
+                    KING_ENDGAME_TABLE[square]
+                } else {
+                    KING_TABLE[square]
+                }
+            }
+        };
+
+        let value = piece_value(piece.1) + positional;
+        if piece.0 == board.whose_turn {
+            score += value;
+        } else {
+            score -= value;
+        }
+    }
+
+    score
+}
+
+fn captured_piece(board: &Board, the_move: Move) -> Option<ChessPiece> {
+    match the_move {
+        Move::PieceMove {
+            tile_to,
+            is_en_passant,
+            ..
+        } => {
+            if is_en_passant {
+                Some(ChessPiece::Pawn)
+            } else {
+                board.get_piece(tile_to).map(|p| p.1)
+            }
+        }
+        Move::PieceMoveWithPromotion { tile_to, .. } => board.get_piece(tile_to).map(|p| p.1),
+        _ => None,
+    }
+}
+
+fn is_noisy(board: &Board, the_move: Move) -> bool {
+    matches!(the_move, Move::PieceMoveWithPromotion { .. })
+        || captured_piece(board, the_move).is_some()
+}
+
+struct Search<'a> {
+    engine: &'a Engine,
+    limits: &'a SearchLimits,
+    start: Instant,
+    nodes: u64,
+    seldepth: u32,
+    aborted: bool,
+    // depth of the current iteration
+    iteration: u32,
+    // quiet moves that caused a cutoff, by ply. check extensions can take
+    //   the search up to MAX_DEPTH * 2 plies deep
+    killers: Vec<[Option<Move>; 2]>,
+    // positions from the root to the current one
+    path: Vec<Board>,
+}
+
+impl<'a> Search<'a> {
+    fn should_stop(&self) -> bool {
+        if self.engine.stop.load(Ordering::Relaxed) {
+            return true;
+        }
+        if let Some(nodes) = self.limits.nodes {
+            if self.nodes >= nodes {
+                return true;
+            }
+        }
+        if let Some(time) = self.limits.time {
+            if self.start.elapsed() >= time {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn check_stop(&mut self) {
+        // the first iteration always finishes
+        if self.iteration > 1 && !self.aborted && self.should_stop() {
+            self.aborted = true;
+        }
+    }
+
+    // captures first (most valuable victim, least valuable attacker), then
+    //   killer moves, then the rest
+    fn order_moves(&self, board: &Board, moves: &mut [Move], ply: usize, first: Option<Move>) {
+        let killers = self.killers.get(ply).copied().unwrap_or([None; 2]);
+
+        moves.sort_by_cached_key(|m| {
+            if Some(*m) == first {
+                return i32::MIN;
+            }
+
+            let mut key = 0;
+            if let Some(victim) = captured_piece(board, *m) {
+                let attacker = match m {
+                    Move::PieceMove { piece, .. } => piece_value(*piece),
+                    _ => 100,
+                };
+                key -= 10_000 + piece_value(victim) * 10 - attacker;
+            }
+            if let Move::PieceMoveWithPromotion { promotion, .. } = m {
+                key -= 9_000 + piece_value(*promotion);
+            }
+            if killers.contains(&Some(*m)) {
+                key -= 5_000;
+            }
+            key
+        });
+    }
+
+    fn probe_tablebase(&self, board: &Board, ep_square: Option<Tile>, ply: u32) -> Option<i32> {
+        let tablebase = self.engine.tablebase.as_ref()?;
+        if board.piece_locations.len() > tablebase.max_pieces() {
+            return None;
+        }
+
+        match tablebase.probe_wdl(board, ep_square).ok()? {
+            Wdl::Win => Some(TABLEBASE_WIN - ply as i32),
+            Wdl::Loss => Some(-TABLEBASE_WIN + ply as i32),
+            _ => Some(0),
+        }
+    }
+
+    fn root(
+        &mut self,
+        board: &Board,
+        root_moves: &[Move],
+        excluded: &[Move],
+        depth: u32,
+    ) -> (i32, Vec<Move>) {
+        self.iteration = depth;
+        let mut alpha = -INFINITY;
+        let mut best_pv = vec![];
+
+        self.path.push(board.clone());
+
+        for the_move in root_moves {
+            if excluded.contains(the_move) {
+                continue;
+            }
+
+            let mut future_board = board.clone();
+            future_board.apply_move(*the_move);
+
+            let mut pv = vec![];
+            let score = -self.negamax(
+                &future_board,
+                the_move.get_en_passant_square(),
+                depth - 1,
+                1,
+                -INFINITY,
+                -alpha,
+                &mut pv,
+            );
+
+            if self.aborted {
+                break;
+            }
+
+            if score > alpha || best_pv.is_empty() {
+                alpha = score;
+                best_pv = vec![*the_move];
+                best_pv.extend(pv);
+            }
+        }
+
+        self.path.pop();
+        (alpha, best_pv)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn negamax(
+        &mut self,
+        board: &Board,
+        ep_square: Option<Tile>,
+        depth: u32,
+        ply: u32,
+        mut alpha: i32,
+        beta: i32,
+        pv: &mut Vec<Move>,
+    ) -> i32 {
+        self.nodes += 1;
+        self.seldepth = std::cmp::max(self.seldepth, ply);
+        self.check_stop();
+        if self.aborted {
+            return 0;
+        }
+
+        // repetition inside the search
+        if self
+            .path
+            .iter()
+            .rev()
+            .skip(1)
+            .step_by(2)
+            .any(|b| b == board)
+        {
+            return 0;
+        }
+
+        if let Some(score) = self.probe_tablebase(board, ep_square, ply) {
+            return score;
+        }
+
+        let in_check = board.is_team_in_check(board.whose_turn, ep_square);
+
+        // look one move further when in check
+        let depth = if in_check { depth + 1 } else { depth };
+
+        if depth == 0 || ply >= MAX_DEPTH * 2 {
+            return self.quiescence(board, ep_square, ply, alpha, beta);
+        }
+
+        let mut moves = board.get_all_legal_moves(ep_square);
+        if moves.is_empty() {
+            return if in_check { -MATE + ply as i32 } else { 0 };
+        }
+
+        self.order_moves(board, &mut moves, ply as usize, None);
+        self.path.push(board.clone());
+
+        for the_move in moves {
+            let mut future_board = board.clone();
+            future_board.apply_move(the_move);
+
+            let mut child_pv = vec![];
+            let score = -self.negamax(
+                &future_board,
+                the_move.get_en_passant_square(),
+                depth - 1,
+                ply + 1,
+                -beta,
+                -alpha,
+                &mut child_pv,
+            );
+
+            if self.aborted {
+                self.path.pop();
+                return 0;
+            }
+
+            if score > alpha {
+                alpha = score;
+                pv.clear();
+                pv.push(the_move);
+                pv.extend(child_pv);
+
+                if alpha >= beta {
+                    if !is_noisy(board, the_move) {
+                        if let Some(killers) = self.killers.get_mut(ply as usize) {
+                            if killers[0] != Some(the_move) {
+                                killers[1] = killers[0];
+                                killers[0] = Some(the_move);
+                            }
+                        }
+                    }
+                    break;
+                }
+            }
+        }
+
+        self.path.pop();
+        alpha
+    }
+
+    // only captures and promotions, until the position is quiet
+    fn quiescence(
+        &mut self,
+        board: &Board,
+        ep_square: Option<Tile>,
+        ply: u32,
+        mut alpha: i32,
+        beta: i32,
+    ) -> i32 {
+        self.nodes += 1;
+        self.seldepth = std::cmp::max(self.seldepth, ply);
+
+        let mut moves = board.get_all_legal_moves(ep_square);
+        if moves.is_empty() {
+            return if board.is_team_in_check(board.whose_turn, ep_square) {
+                -MATE + ply as i32
+            } else {
+                0
+            };
+        }
+
+        let stand_pat = evaluate(board);
+        if stand_pat >= beta {
+            return stand_pat;
+        }
+        alpha = std::cmp::max(alpha, stand_pat);
+
+        moves.retain(|m| is_noisy(board, *m));
+        self.order_moves(board, &mut moves, ply as usize, None);
+
+        for the_move in moves {
+            let mut future_board = board.clone();
+            future_board.apply_move(the_move);
+
+            let score = -self.quiescence(
+                &future_board,
+                the_move.get_en_passant_square(),
+                ply + 1,
+                -beta,
+                -alpha,
+            );
+
+            if score >= beta {
+                return score;
+            }
+            alpha = std::cmp::max(alpha, score);
+        }
+
+        alpha
+    }
+}
+
+// Moves in UCI notation: "e2e4", "e7e8q". Castling is the king's move: "e1g1"
+pub fn move_to_uci(board: &Board, the_move: Move) -> String {
+    let back_rank = match board.whose_turn {
+        ChessTeam::White => "1",
+        ChessTeam::Black => "8",
+    };
+
+    match the_move {
+        Move::PieceMove {
+            tile_from, tile_to, ..
+        } => format!("{}{}", tile_from, tile_to),
+        Move::PieceMoveWithPromotion {
+            tile_from,
+            tile_to,
+            promotion,
+        } => {
+            let promotion = match promotion {
+                ChessPiece::Queen => 'q',
+                ChessPiece::Rook => 'r',
+                ChessPiece::Bishop => 'b',
+                _ => 'n',
+            };
+            format!("{}{}{}", tile_from, tile_to, promotion)
+        }
+        Move::CastleShort => format!("e{}g{}", back_rank, back_rank),
+        Move::CastleLong => format!("e{}c{}", back_rank, back_rank),
+    }
+}
+
+// The legal move with that UCI notation
+pub fn move_from_uci(board: &Board, ep_square: Option<Tile>, uci: &str) -> Option<Move> {
+    board
+        .get_all_legal_moves(ep_square)
+        .into_iter()
+        .find(|m| move_to_uci(board, *m) == uci)
+}
+
+#[cfg(test)]
+#[path = "./tests/engine_tests.rs"]
+mod engine_tests;
@@ -2,7 +2,16 @@
 #![warn(rust_2018_idioms)]
 #![allow(dead_code)]
 
+pub mod clock;
+pub mod engine;
 pub mod move_parser;
+pub mod move_tree;
+pub mod pgn;
+pub mod problem;
+pub mod retrograde;
+pub mod review;
+mod syzygy;
+pub mod tablebase;
 
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -64,7 +73,7 @@ impl fmt::Display for ChessPiece {
 
 // NOTE(lucypero): Most of these will never happen because most of these cases
 //   are caught by MoveParseError (assuming we use chess move notation as input)
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
 pub enum MoveError {
     // there is nothing at that tile!
     TileFromIsEmpty,
@@ -86,6 +95,10 @@ pub enum MoveError {
     CastlingThroughCheck,
     //Your King would be in check. King can't be in check.
     InCheck,
+    //Your time ran out.
+    OutOfTime,
+    //It's not your turn. Only the server checks who sent the move
+    NotYourTurn,
 }
 
 impl fmt::Display for MoveError {
@@ -112,6 +125,8 @@ impl fmt::Display for MoveError {
             }
             MoveError::CastlingThroughCheck => write!(f, "Can't castle while in or through check."),
             MoveError::InCheck => write!(f, "Your King would be in check. King can't be in check."),
+            MoveError::OutOfTime => write!(f, "Your time ran out."),
+            MoveError::NotYourTurn => write!(f, "It's not your turn."),
         }
     }
 }
@@ -526,46 +541,84 @@ impl fmt::Display for Move {
     }
 }
 
+#[derive(Clone)]
 pub struct GameState {
+    //every move tried, with variations
+    tree: move_tree::MoveTree,
+    //the line being played or looked at. A path in the tree to the end of a line
+    line: Vec<usize>,
+    //the moves of the line
     moves: Vec<Move>,
+    //how many moves of the line are played in the current position
+    cursor: usize,
     cached_current_board: Option<Board>,
     pub fifty_move_counter: u32, //the number of halfmoves since the last capture or pawn advance
+    starting_fifty_move_counter: u32,
+    clock: Option<clock::Clock>,
+    //PGN tags like White and Black, in the order they were set
+    tags: Vec<(String, String)>,
     starting_board: Board,
     pub starting_move_count: u32, //The number of the full move (before moves start being counted). It starts at 1, and is incremented after Black's move.
     pub en_passant_square: Option<Tile>,
+    starting_en_passant_square: Option<Tile>,
 }
 
-#[derive(PartialEq)]
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
 pub enum GameEndState {
     Checkmate,
     Draw,
+    //the team to move ran out of time
+    Timeout,
     Running,
 }
 
 impl GameState {
     pub fn init() -> GameState {
         GameState {
+            tree: move_tree::MoveTree::new(),
+            line: vec![],
             moves: vec![],
+            cursor: 0,
             starting_board: Board::start_position(),
             cached_current_board: None,
             fifty_move_counter: 0,
+            starting_fifty_move_counter: 0,
+            clock: None,
+            tags: vec![],
             starting_move_count: 1,
             en_passant_square: None,
+            starting_en_passant_square: None,
         }
     }
 
     pub fn init_from_custom_position(board: Board) -> GameState {
         GameState {
+            tree: move_tree::MoveTree::new(),
+            line: vec![],
             moves: vec![],
+            cursor: 0,
             starting_board: board,
             cached_current_board: None,
             starting_move_count: 1,
             en_passant_square: None,
+            starting_en_passant_square: None,
             fifty_move_counter: 0,
+            starting_fifty_move_counter: 0,
+            clock: None,
+            tags: vec![],
         }
     }
 
     pub fn get_end_state(&mut self) -> GameEndState {
+        // flag fell. It's a draw if the other team couldn't checkmate anyway
+        if let Some(team) = self.clock.as_ref().and_then(|c| c.flagged()) {
+            return if self.get_board().has_mating_material(team.the_other_one()) {
+                GameEndState::Timeout
+            } else {
+                GameEndState::Draw
+            };
+        }
+
         // fifty move rule
         if self.fifty_move_counter == 50 {
             println!("50 move rule!!! hahahaha");
@@ -615,7 +668,7 @@ impl GameState {
         self.moves.last().copied()
     }
 
-    //returns the length of the recorded moves (not the actual number of moves since game start)
+    //returns the length of the recorded moves of the current line (not the actual number of moves since game start)
     pub fn move_count(&self) -> usize {
         self.moves.len()
     }
@@ -631,21 +684,216 @@ impl GameState {
         board
     }
 
-    //Returns the current board position
+    //Returns the en passant square of the board position at move_i
+    pub fn get_en_passant_square_at(&self, move_i: usize) -> Option<Tile> {
+        if move_i == 0 {
+            self.starting_en_passant_square
+        } else {
+            self.moves[move_i - 1].get_en_passant_square()
+        }
+    }
+
+    //Returns the current board position (the one at the cursor)
     pub fn get_board(&mut self) -> &Board {
         //Start with the starting board position then you start mutating it with each
         //  move until you get the current position
         if self.cached_current_board.is_none() {
-            let mut board = self.starting_board.clone();
-            for chess_move in self.moves.iter() {
-                board.apply_move(*chess_move);
-            }
-            self.cached_current_board = Some(board);
+            self.cached_current_board = Some(self.get_board_at(self.cursor));
         }
 
         self.cached_current_board.as_ref().unwrap()
     }
 
+    pub fn get_move_tree(&self) -> &move_tree::MoveTree {
+        &self.tree
+    }
+
+    //comment, NAGs and marks of the move at path. [] is the starting position
+    pub fn get_annotations(&self, path: &[usize]) -> Option<&move_tree::Annotations> {
+        self.tree.annotations(path)
+    }
+
+    pub fn get_annotations_mut(&mut self, path: &[usize]) -> Option<&mut move_tree::Annotations> {
+        self.tree.annotations_mut(path)
+    }
+
+    //The clock of the game. It gets pressed on every move. It's not started
+    //  here: it starts running when the first move is made, or with Clock::start
+    pub fn set_clock(&mut self, clock: Option<clock::Clock>) {
+        self.clock = clock;
+    }
+
+    pub fn get_clock(&self) -> Option<&clock::Clock> {
+        self.clock.as_ref()
+    }
+
+    pub fn get_clock_mut(&mut self) -> Option<&mut clock::Clock> {
+        self.clock.as_mut()
+    }
+
+    //time spent on move [move_i] of the current line, and the time left after it
+    pub fn get_move_time(&self, move_i: usize) -> Option<clock::MoveTime> {
+        self.tree.node(&self.line[..=move_i])?.time
+    }
+
+    //for games rebuilt from their moves, like the ones the server keeps
+    pub fn set_move_time(&mut self, move_i: usize, time: Option<clock::MoveTime>) {
+        let path = self.line[..=move_i].to_vec();
+        if let Some(node) = self.tree.node_mut(&path) {
+            node.time = time;
+        }
+    }
+
+    //path in the move tree of the last move of the current line
+    pub fn get_line_path(&self) -> &[usize] {
+        &self.line
+    }
+
+    //how many moves of the current line are played in the current position.
+    //  perform_move plays the move here, so it's the same as move_count() unless
+    //  the cursor was moved back
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    //path in the move tree of the current position
+    pub fn get_cursor_path(&self) -> &[usize] {
+        &self.line[..self.cursor]
+    }
+
+    //moves the cursor to move [move_i] of the current line
+    pub fn go_to(&mut self, move_i: usize) {
+        assert!(move_i <= self.move_count());
+        self.cursor = move_i;
+        self.sync_cursor();
+    }
+
+    pub fn go_back(&mut self) -> bool {
+        if self.cursor == 0 {
+            return false;
+        }
+        self.go_to(self.cursor - 1);
+        true
+    }
+
+    pub fn go_forward(&mut self) -> bool {
+        if self.cursor == self.move_count() {
+            return false;
+        }
+        self.go_to(self.cursor + 1);
+        true
+    }
+
+    //moves the cursor to the move at path. The current line becomes the one
+    //  that continues from there with the main continuation.
+    pub fn go_to_path(&mut self, path: &[usize]) -> bool {
+        if !self.tree.contains(path) {
+            return false;
+        }
+        self.line = self.tree.main_line_from(path);
+        self.cursor = path.len();
+        self.sync_cursor();
+        true
+    }
+
+    //makes the variation of the move at path the main continuation
+    pub fn promote_variation(&mut self, path: &[usize]) -> bool {
+        let (ply, old_index) = match self.tree.promote_variation(path) {
+            Some(res) => res,
+            None => return false,
+        };
+
+        //the siblings before the variation moved one place
+        if self.line.len() > ply && self.line[..ply] == path[..ply] {
+            if self.line[ply] == old_index {
+                self.line[ply] = 0;
+            } else if self.line[ply] < old_index {
+                self.line[ply] += 1;
+            }
+        }
+
+        self.sync_cursor();
+        true
+    }
+
+    //deletes the move at path and everything after it
+    pub fn delete_variation(&mut self, path: &[usize]) -> bool {
+        let (last, parent) = match path.split_last() {
+            Some(split) => split,
+            None => return false,
+        };
+        if self.tree.delete(path).is_none() {
+            return false;
+        }
+
+        let ply = parent.len();
+        if self.line.len() > ply && self.line[..ply] == *parent {
+            if self.line[ply] == *last {
+                //the current line was deleted
+                self.line = self.tree.main_line_from(parent);
+                self.cursor = std::cmp::min(self.cursor, ply);
+            } else if self.line[ply] > *last {
+                self.line[ply] -= 1;
+            }
+        }
+
+        self.sync_cursor();
+        true
+    }
+
+    //updates what depends on the current line and the cursor
+    fn sync_cursor(&mut self) {
+        self.moves = self.tree.moves_to(&self.line);
+        self.en_passant_square = self.get_en_passant_square_at(self.cursor);
+
+        let mut board = self.starting_board.clone();
+        self.fifty_move_counter = self.starting_fifty_move_counter;
+        for chess_move in self.moves.iter().take(self.cursor) {
+            let was_capture = board.apply_move(*chess_move);
+            let was_pawn_move = matches!(
+                chess_move,
+                Move::PieceMove {
+                    piece: ChessPiece::Pawn,
+                    ..
+                } | Move::PieceMoveWithPromotion { .. }
+            );
+
+            if was_capture || was_pawn_move {
+                self.fifty_move_counter = 0;
+            } else {
+                self.fifty_move_counter += 1;
+            }
+        }
+        self.cached_current_board = Some(board);
+    }
+
+    //Takes back the last move of the current line. It's removed from the move tree.
+    //  En passant, the fifty move counter and castling rights go back with it
+    //  because they come from replaying the line.
+    pub fn undo_move(&mut self) -> Option<Move> {
+        let node = self.tree.delete(&self.line)?;
+        self.line.pop();
+        self.cursor = std::cmp::min(self.cursor, self.line.len());
+        self.sync_cursor();
+        Some(node.the_move)
+    }
+
+    //how many moves to take back so [team] can play its last move again. 0 if
+    //  it didn't move yet
+    pub fn takeback_len(&self, team: ChessTeam) -> usize {
+        let len = if self.get_board_at(self.move_count()).whose_turn == team {
+            2
+        } else {
+            1
+        };
+
+        if len <= self.move_count() {
+            len
+        } else {
+            0
+        }
+    }
+
     //result bool: if it was a capture
     pub fn perform_move(&mut self, mut chess_move: Move) -> Result<bool, MoveError> {
         //performs all move validation here. If it is legal,
@@ -781,11 +1029,34 @@ impl GameState {
             return Err(MoveError::InCheck);
         }
 
-        //Everything is good. adding move to self.moves
-        self.moves.push(chess_move);
+        //pressing the clock
+        let team = self.whose_turn();
+        let move_time = match &mut self.clock {
+            Some(clock) => match clock.press(team) {
+                Ok(move_time) => Some(move_time),
+                Err(_) => return Err(MoveError::OutOfTime),
+            },
+            None => None,
+        };
+
+        //Everything is good. adding move to the tree, after the cursor
+        let index = self.tree.add_move(&self.line[..self.cursor], chess_move);
+        if self.line.get(self.cursor) != Some(&index) {
+            //the move leaves the current line
+            let mut path = self.line[..self.cursor].to_vec();
+            path.push(index);
+            self.line = self.tree.main_line_from(&path);
+            self.moves = self.tree.moves_to(&self.line);
+        }
+        self.cursor += 1;
         self.cached_current_board = None;
         self.en_passant_square = next_ep_square;
 
+        if move_time.is_some() {
+            let path = self.line[..self.cursor].to_vec();
+            self.tree.node_mut(&path).unwrap().time = move_time;
+        }
+
         if was_capture_or_pawn_move {
             self.fifty_move_counter = 0;
         } else {
@@ -796,6 +1067,11 @@ impl GameState {
     }
 
     fn get_full_move_count(&self) -> u32 {
+        self.get_full_move_count_at(self.cursor)
+    }
+
+    // the full move number after [move_i] moves have been played
+    pub fn get_full_move_count_at(&self, move_i: usize) -> u32 {
         // basically we have to figure out how many times
         //    black moved and add it to self.starting_move_count
 
@@ -803,7 +1079,7 @@ impl GameState {
         //          if black starts first, number of black moves is moves / 2 (round up)
 
         let starting_team = self.starting_board.whose_turn;
-        let move_count = self.move_count() as u32;
+        let move_count = move_i as u32;
 
         let added_moves = match starting_team {
             ChessTeam::Black => (move_count + 1) / 2,
@@ -814,140 +1090,8 @@ impl GameState {
     }
 
     pub fn get_move_in_chess_notation(&mut self, move_i: usize) -> String {
-        let mut final_move_str = String::new();
-
         let the_move = self.moves[move_i];
-
-        fn piece_to_str(p: ChessPiece) -> String {
-            let p_str = match p {
-                ChessPiece::Pawn => "",
-                ChessPiece::Rook => "R",
-                ChessPiece::Knight => "N",
-                ChessPiece::Bishop => "B",
-                ChessPiece::Queen => "Q",
-                ChessPiece::King => "K",
-            };
-            p_str.to_string()
-        }
-
-        let prev_board = self.get_board_at(move_i);
-        let mut board = prev_board.clone();
-        let was_capture = board.apply_move(the_move);
-        let capture_str = if was_capture { "x" } else { "" };
-
-        let basic_move = match the_move {
-            Move::PieceMove {
-                piece,
-                tile_from,
-                tile_to,
-                is_en_passant: _,
-            } => {
-                let piece_str = piece_to_str(piece);
-                let tile_to_str = &format!("{}", tile_to);
-
-                let coord_from = Coord::from(tile_from);
-
-                if piece == ChessPiece::Pawn && was_capture {
-                    let tile_from_char = Coord::from(tile_from).get_file_char();
-                    let mut res = String::new();
-                    res.push(tile_from_char);
-                    res + capture_str + tile_to_str
-                } else {
-                    //get pieces of same type and team that can make the same move
-                    let mut pieces = prev_board.find_pieces(prev_board.whose_turn, piece);
-                    pieces.retain(move |&p| {
-                        let tile_from = Tile::try_from(p).unwrap();
-                        is_piece_move_legal(
-                            TeamedChessPiece(prev_board.whose_turn, piece),
-                            tile_from,
-                            tile_to,
-                            None,
-                            &prev_board,
-                            &mut false,
-                        )
-                    });
-                    //take out the piece that made the move
-                    pieces.retain(|p| *p != coord_from);
-
-                    if pieces.is_empty() {
-                        piece_str + capture_str + tile_to_str
-                    } else {
-                        let mut unique_file = true;
-                        let mut unique_rank = true;
-
-                        for p in pieces {
-                            if p.x == coord_from.x {
-                                unique_file = false;
-                            }
-                            if p.y == coord_from.y {
-                                unique_rank = false;
-                            }
-                        }
-
-                        let mut specif_str = String::new();
-
-                        if unique_file {
-                            let file_char = Coord::from(tile_from).get_file_char();
-                            specif_str.push(file_char);
-                        } else if unique_rank {
-                            let rank_char = Coord::from(tile_from).get_rank_char();
-                            specif_str.push(rank_char);
-                        } else {
-                            let file_char = Coord::from(tile_from).get_file_char();
-                            let rank_char = Coord::from(tile_from).get_rank_char();
-                            specif_str.push(file_char);
-                            specif_str.push(rank_char);
-                        }
-
-                         piece_str + &specif_str + capture_str + tile_to_str
-                    }
-                }
-            }
-            Move::PieceMoveWithPromotion {
-                tile_from,
-                tile_to,
-                promotion,
-            } => {
-                let piece_str = piece_to_str(promotion);
-                let tile_to_str = &format!("{}", tile_to);
-
-                if was_capture {
-                    let tile_from_file_char = Coord::from(tile_from).get_file_char();
-                    let mut res = String::new();
-                    res.push(tile_from_file_char);
-                    res + "x" + tile_to_str + "=" + &piece_str
-                } else {
-                    tile_to_str.to_string() + "=" + &piece_str
-                }
-            }
-            Move::CastleShort => "O-O".to_string(),
-            Move::CastleLong => "O-O-O".to_string(),
-        };
-
-        final_move_str += &basic_move;
-
-        // en passant
-        if let Move::PieceMove {
-            piece: _,
-            tile_from: _,
-            tile_to: _,
-            is_en_passant,
-        } = the_move
-        {
-            if is_en_passant {
-                final_move_str += " e.p.";
-            }
-        }
-
-        // Check / checkmate
-
-        //if last move and end game is checkmate, then move is checkmate
-        if move_i == self.move_count() - 1 && self.get_end_state() == GameEndState::Checkmate {
-            final_move_str += "#";
-        } else if board.is_team_in_check(board.whose_turn, None) {
-            final_move_str += "+";
-        }
-        final_move_str
+        self.get_board_at(move_i).get_move_in_chess_notation(the_move)
     }
 
     // whose turn is it?
@@ -1051,24 +1195,29 @@ impl GameState {
         res
     }
 
-    pub fn get_pgn(&mut self) -> String {
-
-        let mut res = String::new();
-
-        let mut move_count = 1;
-
-        for i in 0..self.moves.len() {
+    //sets a tag for the PGN, or replaces its value
+    pub fn set_tag(&mut self, name: &str, value: String) {
+        match self.tags.iter_mut().find(|(tag, _)| tag == name) {
+            Some((_, old)) => *old = value,
+            None => self.tags.push((name.to_string(), value)),
+        }
+    }
 
-            if i % 2 == 0 {
-                res += format!("{}. ", move_count).as_str();
-                move_count+=1;
-            }
+    pub fn get_tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.as_str())
+    }
 
-            res += self.get_move_in_chess_notation(i).as_str();
-            res += " ";
-        }
+    pub fn get_tags(&self) -> &[(String, String)] {
+        &self.tags
+    }
 
-        res
+    //The game in PGN, with variations. Only has the tags that were set, and
+    //  the FEN if it didn't start from the standard position
+    pub fn get_pgn(&mut self) -> String {
+        pgn::write_pgn(self)
     }
 }
 
@@ -1208,7 +1357,8 @@ mod move_processor {
         if rank_coord.is_none() {
             return Err(());
         }
-        Ok(rank_coord.unwrap() as i32)
+        //coords start at 0, ranks at 1
+        Ok(rank_coord.unwrap() as i32 - 1)
     }
 
     fn get_non_pawn_move(
@@ -1638,7 +1788,7 @@ pub fn is_piece_move_legal(
 
 // Describes a snapshot of the board on a given position
 // Basically, what pieces there are and where they are
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct Board {
     pub whose_turn: ChessTeam,
     pub piece_locations: HashMap<Tile, TeamedChessPiece>,
@@ -1796,6 +1946,22 @@ impl Board {
         result
     }
 
+    //if the team has the pieces to checkmate, even with help.
+    //  A lone king can't, and a king with a knight or a bishop can't against a lone king
+    pub fn has_mating_material(&self, team: ChessTeam) -> bool {
+        let pieces = self.find_pieces_of_team(team);
+        let other_pieces = self.find_pieces_of_team(team.the_other_one());
+        let only_minor = pieces.iter().all(|(TeamedChessPiece(_, p), _)| {
+            matches!(p, ChessPiece::King | ChessPiece::Knight | ChessPiece::Bishop)
+        });
+
+        match pieces.len() {
+            1 => false,
+            2 if only_minor => other_pieces.len() > 1,
+            _ => true,
+        }
+    }
+
     pub fn find_pieces_of_team(&self, team: ChessTeam) -> Vec<(TeamedChessPiece, Tile)> {
         let mut result = vec![];
 
@@ -1988,18 +2154,25 @@ impl Board {
         //3. filter out all moves that put the king in check
         moves.retain(|coord_to| {
             let mut future_board: Board = self.clone();
+            let tile_to = Tile::try_from(*coord_to).unwrap();
+
+            // en passant matters here: the captured pawn leaves the board
+            let mut is_en_passant = false;
+            if piece_type == ChessPiece::Pawn {
+                is_piece_move_legal(piece, tile, tile_to, ep_square, self, &mut is_en_passant);
+            }
 
             //the move
             let the_move = Move::PieceMove {
                 piece: piece_type,
                 tile_from: tile,
-                tile_to: Tile::try_from(*coord_to).unwrap(),
-                is_en_passant: false,
+                tile_to,
+                is_en_passant,
             };
 
             // TODO(lucypero): castle moves. not sure if it is necessary.
 
-            // NOTE(lucypero): we ignore things like en_passant and promotion piece because
+            // NOTE(lucypero): we ignore the promotion piece because
             //  that would not affect if the player's king is in check.
             future_board.apply_move(the_move);
             !future_board.is_team_in_check(self.whose_turn, the_move.get_en_passant_square())
@@ -2088,6 +2261,207 @@ impl Board {
         return Ok(());
     }
 
+    // Returns the move in standard algebraic notation (e.g. Nbd7, exd6 e.p., O-O+, e8=Q#)
+    //   the move has to be legal in this position
+    pub fn get_move_in_chess_notation(&self, the_move: Move) -> String {
+        let mut final_move_str = String::new();
+
+        fn piece_to_str(p: ChessPiece) -> String {
+            let p_str = match p {
+                ChessPiece::Pawn => "",
+                ChessPiece::Rook => "R",
+                ChessPiece::Knight => "N",
+                ChessPiece::Bishop => "B",
+                ChessPiece::Queen => "Q",
+                ChessPiece::King => "K",
+            };
+            p_str.to_string()
+        }
+
+        let prev_board = self;
+        let mut board = prev_board.clone();
+        let was_capture = board.apply_move(the_move);
+        let capture_str = if was_capture { "x" } else { "" };
+
+        let basic_move = match the_move {
+            Move::PieceMove {
+                piece,
+                tile_from,
+                tile_to,
+                is_en_passant: _,
+            } => {
+                let piece_str = piece_to_str(piece);
+                let tile_to_str = &format!("{}", tile_to);
+
+                let coord_from = Coord::from(tile_from);
+
+                if piece == ChessPiece::Pawn && was_capture {
+                    let tile_from_char = Coord::from(tile_from).get_file_char();
+                    let mut res = String::new();
+                    res.push(tile_from_char);
+                    res + capture_str + tile_to_str
+                } else {
+                    //get pieces of same type and team that can make the same move
+                    let mut pieces = prev_board.find_pieces(prev_board.whose_turn, piece);
+                    pieces.retain(move |&p| {
+                        let tile_from = Tile::try_from(p).unwrap();
+                        is_piece_move_legal(
+                            TeamedChessPiece(prev_board.whose_turn, piece),
+                            tile_from,
+                            tile_to,
+                            None,
+                            prev_board,
+                            &mut false,
+                        )
+                    });
+                    //take out the piece that made the move
+                    pieces.retain(|p| *p != coord_from);
+
+                    if pieces.is_empty() {
+                        piece_str + capture_str + tile_to_str
+                    } else {
+                        let mut unique_file = true;
+                        let mut unique_rank = true;
+
+                        for p in pieces {
+                            if p.x == coord_from.x {
+                                unique_file = false;
+                            }
+                            if p.y == coord_from.y {
+                                unique_rank = false;
+                            }
+                        }
+
+                        let mut specif_str = String::new();
+
+                        if unique_file {
+                            let file_char = Coord::from(tile_from).get_file_char();
+                            specif_str.push(file_char);
+                        } else if unique_rank {
+                            let rank_char = Coord::from(tile_from).get_rank_char();
+                            specif_str.push(rank_char);
+                        } else {
+                            let file_char = Coord::from(tile_from).get_file_char();
+                            let rank_char = Coord::from(tile_from).get_rank_char();
+                            specif_str.push(file_char);
+                            specif_str.push(rank_char);
+                        }
+
+                         piece_str + &specif_str + capture_str + tile_to_str
+                    }
+                }
+            }
+            Move::PieceMoveWithPromotion {
+                tile_from,
+                tile_to,
+                promotion,
+            } => {
+                let piece_str = piece_to_str(promotion);
+                let tile_to_str = &format!("{}", tile_to);
+
+                if was_capture {
+                    let tile_from_file_char = Coord::from(tile_from).get_file_char();
+                    let mut res = String::new();
+                    res.push(tile_from_file_char);
+                    res + "x" + tile_to_str + "=" + &piece_str
+                } else {
+                    tile_to_str.to_string() + "=" + &piece_str
+                }
+            }
+            Move::CastleShort => "O-O".to_string(),
+            Move::CastleLong => "O-O-O".to_string(),
+        };
+
+        final_move_str += &basic_move;
+
+        // en passant
+        if let Move::PieceMove {
+            piece: _,
+            tile_from: _,
+            tile_to: _,
+            is_en_passant,
+        } = the_move
+        {
+            if is_en_passant {
+                final_move_str += " e.p.";
+            }
+        }
+
+        // Check / checkmate
+
+        // if the other team is in check and can't move, it is checkmate
+        if board.is_team_in_check(board.whose_turn, None) {
+            if board
+                .get_all_legal_moves(the_move.get_en_passant_square())
+                .is_empty()
+            {
+                final_move_str += "#";
+            } else {
+                final_move_str += "+";
+            }
+        }
+        final_move_str
+    }
+
+    // Every legal move of the team whose turn it is, including castling,
+    //   promotions (one move per promotion piece) and en passant captures.
+    //   Moves are listed from A1 to H8 so the order is always the same.
+    pub fn get_all_legal_moves(&self, ep_square: Option<Tile>) -> Vec<Move> {
+        let mut res = vec![];
+
+        for y in 0..=7 {
+            for x in 0..=7 {
+                let tile = Tile::try_from(Coord { x, y }).unwrap();
+                let piece = match self.get_piece(tile) {
+                    Some(piece) if piece.0 == self.whose_turn => piece,
+                    _ => continue,
+                };
+
+                let coords = self
+                    .get_legal_moves_of_piece_in_tile(tile, ep_square)
+                    .unwrap();
+
+                for coord_to in coords {
+                    let tile_to = Tile::try_from(coord_to).unwrap();
+
+                    if piece.1 == ChessPiece::Pawn && (coord_to.y == 0 || coord_to.y == 7) {
+                        for promotion in [
+                            ChessPiece::Queen,
+                            ChessPiece::Rook,
+                            ChessPiece::Bishop,
+                            ChessPiece::Knight,
+                        ] {
+                            res.push(Move::PieceMoveWithPromotion {
+                                tile_from: tile,
+                                tile_to,
+                                promotion,
+                            });
+                        }
+                        continue;
+                    }
+
+                    let mut is_en_passant = false;
+                    is_piece_move_legal(piece, tile, tile_to, ep_square, self, &mut is_en_passant);
+
+                    res.push(Move::PieceMove {
+                        piece: piece.1,
+                        tile_from: tile,
+                        tile_to,
+                        is_en_passant,
+                    });
+                }
+            }
+        }
+
+        for castle in [Move::CastleShort, Move::CastleLong] {
+            if self.is_castle_legal(castle, ep_square).is_ok() {
+                res.push(castle);
+            }
+        }
+
+        res
+    }
+
     pub fn get_king_casle_moves(&self, tile:Tile, ep_square: Option<Tile>) -> Vec<Coord> {
         let mut res = vec![];
         let piece = self.get_piece(tile).unwrap();
@@ -2574,12 +2948,19 @@ pub fn parse_fen(fen: String) -> Option<GameState> {
     };
 
     Some(GameState {
+        tree: move_tree::MoveTree::new(),
+        line: vec![],
         moves: vec![],
+        cursor: 0,
         starting_board: board,
         cached_current_board: None,
         starting_move_count: full_move_counter,
         en_passant_square,
+        starting_en_passant_square: en_passant_square,
         fifty_move_counter,
+        starting_fifty_move_counter: fifty_move_counter,
+        clock: None,
+        tags: vec![],
     })
 }
 
@@ -0,0 +1,219 @@
+// Tree of the moves of a game, with variations
+//
+// Every node is a move, and its children are the moves that were tried after
+//  it. The first child is the main continuation and the rest are variations.
+//  A node is addressed by its path: the index of the child taken at every ply,
+//  starting from the moves of the starting position. [0, 0, 1] is the second
+//  option for the third move after the main line's first two moves.
+//
+// Nodes also keep what was written about the move: a comment, NAGs and the
+//  arrows and squares drawn on the board after it.
+
+use super::*;
+use crate::clock::MoveTime;
+
+// The colours PGN has for arrows and squares: G, R, Y and B
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MarkColor {
+    Green,
+    Red,
+    Yellow,
+    Blue,
+}
+
+impl MarkColor {
+    pub fn letter(self) -> char {
+        match self {
+            MarkColor::Green => 'G',
+            MarkColor::Red => 'R',
+            MarkColor::Yellow => 'Y',
+            MarkColor::Blue => 'B',
+        }
+    }
+
+    pub fn from_letter(letter: char) -> Option<MarkColor> {
+        match letter {
+            'G' => Some(MarkColor::Green),
+            'R' => Some(MarkColor::Red),
+            'Y' => Some(MarkColor::Yellow),
+            'B' => Some(MarkColor::Blue),
+            _ => None,
+        }
+    }
+}
+
+// something drawn on the board
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Mark {
+    Arrow {
+        from: Tile,
+        to: Tile,
+        color: MarkColor,
+    },
+    Square {
+        tile: Tile,
+        color: MarkColor,
+    },
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Annotations {
+    pub comment: String,
+    // numeric annotation glyphs. 1 is !, 2 is ?, and so on
+    pub nags: Vec<u8>,
+    pub marks: Vec<Mark>,
+}
+
+impl Annotations {
+    pub fn is_empty(&self) -> bool {
+        self.comment.is_empty() && self.nags.is_empty() && self.marks.is_empty()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MoveNode {
+    pub the_move: Move,
+    pub annotations: Annotations,
+    // how long the move took, if the game had a clock
+    pub time: Option<MoveTime>,
+    // main continuation first
+    pub children: Vec<MoveNode>,
+}
+
+impl MoveNode {
+    fn new(the_move: Move) -> MoveNode {
+        MoveNode {
+            the_move,
+            annotations: Annotations::default(),
+            time: None,
+            children: vec![],
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MoveTree {
+    // the moves played from the starting position, main line first
+    pub roots: Vec<MoveNode>,
+    // what was written about the starting position
+    pub start: Annotations,
+}
+
+impl MoveTree {
+    pub fn new() -> MoveTree {
+        MoveTree::default()
+    }
+
+    // the moves that were tried after the node at path. [] is the starting position.
+    pub fn children(&self, path: &[usize]) -> Option<&Vec<MoveNode>> {
+        let mut children = &self.roots;
+        for i in path {
+            children = &children.get(*i)?.children;
+        }
+        Some(children)
+    }
+
+    fn children_mut(&mut self, path: &[usize]) -> Option<&mut Vec<MoveNode>> {
+        let mut children = &mut self.roots;
+        for i in path {
+            children = &mut children.get_mut(*i)?.children;
+        }
+        Some(children)
+    }
+
+    pub fn node(&self, path: &[usize]) -> Option<&MoveNode> {
+        let (last, parent) = path.split_last()?;
+        self.children(parent)?.get(*last)
+    }
+
+    pub fn node_mut(&mut self, path: &[usize]) -> Option<&mut MoveNode> {
+        let (last, parent) = path.split_last()?;
+        self.children_mut(parent)?.get_mut(*last)
+    }
+
+    // annotations of the node at path, or of the starting position for []
+    pub fn annotations(&self, path: &[usize]) -> Option<&Annotations> {
+        match path.is_empty() {
+            true => Some(&self.start),
+            false => self.node(path).map(|n| &n.annotations),
+        }
+    }
+
+    pub fn annotations_mut(&mut self, path: &[usize]) -> Option<&mut Annotations> {
+        match path.is_empty() {
+            true => Some(&mut self.start),
+            false => self.node_mut(path).map(|n| &mut n.annotations),
+        }
+    }
+
+    pub fn contains(&self, path: &[usize]) -> bool {
+        self.children(path).is_some()
+    }
+
+    // the moves from the starting position to the node at path
+    pub fn moves_to(&self, path: &[usize]) -> Vec<Move> {
+        let mut moves = vec![];
+        let mut children = &self.roots;
+        for i in path {
+            moves.push(children[*i].the_move);
+            children = &children[*i].children;
+        }
+        moves
+    }
+
+    // the path extended with the main continuation until the end of the line
+    pub fn main_line_from(&self, path: &[usize]) -> Vec<usize> {
+        let mut res = path.to_vec();
+        let mut children = self.children(path).expect("path not in the tree");
+        while let Some(main) = children.first() {
+            res.push(0);
+            children = &main.children;
+        }
+        res
+    }
+
+    // Adds the move after the node at path, or finds it if it was tried already.
+    //   Returns its index among the children.
+    pub fn add_move(&mut self, path: &[usize], the_move: Move) -> usize {
+        let children = self.children_mut(path).expect("path not in the tree");
+        match children.iter().position(|c| c.the_move == the_move) {
+            Some(i) => i,
+            None => {
+                children.push(MoveNode::new(the_move));
+                children.len() - 1
+            }
+        }
+    }
+
+    // Makes the variation that the node at path is in the main continuation of
+    //   its parent. The variation is the deepest one in the path.
+    //   Returns the ply where the variation starts and its old index.
+    pub fn promote_variation(&mut self, path: &[usize]) -> Option<(usize, usize)> {
+        if !self.contains(path) {
+            return None;
+        }
+
+        let ply = path.iter().rposition(|i| *i != 0)?;
+        let index = path[ply];
+        let siblings = self.children_mut(&path[..ply])?;
+        let node = siblings.remove(index);
+        siblings.insert(0, node);
+
+        Some((ply, index))
+    }
+
+    // Removes the node at path and everything after it
+    pub fn delete(&mut self, path: &[usize]) -> Option<MoveNode> {
+        let (last, parent) = path.split_last()?;
+        let siblings = self.children_mut(parent)?;
+        if *last < siblings.len() {
+            Some(siblings.remove(*last))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+#[path = "./tests/move_tree_tests.rs"]
+mod move_tree_tests;
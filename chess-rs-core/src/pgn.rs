@@ -0,0 +1,638 @@
+// PGN import and export
+//
+// Variations go in parentheses after the move they replace:
+//  1. e4 e5 (1... c5 2. Nf3) 2. Nf3
+//  Comments and NAGs go to the move before them. Arrows and squares drawn on
+//  the board are kept in comments with the [%cal] and [%csl] commands:
+//  {[%csl Gd4][%cal Ge2e4,Rd7d5] text}
+//  The time left on the clock after a move is written as [%clk 0:04:58].
+//  The exporter writes the tags set on the game, and ends the movetext with
+//  the Result tag if there is one. The importer skips the tags other than FEN.
+
+use super::*;
+use crate::clock::{MoveTime, TimeBonus, TimeControl};
+use crate::move_tree::{Annotations, Mark, MarkColor, MoveNode};
+use std::time::Duration;
+
+const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+#[derive(Debug, PartialEq)]
+pub enum PgnError {
+    InvalidTag,
+    InvalidFen,
+    UnterminatedComment,
+    // a variation has to come after the move it replaces
+    VariationWithoutMove,
+    UnbalancedVariation,
+    IllegalMove(String),
+}
+
+impl fmt::Display for PgnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PgnError::InvalidTag => write!(f, "Invalid tag."),
+            PgnError::InvalidFen => write!(f, "The FEN tag is not a valid position."),
+            PgnError::UnterminatedComment => write!(f, "A comment is missing its closing brace."),
+            PgnError::VariationWithoutMove => {
+                write!(f, "A variation has to come after a move.")
+            }
+            PgnError::UnbalancedVariation => write!(f, "Unbalanced parentheses."),
+            PgnError::IllegalMove(san) => write!(f, "Illegal move: {}", san),
+        }
+    }
+}
+
+// A piece of the movetext, for showing the moves with their variations
+#[derive(Clone, Debug, PartialEq)]
+pub enum MovetextToken {
+    // "1. e4", "e5", or "1... e5" when Black's move needs its number.
+    //   path is where the move is in the move tree.
+    Move {
+        path: Vec<usize>,
+        text: String,
+        nags: Vec<u8>,
+    },
+    Comment {
+        text: String,
+        marks: Vec<Mark>,
+        // time left on the mover's clock
+        clock: Option<Duration>,
+    },
+    VariationStart,
+    VariationEnd,
+}
+
+// the glyph of the usual NAGs: 1 is "!", 4 is "??"
+pub fn nag_glyph(nag: u8) -> Option<&'static str> {
+    match nag {
+        1 => Some("!"),
+        2 => Some("?"),
+        3 => Some("!!"),
+        4 => Some("??"),
+        5 => Some("!?"),
+        6 => Some("?!"),
+        10 => Some("="),
+        13 => Some("∞"),
+        14 => Some("+="),
+        15 => Some("=+"),
+        16 => Some("+/-"),
+        17 => Some("-/+"),
+        18 => Some("+-"),
+        19 => Some("-+"),
+        _ => None,
+    }
+}
+
+fn glyph_nag(glyph: &str) -> Option<u8> {
+    (1..=19).find(|nag| nag_glyph(*nag) == Some(glyph))
+}
+
+fn comment_token(annotations: &Annotations, time: Option<MoveTime>) -> Option<MovetextToken> {
+    if annotations.comment.is_empty() && annotations.marks.is_empty() && time.is_none() {
+        return None;
+    }
+    Some(MovetextToken::Comment {
+        text: annotations.comment.clone(),
+        marks: annotations.marks.clone(),
+        clock: time.map(|t| t.remaining),
+    })
+}
+
+// the move in plain SAN, without the " e.p." of en passant captures
+fn move_text(board: &Board, the_move: Move, move_number: u32, needs_number: bool) -> String {
+    let san = board
+        .get_move_in_chess_notation(the_move)
+        .replace(" e.p.", "");
+    match board.whose_turn {
+        ChessTeam::White => format!("{}. {}", move_number, san),
+        ChessTeam::Black if needs_number => format!("{}... {}", move_number, san),
+        ChessTeam::Black => san,
+    }
+}
+
+// The line that starts with move [first] of children, followed by its main
+//   continuation. The variations of the main moves go right after them.
+fn push_line(
+    mut board: Board,
+    mut children: &[MoveNode],
+    mut path: Vec<usize>,
+    first: usize,
+    mut move_number: u32,
+    tokens: &mut Vec<MovetextToken>,
+) {
+    let mut index = first;
+    let mut needs_number = true;
+
+    while let Some(node) = children.get(index) {
+        path.push(index);
+        tokens.push(MovetextToken::Move {
+            path: path.clone(),
+            text: move_text(&board, node.the_move, move_number, needs_number),
+            nags: node.annotations.nags.clone(),
+        });
+        needs_number = false;
+
+        if let Some(comment) = comment_token(&node.annotations, node.time) {
+            tokens.push(comment);
+            needs_number = true;
+        }
+
+        // the first move of a variation had its alternatives written already
+        if index == 0 {
+            let parent = &path[..path.len() - 1];
+            for i in 1..children.len() {
+                tokens.push(MovetextToken::VariationStart);
+                push_line(
+                    board.clone(),
+                    children,
+                    parent.to_vec(),
+                    i,
+                    move_number,
+                    tokens,
+                );
+                tokens.push(MovetextToken::VariationEnd);
+                needs_number = true;
+            }
+        }
+
+        if board.whose_turn == ChessTeam::Black {
+            move_number += 1;
+        }
+        board.apply_move(node.the_move);
+        children = &node.children;
+        index = 0;
+    }
+}
+
+// every move of the game in PGN order
+pub fn movetext_tokens(game: &GameState) -> Vec<MovetextToken> {
+    let mut tokens: Vec<MovetextToken> = comment_token(&game.get_move_tree().start, None)
+        .into_iter()
+        .collect();
+    push_line(
+        game.get_board_at(0),
+        &game.get_move_tree().roots,
+        vec![],
+        0,
+        game.get_full_move_count_at(0),
+        &mut tokens,
+    );
+    tokens
+}
+
+pub fn write_movetext(tokens: &[MovetextToken]) -> String {
+    let mut res = String::new();
+    let mut after_start = true;
+
+    for token in tokens {
+        match token {
+            MovetextToken::Move { text, nags, .. } => {
+                if !after_start {
+                    res.push(' ');
+                }
+                res += text;
+                for nag in nags {
+                    res += &format!(" ${}", nag);
+                }
+                after_start = false;
+            }
+            MovetextToken::Comment { text, marks, clock } => {
+                if !after_start {
+                    res.push(' ');
+                }
+                res += &format!("{{{}}}", write_comment(text, marks, *clock));
+                after_start = false;
+            }
+            MovetextToken::VariationStart => {
+                if !res.is_empty() {
+                    res.push(' ');
+                }
+                res.push('(');
+                after_start = true;
+            }
+            MovetextToken::VariationEnd => {
+                res.push(')');
+                after_start = false;
+            }
+        }
+    }
+
+    res
+}
+
+fn write_tag(name: &str, value: &str) -> String {
+    let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("[{} \"{}\"]\n", name, value)
+}
+
+pub fn write_pgn(game: &GameState) -> String {
+    let mut start = game.clone();
+    start.go_to(0);
+    let fen = start.get_fen();
+
+    let mut tags: String = game
+        .get_tags()
+        .iter()
+        .map(|(name, value)| write_tag(name, value))
+        .collect();
+    if fen != START_FEN {
+        tags += &write_tag("SetUp", "1");
+        tags += &write_tag("FEN", &fen);
+    }
+
+    let mut movetext = write_movetext(&movetext_tokens(game));
+    if let Some(result) = game.get_tag("Result") {
+        if !movetext.is_empty() {
+            movetext.push(' ');
+        }
+        movetext += result;
+    }
+    if tags.is_empty() {
+        movetext
+    } else {
+        format!("{}\n{}", tags, movetext)
+    }
+}
+
+// The TimeControl tag: "40/5400:1800" for periods, "180+2" with an increment
+//   and "*60" for an hourglass. There's no standard for delays, so they get
+//   a d: "300d5"
+pub fn time_control_tag(control: &TimeControl) -> String {
+    match control {
+        TimeControl::Periods { periods, bonus } => {
+            let periods: Vec<String> = periods
+                .iter()
+                .map(|period| match period.moves {
+                    Some(moves) => format!("{}/{}", moves, period.time.as_secs()),
+                    None => period.time.as_secs().to_string(),
+                })
+                .collect();
+            let bonus = match bonus {
+                TimeBonus::None => String::new(),
+                TimeBonus::Fischer(time) => format!("+{}", time.as_secs()),
+                TimeBonus::Bronstein(time) | TimeBonus::Delay(time) => {
+                    format!("d{}", time.as_secs())
+                }
+            };
+            periods.join(":") + &bonus
+        }
+        TimeControl::Hourglass(time) => format!("*{}", time.as_secs()),
+    }
+}
+
+// The Date tag of a time in seconds since 1970: "2020.09.13". The days are
+//   turned into a date like in Howard Hinnant's civil_from_days
+pub fn pgn_date(secs: u64) -> String {
+    let days = (secs / 86400) as i64 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    //months from March
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{}.{:02}.{:02}", year, month, day)
+}
+
+// The first second of the date, the other way around. None if it isn't a
+//   date after 1970
+pub fn parse_pgn_date(date: &str) -> Option<u64> {
+    let parts: Vec<i64> = date
+        .split(['.', '-'])
+        .map(|part| part.parse().ok())
+        .collect::<Option<_>>()?;
+    let (year, month, day) = match parts[..] {
+        [year, month, day] => (year, month, day),
+        _ => return None,
+    };
+    if year < 1970 || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let month_index = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+    Some(days as u64 * 86400)
+}
+
+fn parse_tile(name: &str) -> Option<Tile> {
+    let mut chars = name.chars();
+    let file = chars.next()?;
+    let rank = chars.next()?;
+    if chars.next().is_some() || !('a'..='h').contains(&file) || !('1'..='8').contains(&rank) {
+        return None;
+    }
+    Tile::try_from(Coord {
+        x: file as i32 - 'a' as i32,
+        y: rank as i32 - '1' as i32,
+    })
+    .ok()
+}
+
+// h:mm:ss
+fn write_clock(time: Duration) -> String {
+    let secs = time.as_secs();
+    format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+// the comment's text with the marks and the clock as commands
+fn write_comment(text: &str, marks: &[Mark], clock: Option<Duration>) -> String {
+    let mut squares = vec![];
+    let mut arrows = vec![];
+    for mark in marks {
+        match mark {
+            Mark::Square { tile, color } => squares.push(format!("{}{}", color.letter(), tile)),
+            Mark::Arrow { from, to, color } => {
+                arrows.push(format!("{}{}{}", color.letter(), from, to))
+            }
+        }
+    }
+
+    let mut res = String::new();
+    if !squares.is_empty() {
+        res += &format!("[%csl {}]", squares.join(","));
+    }
+    if !arrows.is_empty() {
+        res += &format!("[%cal {}]", arrows.join(","));
+    }
+    if let Some(clock) = clock {
+        res += &format!("[%clk {}]", write_clock(clock));
+    }
+    if !res.is_empty() && !text.is_empty() {
+        res.push(' ');
+    }
+    res + text
+}
+
+// "Gd4" or "Ge2e4"
+fn parse_mark(arg: &str) -> Option<Mark> {
+    let color = MarkColor::from_letter(arg.chars().next()?)?;
+    let tiles = arg.get(1..)?;
+    match tiles.len() {
+        2 => Some(Mark::Square {
+            tile: parse_tile(tiles)?,
+            color,
+        }),
+        4 => Some(Mark::Arrow {
+            from: parse_tile(&tiles[..2])?,
+            to: parse_tile(&tiles[2..])?,
+            color,
+        }),
+        _ => None,
+    }
+}
+
+// Adds a comment to the annotations. [%cal] and [%csl] become marks, the
+//   other commands stay in the text.
+fn read_comment(body: &str, annotations: &mut Annotations) {
+    let mut text = String::new();
+    let mut rest = body;
+
+    while let Some(start) = rest.find("[%") {
+        let end = match rest[start..].find(']') {
+            Some(len) => start + len,
+            None => break,
+        };
+        let command = &rest[start + 2..end];
+        let (name, args) = command.split_at(command.find(' ').unwrap_or(command.len()));
+
+        let marks: Option<Vec<Mark>> = match name {
+            "cal" | "csl" => args.split(',').map(|arg| parse_mark(arg.trim())).collect(),
+            _ => None,
+        };
+
+        match marks {
+            Some(marks) => {
+                annotations.marks.extend(marks);
+                text += &rest[..start];
+            }
+            None => text += &rest[..end + 1],
+        }
+        rest = &rest[end + 1..];
+    }
+    text += rest;
+
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if text.is_empty() {
+        return;
+    }
+    if !annotations.comment.is_empty() {
+        annotations.comment.push(' ');
+    }
+    annotations.comment += &text;
+}
+
+// [Name "Value"] -> (Name, Value). chars[i] is the '['.
+//   Returns the index after the ']' too.
+fn read_tag(chars: &[char], mut i: usize) -> Result<(String, String, usize), PgnError> {
+    i += 1;
+    let mut name = String::new();
+    while i < chars.len() && !chars[i].is_whitespace() && chars[i] != '"' && chars[i] != ']' {
+        name.push(chars[i]);
+        i += 1;
+    }
+    while i < chars.len() && chars[i].is_whitespace() {
+        i += 1;
+    }
+    if name.is_empty() || chars.get(i) != Some(&'"') {
+        return Err(PgnError::InvalidTag);
+    }
+    i += 1;
+
+    let mut value = String::new();
+    loop {
+        match chars.get(i) {
+            Some('\\') => {
+                value.push(*chars.get(i + 1).ok_or(PgnError::InvalidTag)?);
+                i += 2;
+            }
+            Some('"') => break,
+            Some(c) => {
+                value.push(*c);
+                i += 1;
+            }
+            None => return Err(PgnError::InvalidTag),
+        }
+    }
+    i += 1;
+
+    while i < chars.len() && chars[i].is_whitespace() {
+        i += 1;
+    }
+    if chars.get(i) != Some(&']') {
+        return Err(PgnError::InvalidTag);
+    }
+
+    Ok((name, value, i + 1))
+}
+
+// The comment that starts at i, and the index after it
+fn comment_at(chars: &[char], i: usize) -> Result<(String, usize), PgnError> {
+    let (end, next) = match chars[i] {
+        '{' => match chars[i..].iter().position(|c| *c == '}') {
+            Some(len) => (i + len, i + len + 1),
+            None => return Err(PgnError::UnterminatedComment),
+        },
+        // ; comments go to the end of the line
+        _ => match chars[i..].iter().position(|c| *c == '\n') {
+            Some(len) => (i + len, i + len + 1),
+            None => (chars.len(), chars.len()),
+        },
+    };
+    Ok((chars[i + 1..end].iter().collect(), next))
+}
+
+fn is_token_end(c: char) -> bool {
+    c.is_whitespace() || "(){};[$".contains(c)
+}
+
+// the move in a movetext token without its number, and its annotation glyph
+fn token_san(token: &str) -> (&str, &str) {
+    let san = if token.starts_with(|c: char| c.is_ascii_digit()) {
+        token
+            .trim_start_matches(|c: char| c.is_ascii_digit())
+            .trim_start_matches('.')
+    } else {
+        token
+    };
+    let without_glyph = san.trim_end_matches(['!', '?']);
+    (without_glyph, &san[without_glyph.len()..])
+}
+
+// Reads the first game in the PGN
+pub fn parse_pgn(pgn: &str) -> Result<GameState, PgnError> {
+    let chars: Vec<char> = pgn.chars().collect();
+    let mut i = 0;
+
+    //Tags
+    let mut fen = None;
+    let mut start = Annotations::default();
+    while i < chars.len() {
+        match chars[i] {
+            c if c.is_whitespace() => i += 1,
+            '{' | ';' => {
+                let (body, next) = comment_at(&chars, i)?;
+                read_comment(&body, &mut start);
+                i = next;
+            }
+            '[' => {
+                let (name, value, next) = read_tag(&chars, i)?;
+                if name == "FEN" {
+                    fen = Some(value);
+                }
+                i = next;
+            }
+            _ => break,
+        }
+    }
+
+    let mut game = match fen {
+        Some(fen) => parse_fen(fen).ok_or(PgnError::InvalidFen)?,
+        None => GameState::init(),
+    };
+    game.tree.start = start;
+
+    //Movetext
+    // where to go back to at the end of each variation
+    let mut variations: Vec<(Vec<usize>, usize)> = vec![];
+    while i < chars.len() {
+        match chars[i] {
+            c if c.is_whitespace() => i += 1,
+            '{' | ';' => {
+                let (body, next) = comment_at(&chars, i)?;
+                let path = game.get_cursor_path().to_vec();
+                read_comment(&body, game.tree.annotations_mut(&path).unwrap());
+                i = next;
+            }
+            // the next game
+            '[' => break,
+            '(' => {
+                variations.push((game.line.clone(), game.cursor));
+                if !game.go_back() {
+                    return Err(PgnError::VariationWithoutMove);
+                }
+                i += 1;
+            }
+            ')' => {
+                let (line, cursor) = variations.pop().ok_or(PgnError::UnbalancedVariation)?;
+                game.line = line;
+                game.cursor = cursor;
+                game.sync_cursor();
+                i += 1;
+            }
+            '$' => {
+                let start = i + 1;
+                i += 1;
+                while i < chars.len() && chars[i].is_ascii_digit() {
+                    i += 1;
+                }
+                let nag: String = chars[start..i].iter().collect();
+                if let Ok(nag) = nag.parse() {
+                    let path = game.get_cursor_path().to_vec();
+                    game.tree.annotations_mut(&path).unwrap().nags.push(nag);
+                }
+            }
+            _ => {
+                let start = i;
+                while i < chars.len() && !is_token_end(chars[i]) {
+                    i += 1;
+                }
+                let token: String = chars[start..i].iter().collect();
+
+                //game result
+                if ["1-0", "0-1", "1/2-1/2", "*"].contains(&token.as_str()) {
+                    break;
+                }
+                //some write en passant captures as "exd6 e.p."
+                if token == "e.p." {
+                    continue;
+                }
+
+                //some write castling with zeros
+                let token = if token.starts_with("0-0") {
+                    token.replace('0', "O")
+                } else {
+                    token
+                };
+
+                let (san, glyph) = token_san(&token);
+                if !san.is_empty() {
+                    let the_move = move_processor::parse_move(san.to_string(), &mut game)
+                        .map_err(|_| PgnError::IllegalMove(san.to_string()))?;
+                    game.perform_move(the_move)
+                        .map_err(|_| PgnError::IllegalMove(san.to_string()))?;
+                }
+
+                if let Some(nag) = glyph_nag(glyph) {
+                    let path = game.get_cursor_path().to_vec();
+                    game.tree.annotations_mut(&path).unwrap().nags.push(nag);
+                }
+            }
+        }
+    }
+
+    if !variations.is_empty() {
+        return Err(PgnError::UnbalancedVariation);
+    }
+
+    //looking at the end of the main line
+    let main_line = game.tree.main_line_from(&[]);
+    game.go_to_path(&main_line);
+
+    Ok(game)
+}
+
+#[cfg(test)]
+#[path = "./tests/pgn_tests.rs"]
+mod pgn_tests;
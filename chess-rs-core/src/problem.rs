@@ -0,0 +1,450 @@
+// Chess problem solving
+//
+// Finds every key of a composed problem by exhaustive search, with the full
+//  solution tree, so composers can check that a problem is sound:
+//  - a cook is a second key. A sound problem has exactly one.
+//  - a dual is a position in the solution where the attacker has more than one
+//    move that works.
+//
+// Stipulations:
+//  - #n: direct mate. The attacker mates in n moves against any defense.
+//  - s#n: selfmate. The attacker forces the defender to mate it in n moves.
+//  - h#n: helpmate. Both teams cooperate so the team moving first gets mated
+//    on the n-th move of the other one. Every solution is a line, and a
+//    problem with more than one line is cooked.
+//
+// The team whose turn it is in the starting position moves first. That's the
+//  attacker in direct mates and selfmates (White in almost every problem) and
+//  the team that gets mated in helpmates (Black).
+
+use super::*;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Stipulation {
+    // #n: the attacker mates in n moves or less against any defense
+    DirectMate(u32),
+    // s#n: the attacker forces the defender to mate it in n moves or less
+    Selfmate(u32),
+    // h#n: the team moving first helps the other one mate it in exactly n moves
+    Helpmate(u32),
+}
+
+impl Stipulation {
+    // "#2" (or just "2"), "s#2", "h#2"
+    pub fn parse(s: &str) -> Option<Stipulation> {
+        let (kind, n): (fn(u32) -> Stipulation, &str) = if let Some(n) = s.strip_prefix("s#") {
+            (Stipulation::Selfmate, n)
+        } else if let Some(n) = s.strip_prefix("h#") {
+            (Stipulation::Helpmate, n)
+        } else {
+            (Stipulation::DirectMate, s.strip_prefix('#').unwrap_or(s))
+        };
+
+        let n: u32 = n.parse().ok()?;
+        if n == 0 {
+            return None;
+        }
+        Some(kind(n))
+    }
+}
+
+impl fmt::Display for Stipulation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stipulation::DirectMate(n) => write!(f, "#{}", n),
+            Stipulation::Selfmate(n) => write!(f, "s#{}", n),
+            Stipulation::Helpmate(n) => write!(f, "h#{}", n),
+        }
+    }
+}
+
+// A move of the solution and everything that follows it
+#[derive(Clone, Debug, PartialEq)]
+pub struct SolutionNode {
+    pub the_move: Move,
+    // the move in algebraic notation
+    pub notation: String,
+    // after an attacker move: every defense.
+    //   after a defense: every attacker move that still works.
+    //   In helpmates: every move of the other team that leads to a solution.
+    pub children: Vec<SolutionNode>,
+    // attacker moves that would work if the defender could pass.
+    //   Only filled in for keys.
+    pub threats: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProblemSolution {
+    pub stipulation: Stipulation,
+    pub moves_first: ChessTeam,
+    pub keys: Vec<SolutionNode>,
+}
+
+impl ProblemSolution {
+    pub fn is_cooked(&self) -> bool {
+        match self.stipulation {
+            Stipulation::Helpmate(_) => self.lines().len() > 1,
+            _ => self.keys.len() > 1,
+        }
+    }
+
+    // Every line of the solution tree, from the first move to the mate
+    pub fn lines(&self) -> Vec<Vec<String>> {
+        fn find_lines(node: &SolutionNode, line: &mut Vec<String>, res: &mut Vec<Vec<String>>) {
+            line.push(node.notation.clone());
+
+            if node.children.is_empty() {
+                res.push(line.clone());
+            }
+            for child in &node.children {
+                find_lines(child, line, res);
+            }
+
+            line.pop();
+        }
+
+        let mut res = vec![];
+        for key in &self.keys {
+            find_lines(key, &mut vec![], &mut res);
+        }
+        res
+    }
+
+    // One key and no duals. A helpmate needs exactly one line, as it has no
+    //   duals
+    pub fn is_sound(&self) -> bool {
+        match self.stipulation {
+            Stipulation::Helpmate(_) => self.lines().len() == 1,
+            _ => self.keys.len() == 1 && self.duals().is_empty(),
+        }
+    }
+
+    // Every line that ends in a position where the attacker has more than one
+    //   move that works. The line starts with the key.
+    //   Helpmates don't have duals, a second move is a second solution.
+    pub fn duals(&self) -> Vec<Vec<String>> {
+        if let Stipulation::Helpmate(_) = self.stipulation {
+            return vec![];
+        }
+
+        fn find_duals(
+            node: &SolutionNode,
+            is_defense: bool,
+            line: &mut Vec<String>,
+            res: &mut Vec<Vec<String>>,
+        ) {
+            line.push(node.notation.clone());
+
+            // the children of a defense are attacker moves
+            if is_defense && node.children.len() > 1 {
+                res.push(line.clone());
+            }
+
+            for child in &node.children {
+                find_duals(child, !is_defense, line, res);
+            }
+
+            line.pop();
+        }
+
+        let mut res = vec![];
+        for key in &self.keys {
+            find_duals(key, false, &mut vec![], &mut res);
+        }
+        res
+    }
+}
+
+// Writes the solution in problem notation, one line per variation. e.g.
+//
+// 1.Qd7! (2.Qc7#)
+//   1...Kb8 2.Qc7#
+//   1...Ka8 2.Qc8#
+//
+// Helpmates are written one solution per line, each move number with the move
+//   of the team moving first and the answer. e.g. 1.Kb8 Rh8#
+impl fmt::Display for ProblemSolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn write_attack(
+            f: &mut fmt::Formatter<'_>,
+            node: &SolutionNode,
+            move_number: u32,
+            indent: usize,
+        ) -> fmt::Result {
+            for defense in &node.children {
+                write!(
+                    f,
+                    "\n{:indent$}{}...{}",
+                    "",
+                    move_number,
+                    defense.notation,
+                    indent = indent
+                )?;
+
+                match defense.children.len() {
+                    0 => {}
+                    1 => {
+                        let reply = &defense.children[0];
+                        write!(f, " {}.{}", move_number + 1, reply.notation)?;
+                        write_attack(f, reply, move_number + 1, indent + 2)?;
+                    }
+                    _ => {
+                        for reply in &defense.children {
+                            write!(
+                                f,
+                                "\n{:indent$}{}.{} (dual)",
+                                "",
+                                move_number + 1,
+                                reply.notation,
+                                indent = indent + 2
+                            )?;
+                            write_attack(f, reply, move_number + 1, indent + 4)?;
+                        }
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        if self.keys.is_empty() {
+            return write!(f, "{}: no solution", self.stipulation);
+        }
+
+        if let Stipulation::Helpmate(_) = self.stipulation {
+            let lines: Vec<String> = self
+                .lines()
+                .iter()
+                .map(|line| {
+                    let moves: Vec<String> = line
+                        .chunks(2)
+                        .enumerate()
+                        .map(|(i, pair)| format!("{}.{}", i + 1, pair.join(" ")))
+                        .collect();
+                    moves.join(" ")
+                })
+                .collect();
+            return write!(f, "{}", lines.join("\n"));
+        }
+
+        for (i, key) in self.keys.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+
+            write!(f, "1.{}!", key.notation)?;
+            if !key.threats.is_empty() {
+                let threats: Vec<String> = key.threats.iter().map(|t| format!("2.{}", t)).collect();
+                write!(f, " ({})", threats.join(", "))?;
+            }
+            write_attack(f, key, 1, 2)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn is_checkmate(board: &Board, ep_square: Option<Tile>) -> bool {
+    board.is_team_in_check(board.whose_turn, ep_square)
+        && board.get_all_legal_moves(ep_square).is_empty()
+}
+
+fn make_move(board: &Board, the_move: Move) -> (Board, Option<Tile>) {
+    let mut future_board = board.clone();
+    future_board.apply_move(the_move);
+    (future_board, the_move.get_en_passant_square())
+}
+
+// The team to move wins in n moves or less against any defense: it mates,
+//   or it gets mated in a selfmate
+fn attack_works(board: &Board, ep_square: Option<Tile>, n: u32, selfmate: bool) -> bool {
+    if n == 0 {
+        return false;
+    }
+
+    board
+        .get_all_legal_moves(ep_square)
+        .into_iter()
+        .any(|the_move| {
+            let (board, ep_square) = make_move(board, the_move);
+            defense_fails(&board, ep_square, n - 1, selfmate)
+        })
+}
+
+// Whatever the team to move does, it gets mated (or in a selfmate, it has to
+//   mate) now or in n attacker moves or less
+fn defense_fails(board: &Board, ep_square: Option<Tile>, n: u32, selfmate: bool) -> bool {
+    let defenses = board.get_all_legal_moves(ep_square);
+
+    if defenses.is_empty() {
+        return !selfmate && board.is_team_in_check(board.whose_turn, ep_square);
+    }
+
+    defenses.into_iter().all(|defense| {
+        let (board, ep_square) = make_move(board, defense);
+        (selfmate && is_checkmate(&board, ep_square))
+            || attack_works(&board, ep_square, n, selfmate)
+    })
+}
+
+// every attacker move that wins in n moves or less, with their variations
+fn attack_moves(
+    board: &Board,
+    ep_square: Option<Tile>,
+    n: u32,
+    selfmate: bool,
+) -> Vec<SolutionNode> {
+    let mut res = vec![];
+
+    if n == 0 {
+        return res;
+    }
+
+    for the_move in board.get_all_legal_moves(ep_square) {
+        let (future_board, future_ep) = make_move(board, the_move);
+        if !defense_fails(&future_board, future_ep, n - 1, selfmate) {
+            continue;
+        }
+
+        let mut children = vec![];
+        if !is_checkmate(&future_board, future_ep) {
+            for defense in future_board.get_all_legal_moves(future_ep) {
+                let (defense_board, defense_ep) = make_move(&future_board, defense);
+                let replies = if is_checkmate(&defense_board, defense_ep) {
+                    vec![]
+                } else {
+                    attack_moves(&defense_board, defense_ep, n - 1, selfmate)
+                };
+
+                children.push(SolutionNode {
+                    the_move: defense,
+                    notation: future_board.get_move_in_chess_notation(defense),
+                    children: replies,
+                    threats: vec![],
+                });
+            }
+        }
+
+        res.push(SolutionNode {
+            the_move,
+            notation: board.get_move_in_chess_notation(the_move),
+            children,
+            threats: vec![],
+        });
+    }
+
+    res
+}
+
+// Every move of the team to move that lets the other team mate it with its
+//   n-th move from now, with the rest of the solution
+fn help_moves(board: &Board, ep_square: Option<Tile>, n: u32) -> Vec<SolutionNode> {
+    let mut res = vec![];
+
+    for the_move in board.get_all_legal_moves(ep_square) {
+        let (future_board, future_ep) = make_move(board, the_move);
+
+        let mut answers = vec![];
+        for answer in future_board.get_all_legal_moves(future_ep) {
+            let (answer_board, answer_ep) = make_move(&future_board, answer);
+
+            // the mate has to come on the last move, not before
+            let is_mate = is_checkmate(&answer_board, answer_ep);
+            let children = if n == 1 || is_mate {
+                vec![]
+            } else {
+                help_moves(&answer_board, answer_ep, n - 1)
+            };
+
+            if (n == 1 && is_mate) || !children.is_empty() {
+                answers.push(SolutionNode {
+                    the_move: answer,
+                    notation: future_board.get_move_in_chess_notation(answer),
+                    children,
+                    threats: vec![],
+                });
+            }
+        }
+
+        if !answers.is_empty() {
+            res.push(SolutionNode {
+                the_move,
+                notation: board.get_move_in_chess_notation(the_move),
+                children: answers,
+                threats: vec![],
+            });
+        }
+    }
+
+    res
+}
+
+// What the attacker would do after the key if the defender could pass
+fn threats(board: &Board, n: u32) -> Vec<String> {
+    if n == 0 || board.is_team_in_check(board.whose_turn, None) {
+        return vec![];
+    }
+
+    let mut passed = board.clone();
+    passed.whose_turn = board.whose_turn.the_other_one();
+
+    passed
+        .get_all_legal_moves(None)
+        .into_iter()
+        .filter(|the_move| {
+            let (future_board, future_ep) = make_move(&passed, *the_move);
+            defense_fails(&future_board, future_ep, n - 1, false)
+        })
+        .map(|the_move| passed.get_move_in_chess_notation(the_move))
+        .collect()
+}
+
+// Solves the problem for the team whose turn it is
+pub fn solve(board: &Board, ep_square: Option<Tile>, stipulation: Stipulation) -> ProblemSolution {
+    let keys = match stipulation {
+        Stipulation::DirectMate(n) => {
+            let mut keys = attack_moves(board, ep_square, n, false);
+            for key in keys.iter_mut() {
+                let (future_board, _) = make_move(board, key.the_move);
+                key.threats = threats(&future_board, n - 1);
+            }
+            keys
+        }
+        Stipulation::Selfmate(n) => attack_moves(board, ep_square, n, true),
+        Stipulation::Helpmate(n) => help_moves(board, ep_square, n),
+    };
+
+    ProblemSolution {
+        stipulation,
+        moves_first: board.whose_turn,
+        keys,
+    }
+}
+
+// Solves the problem in the current position of the game
+pub fn solve_game(game: &mut GameState, stipulation: Stipulation) -> ProblemSolution {
+    let ep_square = game.en_passant_square;
+    solve(game.get_board(), ep_square, stipulation)
+}
+
+// Solves the problem in a FEN. None if the FEN is invalid or the position
+//   is not legal
+pub fn solve_fen(fen: &str, stipulation: Stipulation) -> Option<ProblemSolution> {
+    let mut game = parse_fen(fen.to_string())?;
+    let ep_square = game.en_passant_square;
+    let board = game.get_board();
+
+    for team in [ChessTeam::White, ChessTeam::Black] {
+        if board.find_pieces(team, ChessPiece::King).len() != 1 {
+            return None;
+        }
+    }
+    if board.is_team_in_check(board.whose_turn.the_other_one(), ep_square) {
+        return None;
+    }
+
+    Some(solve(board, ep_square, stipulation))
+}
+
+#[cfg(test)]
+#[path = "./tests/problem_tests.rs"]
+mod problem_tests;
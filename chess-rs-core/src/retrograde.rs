@@ -0,0 +1,1297 @@
+// Retrograde analysis of small endgames
+//
+// Builds complete distance to mate tables for material like KQvK, KRvK, KBNvK or KPvK.
+//  Starting from every checkmate, the generator walks the moves backwards
+//  ("unmoves") one halfmove at a time until every position that can be won
+//  or lost has its distance to mate. The rest are draws.
+//
+// The generator has its own small move generator that works on piece squares
+//  instead of a Board, so it also works as an independent check of the real one.
+//
+// Positions with castling rights or an en passant square are not stored.
+//  En passant is handled when probing by looking one move ahead.
+
+use super::*;
+use crate::tablebase::{self, Tablebase, TablebaseError, Wdl};
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+// The generator indexes every placement of the pieces, 64^pieces per team
+//   to move, so more than 4 pieces would not fit in memory.
+pub const MAX_PIECES: usize = 4;
+
+const FILE_MAGIC: [u8; 4] = *b"CRTB";
+const FILE_VERSION: u8 = 1;
+pub const FILE_EXTENSION: &str = "dtm";
+
+// Table values. Every other value is the distance to mate in halfmoves + 1.
+//  Odd distances are wins for the team to move (it gives the mate),
+//  even distances are losses (0 means it is checkmated right now).
+const DRAW: u8 = 0;
+const ILLEGAL: u8 = 255;
+
+fn value_from_distance(plies: u32) -> u8 {
+    (plies + 1) as u8
+}
+
+fn distance_from_value(value: u8) -> u32 {
+    value as u32 - 1
+}
+
+fn is_win(value: u8) -> bool {
+    value != DRAW && value != ILLEGAL && distance_from_value(value) % 2 == 1
+}
+
+fn is_loss(value: u8) -> bool {
+    value != DRAW && value != ILLEGAL && !is_win(value)
+}
+
+// The value of the position before a move, given the value after it
+fn value_before_move(value_after: u8) -> u8 {
+    match value_after {
+        DRAW | ILLEGAL => value_after,
+        _ => value_from_distance(distance_from_value(value_after) + 1),
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum GeneratorError {
+    // not a material like "KQvK"
+    InvalidMaterial(String),
+    // more than MAX_PIECES pieces
+    TooManyPieces(String),
+}
+
+impl fmt::Display for GeneratorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GeneratorError::InvalidMaterial(key) => write!(f, "Invalid material: {}", key),
+            GeneratorError::TooManyPieces(key) => write!(
+                f,
+                "{} has too many pieces. The generator supports up to {}.",
+                key, MAX_PIECES
+            ),
+        }
+    }
+}
+
+fn piece_value(piece: ChessPiece) -> u32 {
+    match piece {
+        ChessPiece::Pawn => 1,
+        ChessPiece::Knight | ChessPiece::Bishop => 3,
+        ChessPiece::Rook => 5,
+        ChessPiece::Queen => 9,
+        ChessPiece::King => 0,
+    }
+}
+
+fn piece_from_char(c: char) -> Option<ChessPiece> {
+    match c {
+        'K' => Some(ChessPiece::King),
+        'Q' => Some(ChessPiece::Queen),
+        'R' => Some(ChessPiece::Rook),
+        'B' => Some(ChessPiece::Bishop),
+        'N' => Some(ChessPiece::Knight),
+        'P' => Some(ChessPiece::Pawn),
+        _ => None,
+    }
+}
+
+fn piece_to_char(piece: ChessPiece) -> char {
+    match piece {
+        ChessPiece::King => 'K',
+        ChessPiece::Queen => 'Q',
+        ChessPiece::Rook => 'R',
+        ChessPiece::Bishop => 'B',
+        ChessPiece::Knight => 'N',
+        ChessPiece::Pawn => 'P',
+    }
+}
+
+// order of the pieces in a material key
+fn piece_order(piece: ChessPiece) -> usize {
+    match piece {
+        ChessPiece::King => 0,
+        ChessPiece::Queen => 1,
+        ChessPiece::Rook => 2,
+        ChessPiece::Bishop => 3,
+        ChessPiece::Knight => 4,
+        ChessPiece::Pawn => 5,
+    }
+}
+
+// The pieces of a table, white ones first. The kings are always the first
+//   piece of each team.
+#[derive(Clone, Debug, PartialEq)]
+struct Material {
+    pieces: Vec<(ChessTeam, ChessPiece)>,
+}
+
+impl Material {
+    fn parse(key: &str) -> Result<Material, GeneratorError> {
+        let err = || GeneratorError::InvalidMaterial(key.to_string());
+
+        let mut split = key.split('v');
+        let (white, black) = match (split.next(), split.next(), split.next()) {
+            (Some(w), Some(b), None) => (w, b),
+            _ => return Err(err()),
+        };
+
+        let mut pieces = vec![];
+
+        for (team, side) in [(ChessTeam::White, white), (ChessTeam::Black, black)] {
+            let mut side_pieces = vec![];
+            for c in side.chars() {
+                side_pieces.push(piece_from_char(c).ok_or_else(err)?);
+            }
+
+            if side_pieces
+                .iter()
+                .filter(|p| **p == ChessPiece::King)
+                .count()
+                != 1
+            {
+                return Err(err());
+            }
+
+            side_pieces.sort_by_key(|p| piece_order(*p));
+            pieces.extend(side_pieces.into_iter().map(|p| (team, p)));
+        }
+
+        if pieces.len() > MAX_PIECES {
+            return Err(GeneratorError::TooManyPieces(key.to_string()));
+        }
+
+        Ok(Material { pieces })
+    }
+
+    fn from_pieces(mut pieces: Vec<(ChessTeam, ChessPiece)>) -> Material {
+        pieces.sort_by_key(|(team, piece)| (*team == ChessTeam::Black, piece_order(*piece)));
+        Material { pieces }
+    }
+
+    fn key(&self) -> String {
+        let mut res = String::new();
+        for team in [ChessTeam::White, ChessTeam::Black] {
+            if team == ChessTeam::Black {
+                res.push('v');
+            }
+            for (t, piece) in &self.pieces {
+                if *t == team {
+                    res.push(piece_to_char(*piece));
+                }
+            }
+        }
+        res
+    }
+
+    fn strength(&self, team: ChessTeam) -> u32 {
+        self.pieces
+            .iter()
+            .filter(|(t, _)| *t == team)
+            .map(|(_, p)| piece_value(*p))
+            .sum()
+    }
+
+    // Tables are only built for one colour of each material: the one where
+    //   White is the stronger team. The other one is probed with the board flipped.
+    fn is_canonical(&self) -> bool {
+        let white = self.strength(ChessTeam::White);
+        let black = self.strength(ChessTeam::Black);
+
+        if white != black {
+            return white > black;
+        }
+
+        let key = self.key();
+        key >= tablebase::flip_material_key(&key)
+    }
+
+    fn flipped(&self) -> Material {
+        Material::from_pieces(
+            self.pieces
+                .iter()
+                .map(|(team, piece)| (team.the_other_one(), *piece))
+                .collect(),
+        )
+    }
+
+    fn table_size(&self) -> usize {
+        2 * 64usize.pow(self.pieces.len() as u32)
+    }
+
+    // every material that a capture or a promotion can lead to
+    fn successors(&self) -> Vec<Material> {
+        let mut res = vec![];
+
+        for (i, (team, piece)) in self.pieces.iter().enumerate() {
+            if *piece == ChessPiece::King {
+                continue;
+            }
+
+            let mut pieces = self.pieces.clone();
+            pieces.remove(i);
+            res.push(Material::from_pieces(pieces));
+
+            if *piece == ChessPiece::Pawn {
+                for promotion in [
+                    ChessPiece::Queen,
+                    ChessPiece::Rook,
+                    ChessPiece::Bishop,
+                    ChessPiece::Knight,
+                ] {
+                    let mut pieces = self.pieces.clone();
+                    pieces[i] = (*team, promotion);
+                    res.push(Material::from_pieces(pieces));
+                }
+            }
+        }
+
+        res
+    }
+}
+
+// Squares go from 0 (a1) to 63 (h8), rank by rank.
+fn square_from_tile(tile: Tile) -> u8 {
+    let coord = Coord::from(tile);
+    (coord.y * 8 + coord.x) as u8
+}
+
+fn tile_from_square(square: u8) -> Tile {
+    Tile::try_from(Coord {
+        x: (square % 8) as i32,
+        y: (square / 8) as i32,
+    })
+    .unwrap()
+}
+
+// same square seen from the other side of the board
+fn flip_square(square: u8) -> u8 {
+    square ^ 56
+}
+
+// 1 mirrors the files, 2 the ranks, 4 swaps files and ranks
+fn transform_square(square: u8, symmetry: u8) -> u8 {
+    let (mut x, mut y) = (square % 8, square / 8);
+    if symmetry & 1 != 0 {
+        x = 7 - x;
+    }
+    if symmetry & 2 != 0 {
+        y = 7 - y;
+    }
+    if symmetry & 4 != 0 {
+        std::mem::swap(&mut x, &mut y);
+    }
+    y * 8 + x
+}
+
+fn square_offset(square: u8, dx: i32, dy: i32) -> Option<u8> {
+    let x = (square % 8) as i32 + dx;
+    let y = (square / 8) as i32 + dy;
+    if (0..8).contains(&x) && (0..8).contains(&y) {
+        Some((y * 8 + x) as u8)
+    } else {
+        None
+    }
+}
+
+const KNIGHT_OFFSETS: [(i32, i32); 8] = [
+    (1, 2),
+    (2, 1),
+    (2, -1),
+    (1, -2),
+    (-1, -2),
+    (-2, -1),
+    (-2, 1),
+    (-1, 2),
+];
+const KING_OFFSETS: [(i32, i32); 8] = [
+    (1, 0),
+    (1, 1),
+    (0, 1),
+    (-1, 1),
+    (-1, 0),
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+];
+const ROOK_DIRECTIONS: [(i32, i32); 4] = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+const BISHOP_DIRECTIONS: [(i32, i32); 4] = [(1, 1), (-1, 1), (-1, -1), (1, -1)];
+
+fn slider_directions(piece: ChessPiece) -> &'static [(i32, i32)] {
+    match piece {
+        ChessPiece::Rook => &ROOK_DIRECTIONS,
+        ChessPiece::Bishop => &BISHOP_DIRECTIONS,
+        ChessPiece::Queen => &KING_OFFSETS,
+        _ => &[],
+    }
+}
+
+fn pawn_direction(team: ChessTeam) -> i32 {
+    match team {
+        ChessTeam::White => 1,
+        ChessTeam::Black => -1,
+    }
+}
+
+// A position of a table: the square of each piece of the material
+//  (in the same order) and whose turn it is.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Position {
+    squares: [u8; MAX_PIECES],
+    whose_turn: ChessTeam,
+}
+
+// where a legal move of the generator leads
+enum MoveResult {
+    // to another position of the same table
+    Internal(usize),
+    // out of the table (capture or promotion). Value of the new position
+    Exit(u8),
+}
+
+struct TableView<'a> {
+    material: &'a Material,
+}
+
+impl<'a> TableView<'a> {
+    fn len(&self) -> usize {
+        self.material.pieces.len()
+    }
+
+    fn team(&self, slot: usize) -> ChessTeam {
+        self.material.pieces[slot].0
+    }
+
+    fn piece(&self, slot: usize) -> ChessPiece {
+        self.material.pieces[slot].1
+    }
+
+    fn index(&self, pos: &Position) -> usize {
+        let mut index = 0;
+        for i in (0..self.len()).rev() {
+            index = index * 64 + pos.squares[i] as usize;
+        }
+        if pos.whose_turn == ChessTeam::Black {
+            index += 64usize.pow(self.len() as u32);
+        }
+        index
+    }
+
+    fn position(&self, mut index: usize) -> Position {
+        let mut squares = [0; MAX_PIECES];
+        for square in squares.iter_mut().take(self.len()) {
+            *square = (index % 64) as u8;
+            index /= 64;
+        }
+        Position {
+            squares,
+            whose_turn: if index == 0 {
+                ChessTeam::White
+            } else {
+                ChessTeam::Black
+            },
+        }
+    }
+
+    // The board can be mirrored and rotated without changing the result.
+    //   Pawns only allow mirroring the files.
+    fn symmetries(&self) -> u8 {
+        if self
+            .material
+            .pieces
+            .iter()
+            .any(|(_, p)| *p == ChessPiece::Pawn)
+        {
+            2
+        } else {
+            8
+        }
+    }
+
+    // smallest index of the position over all its symmetries
+    fn canonical_index(&self, pos: &Position) -> usize {
+        (0..self.symmetries())
+            .map(|symmetry| {
+                let mut mirrored = *pos;
+                for square in mirrored.squares.iter_mut().take(self.len()) {
+                    *square = transform_square(*square, symmetry);
+                }
+                self.index(&mirrored)
+            })
+            .min()
+            .unwrap()
+    }
+
+    fn slot_at(&self, pos: &Position, square: u8, captured: Option<usize>) -> Option<usize> {
+        (0..self.len()).find(|i| pos.squares[*i] == square && Some(*i) != captured)
+    }
+
+    fn king_slot(&self, team: ChessTeam) -> usize {
+        (0..self.len())
+            .find(|i| self.material.pieces[*i] == (team, ChessPiece::King))
+            .unwrap()
+    }
+
+    // can the piece in slot capture on target? (captured is a piece that is not
+    //   on the board anymore)
+    fn attacks(&self, pos: &Position, slot: usize, target: u8, captured: Option<usize>) -> bool {
+        let from = pos.squares[slot];
+        let dx = (target % 8) as i32 - (from % 8) as i32;
+        let dy = (target / 8) as i32 - (from / 8) as i32;
+
+        match self.piece(slot) {
+            ChessPiece::Pawn => dx.abs() == 1 && dy == pawn_direction(self.team(slot)),
+            ChessPiece::Knight => {
+                (dx.abs() == 1 && dy.abs() == 2) || (dx.abs() == 2 && dy.abs() == 1)
+            }
+            ChessPiece::King => dx.abs() <= 1 && dy.abs() <= 1 && (dx, dy) != (0, 0),
+            piece => {
+                let is_line = dx == 0 || dy == 0;
+                let is_diagonal = dx.abs() == dy.abs();
+                let fits = match piece {
+                    ChessPiece::Rook => is_line,
+                    ChessPiece::Bishop => is_diagonal,
+                    _ => is_line || is_diagonal,
+                };
+                if !fits || (dx, dy) == (0, 0) {
+                    return false;
+                }
+
+                // nothing in between
+                let steps = std::cmp::max(dx.abs(), dy.abs());
+                (1..steps).all(|i| {
+                    let square = square_offset(from, dx.signum() * i, dy.signum() * i).unwrap();
+                    self.slot_at(pos, square, captured).is_none()
+                })
+            }
+        }
+    }
+
+    fn is_attacked_by(
+        &self,
+        pos: &Position,
+        target: u8,
+        team: ChessTeam,
+        captured: Option<usize>,
+    ) -> bool {
+        (0..self.len()).any(|slot| {
+            Some(slot) != captured
+                && self.team(slot) == team
+                && self.attacks(pos, slot, target, captured)
+        })
+    }
+
+    fn is_in_check(&self, pos: &Position, team: ChessTeam, captured: Option<usize>) -> bool {
+        let king = pos.squares[self.king_slot(team)];
+        self.is_attacked_by(pos, king, team.the_other_one(), captured)
+    }
+
+    fn is_legal(&self, pos: &Position) -> bool {
+        for i in 0..self.len() {
+            for j in 0..i {
+                if pos.squares[i] == pos.squares[j] {
+                    return false;
+                }
+            }
+
+            let rank = pos.squares[i] / 8;
+            if self.piece(i) == ChessPiece::Pawn && (rank == 0 || rank == 7) {
+                return false;
+            }
+        }
+
+        // the team that just moved can't be in check
+        !self.is_in_check(pos, pos.whose_turn.the_other_one(), None)
+    }
+
+    // destinations of the piece in slot, as (square, captured slot).
+    //  The king of the team moving may be left in check.
+    fn pseudo_moves(&self, pos: &Position, slot: usize) -> Vec<(u8, Option<usize>)> {
+        let team = self.team(slot);
+        let from = pos.squares[slot];
+        let mut res = vec![];
+
+        let add = |square: u8, res: &mut Vec<(u8, Option<usize>)>| -> bool {
+            match self.slot_at(pos, square, None) {
+                None => {
+                    res.push((square, None));
+                    true
+                }
+                Some(other) => {
+                    if self.team(other) != team {
+                        res.push((square, Some(other)));
+                    }
+                    false
+                }
+            }
+        };
+
+        match self.piece(slot) {
+            ChessPiece::Pawn => {
+                let dir = pawn_direction(team);
+                if let Some(square) = square_offset(from, 0, dir) {
+                    if self.slot_at(pos, square, None).is_none() {
+                        res.push((square, None));
+
+                        let start_rank = if team == ChessTeam::White { 1 } else { 6 };
+                        if from / 8 == start_rank {
+                            let square = square_offset(from, 0, 2 * dir).unwrap();
+                            if self.slot_at(pos, square, None).is_none() {
+                                res.push((square, None));
+                            }
+                        }
+                    }
+                }
+                for dx in [-1, 1] {
+                    if let Some(square) = square_offset(from, dx, dir) {
+                        if let Some(other) = self.slot_at(pos, square, None) {
+                            if self.team(other) != team {
+                                res.push((square, Some(other)));
+                            }
+                        }
+                    }
+                }
+            }
+            ChessPiece::Knight | ChessPiece::King => {
+                let offsets = if self.piece(slot) == ChessPiece::Knight {
+                    &KNIGHT_OFFSETS
+                } else {
+                    &KING_OFFSETS
+                };
+                for (dx, dy) in offsets.iter() {
+                    if let Some(square) = square_offset(from, *dx, *dy) {
+                        add(square, &mut res);
+                    }
+                }
+            }
+            piece => {
+                for (dx, dy) in slider_directions(piece) {
+                    let mut square = from;
+                    while let Some(next) = square_offset(square, *dx, *dy) {
+                        square = next;
+                        if !add(square, &mut res) {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+
+        res
+    }
+
+    // squares the piece in slot could have come from with a move that was not
+    //   a capture or a promotion
+    fn unmoves(&self, pos: &Position, slot: usize) -> Vec<u8> {
+        let from = pos.squares[slot];
+        let mut res = vec![];
+
+        match self.piece(slot) {
+            ChessPiece::Pawn => {
+                let dir = -pawn_direction(self.team(slot));
+                let (first_rank, double_rank) = match self.team(slot) {
+                    ChessTeam::White => (1, 3),
+                    ChessTeam::Black => (6, 4),
+                };
+
+                if let Some(square) = square_offset(from, 0, dir) {
+                    let rank = square / 8;
+                    if rank != 0 && rank != 7 && self.slot_at(pos, square, None).is_none() {
+                        res.push(square);
+
+                        if from / 8 == double_rank {
+                            let square = square_offset(from, 0, 2 * dir).unwrap();
+                            if square / 8 == first_rank && self.slot_at(pos, square, None).is_none()
+                            {
+                                res.push(square);
+                            }
+                        }
+                    }
+                }
+            }
+            ChessPiece::Knight | ChessPiece::King => {
+                let offsets = if self.piece(slot) == ChessPiece::Knight {
+                    &KNIGHT_OFFSETS
+                } else {
+                    &KING_OFFSETS
+                };
+                for (dx, dy) in offsets.iter() {
+                    if let Some(square) = square_offset(from, *dx, *dy) {
+                        if self.slot_at(pos, square, None).is_none() {
+                            res.push(square);
+                        }
+                    }
+                }
+            }
+            piece => {
+                for (dx, dy) in slider_directions(piece) {
+                    let mut square = from;
+                    while let Some(next) = square_offset(square, *dx, *dy) {
+                        if self.slot_at(pos, next, None).is_some() {
+                            break;
+                        }
+                        square = next;
+                        res.push(square);
+                    }
+                }
+            }
+        }
+
+        res
+    }
+}
+
+// A distance to mate table for one material
+pub struct DtmTable {
+    material: Material,
+    values: Vec<u8>,
+}
+
+impl DtmTable {
+    // e.g. "KQvK"
+    pub fn key(&self) -> String {
+        self.material.key()
+    }
+
+    pub fn piece_count(&self) -> usize {
+        self.material.pieces.len()
+    }
+
+    // longest win in the table, in halfmoves
+    pub fn longest_mate(&self) -> u32 {
+        self.values
+            .iter()
+            .filter(|v| is_win(**v))
+            .map(|v| distance_from_value(*v))
+            .max()
+            .unwrap_or(0)
+    }
+
+    // Writes the table. Only legal positions that can't be mirrored into a
+    //   smaller index are written, the reader works out the rest. Without pawns
+    //   that leaves about one position in 8.
+    //
+    // magic "CRTB", version, key length, key, number of values (u64, little endian),
+    //   then one byte per value
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let key = self.key();
+        let view = TableView {
+            material: &self.material,
+        };
+
+        let mut buf = vec![];
+        for (index, value) in self.values.iter().enumerate() {
+            if *value == ILLEGAL {
+                continue;
+            }
+            if view.canonical_index(&view.position(index)) == index {
+                buf.push(*value);
+            }
+        }
+
+        w.write_all(&FILE_MAGIC)?;
+        w.write_all(&[FILE_VERSION, key.len() as u8])?;
+        w.write_all(key.as_bytes())?;
+        w.write_all(&(buf.len() as u64).to_le_bytes())?;
+        w.write_all(&buf)
+    }
+
+    pub fn read<R: Read>(r: &mut R) -> io::Result<DtmTable> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+        let mut header = [0u8; 6];
+        r.read_exact(&mut header)?;
+        if header[0..4] != FILE_MAGIC {
+            return Err(invalid("not a chess-rs tablebase"));
+        }
+        if header[4] != FILE_VERSION {
+            return Err(invalid("unsupported tablebase version"));
+        }
+
+        let mut key = vec![0u8; header[5] as usize];
+        r.read_exact(&mut key)?;
+        let key = String::from_utf8(key).map_err(|_| invalid("invalid material"))?;
+        let material = Material::parse(&key).map_err(|e| invalid(&e.to_string()))?;
+
+        let mut len = [0u8; 8];
+        r.read_exact(&mut len)?;
+        let len = u64::from_le_bytes(len) as usize;
+
+        let mut data = vec![];
+        r.read_to_end(&mut data)?;
+        if data.len() != len {
+            return Err(invalid("wrong table size"));
+        }
+
+        let view = TableView {
+            material: &material,
+        };
+        let mut values = vec![ILLEGAL; material.table_size()];
+        let mut bytes = data.iter();
+
+        // the values come in index order, and a mirrored position always has a
+        //   bigger index than the one that was written
+        for index in 0..values.len() {
+            let pos = view.position(index);
+            if !view.is_legal(&pos) {
+                continue;
+            }
+
+            let canonical = view.canonical_index(&pos);
+            values[index] = if canonical == index {
+                *bytes.next().ok_or_else(|| invalid("truncated table"))?
+            } else {
+                values[canonical]
+            };
+        }
+
+        if bytes.next().is_some() {
+            return Err(invalid("table is too long"));
+        }
+
+        Ok(DtmTable { material, values })
+    }
+}
+
+// The result of a position according to the tables
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Solution {
+    // for the team whose turn it is
+    pub wdl: Wdl,
+    // halfmoves until mate. Positive if the team whose turn it is mates,
+    //   negative if it gets mated, 0 for draws.
+    pub dtm: i32,
+    // the move that mates the fastest, or that resists the longest.
+    //   None if there are no legal moves.
+    pub best_move: Option<Move>,
+}
+
+// A set of tables, generated or read from files
+#[derive(Default)]
+pub struct DtmTables {
+    tables: HashMap<String, DtmTable>,
+}
+
+impl DtmTables {
+    pub fn new() -> DtmTables {
+        DtmTables {
+            tables: HashMap::new(),
+        }
+    }
+
+    // Reads every table in dir
+    pub fn open<P: AsRef<Path>>(dir: P) -> io::Result<DtmTables> {
+        let mut tables = DtmTables::new();
+
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(FILE_EXTENSION) {
+                continue;
+            }
+
+            let mut file = io::BufReader::new(fs::File::open(&path)?);
+            let table = DtmTable::read(&mut file)?;
+            tables.insert(table);
+        }
+
+        Ok(tables)
+    }
+
+    // Writes every table in dir, one file per material (e.g. KQvK.dtm)
+    pub fn save<P: AsRef<Path>>(&self, dir: P) -> io::Result<()> {
+        fs::create_dir_all(dir.as_ref())?;
+
+        for (key, table) in &self.tables {
+            let path = dir.as_ref().join(format!("{}.{}", key, FILE_EXTENSION));
+            let mut file = io::BufWriter::new(fs::File::create(path)?);
+            table.write(&mut file)?;
+            file.flush()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn insert(&mut self, table: DtmTable) {
+        self.tables.insert(table.key(), table);
+    }
+
+    pub fn get(&self, key: &str) -> Option<&DtmTable> {
+        self.tables.get(key)
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = self.tables.keys().cloned().collect();
+        keys.sort();
+        keys
+    }
+
+    // Generates the table for the material (e.g. "KQvK"), and the tables for
+    //   everything it can turn into with captures and promotions.
+    //   Materials are always generated with White as the stronger team.
+    pub fn generate(&mut self, key: &str) -> Result<(), GeneratorError> {
+        let mut material = Material::parse(key)?;
+        if !material.is_canonical() {
+            material = material.flipped();
+        }
+        self.generate_material(&material);
+        Ok(())
+    }
+
+    fn generate_material(&mut self, material: &Material) {
+        if material.pieces.len() <= 2 || self.tables.contains_key(&material.key()) {
+            return;
+        }
+
+        for successor in material.successors() {
+            let successor = if successor.is_canonical() {
+                successor
+            } else {
+                successor.flipped()
+            };
+            self.generate_material(&successor);
+        }
+
+        let table = Generator::new(material, self).run();
+        self.insert(table);
+    }
+
+    // value of a position given as (team, piece, square) for every piece
+    fn lookup(
+        &self,
+        pieces: &[(ChessTeam, ChessPiece, u8)],
+        whose_turn: ChessTeam,
+    ) -> Result<u8, TablebaseError> {
+        if pieces.len() == 2 {
+            return Ok(DRAW);
+        }
+
+        let material = Material::from_pieces(pieces.iter().map(|(t, p, _)| (*t, *p)).collect());
+
+        // the table has the colours the other way around: flip the board
+        let flip = !material.is_canonical();
+        let (material, whose_turn) = if flip {
+            (material.flipped(), whose_turn.the_other_one())
+        } else {
+            (material, whose_turn)
+        };
+
+        let key = material.key();
+        let table = self
+            .tables
+            .get(&key)
+            .ok_or(TablebaseError::MissingTable(key))?;
+
+        let view = TableView {
+            material: &table.material,
+        };
+
+        let mut pos = Position {
+            squares: [0; MAX_PIECES],
+            whose_turn,
+        };
+        let mut used = [false; MAX_PIECES];
+
+        for (slot, (team, piece)) in table.material.pieces.iter().enumerate() {
+            let i = (0..pieces.len())
+                .find(|i| {
+                    let (t, p, _) = pieces[*i];
+                    let t = if flip { t.the_other_one() } else { t };
+                    !used[*i] && t == *team && p == *piece
+                })
+                .unwrap();
+            used[i] = true;
+
+            pos.squares[slot] = if flip {
+                flip_square(pieces[i].2)
+            } else {
+                pieces[i].2
+            };
+        }
+
+        Ok(table.values[view.index(&pos)])
+    }
+
+    fn lookup_board(&self, board: &Board) -> Result<u8, TablebaseError> {
+        if board.castling_rights != (false, false, false, false) {
+            return Err(TablebaseError::CastlingRights);
+        }
+
+        let pieces: Vec<(ChessTeam, ChessPiece, u8)> = board
+            .find_all_pieces()
+            .into_iter()
+            .map(|(p, tile)| (p.0, p.1, square_from_tile(tile)))
+            .collect();
+
+        if pieces.len() > MAX_PIECES {
+            return Err(TablebaseError::TooManyPieces(pieces.len()));
+        }
+
+        self.lookup(&pieces, board.whose_turn)
+    }
+
+    // value of every legal move (for the team making it)
+    fn rate_moves(
+        &self,
+        board: &Board,
+        ep_square: Option<Tile>,
+    ) -> Result<Vec<(Move, u8)>, TablebaseError> {
+        let mut res = vec![];
+
+        for the_move in board.get_all_legal_moves(ep_square) {
+            let mut future_board = board.clone();
+            future_board.apply_move(the_move);
+            let value = self.probe_value(&future_board, the_move.get_en_passant_square())?;
+            res.push((the_move, value_before_move(value)));
+        }
+
+        Ok(res)
+    }
+
+    fn probe_value(&self, board: &Board, ep_square: Option<Tile>) -> Result<u8, TablebaseError> {
+        let moves = board.get_all_legal_moves(ep_square);
+
+        // the tables don't know about en passant, look one move ahead instead
+        let has_en_passant = moves.iter().any(|m| {
+            matches!(
+                m,
+                Move::PieceMove {
+                    is_en_passant: true,
+                    ..
+                }
+            )
+        });
+
+        if !has_en_passant {
+            return self.lookup_board(board);
+        }
+
+        Ok(best_value(
+            self.rate_moves(board, ep_square)?.iter().map(|(_, v)| *v),
+        ))
+    }
+
+    // Solves the position with the tables
+    pub fn solve(&self, board: &Board) -> Result<Solution, TablebaseError> {
+        self.solve_with_ep(board, None)
+    }
+
+    pub fn solve_with_ep(
+        &self,
+        board: &Board,
+        ep_square: Option<Tile>,
+    ) -> Result<Solution, TablebaseError> {
+        if board.castling_rights != (false, false, false, false) {
+            return Err(TablebaseError::CastlingRights);
+        }
+
+        let moves = self.rate_moves(board, ep_square)?;
+
+        let value = if moves.is_empty() {
+            self.lookup_board(board)?
+        } else {
+            best_value(moves.iter().map(|(_, v)| *v))
+        };
+
+        let best_move = moves.iter().find(|(_, v)| *v == value).map(|(m, _)| *m);
+
+        Ok(Solution {
+            wdl: wdl_from_value(value),
+            dtm: signed_distance(value),
+            best_move,
+        })
+    }
+}
+
+// the best of the values a team can choose from
+fn best_value<I: Iterator<Item = u8>>(values: I) -> u8 {
+    values
+        .max_by_key(|v| {
+            if is_win(*v) {
+                // faster wins first
+                (2, -(distance_from_value(*v) as i64))
+            } else if is_loss(*v) {
+                // slower losses first
+                (0, distance_from_value(*v) as i64)
+            } else {
+                (1, 0)
+            }
+        })
+        .unwrap_or(DRAW)
+}
+
+fn wdl_from_value(value: u8) -> Wdl {
+    if is_win(value) {
+        Wdl::Win
+    } else if is_loss(value) {
+        Wdl::Loss
+    } else {
+        Wdl::Draw
+    }
+}
+
+fn signed_distance(value: u8) -> i32 {
+    if is_win(value) {
+        distance_from_value(value) as i32
+    } else if is_loss(value) {
+        -(distance_from_value(value) as i32)
+    } else {
+        0
+    }
+}
+
+// Distance to mate is never shorter than the distance to the next capture or
+//   pawn move, so these tables can stand in for Syzygy tables.
+impl Tablebase for DtmTables {
+    fn max_pieces(&self) -> usize {
+        self.tables
+            .values()
+            .map(|t| t.piece_count())
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn probe_wdl(&self, board: &Board, ep_square: Option<Tile>) -> Result<Wdl, TablebaseError> {
+        Ok(wdl_from_value(self.probe_value(board, ep_square)?))
+    }
+
+    fn probe_dtz(&self, board: &Board, ep_square: Option<Tile>) -> Result<i32, TablebaseError> {
+        Ok(signed_distance(self.probe_value(board, ep_square)?))
+    }
+}
+
+struct Generator<'a> {
+    view: TableView<'a>,
+    tables: &'a DtmTables,
+    values: Vec<u8>,
+    // legal moves that stay in the table and are not known to lose yet
+    moves_left: Vec<u8>,
+    // a move out of the table does not lose, so the position can't be lost
+    can_escape: Vec<bool>,
+    // longest loss through a move out of the table
+    longest_exit: Vec<u8>,
+    // already queued as a win
+    queued_win: Vec<bool>,
+    // queue[distance] holds the positions that get that distance
+    queue: Vec<Vec<usize>>,
+}
+
+impl<'a> Generator<'a> {
+    fn new(material: &'a Material, tables: &'a DtmTables) -> Generator<'a> {
+        let size = material.table_size();
+        Generator {
+            view: TableView { material },
+            tables,
+            values: vec![ILLEGAL; size],
+            moves_left: vec![0; size],
+            can_escape: vec![false; size],
+            longest_exit: vec![0; size],
+            queued_win: vec![false; size],
+            queue: vec![],
+        }
+    }
+
+    fn push(&mut self, index: usize, plies: u32) {
+        let plies = plies as usize;
+        if self.queue.len() <= plies {
+            self.queue.resize(plies + 1, vec![]);
+        }
+        self.queue[plies].push(index);
+    }
+
+    // value of the position after a capture or a promotion
+    fn exit_value(
+        &self,
+        pos: &Position,
+        captured: Option<usize>,
+        promoted: Option<ChessPiece>,
+    ) -> u8 {
+        let mut pieces = vec![];
+        for slot in 0..self.view.len() {
+            if Some(slot) == captured {
+                continue;
+            }
+            let rank = pos.squares[slot] / 8;
+            let piece = match promoted {
+                Some(promoted)
+                    if self.view.piece(slot) == ChessPiece::Pawn && (rank == 0 || rank == 7) =>
+                {
+                    promoted
+                }
+                _ => self.view.piece(slot),
+            };
+            pieces.push((self.view.team(slot), piece, pos.squares[slot]));
+        }
+
+        self.tables
+            .lookup(&pieces, pos.whose_turn)
+            .expect("tables for captures and promotions are generated first")
+    }
+
+    fn legal_moves(&self, pos: &Position) -> Vec<MoveResult> {
+        let team = pos.whose_turn;
+        let mut res = vec![];
+
+        for slot in 0..self.view.len() {
+            if self.view.team(slot) != team {
+                continue;
+            }
+
+            for (square, captured) in self.view.pseudo_moves(pos, slot) {
+                let mut future = *pos;
+                future.squares[slot] = square;
+                future.whose_turn = team.the_other_one();
+
+                if self.view.is_in_check(&future, team, captured) {
+                    continue;
+                }
+
+                let rank = square / 8;
+                let promotes =
+                    self.view.piece(slot) == ChessPiece::Pawn && (rank == 0 || rank == 7);
+
+                if promotes {
+                    for promotion in [
+                        ChessPiece::Queen,
+                        ChessPiece::Rook,
+                        ChessPiece::Bishop,
+                        ChessPiece::Knight,
+                    ] {
+                        res.push(MoveResult::Exit(self.exit_value(
+                            &future,
+                            captured,
+                            Some(promotion),
+                        )));
+                    }
+                } else if captured.is_some() {
+                    res.push(MoveResult::Exit(self.exit_value(&future, captured, None)));
+                } else {
+                    res.push(MoveResult::Internal(self.view.index(&future)));
+                }
+            }
+        }
+
+        res
+    }
+
+    fn run(mut self) -> DtmTable {
+        // 1. every legal position: count its moves, look out of the table
+        //    for captures and promotions, and find the checkmates
+        for index in 0..self.values.len() {
+            let pos = self.view.position(index);
+            if !self.view.is_legal(&pos) {
+                continue;
+            }
+            self.values[index] = DRAW;
+
+            let moves = self.legal_moves(&pos);
+
+            let mut internal = 0;
+            let mut fastest_exit_win: Option<u32> = None;
+            for m in &moves {
+                match m {
+                    MoveResult::Internal(_) => internal += 1,
+                    MoveResult::Exit(value) => {
+                        let value = value_before_move(*value);
+                        if is_win(value) {
+                            let plies = distance_from_value(value);
+                            fastest_exit_win =
+                                Some(fastest_exit_win.map_or(plies, |p| std::cmp::min(p, plies)));
+                        } else if is_loss(value) {
+                            let plies = distance_from_value(value) as u8;
+                            self.longest_exit[index] =
+                                std::cmp::max(self.longest_exit[index], plies);
+                        } else {
+                            self.can_escape[index] = true;
+                        }
+                    }
+                }
+            }
+            self.moves_left[index] = internal;
+
+            if moves.is_empty() {
+                if self.view.is_in_check(&pos, pos.whose_turn, None) {
+                    self.push(index, 0);
+                }
+            } else if let Some(plies) = fastest_exit_win {
+                self.queued_win[index] = true;
+                self.push(index, plies);
+            } else if internal == 0 && !self.can_escape[index] {
+                let plies = self.longest_exit[index] as u32;
+                self.push(index, plies);
+            }
+        }
+
+        // 2. go through the queue by distance. The first time a position comes
+        //    out of the queue it gets its value.
+        let mut plies = 0;
+        while plies < self.queue.len() {
+            let batch = std::mem::take(&mut self.queue[plies]);
+
+            for index in batch {
+                if self.values[index] != DRAW {
+                    continue;
+                }
+                self.values[index] = value_from_distance(plies as u32);
+
+                let pos = self.view.position(index);
+                let moved = pos.whose_turn.the_other_one();
+
+                for slot in 0..self.view.len() {
+                    if self.view.team(slot) != moved {
+                        continue;
+                    }
+
+                    for square in self.view.unmoves(&pos, slot) {
+                        let mut prev = pos;
+                        prev.squares[slot] = square;
+                        prev.whose_turn = moved;
+
+                        if !self.view.is_legal(&prev) {
+                            continue;
+                        }
+
+                        let prev_index = self.view.index(&prev);
+                        if self.values[prev_index] != DRAW {
+                            continue;
+                        }
+
+                        if plies % 2 == 0 {
+                            // this position is lost, so the one before it is won
+                            if !self.queued_win[prev_index] {
+                                self.queued_win[prev_index] = true;
+                                self.push(prev_index, plies as u32 + 1);
+                            }
+                        } else {
+                            // one more move of the position before it leads to a loss
+                            self.moves_left[prev_index] -= 1;
+                            if self.moves_left[prev_index] == 0
+                                && !self.can_escape[prev_index]
+                                && !self.queued_win[prev_index]
+                            {
+                                let longest = std::cmp::max(
+                                    plies as u32,
+                                    self.longest_exit[prev_index] as u32,
+                                );
+                                self.push(prev_index, longest + 1);
+                            }
+                        }
+                    }
+                }
+            }
+
+            plies += 1;
+        }
+
+        DtmTable {
+            material: self.view.material.clone(),
+            values: self.values,
+        }
+    }
+}
+
+#[cfg(test)]
+#[path = "./tests/retrograde_tests.rs"]
+mod retrograde_tests;
@@ -0,0 +1,310 @@
+// Post-game review
+//
+// Runs the engine on every position of a finished game. Each move is compared
+//  with the engine's best move: the centipawn loss is how much worse the
+//  position got for the team that moved, and it decides the move's label.
+//
+// Accuracy works like lichess: evaluations become winning chances (0 to 100),
+//  every move scores by how many winning chances it lost, and a team's accuracy
+//  is the average of its moves.
+
+use super::*;
+use crate::engine::{Engine, Score, SearchLimits};
+
+// mates count as this many centipawns, so losing a mate isn't worth more than
+//  throwing away a won game
+const MATE_CP: i32 = 1000;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MoveClass {
+    // the engine's choice
+    Best,
+    Good,
+    Inaccuracy,
+    Mistake,
+    Blunder,
+}
+
+impl MoveClass {
+    fn from_cp_loss(cp_loss: i32) -> MoveClass {
+        match cp_loss {
+            l if l < 50 => MoveClass::Good,
+            l if l < 100 => MoveClass::Inaccuracy,
+            l if l < 300 => MoveClass::Mistake,
+            _ => MoveClass::Blunder,
+        }
+    }
+
+    // PGN numeric annotation glyph: ?! ? ??
+    pub fn nag(self) -> Option<u8> {
+        match self {
+            MoveClass::Best | MoveClass::Good => None,
+            MoveClass::Inaccuracy => Some(6),
+            MoveClass::Mistake => Some(2),
+            MoveClass::Blunder => Some(4),
+        }
+    }
+}
+
+impl fmt::Display for MoveClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoveClass::Best => write!(f, "Best move"),
+            MoveClass::Good => write!(f, "Good move"),
+            MoveClass::Inaccuracy => write!(f, "Inaccuracy"),
+            MoveClass::Mistake => write!(f, "Mistake"),
+            MoveClass::Blunder => write!(f, "Blunder"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MoveReview {
+    pub the_move: Move,
+    pub notation: String,
+    pub team: ChessTeam,
+    // the engine's move, if it's not the one that was played
+    pub best_notation: Option<String>,
+    // White's point of view, before and after the move. Both come from
+    //   searching the position before the move, so they are comparable.
+    pub eval_before: Score,
+    pub eval_after: Score,
+    pub cp_loss: i32,
+    pub class: MoveClass,
+    // 0 to 100
+    pub accuracy: f32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GameReview {
+    pub moves: Vec<MoveReview>,
+    // evaluation of every position of the game from White's point of view,
+    //   one more than there are moves. None if the team to move is checkmated.
+    pub evals: Vec<Option<Score>>,
+    starting_move_number: u32,
+    starting_team: ChessTeam,
+}
+
+impl GameReview {
+    // average accuracy of a team's moves
+    pub fn accuracy(&self, team: ChessTeam) -> Option<f32> {
+        let accuracies: Vec<f32> = self
+            .moves
+            .iter()
+            .filter(|m| m.team == team)
+            .map(|m| m.accuracy)
+            .collect();
+
+        if accuracies.is_empty() {
+            None
+        } else {
+            Some(accuracies.iter().sum::<f32>() / accuracies.len() as f32)
+        }
+    }
+
+    pub fn count(&self, team: ChessTeam, class: MoveClass) -> usize {
+        self.moves
+            .iter()
+            .filter(|m| m.team == team && m.class == class)
+            .count()
+    }
+
+    // evaluations in centipawns from White's point of view, for graphs
+    pub fn eval_graph(&self) -> Vec<i32> {
+        self.evals
+            .iter()
+            .enumerate()
+            .map(|(i, eval)| match eval {
+                Some(score) => score_to_cp(*score),
+                // checkmated
+                None => match self.team_of_position(i) {
+                    ChessTeam::White => -MATE_CP,
+                    ChessTeam::Black => MATE_CP,
+                },
+            })
+            .collect()
+    }
+
+    fn team_of_position(&self, i: usize) -> ChessTeam {
+        match i % 2 {
+            0 => self.starting_team,
+            _ => self.starting_team.the_other_one(),
+        }
+    }
+
+    // The moves of the game in PGN, with NAGs on the inaccuracies, mistakes
+    //   and blunders and a comment saying what was best
+    pub fn to_pgn(&self) -> String {
+        let mut res = String::new();
+        let mut move_number = self.starting_move_number;
+        // after a comment Black's moves get their number again
+        let mut needs_number = true;
+
+        for review in &self.moves {
+            match review.team {
+                ChessTeam::White => res += &format!("{}. ", move_number),
+                ChessTeam::Black if needs_number => res += &format!("{}... ", move_number),
+                ChessTeam::Black => {}
+            }
+            if review.team == ChessTeam::Black {
+                move_number += 1;
+            }
+
+            res += &review.notation;
+            needs_number = false;
+
+            if let Some(nag) = review.class.nag() {
+                res += &format!(" ${}", nag);
+                res += &format!(
+                    " {{ ({} → {}) {}.",
+                    review.eval_before, review.eval_after, review.class
+                );
+                if let Some(best) = &review.best_notation {
+                    res += &format!(" {} was best.", best);
+                }
+                res += " }";
+                needs_number = true;
+            }
+
+            res += " ";
+        }
+
+        res.trim_end().to_string()
+    }
+}
+
+fn score_to_cp(score: Score) -> i32 {
+    match score {
+        Score::Cp(cp) => cp.clamp(-MATE_CP, MATE_CP),
+        Score::Mate(n) if n > 0 => MATE_CP,
+        Score::Mate(_) => -MATE_CP,
+        Score::Tablebase(wdl) => wdl.value().signum() * MATE_CP,
+    }
+}
+
+// chances of winning from 0 to 100, for the team the score is for
+fn win_percent(cp: i32) -> f32 {
+    50. + 50. * (2. / (1. + (-0.003_682_08 * cp as f32).exp()) - 1.)
+}
+
+fn move_accuracy(win_before: f32, win_after: f32) -> f32 {
+    let accuracy = 103.1668 * (-0.04354 * (win_before - win_after)).exp() - 3.1669;
+    accuracy.clamp(0., 100.)
+}
+
+fn review_move(
+    board: &Board,
+    the_move: Move,
+    best_move: Option<Move>,
+    best_score: Score,
+    played_score: Score,
+) -> MoveReview {
+    let best = score_to_cp(best_score);
+    let played = score_to_cp(played_score);
+    let cp_loss = std::cmp::max(0, best - played);
+
+    let is_best = best_move == Some(the_move);
+    let class = if is_best {
+        MoveClass::Best
+    } else {
+        MoveClass::from_cp_loss(cp_loss)
+    };
+
+    let white_pov = |score: Score| match board.whose_turn {
+        ChessTeam::White => score,
+        ChessTeam::Black => score.flip(),
+    };
+
+    MoveReview {
+        the_move,
+        notation: board.get_move_in_chess_notation(the_move),
+        team: board.whose_turn,
+        best_notation: match best_move {
+            Some(best_move) if !is_best => Some(board.get_move_in_chess_notation(best_move)),
+            _ => None,
+        },
+        eval_before: white_pov(best_score),
+        eval_after: white_pov(played_score),
+        cp_loss,
+        class,
+        accuracy: move_accuracy(win_percent(best), win_percent(played)),
+    }
+}
+
+// Reviews every move of the game. on_progress gets the number of positions
+//   searched and the total, and the review is abandoned if it returns false.
+pub fn review_game<F: FnMut(usize, usize) -> bool>(
+    game: &GameState,
+    engine: &Engine,
+    limits: &SearchLimits,
+    mut on_progress: F,
+) -> Option<GameReview> {
+    let limits = SearchLimits {
+        multi_pv: 1,
+        ..limits.clone()
+    };
+
+    let position_count = game.move_count() + 1;
+
+    // evaluation of every position, from White's point of view
+    let mut evals: Vec<Option<Score>> = vec![];
+    let mut moves = vec![];
+
+    for i in 0..position_count {
+        let board = game.get_board_at(i);
+        let ep_square = game.get_en_passant_square_at(i);
+        let info = engine.search(&board, ep_square, &limits, |_| {});
+
+        match info.lines.first() {
+            Some(line) if board.whose_turn == ChessTeam::White => evals.push(Some(line.score)),
+            Some(line) => evals.push(Some(line.score.flip())),
+            None if board.is_team_in_check(board.whose_turn, ep_square) => evals.push(None),
+            // stalemate
+            None => evals.push(Some(Score::Cp(0))),
+        }
+
+        if i < game.move_count() {
+            let the_move = game.get_move(i);
+            let best_move = info.best_move();
+            let best_score = info.lines[0].score;
+
+            // The played move is searched from the same position and to the
+            //   same depth as the best one. Comparing with the search of the
+            //   next position would mix in the swings between odd and even
+            //   depths.
+            let played_score = if best_move == Some(the_move) {
+                best_score
+            } else {
+                let played_limits = SearchLimits {
+                    search_moves: Some(vec![the_move]),
+                    ..limits.clone()
+                };
+                let played = engine.search(&board, ep_square, &played_limits, |_| {});
+                played.lines[0].score
+            };
+
+            moves.push(review_move(
+                &board,
+                the_move,
+                best_move,
+                best_score,
+                played_score,
+            ));
+        }
+
+        if !on_progress(i + 1, position_count) {
+            return None;
+        }
+    }
+
+    Some(GameReview {
+        moves,
+        evals,
+        starting_move_number: game.get_full_move_count_at(0),
+        starting_team: game.get_board_at(0).whose_turn,
+    })
+}
+
+#[cfg(test)]
+#[path = "./tests/review_tests.rs"]
+mod review_tests;
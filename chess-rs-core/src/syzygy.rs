@@ -0,0 +1,1038 @@
+// Reading Syzygy tables
+//
+// Syzygy tables come in two files per material: .rtbw has the win/draw/loss of
+//  every position and .rtbz the distance to zeroing (the next capture or pawn
+//  move). The positions are turned into an index using the symmetries of the
+//  board, and the values are Huffman coded in blocks. A sparse index tells
+//  roughly which block has a given index.
+//
+// This only reads what a table stores. The tables leave out what a short search
+//  can find (captures, en passant, one of the sides to move in .rtbz files),
+//  that is done by SyzygyTablebase in tablebase.rs.
+//
+// The layout follows the generator: https://github.com/syzygy1/tb
+
+use super::*;
+use crate::tablebase::{flip_material_key, TablebaseError, Wdl};
+
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+pub const WDL_MAGIC: [u8; 4] = [0x71, 0xe8, 0x23, 0x5d];
+pub const DTZ_MAGIC: [u8; 4] = [0xd7, 0x66, 0x0c, 0xa5];
+
+// layout of the whole file
+const SPLIT: u8 = 1;
+const HAS_PAWNS: u8 = 2;
+
+// flags of each subtable
+const STM: u8 = 1;
+const MAPPED: u8 = 2;
+const WIN_PLIES: u8 = 4;
+const LOSS_PLIES: u8 = 8;
+const WIDE_DTZ: u8 = 16;
+const SINGLE_VALUE: u8 = 128;
+
+const MAX_BLOCK_SIZE: u32 = 1024;
+
+// Squares go from 0 (a1) to 63 (h8), like in retrograde.rs
+
+// The a1-d1-d4 triangle, with the diagonal last
+#[rustfmt::skip]
+const TRIANGLE: [u64; 64] = [
+    6, 0, 1, 2, 2, 1, 0, 6,
+    0, 7, 3, 4, 4, 3, 7, 0,
+    1, 3, 8, 5, 5, 8, 3, 1,
+    2, 4, 5, 9, 9, 5, 4, 2,
+    2, 4, 5, 9, 9, 5, 4, 2,
+    1, 3, 8, 5, 5, 8, 3, 1,
+    0, 7, 3, 4, 4, 3, 7, 0,
+    6, 0, 1, 2, 2, 1, 0, 6,
+];
+
+// the square of each TRIANGLE value
+const INV_TRIANGLE: [u8; 10] = [1, 2, 3, 10, 11, 19, 0, 9, 18, 27];
+
+// The squares under the a1-h8 diagonal, with the diagonal last
+#[rustfmt::skip]
+const LOWER: [u64; 64] = [
+    28,  0,  1,  2,  3,  4,  5,  6,
+     0, 29,  7,  8,  9, 10, 11, 12,
+     1,  7, 30, 13, 14, 15, 16, 17,
+     2,  8, 13, 31, 18, 19, 20, 21,
+     3,  9, 14, 18, 32, 22, 23, 24,
+     4, 10, 15, 19, 22, 33, 25, 26,
+     5, 11, 16, 20, 23, 25, 34, 27,
+     6, 12, 17, 21, 24, 26, 27, 35,
+];
+
+fn file_of(square: u8) -> u8 {
+    square & 7
+}
+
+fn rank_of(square: u8) -> u8 {
+    square >> 3
+}
+
+fn flip_vertical(square: u8) -> u8 {
+    square ^ 56
+}
+
+fn flip_horizontal(square: u8) -> u8 {
+    square ^ 7
+}
+
+// mirrors the square on the a1-h8 diagonal
+fn flip_diagonal(square: u8) -> u8 {
+    ((square >> 3) | (square << 3)) & 63
+}
+
+fn square_from_tile(tile: Tile) -> u8 {
+    let coord = Coord::from(tile);
+    (coord.y * 8 + coord.x) as u8
+}
+
+fn is_off_diagonal(square: u8) -> bool {
+    file_of(square) != rank_of(square)
+}
+
+fn binomial(n: u64, k: u64) -> u64 {
+    if k > n {
+        return 0;
+    }
+    let k = std::cmp::min(k, n - k);
+    (0..k).fold(1, |res, i| res * (n - i) / (i + 1))
+}
+
+// Index of two kings that aren't next to each other. The first one is in the
+//  triangle, and if it is on the diagonal the second one is on or under it.
+//  Both kings on the diagonal go last. 462 in total.
+fn kings_index() -> &'static [[u16; 64]; 10] {
+    static INDEX: OnceLock<[[u16; 64]; 10]> = OnceLock::new();
+    INDEX.get_or_init(|| {
+        let mut index = [[u16::MAX; 64]; 10];
+        let mut both_on_diagonal = vec![];
+        let mut next = 0;
+
+        for (i, &king) in INV_TRIANGLE.iter().enumerate() {
+            for other in 0..64 {
+                let dx = (file_of(king) as i32 - file_of(other) as i32).abs();
+                let dy = (rank_of(king) as i32 - rank_of(other) as i32).abs();
+                if dx <= 1 && dy <= 1 {
+                    continue;
+                }
+
+                if !is_off_diagonal(king) {
+                    if rank_of(other) > file_of(other) {
+                        continue;
+                    }
+                    if !is_off_diagonal(other) {
+                        both_on_diagonal.push((i, other));
+                        continue;
+                    }
+                }
+
+                index[i][other as usize] = next;
+                next += 1;
+            }
+        }
+
+        for (i, other) in both_on_diagonal {
+            index[i][other as usize] = next;
+            next += 1;
+        }
+
+        index
+    })
+}
+
+// Pawns are indexed on the squares they can be on (ranks 2 to 7), with the
+//  leading pawn (the one that decides the subtable) in the files a to d.
+struct PawnIndex {
+    // squares from 47 down, a2 h2 b2 g2 ...
+    squares: [u64; 64],
+    // start of each leading pawn square, by number of leading pawns
+    lead: [[u64; 64]; 6],
+    // positions of the leading pawns in each file
+    lead_size: [[u64; 4]; 6],
+}
+
+fn pawn_index() -> &'static PawnIndex {
+    static INDEX: OnceLock<PawnIndex> = OnceLock::new();
+    INDEX.get_or_init(|| {
+        let mut index = PawnIndex {
+            squares: [0; 64],
+            lead: [[0; 64]; 6],
+            lead_size: [[0; 4]; 6],
+        };
+        let mut available = 48;
+
+        for count in 1..6 {
+            for file in 0..4 {
+                let mut idx = 0;
+                for rank in 1..7 {
+                    let square = file + 8 * rank;
+                    if count == 1 {
+                        available -= 1;
+                        index.squares[square] = available;
+                        available -= 1;
+                        index.squares[square ^ 7] = available;
+                    }
+                    index.lead[count][square] = idx;
+                    idx += binomial(index.squares[square], count as u64 - 1);
+                }
+                index.lead_size[count][file] = idx;
+            }
+        }
+
+        index
+    })
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TableKind {
+    Wdl,
+    Dtz,
+}
+
+// A piece as the table sees it. White is the team named first in the file name.
+type TablePiece = (ChessTeam, ChessPiece);
+
+fn piece_from_nibble(nibble: u8) -> Option<TablePiece> {
+    let team = if nibble & 8 == 0 {
+        ChessTeam::White
+    } else {
+        ChessTeam::Black
+    };
+    let piece = match nibble & 7 {
+        1 => ChessPiece::Pawn,
+        2 => ChessPiece::Knight,
+        3 => ChessPiece::Bishop,
+        4 => ChessPiece::Rook,
+        5 => ChessPiece::Queen,
+        6 => ChessPiece::King,
+        _ => return None,
+    };
+    Some((team, piece))
+}
+
+// "KQvK" for the pieces, like tablebase::material_key
+fn pieces_key(pieces: &[TablePiece]) -> String {
+    let side = |team| {
+        let mut res = String::new();
+        for (piece, c) in [
+            (ChessPiece::King, 'K'),
+            (ChessPiece::Queen, 'Q'),
+            (ChessPiece::Rook, 'R'),
+            (ChessPiece::Bishop, 'B'),
+            (ChessPiece::Knight, 'N'),
+            (ChessPiece::Pawn, 'P'),
+        ] {
+            for _ in pieces.iter().filter(|p| **p == (team, piece)) {
+                res.push(c);
+            }
+        }
+        res
+    };
+
+    side(ChessTeam::White) + "v" + &side(ChessTeam::Black)
+}
+
+// Reads parts of the file as they are needed. Tables can be big.
+struct TableFile {
+    path: PathBuf,
+    file: Mutex<fs::File>,
+}
+
+impl TableFile {
+    fn invalid(&self) -> TablebaseError {
+        TablebaseError::InvalidFile(self.path.display().to_string())
+    }
+
+    fn io_error(&self, e: io::Error) -> TablebaseError {
+        TablebaseError::InvalidFile(format!("{}: {}", self.path.display(), e))
+    }
+
+    // Past the end of the file reads zeros. The last block of a table can be
+    //  read a few bytes too far.
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<(), TablebaseError> {
+        let mut file = self.file.lock().unwrap();
+        file.seek(SeekFrom::Start(offset))
+            .map_err(|e| self.io_error(e))?;
+
+        let mut read = 0;
+        while read < buf.len() {
+            match file.read(&mut buf[read..]) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(self.io_error(e)),
+            }
+        }
+        buf[read..].iter_mut().for_each(|b| *b = 0);
+
+        Ok(())
+    }
+
+    fn u8_at(&self, offset: u64) -> Result<u8, TablebaseError> {
+        let mut buf = [0; 1];
+        self.read_at(offset, &mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn u16_at(&self, offset: u64) -> Result<u16, TablebaseError> {
+        let mut buf = [0; 2];
+        self.read_at(offset, &mut buf)?;
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    fn u32_at(&self, offset: u64) -> Result<u32, TablebaseError> {
+        let mut buf = [0; 4];
+        self.read_at(offset, &mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+}
+
+// How the pieces of a subtable are turned into an index. The pieces are split
+//  in groups (the leading pieces or pawns, then the pieces that are the same),
+//  and each group is a "digit" of the index.
+struct Groups {
+    pieces: Vec<TablePiece>,
+    lens: Vec<usize>,
+    factors: Vec<u64>,
+}
+
+impl Groups {
+    fn new(
+        pieces: Vec<TablePiece>,
+        order: [u8; 2],
+        file: usize,
+        unique_pieces: usize,
+    ) -> Option<Groups> {
+        let has_pawns = pieces.iter().any(|p| p.1 == ChessPiece::Pawn);
+        let both_have_pawns = pieces.contains(&(ChessTeam::White, ChessPiece::Pawn))
+            && pieces.contains(&(ChessTeam::Black, ChessPiece::Pawn));
+
+        // without pawns, 3 unique pieces go first, or the kings if there aren't
+        let first_len = if has_pawns {
+            0
+        } else if unique_pieces >= 3 {
+            3
+        } else if unique_pieces == 2 {
+            2
+        } else {
+            return None;
+        };
+
+        let mut lens = vec![];
+        if first_len > 0 {
+            lens.push(first_len);
+        }
+        for (i, piece) in pieces.iter().enumerate().skip(first_len) {
+            if i > first_len && pieces[i - 1] == *piece {
+                *lens.last_mut()? += 1;
+            } else {
+                lens.push(1);
+            }
+        }
+
+        let mut factors = vec![0; lens.len() + 1];
+        let mut free_squares = 64 - lens[0] - if both_have_pawns { lens[1] } else { 0 };
+        let mut next = if both_have_pawns { 2 } else { 1 };
+        let mut idx = 1;
+        let mut k = 0;
+
+        while next < lens.len() || k == order[0] || k == order[1] {
+            if k == order[0] {
+                factors[0] = idx;
+                idx *= if has_pawns {
+                    *pawn_index().lead_size.get(lens[0])?.get(file)?
+                } else if unique_pieces >= 3 {
+                    31_332
+                } else {
+                    462
+                };
+            } else if k == order[1] {
+                // the pawns of the other team
+                factors[1] = idx;
+                idx *= binomial(48 - lens[0] as u64, lens[1] as u64);
+            } else {
+                factors[next] = idx;
+                idx *= binomial(free_squares as u64, lens[next] as u64);
+                free_squares -= lens[next];
+                next += 1;
+            }
+            k += 1;
+        }
+        factors[lens.len()] = idx;
+
+        Some(Groups {
+            pieces,
+            lens,
+            factors,
+        })
+    }
+
+    fn table_size(&self) -> u64 {
+        self.factors[self.lens.len()]
+    }
+}
+
+// A node of the Huffman tree. A symbol stands for a run of len + 1 values
+struct Symbol {
+    left: u16,
+    right: u16,
+    len: u32,
+}
+
+impl Symbol {
+    fn is_leaf(&self) -> bool {
+        self.right == 0xfff
+    }
+}
+
+// DTZ values can be stored as an index into a list of the values that are used
+struct DtzMap {
+    offset: u64,
+    // where the list of each result starts: win, loss, cursed win, blessed loss
+    by_wdl: [u64; 4],
+    wide: bool,
+}
+
+// One subtable: a side to move, for one file of the leading pawn
+struct Subtable {
+    flags: u8,
+    groups: Groups,
+
+    block_size: u32,
+    span: u32,
+    blocks: u32,
+
+    min_symbol_len: u8,
+    lowest_symbol: Vec<u16>,
+    base: Vec<u64>,
+    symbols: Vec<Symbol>,
+
+    sparse_index: u64,
+    sparse_index_size: u64,
+    block_lengths: u64,
+    block_lengths_size: u64,
+    data: u64,
+
+    dtz_map: Option<DtzMap>,
+}
+
+impl Subtable {
+    // Reads the header at offset. Returns the subtable and where the next one starts.
+    fn read(
+        file: &TableFile,
+        offset: u64,
+        groups: Groups,
+        kind: TableKind,
+    ) -> Result<(Subtable, u64), TablebaseError> {
+        let flags = file.u8_at(offset)?;
+
+        let mut subtable = Subtable {
+            flags,
+            groups,
+            block_size: 0,
+            span: 0,
+            blocks: 0,
+            min_symbol_len: 0,
+            lowest_symbol: vec![],
+            base: vec![],
+            symbols: vec![],
+            sparse_index: 0,
+            sparse_index_size: 0,
+            block_lengths: 0,
+            block_lengths_size: 0,
+            data: 0,
+            dtz_map: None,
+        };
+
+        // every position has the same value. DTZ tables don't store it, it is 0
+        if flags & SINGLE_VALUE != 0 {
+            if kind == TableKind::Wdl {
+                subtable.min_symbol_len = file.u8_at(offset + 1)?;
+            }
+            return Ok((subtable, offset + 2));
+        }
+
+        let mut header = [0; 10];
+        file.read_at(offset, &mut header)?;
+
+        subtable.block_size = 1u32.checked_shl(header[1] as u32).unwrap_or(0);
+        subtable.span = 1u32.checked_shl(header[2] as u32).unwrap_or(0);
+        if subtable.block_size == 0 || subtable.block_size > MAX_BLOCK_SIZE || subtable.span == 0 {
+            return Err(file.invalid());
+        }
+
+        let padding = header[3];
+        subtable.blocks = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        subtable.sparse_index_size = subtable.groups.table_size().div_ceil(subtable.span as u64);
+        subtable.block_lengths_size = subtable.blocks as u64 + padding as u64;
+
+        let max_symbol_len = header[8];
+        let min_symbol_len = header[9];
+        if min_symbol_len == 0 || max_symbol_len > 32 || max_symbol_len < min_symbol_len {
+            return Err(file.invalid());
+        }
+        subtable.min_symbol_len = min_symbol_len;
+        let lengths = (max_symbol_len - min_symbol_len + 1) as usize;
+
+        for i in 0..lengths {
+            subtable
+                .lowest_symbol
+                .push(file.u16_at(offset + 10 + 2 * i as u64)?);
+        }
+
+        // canonical Huffman codes: base[i] is the smallest code of length
+        //   min_symbol_len + i, left aligned
+        let mut base = vec![0u64; lengths];
+        for i in (0..lengths - 1).rev() {
+            base[i] = (base[i + 1] + subtable.lowest_symbol[i] as u64)
+                .checked_sub(subtable.lowest_symbol[i + 1] as u64)
+                .ok_or_else(|| file.invalid())?
+                / 2;
+        }
+        for (i, b) in base.iter_mut().enumerate() {
+            *b <<= 64 - (min_symbol_len as u32 + i as u32);
+        }
+        subtable.base = base;
+
+        let mut offset = offset + 10 + 2 * lengths as u64;
+        let symbol_count = file.u16_at(offset)? as usize;
+        offset += 2;
+
+        let mut tree = vec![0; 3 * symbol_count];
+        file.read_at(offset, &mut tree)?;
+        subtable.symbols = read_symbols(&tree).ok_or_else(|| file.invalid())?;
+        offset += 3 * symbol_count as u64 + (symbol_count as u64 & 1);
+
+        Ok((subtable, offset))
+    }
+
+    // The value stored for idx
+    fn decompress(&self, file: &TableFile, idx: u64) -> Result<u16, TablebaseError> {
+        if self.flags & SINGLE_VALUE != 0 {
+            return Ok(self.min_symbol_len as u16);
+        }
+
+        // the sparse index points to a value near idx
+        let entry = idx / self.span as u64;
+        if entry >= self.sparse_index_size {
+            return Err(file.invalid());
+        }
+        let mut block = file.u32_at(self.sparse_index + 6 * entry)?;
+        let mut lit_idx = (idx % self.span as u64) as i64 - self.span as i64 / 2
+            + file.u16_at(self.sparse_index + 6 * entry + 4)? as i64;
+
+        // then the block lengths tell how many values each block has
+        let block_length = |block: u32| -> Result<i64, TablebaseError> {
+            if block as u64 >= self.block_lengths_size {
+                return Err(file.invalid());
+            }
+            Ok(file.u16_at(self.block_lengths + 2 * block as u64)? as i64 + 1)
+        };
+        while lit_idx < 0 {
+            block = block.checked_sub(1).ok_or_else(|| file.invalid())?;
+            lit_idx += block_length(block)?;
+        }
+        loop {
+            let len = block_length(block)?;
+            if lit_idx < len {
+                break;
+            }
+            lit_idx -= len;
+            block += 1;
+        }
+
+        // 4 more bytes, to refill the bit buffer after the last symbol
+        let mut data = vec![0; self.block_size as usize + 4];
+        file.read_at(self.data + block as u64 * self.block_size as u64, &mut data)?;
+        let mut next_byte = 8;
+        let mut buf = u64::from_be_bytes([
+            data[0], data[1], data[2], data[3], data[4], data[5], data[6], data[7],
+        ]);
+        let mut buf_size = 64;
+
+        // find the symbol that has the value at lit_idx
+        let mut symbol = loop {
+            let mut len = 0;
+            while buf < self.base[len] {
+                len += 1;
+                if len == self.base.len() {
+                    return Err(file.invalid());
+                }
+            }
+
+            let symbol = ((buf - self.base[len]) >> (64 - len - self.min_symbol_len as usize))
+                as usize
+                + self.lowest_symbol[len] as usize;
+            let symbol = self.symbols.get(symbol).ok_or_else(|| file.invalid())?;
+
+            if lit_idx <= symbol.len as i64 {
+                break symbol;
+            }
+            lit_idx -= symbol.len as i64 + 1;
+
+            let len = len + self.min_symbol_len as usize;
+            buf <<= len;
+            buf_size -= len;
+
+            if buf_size <= 32 {
+                if next_byte + 4 > data.len() {
+                    return Err(file.invalid());
+                }
+                let more = u32::from_be_bytes([
+                    data[next_byte],
+                    data[next_byte + 1],
+                    data[next_byte + 2],
+                    data[next_byte + 3],
+                ]);
+                next_byte += 4;
+                buf_size += 32;
+                buf |= (more as u64) << (64 - buf_size);
+            }
+        };
+
+        // and walk down its pairs to the value
+        while !symbol.is_leaf() {
+            let left = &self.symbols[symbol.left as usize];
+            if lit_idx <= left.len as i64 {
+                symbol = left;
+            } else {
+                lit_idx -= left.len as i64 + 1;
+                symbol = &self.symbols[symbol.right as usize];
+            }
+        }
+
+        Ok(symbol.left)
+    }
+}
+
+// Every symbol is a value, or a pair of two other symbols.
+fn read_symbols(tree: &[u8]) -> Option<Vec<Symbol>> {
+    let count = tree.len() / 3;
+    let mut symbols: Vec<Option<Symbol>> = (0..count).map(|_| None).collect();
+
+    fn visit(tree: &[u8], symbols: &mut Vec<Option<Symbol>>, i: usize, depth: u32) -> Option<u32> {
+        if let Some(symbol) = symbols.get(i)? {
+            return Some(symbol.len);
+        }
+
+        let bytes = &tree[3 * i..3 * i + 3];
+        let left = ((bytes[1] as u16 & 0xf) << 8) | bytes[0] as u16;
+        let right = ((bytes[2] as u16) << 4) | (bytes[1] as u16 >> 4);
+
+        let len = if right == 0xfff {
+            0
+        } else {
+            // the pairs can't be deeper than the values are long
+            let depth = depth.checked_sub(1)?;
+            visit(tree, symbols, left as usize, depth)?
+                + visit(tree, symbols, right as usize, depth)?
+                + 1
+        };
+
+        symbols[i] = Some(Symbol { left, right, len });
+        Some(len)
+    }
+
+    for i in 0..count {
+        visit(tree, &mut symbols, i, 16)?;
+    }
+
+    symbols.into_iter().collect()
+}
+
+// A .rtbw or .rtbz file
+pub struct SyzygyTable {
+    file: TableFile,
+    kind: TableKind,
+    // the material as the table sees it, e.g. "KQvK"
+    key: String,
+    has_pawns: bool,
+    is_symmetric: bool,
+    unique_pieces: usize,
+    // one list of subtables per file of the leading pawn (a to d), or one
+    //   without pawns. WDL tables have one subtable for each side to move,
+    //   unless both sides have the same material.
+    subtables: Vec<Vec<Subtable>>,
+}
+
+impl SyzygyTable {
+    // key is the material in the name of the file, e.g. "KRvKP"
+    pub fn open(path: &Path, key: &str, kind: TableKind) -> Result<SyzygyTable, TablebaseError> {
+        let file =
+            TableFile {
+                path: path.to_path_buf(),
+                file: Mutex::new(fs::File::open(path).map_err(|e| {
+                    TablebaseError::InvalidFile(format!("{}: {}", path.display(), e))
+                })?),
+            };
+
+        let mut magic = [0; 4];
+        file.read_at(0, &mut magic)?;
+        let expected = match kind {
+            TableKind::Wdl => WDL_MAGIC,
+            TableKind::Dtz => DTZ_MAGIC,
+        };
+        if magic != expected {
+            return Err(file.invalid());
+        }
+
+        let layout = file.u8_at(4)?;
+        let has_pawns = layout & HAS_PAWNS != 0;
+        let is_symmetric = layout & SPLIT == 0;
+        if has_pawns != key.contains('P') || is_symmetric != (flip_material_key(key) == key) {
+            return Err(file.invalid());
+        }
+
+        let piece_count = key.len() - 1;
+        let both_have_pawns = key.split('v').all(|side| side.contains('P'));
+        let file_count = if has_pawns { 4 } else { 1 };
+        let side_count = if kind == TableKind::Wdl && !is_symmetric {
+            2
+        } else {
+            1
+        };
+
+        let mut offset = 5;
+        let mut groups = vec![];
+        for table_file in 0..file_count {
+            let order = [
+                file.u8_at(offset)?,
+                if both_have_pawns {
+                    file.u8_at(offset + 1)?
+                } else {
+                    0xff
+                },
+            ];
+            offset += if both_have_pawns { 2 } else { 1 };
+
+            let mut bytes = vec![0; piece_count];
+            file.read_at(offset, &mut bytes)?;
+            offset += piece_count as u64;
+
+            let mut sides = vec![];
+            for side in 0..side_count {
+                // white to move in the low nibbles, black in the high ones
+                let nibble = |byte: u8| if side == 0 { byte & 0xf } else { byte >> 4 };
+                let pieces = bytes
+                    .iter()
+                    .map(|byte| piece_from_nibble(nibble(*byte)))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| file.invalid())?;
+
+                let key_of_pieces = pieces_key(&pieces);
+                if key_of_pieces != key && flip_material_key(&key_of_pieces) != key {
+                    return Err(file.invalid());
+                }
+
+                let unique_pieces = pieces
+                    .iter()
+                    .filter(|p| pieces.iter().filter(|q| q == p).count() == 1)
+                    .count();
+                let order = [nibble(order[0]), nibble(order[1])];
+                sides.push(
+                    Groups::new(pieces, order, table_file, unique_pieces)
+                        .ok_or_else(|| file.invalid())?,
+                );
+            }
+            groups.push(sides);
+        }
+        offset += offset & 1;
+
+        let first = &groups[0][0].pieces;
+        if (first[0].1 == ChessPiece::Pawn) != has_pawns {
+            return Err(file.invalid());
+        }
+        let table_key = pieces_key(first);
+        let unique_pieces = first
+            .iter()
+            .filter(|p| first.iter().filter(|q| q == p).count() == 1)
+            .count();
+
+        let mut subtables = vec![];
+        for sides in groups {
+            let mut file_subtables = vec![];
+            for side_groups in sides {
+                let (subtable, next) = Subtable::read(&file, offset, side_groups, kind)?;
+                file_subtables.push(subtable);
+                offset = next;
+            }
+            subtables.push(file_subtables);
+        }
+
+        if kind == TableKind::Dtz {
+            let map_offset = offset;
+            for file_subtables in subtables.iter_mut() {
+                let subtable = &mut file_subtables[0];
+                if subtable.flags & MAPPED == 0 {
+                    continue;
+                }
+
+                let wide = subtable.flags & WIDE_DTZ != 0;
+                let mut by_wdl = [0; 4];
+                for start in by_wdl.iter_mut() {
+                    if wide {
+                        *start = (offset - map_offset + 2) / 2;
+                        offset += file.u16_at(offset)? as u64 * 2 + 2;
+                    } else {
+                        *start = offset - map_offset + 1;
+                        offset += file.u8_at(offset)? as u64 + 1;
+                    }
+                }
+                subtable.dtz_map = Some(DtzMap {
+                    offset: map_offset,
+                    by_wdl,
+                    wide,
+                });
+            }
+            offset += offset & 1;
+        }
+
+        for subtable in subtables.iter_mut().flatten() {
+            subtable.sparse_index = offset;
+            offset += subtable.sparse_index_size * 6;
+        }
+        for subtable in subtables.iter_mut().flatten() {
+            subtable.block_lengths = offset;
+            offset += subtable.block_lengths_size * 2;
+        }
+        for subtable in subtables.iter_mut().flatten() {
+            // the blocks of each subtable are 64 byte aligned
+            offset = (offset + 0x3f) & !0x3f;
+            subtable.data = offset;
+            offset += subtable.blocks as u64 * subtable.block_size as u64;
+        }
+
+        Ok(SyzygyTable {
+            file,
+            kind,
+            key: table_key,
+            has_pawns,
+            is_symmetric,
+            unique_pieces,
+            subtables,
+        })
+    }
+
+    // The subtable and index of the position. None for DTZ tables that only
+    //   store the other side to move.
+    fn encode(&self, board: &Board) -> Result<Option<(&Subtable, u64)>, TablebaseError> {
+        let black_to_move = board.whose_turn == ChessTeam::Black;
+        // the table has white as the stronger team, and white to move if both
+        //   have the same pieces
+        let flip =
+            (self.is_symmetric && black_to_move) || tablebase::material_key(board) != self.key;
+        let table_black_to_move = black_to_move != flip;
+
+        let team_on_board = |team: ChessTeam| if flip { team.the_other_one() } else { team };
+        let square_in_table = |square: u8| if flip { flip_vertical(square) } else { square };
+
+        let mut pieces_left: Vec<(u8, TeamedChessPiece)> = board
+            .piece_locations
+            .iter()
+            .map(|(tile, piece)| (square_from_tile(*tile), *piece))
+            .collect();
+        pieces_left.sort_by_key(|(square, _)| *square);
+
+        // the lowest square with the piece, in the table
+        let mut take_square = |(team, piece): TablePiece| {
+            let i = pieces_left
+                .iter()
+                .position(|(_, p)| *p == TeamedChessPiece(team_on_board(team), piece))?;
+            Some(square_in_table(pieces_left.remove(i).0))
+        };
+
+        let mut squares = vec![];
+
+        // with pawns there is a subtable for each file of the leading pawn
+        let table_file = if self.has_pawns {
+            let lead = self.subtables[0][0].groups.pieces[0];
+            while let Some(square) = take_square(lead) {
+                squares.push(square);
+            }
+            if squares.is_empty() {
+                return Err(self.file.invalid());
+            }
+
+            let index = pawn_index();
+            for i in 1..squares.len() {
+                if index.squares[squares[0] as usize] < index.squares[squares[i] as usize] {
+                    squares.swap(0, i);
+                }
+            }
+            std::cmp::min(file_of(squares[0]), 7 - file_of(squares[0])) as usize
+        } else {
+            0
+        };
+
+        let sides = &self.subtables[table_file];
+        let subtable = &sides[if table_black_to_move {
+            sides.len() - 1
+        } else {
+            0
+        }];
+
+        if self.kind == TableKind::Dtz
+            && (subtable.flags & STM != 0) != table_black_to_move
+            && (!self.is_symmetric || self.has_pawns)
+        {
+            return Ok(None);
+        }
+
+        let lead_pawns = squares.len();
+        for piece in subtable.groups.pieces.iter().skip(lead_pawns) {
+            squares.push(take_square(*piece).ok_or_else(|| self.file.invalid())?);
+        }
+
+        if file_of(squares[0]) >= 4 {
+            squares.iter_mut().for_each(|s| *s = flip_horizontal(*s));
+        }
+
+        let mut idx = if self.has_pawns {
+            let index = pawn_index();
+            let mut idx = index.lead[lead_pawns][squares[0] as usize];
+
+            squares[1..lead_pawns].sort_by_key(|s| index.squares[*s as usize]);
+            for (i, square) in squares.iter().enumerate().take(lead_pawns).skip(1) {
+                idx += binomial(index.squares[*square as usize], i as u64);
+            }
+            idx
+        } else {
+            if rank_of(squares[0]) >= 4 {
+                squares.iter_mut().for_each(|s| *s = flip_vertical(*s));
+            }
+
+            // under the diagonal, or on it if the first pieces are
+            for i in 0..subtable.groups.lens[0] {
+                if !is_off_diagonal(squares[i]) {
+                    continue;
+                }
+                if rank_of(squares[i]) > file_of(squares[i]) {
+                    squares[i..].iter_mut().for_each(|s| *s = flip_diagonal(*s));
+                }
+                break;
+            }
+
+            if self.unique_pieces > 2 {
+                let (s0, s1, s2) = (squares[0] as u64, squares[1] as u64, squares[2] as u64);
+                let adjust1 = (s1 > s0) as u64;
+                let adjust2 = (s2 > s0) as u64 + (s2 > s1) as u64;
+                let (r0, r1, r2) = (s0 / 8, s1 / 8, s2 / 8);
+
+                if is_off_diagonal(squares[0]) {
+                    TRIANGLE[s0 as usize] * 63 * 62 + (s1 - adjust1) * 62 + (s2 - adjust2)
+                } else if is_off_diagonal(squares[1]) {
+                    6 * 63 * 62 + r0 * 28 * 62 + LOWER[s1 as usize] * 62 + s2 - adjust2
+                } else if is_off_diagonal(squares[2]) {
+                    6 * 63 * 62
+                        + 4 * 28 * 62
+                        + r0 * 7 * 28
+                        + (r1 - adjust1) * 28
+                        + LOWER[s2 as usize]
+                } else {
+                    6 * 63 * 62
+                        + 4 * 28 * 62
+                        + 4 * 7 * 28
+                        + r0 * 7 * 6
+                        + (r1 - adjust1) * 6
+                        + (r2 - adjust2)
+                }
+            } else {
+                let idx =
+                    kings_index()[TRIANGLE[squares[0] as usize] as usize][squares[1] as usize];
+                if idx == u16::MAX {
+                    return Err(self.file.invalid());
+                }
+                idx as u64
+            }
+        };
+        idx *= subtable.groups.factors[0];
+
+        // the other groups, each one as a combination of the squares that are left
+        let mut other_pawns = self.has_pawns && self.key.split('v').all(|side| side.contains('P'));
+        let mut start = subtable.groups.lens[0];
+        for (len, factor) in subtable.groups.lens[1..]
+            .iter()
+            .zip(&subtable.groups.factors[1..])
+        {
+            let (before, group) = squares.split_at_mut(start);
+            let group = &mut group[..*len];
+            group.sort_unstable();
+
+            let mut n = 0;
+            for (i, square) in group.iter().enumerate() {
+                let adjust = before.iter().filter(|s| *square > **s).count() as u64;
+                let pawn_adjust = if other_pawns { 8 } else { 0 };
+                n += binomial(*square as u64 - adjust - pawn_adjust, i as u64 + 1);
+            }
+
+            other_pawns = false;
+            idx += n * factor;
+            start += len;
+        }
+
+        if idx >= subtable.groups.table_size() {
+            return Err(self.file.invalid());
+        }
+
+        Ok(Some((subtable, idx)))
+    }
+
+    // What the table has for the position. Captures are not looked at, if one
+    //   is better the table can have anything.
+    pub fn probe_wdl(&self, board: &Board) -> Result<Wdl, TablebaseError> {
+        let (subtable, idx) = self.encode(board)?.ok_or_else(|| self.file.invalid())?;
+
+        match subtable.decompress(&self.file, idx)? {
+            0 => Ok(Wdl::Loss),
+            1 => Ok(Wdl::BlessedLoss),
+            2 => Ok(Wdl::Draw),
+            3 => Ok(Wdl::CursedWin),
+            4 => Ok(Wdl::Win),
+            _ => Err(self.file.invalid()),
+        }
+    }
+
+    // Halfmoves to add to the first zeroing move, for a position that isn't a
+    //   draw. Some tables only store full moves, then the count is rounded
+    //   (always even). None if the table doesn't have this side to move.
+    pub fn probe_dtz(&self, board: &Board, wdl: Wdl) -> Result<Option<i32>, TablebaseError> {
+        let (subtable, idx) = match self.encode(board)? {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+
+        let mut value = subtable.decompress(&self.file, idx)? as u64;
+
+        let (wdl_index, plies_flag) = match wdl {
+            Wdl::Win => (0, WIN_PLIES),
+            Wdl::Loss => (1, LOSS_PLIES),
+            Wdl::CursedWin => (2, 0),
+            Wdl::BlessedLoss => (3, 0),
+            Wdl::Draw => return Ok(Some(0)),
+        };
+
+        if let Some(map) = &subtable.dtz_map {
+            let entry = map.by_wdl[wdl_index] + value;
+            value = if map.wide {
+                self.file.u16_at(map.offset + 2 * entry)? as u64
+            } else {
+                self.file.u8_at(map.offset + entry)? as u64
+            };
+        }
+
+        let plies = if plies_flag != 0 && subtable.flags & plies_flag != 0 {
+            value
+        } else {
+            2 * value
+        };
+        Ok(Some(plies as i32))
+    }
+}
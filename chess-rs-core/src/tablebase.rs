@@ -0,0 +1,609 @@
+// Endgame tablebase probing
+//
+// A tablebase knows the exact result of every position with a given material
+//  (e.g. KQvK: king and queen against a lone king). This module has the probing
+//  interface that the rest of the code uses (adjudication, picking the best move,
+//  the endgame panel in the client) and the Syzygy file handling.
+//
+// Results are always from the point of view of the team whose turn it is.
+
+use super::*;
+use crate::syzygy::{self, SyzygyTable, TableKind};
+
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+// Win/Draw/Loss of a position.
+//  Cursed wins and blessed losses are wins and losses that can't be converted
+//  because of the fifty move rule.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Wdl {
+    Loss,
+    BlessedLoss,
+    Draw,
+    CursedWin,
+    Win,
+}
+
+impl Wdl {
+    // the same result seen by the other team
+    pub fn flip(self) -> Wdl {
+        match self {
+            Wdl::Loss => Wdl::Win,
+            Wdl::BlessedLoss => Wdl::CursedWin,
+            Wdl::Draw => Wdl::Draw,
+            Wdl::CursedWin => Wdl::BlessedLoss,
+            Wdl::Win => Wdl::Loss,
+        }
+    }
+
+    // -2 (loss) to 2 (win). Useful for comparing results
+    pub fn value(self) -> i32 {
+        match self {
+            Wdl::Loss => -2,
+            Wdl::BlessedLoss => -1,
+            Wdl::Draw => 0,
+            Wdl::CursedWin => 1,
+            Wdl::Win => 2,
+        }
+    }
+}
+
+impl fmt::Display for Wdl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Wdl::Loss => write!(f, "Loss"),
+            Wdl::BlessedLoss => write!(f, "Loss, but drawn by the fifty move rule"),
+            Wdl::Draw => write!(f, "Draw"),
+            Wdl::CursedWin => write!(f, "Win, but drawn by the fifty move rule"),
+            Wdl::Win => write!(f, "Win"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TablebaseError {
+    // there is no table for this material (e.g. "KRvKN")
+    MissingTable(String),
+    // the position has more pieces than the tables cover
+    TooManyPieces(usize),
+    // tablebases don't store positions where castling is still possible
+    CastlingRights,
+    // a table file could not be read or is not a table
+    InvalidFile(String),
+}
+
+impl fmt::Display for TablebaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TablebaseError::MissingTable(key) => write!(f, "No tablebase for {}.", key),
+            TablebaseError::TooManyPieces(count) => {
+                write!(f, "Too many pieces for the tablebases ({}).", count)
+            }
+            TablebaseError::CastlingRights => {
+                write!(
+                    f,
+                    "Positions with castling rights are not in the tablebases."
+                )
+            }
+            TablebaseError::InvalidFile(file) => write!(f, "Invalid tablebase file: {}", file),
+        }
+    }
+}
+
+// Anything that can tell the exact result of small endgames.
+pub trait Tablebase: Send + Sync {
+    // largest number of pieces (kings included) that the tables cover
+    fn max_pieces(&self) -> usize;
+
+    // win/draw/loss for the team whose turn it is
+    fn probe_wdl(&self, board: &Board, ep_square: Option<Tile>) -> Result<Wdl, TablebaseError>;
+
+    // distance (in halfmoves) to the next capture or pawn move that keeps the result,
+    //   or to mate. Positive if the team whose turn it is wins, negative if it loses,
+    //   0 for draws.
+    fn probe_dtz(&self, board: &Board, ep_square: Option<Tile>) -> Result<i32, TablebaseError>;
+}
+
+// Returns the material of the board in tablebase notation. e.g: "KRPvKR"
+//  White's pieces go first.
+pub fn material_key(board: &Board) -> String {
+    fn team_str(board: &Board, team: ChessTeam) -> String {
+        let mut res = String::new();
+        for (piece, c) in [
+            (ChessPiece::King, 'K'),
+            (ChessPiece::Queen, 'Q'),
+            (ChessPiece::Rook, 'R'),
+            (ChessPiece::Bishop, 'B'),
+            (ChessPiece::Knight, 'N'),
+            (ChessPiece::Pawn, 'P'),
+        ] {
+            for _ in board.find_pieces(team, piece) {
+                res.push(c);
+            }
+        }
+        res
+    }
+
+    team_str(board, ChessTeam::White) + "v" + &team_str(board, ChessTeam::Black)
+}
+
+// The same material with the teams swapped. "KRPvKR" -> "KRvKRP"
+pub fn flip_material_key(key: &str) -> String {
+    let mut split = key.split('v');
+    let white = split.next().unwrap_or("");
+    let black = split.next().unwrap_or("");
+    format!("{}v{}", black, white)
+}
+
+fn is_material_key(key: &str) -> bool {
+    let mut split = key.split('v');
+    let (white, black) = match (split.next(), split.next(), split.next()) {
+        (Some(w), Some(b), None) => (w, b),
+        _ => return false,
+    };
+
+    [white, black].iter().all(|side| {
+        side.starts_with('K')
+            && side.chars().filter(|c| *c == 'K').count() == 1
+            && side.chars().all(|c| "KQRBNP".contains(c))
+    })
+}
+
+// Checks what can be checked before asking a table: castling rights and piece count.
+fn check_probe(tb: &dyn Tablebase, board: &Board) -> Result<(), TablebaseError> {
+    if board.castling_rights != (false, false, false, false) {
+        return Err(TablebaseError::CastlingRights);
+    }
+
+    let count = board.piece_locations.len();
+    if count > tb.max_pieces() {
+        return Err(TablebaseError::TooManyPieces(count));
+    }
+
+    Ok(())
+}
+
+// A move and what it leads to, for the team making it
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TablebaseMove {
+    pub the_move: Move,
+    pub wdl: Wdl,
+    // halfmoves, same sign convention as Tablebase::probe_dtz
+    pub dtz: i32,
+}
+
+// Rates every legal move of the position with the tablebase.
+//  Mates and stalemates are found without probing.
+pub fn rate_moves(
+    tb: &dyn Tablebase,
+    board: &Board,
+    ep_square: Option<Tile>,
+) -> Result<Vec<TablebaseMove>, TablebaseError> {
+    check_probe(tb, board)?;
+
+    let mut res = vec![];
+
+    for the_move in board.get_all_legal_moves(ep_square) {
+        let mut future_board = board.clone();
+        let was_capture = future_board.apply_move(the_move);
+        let next_ep_square = the_move.get_en_passant_square();
+
+        let is_zeroing = was_capture
+            || matches!(
+                the_move,
+                Move::PieceMove {
+                    piece: ChessPiece::Pawn,
+                    ..
+                } | Move::PieceMoveWithPromotion { .. }
+            );
+
+        let (wdl, dtz) = if future_board.get_all_legal_moves(next_ep_square).is_empty() {
+            if future_board.is_team_in_check(future_board.whose_turn, None) {
+                (Wdl::Win, 1)
+            } else {
+                (Wdl::Draw, 0)
+            }
+        } else if future_board.piece_locations.len() == 2 {
+            (Wdl::Draw, 0)
+        } else {
+            let wdl = tb.probe_wdl(&future_board, next_ep_square)?.flip();
+            let dtz = -tb.probe_dtz(&future_board, next_ep_square)?;
+
+            // a capture or pawn move restarts the count, so it is the best
+            //   a winning move can do
+            let dtz = match wdl {
+                Wdl::Draw => 0,
+                _ if is_zeroing => dtz.signum(),
+                _ => dtz + dtz.signum(),
+            };
+            (wdl, dtz)
+        };
+
+        res.push(TablebaseMove { the_move, wdl, dtz });
+    }
+
+    Ok(res)
+}
+
+// The move that keeps the best result. When winning it is the fastest one,
+//   when losing it is the one that resists the longest.
+//   None if there are no legal moves.
+pub fn best_move(
+    tb: &dyn Tablebase,
+    board: &Board,
+    ep_square: Option<Tile>,
+) -> Result<Option<TablebaseMove>, TablebaseError> {
+    let moves = rate_moves(tb, board, ep_square)?;
+
+    let is_mate = |m: &TablebaseMove| {
+        let mut future_board = board.clone();
+        future_board.apply_move(m.the_move);
+        future_board.is_team_in_check(future_board.whose_turn, None)
+            && future_board
+                .get_all_legal_moves(m.the_move.get_en_passant_square())
+                .is_empty()
+    };
+
+    // max_by returns the last of the best moves, so the list is reversed
+    //   to prefer the first one generated
+    Ok(moves.into_iter().rev().max_by(|a, b| {
+        a.wdl.value().cmp(&b.wdl.value()).then_with(|| {
+            if a.wdl.value() > 0 {
+                // shorter wins are better, and mating is the shortest
+                is_mate(a)
+                    .cmp(&is_mate(b))
+                    .then_with(|| b.dtz.abs().cmp(&a.dtz.abs()))
+            } else {
+                // longer losses are better
+                a.dtz.abs().cmp(&b.dtz.abs())
+            }
+        })
+    }))
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Adjudication {
+    // the team wins with best play
+    Win(ChessTeam),
+    // drawn with best play, including wins spoiled by the fifty move rule
+    Draw,
+}
+
+// get_end_state, but it also ends games that the tablebase already knows the result of.
+//   None if the game is over already or the position is not in the tablebase.
+pub fn adjudicate(tb: &dyn Tablebase, game: &mut GameState) -> Option<Adjudication> {
+    if game.get_end_state() != GameEndState::Running {
+        return None;
+    }
+
+    let ep_square = game.en_passant_square;
+    let board = game.get_board();
+
+    if board.piece_locations.len() == 2 {
+        return Some(Adjudication::Draw);
+    }
+
+    check_probe(tb, board).ok()?;
+
+    match tb.probe_wdl(board, ep_square).ok()? {
+        Wdl::Win => Some(Adjudication::Win(board.whose_turn)),
+        Wdl::Loss => Some(Adjudication::Win(board.whose_turn.the_other_one())),
+        Wdl::CursedWin | Wdl::BlessedLoss | Wdl::Draw => Some(Adjudication::Draw),
+    }
+}
+
+#[derive(Default)]
+struct SyzygyFiles {
+    wdl: Option<PathBuf>,
+    dtz: Option<PathBuf>,
+    // opened on the first probe
+    wdl_table: OnceLock<Result<SyzygyTable, TablebaseError>>,
+    dtz_table: OnceLock<Result<SyzygyTable, TablebaseError>>,
+}
+
+// Syzygy tablebases (.rtbw and .rtbz files) found in a directory.
+//
+// The tables only have what can't be found with a short search, so probing
+//  looks at the captures and, for the DTZ, at the moves of the side to move
+//  that the .rtbz file doesn't store. Most DTZ tables count full moves, so a
+//  DTZ can be one halfmove more than the real one. Cursed wins and blessed
+//  losses are over 100.
+pub struct SyzygyTablebase {
+    tables: HashMap<String, SyzygyFiles>,
+    max_pieces: usize,
+}
+
+// a result is better than another for the team to move
+fn better(a: Wdl, b: Wdl) -> Wdl {
+    if b.value() > a.value() {
+        b
+    } else {
+        a
+    }
+}
+
+fn is_capture(board: &Board, the_move: &Move) -> bool {
+    match *the_move {
+        Move::PieceMove {
+            tile_to,
+            is_en_passant,
+            ..
+        } => is_en_passant || board.get_piece(tile_to).is_some(),
+        Move::PieceMoveWithPromotion { tile_to, .. } => board.get_piece(tile_to).is_some(),
+        Move::CastleShort | Move::CastleLong => false,
+    }
+}
+
+fn is_zeroing(board: &Board, the_move: &Move) -> bool {
+    is_capture(board, the_move)
+        || matches!(
+            the_move,
+            Move::PieceMove {
+                piece: ChessPiece::Pawn,
+                ..
+            } | Move::PieceMoveWithPromotion { .. }
+        )
+}
+
+impl SyzygyTablebase {
+    // Looks for tables in dir. Files that are named like tables but don't
+    //   look like one are an error.
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<SyzygyTablebase, TablebaseError> {
+        let mut tb = SyzygyTablebase {
+            tables: HashMap::new(),
+            max_pieces: 0,
+        };
+        tb.add_directory(dir)?;
+        Ok(tb)
+    }
+
+    pub fn add_directory<P: AsRef<Path>>(&mut self, dir: P) -> Result<(), TablebaseError> {
+        let dir = dir.as_ref();
+        let entries = fs::read_dir(dir)
+            .map_err(|e| TablebaseError::InvalidFile(format!("{}: {}", dir.display(), e)))?;
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+
+            let (key, extension) = match (path.file_stem(), path.extension()) {
+                (Some(stem), Some(ext)) => (
+                    stem.to_string_lossy().to_string(),
+                    ext.to_string_lossy().to_string(),
+                ),
+                _ => continue,
+            };
+
+            let magic = match extension.as_str() {
+                "rtbw" => syzygy::WDL_MAGIC,
+                "rtbz" => syzygy::DTZ_MAGIC,
+                _ => continue,
+            };
+
+            if !is_material_key(&key) {
+                continue;
+            }
+
+            let mut file_magic = [0u8; 4];
+            fs::File::open(&path)
+                .and_then(|mut file| file.read_exact(&mut file_magic))
+                .map_err(|e| TablebaseError::InvalidFile(format!("{}: {}", path.display(), e)))?;
+
+            if file_magic != magic {
+                return Err(TablebaseError::InvalidFile(path.display().to_string()));
+            }
+
+            self.max_pieces = std::cmp::max(self.max_pieces, key.len() - 1);
+
+            let files = self.tables.entry(key).or_default();
+            if extension == "rtbw" {
+                files.wdl = Some(path);
+            } else {
+                files.dtz = Some(path);
+            }
+        }
+
+        Ok(())
+    }
+
+    // number of material combinations found
+    pub fn table_count(&self) -> usize {
+        self.tables.len()
+    }
+
+    fn table(&self, board: &Board, kind: TableKind) -> Result<&SyzygyTable, TablebaseError> {
+        // Syzygy only has one file for both colour combinations of the same material
+        let key = material_key(board);
+        let (table_key, files) = self
+            .tables
+            .get_key_value(&key)
+            .or_else(|| self.tables.get_key_value(&flip_material_key(&key)))
+            .ok_or_else(|| TablebaseError::MissingTable(key.clone()))?;
+
+        let (path, table) = match kind {
+            TableKind::Wdl => (&files.wdl, &files.wdl_table),
+            TableKind::Dtz => (&files.dtz, &files.dtz_table),
+        };
+        let path = path.as_ref().ok_or(TablebaseError::MissingTable(key))?;
+
+        table
+            .get_or_init(|| SyzygyTable::open(path, table_key, kind))
+            .as_ref()
+            .map_err(|e| e.clone())
+    }
+
+    fn probe_table(&self, board: &Board) -> Result<Wdl, TablebaseError> {
+        if board.piece_locations.len() == 2 {
+            return Ok(Wdl::Draw);
+        }
+        self.table(board, TableKind::Wdl)?.probe_wdl(board)
+    }
+
+    // Looks at the captures with alpha-beta. No en passant here, captures
+    //   don't give en passant squares.
+    fn probe_captures(
+        &self,
+        board: &Board,
+        mut alpha: Wdl,
+        beta: Wdl,
+    ) -> Result<Wdl, TablebaseError> {
+        for the_move in board.get_all_legal_moves(None) {
+            if !is_capture(board, &the_move) {
+                continue;
+            }
+
+            let mut future_board = board.clone();
+            future_board.apply_move(the_move);
+            let wdl = self
+                .probe_captures(&future_board, beta.flip(), alpha.flip())?
+                .flip();
+
+            if wdl.value() >= beta.value() {
+                return Ok(wdl);
+            }
+            alpha = better(alpha, wdl);
+        }
+
+        Ok(better(alpha, self.probe_table(board)?))
+    }
+
+    // The result of the position, and if the best move is a capture (then
+    //   the DTZ is known without the table)
+    fn probe(&self, board: &Board, ep_square: Option<Tile>) -> Result<(Wdl, bool), TablebaseError> {
+        let moves = board.get_all_legal_moves(ep_square);
+
+        let mut best_capture = Wdl::Loss;
+        let mut best_en_passant = Wdl::Loss;
+
+        for the_move in moves.iter().filter(|m| is_capture(board, m)) {
+            let mut future_board = board.clone();
+            future_board.apply_move(*the_move);
+            let wdl = self
+                .probe_captures(&future_board, Wdl::Loss, best_capture.flip())?
+                .flip();
+
+            if wdl == Wdl::Win {
+                return Ok((wdl, true));
+            }
+
+            match the_move {
+                Move::PieceMove {
+                    is_en_passant: true,
+                    ..
+                } => best_en_passant = better(best_en_passant, wdl),
+                _ => best_capture = better(best_capture, wdl),
+            }
+        }
+
+        let wdl = self.probe_table(board)?;
+
+        // the table doesn't know about en passant
+        if best_en_passant.value() > better(wdl, best_capture).value() {
+            return Ok((best_en_passant, true));
+        }
+        let best_capture = better(best_capture, best_en_passant);
+
+        if best_capture.value() >= wdl.value() {
+            return Ok((best_capture, best_capture.value() > 0));
+        }
+
+        // stalemate, if it wasn't for en passant
+        if wdl == Wdl::Draw
+            && !moves.is_empty()
+            && moves.iter().all(|m| {
+                matches!(
+                    m,
+                    Move::PieceMove {
+                        is_en_passant: true,
+                        ..
+                    }
+                )
+            })
+        {
+            return Ok((best_en_passant, true));
+        }
+
+        Ok((wdl, false))
+    }
+
+    fn dtz(&self, board: &Board, ep_square: Option<Tile>) -> Result<i32, TablebaseError> {
+        let (wdl, capture_is_best) = self.probe(board, ep_square)?;
+
+        // the DTZ right before a capture or pawn move
+        let zeroing = match wdl {
+            Wdl::Draw => return Ok(0),
+            Wdl::Win => 1,
+            Wdl::CursedWin => 101,
+            Wdl::BlessedLoss => -101,
+            Wdl::Loss => -1,
+        };
+        if capture_is_best {
+            return Ok(zeroing);
+        }
+
+        let moves = board.get_all_legal_moves(ep_square);
+
+        // a pawn move that keeps the win (captures were looked at already)
+        if zeroing > 0 {
+            for the_move in moves.iter().filter(|m| is_zeroing(board, m)) {
+                let mut future_board = board.clone();
+                future_board.apply_move(*the_move);
+                let (future_wdl, _) =
+                    self.probe(&future_board, the_move.get_en_passant_square())?;
+                if future_wdl.flip() == wdl {
+                    return Ok(zeroing);
+                }
+            }
+        }
+
+        if let Some(plies) = self.table(board, TableKind::Dtz)?.probe_dtz(board, wdl)? {
+            return Ok(zeroing + zeroing.signum() * plies);
+        }
+
+        // the table only has the other side to move, so look one move ahead
+        let mut best = if zeroing > 0 { None } else { Some(zeroing) };
+        for the_move in moves.iter().filter(|m| !is_zeroing(board, m)) {
+            let mut future_board = board.clone();
+            future_board.apply_move(*the_move);
+            let next_ep_square = the_move.get_en_passant_square();
+            let dtz = -self.dtz(&future_board, next_ep_square)?;
+
+            if dtz == 1
+                && future_board.is_team_in_check(future_board.whose_turn, None)
+                && future_board.get_all_legal_moves(next_ep_square).is_empty()
+            {
+                best = Some(1);
+            } else if dtz.signum() == zeroing.signum() {
+                let dtz = dtz + dtz.signum();
+                best = match best {
+                    Some(best) if best <= dtz => Some(best),
+                    _ => Some(dtz),
+                };
+            }
+        }
+
+        best.ok_or_else(|| TablebaseError::InvalidFile(material_key(board)))
+    }
+}
+
+impl Tablebase for SyzygyTablebase {
+    fn max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    fn probe_wdl(&self, board: &Board, ep_square: Option<Tile>) -> Result<Wdl, TablebaseError> {
+        check_probe(self, board)?;
+        Ok(self.probe(board, ep_square)?.0)
+    }
+
+    fn probe_dtz(&self, board: &Board, ep_square: Option<Tile>) -> Result<i32, TablebaseError> {
+        check_probe(self, board)?;
+        self.dtz(board, ep_square)
+    }
+}
+
+#[cfg(test)]
+#[path = "./tests/tablebase_tests.rs"]
+mod tablebase_tests;
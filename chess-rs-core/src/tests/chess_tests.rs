@@ -170,3 +170,156 @@ mod fen_tests {
         assert!(parse_fen("w - e3 0 2".to_string()).is_none());
     }
 }
+
+mod move_generation_tests {
+
+    use super::*;
+
+    // counts the leaf nodes of the move tree (https://www.chessprogramming.org/Perft)
+    fn perft(board: &Board, ep_square: Option<Tile>, depth: u32) -> u64 {
+        let moves = board.get_all_legal_moves(ep_square);
+
+        if depth == 1 {
+            return moves.len() as u64;
+        }
+
+        moves
+            .iter()
+            .map(|m| {
+                let mut future_board = board.clone();
+                future_board.apply_move(*m);
+                perft(&future_board, m.get_en_passant_square(), depth - 1)
+            })
+            .sum()
+    }
+
+    fn perft_fen(fen: &str, depth: u32) -> u64 {
+        let mut game = parse_fen(fen.to_string()).unwrap();
+        let ep_square = game.en_passant_square;
+        perft(game.get_board(), ep_square, depth)
+    }
+
+    #[test]
+    fn perft_positions() {
+        assert_eq!(
+            perft_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", 3),
+            8902
+        );
+        // castling, promotions, pins
+        assert_eq!(
+            perft_fen(
+                "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                2
+            ),
+            2039
+        );
+        // en passant that gets out of check and en passant that exposes the king
+        assert_eq!(perft_fen("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", 4), 43238);
+        assert_eq!(
+            perft_fen(
+                "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+                2
+            ),
+            264
+        );
+    }
+
+    // the rank of a capture or of the piece that moves, e.g. exd5 and R1a3
+    #[test]
+    fn ranks_in_moves() {
+        let mut game = GameState::init();
+        for the_move in ["e4", "d5", "exd5"] {
+            let m = move_processor::parse_move(the_move.to_string(), &mut game).expect(the_move);
+            game.perform_move(m).unwrap();
+        }
+        assert_eq!(
+            game.get_board().piece_locations.get(&Tile::D5),
+            Some(&TeamedChessPiece(ChessTeam::White, ChessPiece::Pawn))
+        );
+
+        let white_rook = Some(&TeamedChessPiece(ChessTeam::White, ChessPiece::Rook));
+        let mut game = parse_fen("7k/8/8/R7/8/8/8/R3K3 w - - 0 1".to_string()).unwrap();
+        let m = move_processor::parse_move("R1a3".to_string(), &mut game).unwrap();
+        game.perform_move(m).unwrap();
+        let board = game.get_board();
+        assert_eq!(board.piece_locations.get(&Tile::A3), white_rook);
+        assert_eq!(board.piece_locations.get(&Tile::A5), white_rook);
+        assert_eq!(board.piece_locations.get(&Tile::A1), None);
+    }
+
+    #[test]
+    fn chess_notation() {
+        let mut game = GameState::init();
+        for the_move in ["e4", "f5", "Qh5"] {
+            let m = move_processor::parse_move(the_move.to_string(), &mut game).unwrap();
+            game.perform_move(m).unwrap();
+        }
+        assert_eq!(game.get_move_in_chess_notation(2), "Qh5+");
+
+        let mut game = GameState::init();
+        for the_move in ["f3", "e5", "g4", "Qh4"] {
+            let m = move_processor::parse_move(the_move.to_string(), &mut game).unwrap();
+            game.perform_move(m).unwrap();
+        }
+        assert_eq!(game.get_move_in_chess_notation(3), "Qh4#");
+    }
+}
+
+mod undo_tests {
+
+    use super::*;
+
+    fn play(game: &mut GameState, moves: &[&str]) {
+        for the_move in moves {
+            let m = move_processor::parse_move(the_move.to_string(), game).expect(the_move);
+            game.perform_move(m).unwrap();
+        }
+    }
+
+    #[test]
+    fn undo_move() {
+        let mut game = GameState::init();
+        assert_eq!(game.undo_move(), None);
+
+        play(&mut game, &["e4", "Nf6", "Nf3", "Nc6", "Be2", "a6", "e5"]);
+        let fen_after_e5 = game.get_fen();
+        play(&mut game, &["d5"]);
+        let fen_after_d5 = game.get_fen();
+        play(&mut game, &["exd6", "Ng8", "O-O"]);
+
+        // castling rights and the en passant square come back
+        assert_eq!(game.undo_move(), Some(Move::CastleShort));
+        assert!(game.undo_move().is_some());
+        assert!(game.undo_move().is_some());
+        assert_eq!(game.get_fen(), fen_after_d5);
+        assert_eq!(game.en_passant_square, Some(Tile::D6));
+
+        assert!(game.undo_move().is_some());
+        assert_eq!(game.get_fen(), fen_after_e5);
+        assert_eq!(game.fifty_move_counter, 0);
+        assert_eq!(game.move_count(), 7);
+
+        // the move is gone, not kept as a variation
+        play(&mut game, &["Nd4"]);
+        assert_eq!(game.get_move_tree().children(&[0; 7]).unwrap().len(), 1);
+
+        // the fifty move counter comes back after undoing a capture (Nf3xd4)
+        assert_eq!(game.fifty_move_counter, 1);
+        play(&mut game, &["Nd4"]);
+        assert_eq!(game.fifty_move_counter, 0);
+        assert!(game.undo_move().is_some());
+        assert_eq!(game.fifty_move_counter, 1);
+    }
+
+    #[test]
+    fn takeback_len() {
+        let mut game = GameState::init();
+        assert_eq!(game.takeback_len(ChessTeam::White), 0);
+        play(&mut game, &["e4"]);
+        assert_eq!(game.takeback_len(ChessTeam::White), 1);
+        assert_eq!(game.takeback_len(ChessTeam::Black), 0);
+        play(&mut game, &["e5"]);
+        assert_eq!(game.takeback_len(ChessTeam::White), 2);
+        assert_eq!(game.takeback_len(ChessTeam::Black), 1);
+    }
+}
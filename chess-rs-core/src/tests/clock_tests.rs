@@ -0,0 +1,264 @@
+use super::*;
+
+fn secs(s: u64) -> Duration {
+    Duration::from_secs(s)
+}
+
+fn manual_clock(control: TimeControl) -> (Clock, Arc<ManualTime>) {
+    let time = Arc::new(ManualTime::new());
+    (Clock::new(control, time.clone()), time)
+}
+
+#[test]
+fn fischer() {
+    let (mut clock, time) = manual_clock(TimeControl::fischer(secs(300), secs(3)));
+    assert_eq!(clock.running(), None);
+
+    // the first move starts the clocks, takes no time and gets no increment
+    let white = clock.press(ChessTeam::White).unwrap();
+    assert_eq!(white.spent, secs(0));
+    assert_eq!(white.remaining, secs(300));
+    assert_eq!(clock.running(), Some(ChessTeam::Black));
+
+    time.advance(secs(10));
+    assert_eq!(clock.remaining(ChessTeam::Black), secs(290));
+    assert_eq!(clock.remaining(ChessTeam::White), secs(300));
+
+    let black = clock.press(ChessTeam::Black).unwrap();
+    assert_eq!(black.spent, secs(10));
+    assert_eq!(black.remaining, secs(293));
+    assert_eq!(clock.running(), Some(ChessTeam::White));
+}
+
+#[test]
+fn berserk() {
+    let (mut clock, time) = manual_clock(TimeControl::fischer(secs(180), secs(2)));
+    clock.berserk(ChessTeam::White);
+    assert!(clock.is_berserked(ChessTeam::White));
+    assert_eq!(clock.remaining(ChessTeam::White), secs(90));
+
+    // black berserks while its clock runs, and gets no increment either
+    clock.press(ChessTeam::White).unwrap();
+    time.advance(secs(10));
+    clock.berserk(ChessTeam::Black);
+    assert_eq!(clock.remaining(ChessTeam::Black), secs(85));
+    assert_eq!(clock.running(), Some(ChessTeam::Black));
+    time.advance(secs(5));
+    assert_eq!(clock.press(ChessTeam::Black).unwrap().remaining, secs(80));
+
+    time.advance(secs(10));
+    assert_eq!(clock.press(ChessTeam::White).unwrap().remaining, secs(80));
+}
+
+#[test]
+fn bronstein_and_delay() {
+    let (mut clock, time) = manual_clock(TimeControl::bronstein(secs(60), secs(5)));
+    clock.start(ChessTeam::White);
+    time.advance(secs(3));
+    assert_eq!(clock.press(ChessTeam::White).unwrap().remaining, secs(60));
+    time.advance(secs(8));
+    assert_eq!(clock.press(ChessTeam::Black).unwrap().remaining, secs(57));
+
+    let (mut clock, time) = manual_clock(TimeControl::delay(secs(60), secs(5)));
+    clock.start(ChessTeam::White);
+    time.advance(secs(3));
+    assert_eq!(clock.remaining(ChessTeam::White), secs(60));
+    time.advance(secs(5));
+    assert_eq!(clock.remaining(ChessTeam::White), secs(57));
+    assert_eq!(clock.press(ChessTeam::White).unwrap().remaining, secs(57));
+}
+
+#[test]
+fn periods() {
+    // 2 moves in 10 seconds, then 5 more seconds every move
+    let control = TimeControl::Periods {
+        periods: vec![
+            Period {
+                moves: Some(2),
+                time: secs(10),
+            },
+            Period {
+                moves: Some(1),
+                time: secs(5),
+            },
+        ],
+        bonus: TimeBonus::None,
+    };
+    let (mut clock, time) = manual_clock(control);
+    clock.start(ChessTeam::White);
+
+    time.advance(secs(4));
+    assert_eq!(clock.press(ChessTeam::White).unwrap().remaining, secs(6));
+    clock.press(ChessTeam::Black).unwrap();
+    time.advance(secs(4));
+    assert_eq!(clock.press(ChessTeam::White).unwrap().remaining, secs(7));
+    clock.press(ChessTeam::Black).unwrap();
+    time.advance(secs(1));
+    assert_eq!(clock.press(ChessTeam::White).unwrap().remaining, secs(11));
+}
+
+#[test]
+fn hourglass() {
+    let (mut clock, time) = manual_clock(TimeControl::Hourglass(secs(30)));
+    clock.start(ChessTeam::White);
+    time.advance(secs(10));
+    assert_eq!(clock.remaining(ChessTeam::White), secs(20));
+    assert_eq!(clock.remaining(ChessTeam::Black), secs(40));
+
+    clock.press(ChessTeam::White).unwrap();
+    time.advance(secs(5));
+    assert_eq!(clock.remaining(ChessTeam::White), secs(25));
+    assert_eq!(clock.remaining(ChessTeam::Black), secs(35));
+}
+
+#[test]
+fn estimated_duration() {
+    assert_eq!(
+        TimeControl::fischer(secs(180), secs(2)).estimated_duration(),
+        Some(secs(260))
+    );
+    assert_eq!(
+        TimeControl::sudden_death(secs(60)).estimated_duration(),
+        Some(secs(60))
+    );
+    assert_eq!(
+        TimeControl::Hourglass(secs(30)).estimated_duration(),
+        Some(secs(30))
+    );
+
+    // 40 moves in 90 minutes, then 30 minutes for the rest
+    let classical = TimeControl::Periods {
+        periods: vec![
+            Period {
+                moves: Some(40),
+                time: secs(90 * 60),
+            },
+            Period {
+                moves: None,
+                time: secs(30 * 60),
+            },
+        ],
+        bonus: TimeBonus::Fischer(secs(30)),
+    };
+    assert_eq!(
+        classical.estimated_duration(),
+        Some(secs(90 * 60 + 20 * 60))
+    );
+
+    // 10 minutes every 20 moves
+    let repeating = TimeControl::Periods {
+        periods: vec![Period {
+            moves: Some(20),
+            time: secs(600),
+        }],
+        bonus: TimeBonus::None,
+    };
+    assert_eq!(repeating.estimated_duration(), Some(secs(1200)));
+
+    let no_periods = TimeControl::Periods {
+        periods: vec![],
+        bonus: TimeBonus::None,
+    };
+    assert_eq!(no_periods.estimated_duration(), None);
+    let huge_bonus = TimeControl::fischer(secs(60), Duration::MAX / 2);
+    assert_eq!(huge_bonus.estimated_duration(), None);
+}
+
+#[test]
+fn valid_time_controls() {
+    assert!(TimeControl::fischer(secs(180), secs(2)).is_valid());
+    assert!(TimeControl::Hourglass(secs(30)).is_valid());
+
+    let no_periods = TimeControl::Periods {
+        periods: vec![],
+        bonus: TimeBonus::None,
+    };
+    assert!(!no_periods.is_valid());
+    assert_eq!(no_periods.starting_time(), secs(0));
+    assert!(!TimeControl::sudden_death(secs(0)).is_valid());
+    assert!(!TimeControl::sudden_death(MAX_TIME + secs(1)).is_valid());
+    assert!(!TimeControl::delay(secs(60), Duration::MAX).is_valid());
+    assert!(!TimeControl::Hourglass(secs(0)).is_valid());
+    let no_moves = TimeControl::Periods {
+        periods: vec![Period {
+            moves: Some(0),
+            time: secs(60),
+        }],
+        bonus: TimeBonus::None,
+    };
+    assert!(!no_moves.is_valid());
+
+    // a clock still runs without periods, and the other team has no time
+    let (mut clock, _) = manual_clock(no_periods);
+    assert!(clock.press(ChessTeam::White).is_ok());
+    assert_eq!(clock.flagged(), Some(ChessTeam::Black));
+}
+
+#[test]
+fn flag_and_pause() {
+    let (mut clock, time) = manual_clock(TimeControl::sudden_death(secs(10)));
+    clock.start(ChessTeam::White);
+    time.advance(secs(4));
+
+    // nothing runs while paused
+    clock.stop();
+    time.advance(secs(100));
+    assert_eq!(clock.remaining(ChessTeam::White), secs(6));
+    assert_eq!(clock.flagged(), None);
+
+    clock.start(ChessTeam::White);
+    time.advance(secs(6));
+    assert_eq!(clock.flagged(), Some(ChessTeam::White));
+    assert_eq!(clock.press(ChessTeam::White), Err(ChessTeam::White));
+}
+
+#[test]
+fn game_timeout() {
+    let (clock, time) = manual_clock(TimeControl::fischer(secs(60), secs(1)));
+    let mut game = GameState::init();
+    game.set_clock(Some(clock));
+
+    let e4 = move_processor::parse_move("e4".to_string(), &mut game).unwrap();
+    game.perform_move(e4).unwrap();
+    time.advance(secs(2));
+    let e5 = move_processor::parse_move("e5".to_string(), &mut game).unwrap();
+    game.perform_move(e5).unwrap();
+
+    let black_time = game.get_move_time(1).unwrap();
+    assert_eq!(black_time.spent, secs(2));
+    assert_eq!(black_time.remaining, secs(59));
+    assert_eq!(game.get_move_time(0).unwrap().spent, secs(0));
+    assert!(game.get_end_state() == GameEndState::Running);
+
+    time.advance(secs(61));
+    assert!(game.get_end_state() == GameEndState::Timeout);
+    let nf3 = move_processor::parse_move("Nf3".to_string(), &mut game).unwrap();
+    assert_eq!(game.perform_move(nf3).err(), Some(MoveError::OutOfTime));
+    assert_eq!(game.move_count(), 2);
+}
+
+#[test]
+fn timeout_without_mating_material() {
+    let (clock, time) = manual_clock(TimeControl::sudden_death(secs(60)));
+    let mut game = parse_fen("8/8/4k3/8/8/8/3NK3/8 b - - 0 1".to_string()).unwrap();
+    game.set_clock(Some(clock));
+    game.get_clock_mut().unwrap().start(ChessTeam::Black);
+    time.advance(secs(60));
+    // a knight can't checkmate a lone king
+    assert!(game.get_end_state() == GameEndState::Draw);
+}
+
+#[test]
+fn sync() {
+    let (mut clock, time) = manual_clock(TimeControl::sudden_death(secs(60)));
+    clock.sync([secs(30), secs(5)], Some(ChessTeam::Black));
+    assert_eq!(clock.remaining(ChessTeam::White), secs(30));
+    time.advance(secs(2));
+    assert_eq!(clock.remaining(ChessTeam::Black), secs(3));
+
+    time.advance(secs(3));
+    assert_eq!(clock.flagged(), Some(ChessTeam::Black));
+    clock.sync([secs(30), secs(1)], None);
+    assert_eq!(clock.flagged(), None);
+    assert_eq!(clock.running(), None);
+}
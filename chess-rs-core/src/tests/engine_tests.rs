@@ -0,0 +1,217 @@
+use super::*;
+use crate::retrograde::DtmTables;
+
+fn board_from_fen(fen: &str) -> (Board, Option<Tile>) {
+    let mut game = parse_fen(fen.to_string()).unwrap();
+    let ep_square = game.en_passant_square;
+    (game.get_board().clone(), ep_square)
+}
+
+fn search_fen(fen: &str, limits: &SearchLimits) -> SearchInfo {
+    let (board, ep_square) = board_from_fen(fen);
+    Engine::new().search(&board, ep_square, limits, |_| {})
+}
+
+fn depth(depth: u32) -> SearchLimits {
+    SearchLimits {
+        depth: Some(depth),
+        ..SearchLimits::default()
+    }
+}
+
+#[test]
+fn finds_mates() {
+    let info = search_fen("k7/8/1K6/8/8/8/8/7R w - - 0 1", &depth(3));
+    assert_eq!(info.lines[0].score, Score::Mate(1));
+    assert_eq!(info.lines[0].san, vec!["Rh8#"]);
+    // a mate ends the search early
+    assert_eq!(info.depth, 1);
+
+    let info = search_fen("k7/8/2K5/8/8/8/8/1R6 w - - 0 1", &depth(4));
+    assert_eq!(info.lines[0].score, Score::Mate(2));
+    assert_eq!(info.lines[0].san, vec!["Kc7", "Ka7", "Ra1#"]);
+
+    // getting mated
+    let info = search_fen("k7/8/1K6/8/8/8/8/7R b - - 0 1", &depth(3));
+    assert_eq!(info.lines[0].score, Score::Mate(-1));
+}
+
+#[test]
+fn wins_material() {
+    // the queen is hanging
+    let info = search_fen(
+        "rnb1kbnr/pppp1ppp/8/4p1q1/4P3/3P4/PPP2PPP/RNBQKBNR w KQkq - 0 1",
+        &depth(2),
+    );
+    assert_eq!(info.lines[0].san[0], "Bxg5");
+    assert!(matches!(info.lines[0].score, Score::Cp(cp) if cp > 500));
+}
+
+#[test]
+fn multi_pv() {
+    let limits = SearchLimits {
+        depth: Some(2),
+        multi_pv: 3,
+        ..SearchLimits::default()
+    };
+
+    let mut infos = vec![];
+    let (board, ep_square) =
+        board_from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+    let info = Engine::new().search(&board, ep_square, &limits, |info| infos.push(info.clone()));
+
+    // one report per iteration, the last one is the result
+    assert_eq!(
+        infos.iter().map(|i| i.depth).collect::<Vec<_>>(),
+        vec![1, 2]
+    );
+    assert_eq!(infos.last(), Some(&info));
+    assert!(infos[1].nodes > infos[0].nodes);
+
+    assert_eq!(info.lines.len(), 3);
+    let first_moves: Vec<Move> = info.lines.iter().map(|l| l.moves[0]).collect();
+    assert!(first_moves[0] != first_moves[1] && first_moves[1] != first_moves[2]);
+    assert_eq!(info.best_move(), Some(first_moves[0]));
+
+    // best first
+    let cp = |score: Score| match score {
+        Score::Cp(cp) => cp,
+        Score::Mate(n) => n.signum() * 100_000,
+        Score::Tablebase(wdl) => wdl.value().signum() * 50_000,
+    };
+    assert!(cp(info.lines[0].score) >= cp(info.lines[1].score));
+    assert!(cp(info.lines[1].score) >= cp(info.lines[2].score));
+
+    // the lines are legal, and their notation matches
+    for line in &info.lines {
+        assert_eq!(line.san, pv_notation(&board, &line.moves));
+        let mut line_board = board.clone();
+        let mut line_ep = ep_square;
+        for the_move in &line.moves {
+            assert!(line_board.get_all_legal_moves(line_ep).contains(the_move));
+            line_board.apply_move(*the_move);
+            line_ep = the_move.get_en_passant_square();
+        }
+    }
+
+    // fewer legal moves than lines
+    let limits = SearchLimits {
+        depth: Some(2),
+        multi_pv: 5,
+        ..SearchLimits::default()
+    };
+    let info = search_fen("7k/8/8/8/8/8/6q1/7K w - - 0 1", &limits);
+    assert_eq!(info.lines.len(), 1);
+    assert_eq!(info.lines[0].san[0], "Kxg2");
+}
+
+#[test]
+fn limits() {
+    let limits = SearchLimits {
+        nodes: Some(2000),
+        ..SearchLimits::default()
+    };
+    let info = search_fen(
+        "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 0 1",
+        &limits,
+    );
+    assert!(info.depth >= 1 && info.depth < MAX_DEPTH);
+    assert!(info.best_move().is_some());
+
+    // stopped from another thread
+    let engine = Engine::new();
+    let stop = engine.stop_flag();
+    let handle = std::thread::spawn(move || {
+        let (board, ep_square) =
+            board_from_fen("r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 0 1");
+        engine.search(&board, ep_square, &SearchLimits::default(), |_| {})
+    });
+    std::thread::sleep(Duration::from_millis(200));
+    stop.store(true, Ordering::Relaxed);
+    let info = handle.join().unwrap();
+    assert!(info.best_move().is_some());
+
+    // only the given root moves
+    let (board, ep_square) = board_from_fen("k7/8/1K6/8/8/8/8/7R w - - 0 1");
+    let slow_mate = Move::PieceMove {
+        piece: ChessPiece::Rook,
+        tile_from: Tile::H1,
+        tile_to: Tile::H7,
+        is_en_passant: false,
+    };
+    let limits = SearchLimits {
+        depth: Some(2),
+        multi_pv: 3,
+        search_moves: Some(vec![slow_mate]),
+        ..SearchLimits::default()
+    };
+    let info = Engine::new().search(&board, ep_square, &limits, |_| {});
+    assert_eq!(info.lines.len(), 1);
+    assert_eq!(info.best_move(), Some(slow_mate));
+
+    // no legal moves
+    let info = search_fen("k7/1Q6/1K6/8/8/8/8/8 b - - 0 1", &depth(3));
+    assert!(info.lines.is_empty());
+    assert_eq!(info.best_move(), None);
+}
+
+#[test]
+fn tablebase() {
+    let mut tables = DtmTables::new();
+    tables.generate("KQvK").unwrap();
+
+    let mut engine = Engine::new();
+    engine.set_tablebase(Some(Arc::new(tables)));
+
+    let (board, ep_square) = board_from_fen("8/8/8/4k3/8/8/8/KQ6 w - - 0 1");
+    let info = engine.search(&board, ep_square, &depth(1), |_| {});
+    assert_eq!(info.lines[0].score, Score::Tablebase(Wdl::Win));
+    assert_eq!(info.lines[0].score.flip(), Score::Tablebase(Wdl::Loss));
+}
+
+#[test]
+fn uci_moves() {
+    let (board, ep_square) =
+        board_from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+    assert_eq!(
+        move_from_uci(&board, ep_square, "e1g1"),
+        Some(Move::CastleShort)
+    );
+    assert_eq!(
+        move_from_uci(&board, ep_square, "e1c1"),
+        Some(Move::CastleLong)
+    );
+    assert_eq!(move_from_uci(&board, ep_square, "e2e4"), None);
+
+    for the_move in board.get_all_legal_moves(ep_square) {
+        let uci = move_to_uci(&board, the_move);
+        assert_eq!(move_from_uci(&board, ep_square, &uci), Some(the_move));
+    }
+
+    let (board, ep_square) = board_from_fen("8/1P5k/8/8/8/8/8/K7 w - - 0 1");
+    assert_eq!(
+        move_from_uci(&board, ep_square, "b7b8n"),
+        Some(Move::PieceMoveWithPromotion {
+            tile_from: Tile::B7,
+            tile_to: Tile::B8,
+            promotion: ChessPiece::Knight,
+        })
+    );
+}
+
+#[test]
+fn notation() {
+    assert_eq!(Score::Cp(35).to_string(), "+0.35");
+    assert_eq!(Score::Cp(-120).to_string(), "-1.20");
+    assert_eq!(Score::Mate(3).to_string(), "#3");
+    assert_eq!(Score::Tablebase(Wdl::Win).to_string(), "+TB");
+    assert_eq!(Score::Mate(-2).flip(), Score::Mate(2));
+
+    let line = PvLine {
+        score: Score::Cp(0),
+        moves: vec![],
+        san: vec!["e5".to_string(), "Nf3".to_string(), "Nc6".to_string()],
+    };
+    assert_eq!(line.format_san(1, ChessTeam::Black), "1...e5 2.Nf3 Nc6");
+    assert_eq!(line.format_san(7, ChessTeam::White), "7.e5 Nf3 8.Nc6");
+}
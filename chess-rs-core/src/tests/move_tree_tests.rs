@@ -0,0 +1,110 @@
+use super::*;
+use crate::engine;
+
+fn uci(game: &mut GameState, uci: &str) -> Move {
+    let ep_square = game.en_passant_square;
+    engine::move_from_uci(game.get_board(), ep_square, uci).unwrap()
+}
+
+fn play(game: &mut GameState, uci_moves: &[&str]) {
+    for m in uci_moves {
+        let the_move = uci(game, m);
+        game.perform_move(the_move).unwrap();
+    }
+}
+
+#[test]
+fn tree() {
+    let mut game = GameState::init();
+    let e4 = uci(&mut game, "e2e4");
+    let d4 = uci(&mut game, "d2d4");
+
+    let mut tree = MoveTree::new();
+    assert_eq!(tree.add_move(&[], e4), 0);
+    assert_eq!(tree.add_move(&[], d4), 1);
+    assert_eq!(tree.add_move(&[], e4), 0);
+    assert_eq!(tree.add_move(&[1], e4), 0);
+
+    assert!(tree.contains(&[1, 0]));
+    assert!(!tree.contains(&[2]));
+    assert_eq!(tree.moves_to(&[1, 0]), vec![d4, e4]);
+    assert_eq!(tree.main_line_from(&[1]), vec![1, 0]);
+    assert_eq!(tree.node(&[1]).unwrap().the_move, d4);
+
+    // the main line has nothing to promote
+    assert_eq!(tree.promote_variation(&[0]), None);
+    assert_eq!(tree.promote_variation(&[1, 0]), Some((0, 1)));
+    assert_eq!(tree.roots[0].the_move, d4);
+
+    assert_eq!(tree.delete(&[1]).unwrap().the_move, e4);
+    assert_eq!(tree.delete(&[1]), None);
+    assert_eq!(tree.roots.len(), 1);
+}
+
+#[test]
+fn variations() {
+    let mut game = GameState::init();
+    play(&mut game, &["e2e4", "e7e5", "g1f3"]);
+    let main_line = game.get_pgn();
+
+    // trying something else for Black
+    game.go_to(1);
+    assert_eq!(
+        game.get_fen(),
+        parse_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1".to_string())
+            .unwrap()
+            .get_fen()
+    );
+    play(&mut game, &["c7c5", "g1f3"]);
+    assert_eq!(game.get_line_path(), &[0, 1, 0]);
+    assert_eq!(game.cursor(), 3);
+    assert_eq!(game.get_pgn(), "1. e4 e5 (1... c5 2. Nf3) 2. Nf3");
+
+    // playing a move that was tried follows its line
+    game.go_to(1);
+    play(&mut game, &["e7e5"]);
+    assert_eq!(game.get_line_path(), &[0, 0, 0]);
+    assert_eq!(game.cursor(), 2);
+    assert_eq!(game.move_count(), 3);
+
+    assert!(game.go_to_path(&[0, 1]));
+    assert_eq!(game.get_cursor_path(), &[0, 1]);
+    assert_eq!(game.get_line_path(), &[0, 1, 0]);
+    assert!(game.go_forward());
+    assert!(!game.go_forward());
+    assert!(game.get_board_at(3) == *game.get_board());
+    assert!(!game.go_to_path(&[0, 2]));
+
+    // the Sicilian becomes the main line, the cursor stays on it
+    assert!(game.promote_variation(&[0, 1, 0]));
+    assert_eq!(game.get_line_path(), &[0, 0, 0]);
+    assert_eq!(game.get_pgn(), "1. e4 c5 (1... e5 2. Nf3) 2. Nf3");
+
+    assert!(game.delete_variation(&[0, 0]));
+    assert_eq!(game.get_line_path(), &[0, 0, 0]);
+    assert_eq!(game.cursor(), 1);
+    assert_eq!(game.get_pgn(), main_line);
+    assert!(!game.delete_variation(&[]));
+}
+
+#[test]
+fn cursor_state() {
+    let mut game = GameState::init();
+    play(&mut game, &["e2e4", "g8f6", "g1f3"]);
+    assert_eq!(game.fifty_move_counter, 2);
+
+    game.go_to(1);
+    assert_eq!(game.fifty_move_counter, 0);
+    assert_eq!(game.en_passant_square, game.get_en_passant_square_at(1));
+    assert_eq!(game.whose_turn(), ChessTeam::Black);
+    assert_eq!(
+        game.get_fen(),
+        "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1"
+    );
+
+    game.go_to(3);
+    assert_eq!(
+        game.get_fen(),
+        "rnbqkb1r/pppppppp/5n2/8/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 2 2"
+    );
+}
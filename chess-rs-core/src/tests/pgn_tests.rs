@@ -0,0 +1,249 @@
+use super::*;
+
+#[test]
+fn round_trip() {
+    let pgn = "1. e4 e5 (1... c5 2. Nf3 (2. c3 d5) 2... d6) (1... e6) 2. Nf3 Nc6 3. Bb5 (3. Bc4 Bc5) 3... a6";
+    let mut game = parse_pgn(pgn).unwrap();
+    assert_eq!(game.get_pgn(), pgn);
+
+    // looking at the end of the main line
+    assert_eq!(game.move_count(), 6);
+    assert_eq!(game.cursor(), 6);
+    assert_eq!(game.get_line_path(), &[0, 0, 0, 0, 0, 0]);
+
+    let tree = game.get_move_tree();
+    assert_eq!(tree.children(&[0]).unwrap().len(), 3);
+    assert_eq!(tree.children(&[0, 1]).unwrap().len(), 2);
+}
+
+#[test]
+fn en_passant() {
+    let pgn = "1. e4 a6 2. e5 d5 3. exd6 c6";
+    let mut game = parse_pgn(pgn).unwrap();
+    assert_eq!(game.get_pgn(), pgn);
+
+    // the e.p. some add after the capture is not a move
+    let mut game = parse_pgn("1. e4 a6 2. e5 d5 3. exd6 e.p. c6").unwrap();
+    assert_eq!(game.get_pgn(), pgn);
+}
+
+#[test]
+fn tags_and_comments() {
+    let pgn = r#"[Event "Casual game"]
+[White "Someone \"quoted\""]
+
+{ opening } 1.e4 $1 e5!? 2. Nf3 ; the knight
+Nc6?? (2... d6! {Philidor}) 3. 0-0-0? 1-0"#;
+
+    // castling long isn't legal there
+    assert_eq!(
+        parse_pgn(pgn).err(),
+        Some(PgnError::IllegalMove("O-O-O".to_string()))
+    );
+
+    let pgn = pgn.replace("0-0-0?", "Bb5");
+    let mut game = parse_pgn(&pgn).unwrap();
+    assert_eq!(
+        game.get_pgn(),
+        "{opening} 1. e4 $1 e5 $5 2. Nf3 {the knight} 2... Nc6 $4 (2... d6 $1 {Philidor}) 3. Bb5"
+    );
+}
+
+#[test]
+fn annotations() {
+    let pgn = "1. e4 {[%csl Gd5,Re5][%cal Ge2e4,Bd7d5] The center.} 1... e5 $2 {[%clk 0:05:00]}";
+    let mut game = parse_pgn(pgn).unwrap();
+    assert_eq!(game.get_pgn(), pgn);
+
+    let e4 = game.get_annotations(&[0]).unwrap();
+    assert_eq!(e4.comment, "The center.");
+    assert_eq!(
+        e4.marks,
+        vec![
+            Mark::Square {
+                tile: Tile::D5,
+                color: MarkColor::Green
+            },
+            Mark::Square {
+                tile: Tile::E5,
+                color: MarkColor::Red
+            },
+            Mark::Arrow {
+                from: Tile::E2,
+                to: Tile::E4,
+                color: MarkColor::Green
+            },
+            Mark::Arrow {
+                from: Tile::D7,
+                to: Tile::D5,
+                color: MarkColor::Blue
+            },
+        ]
+    );
+
+    // other commands stay in the comment
+    let e5 = game.get_annotations(&[0, 0]).unwrap();
+    assert_eq!(e5.nags, vec![2]);
+    assert_eq!(e5.comment, "[%clk 0:05:00]");
+    assert_eq!(nag_glyph(2), Some("?"));
+
+    // marks alone, on the starting position
+    let annotations = game.get_annotations_mut(&[]).unwrap();
+    annotations.marks.push(Mark::Arrow {
+        from: Tile::G1,
+        to: Tile::F3,
+        color: MarkColor::Yellow,
+    });
+    assert_eq!(game.get_pgn(), format!("{{[%cal Yg1f3]}} {}", pgn));
+}
+
+#[test]
+fn custom_position() {
+    let pgn = "[SetUp \"1\"]\n[FEN \"4k3/8/8/8/8/8/4P3/4K3 b - - 0 30\"]\n\n30... Kd7 31. e4 (31. Kd2 Ke6) 31... Kd6";
+    let mut game = parse_pgn(pgn).unwrap();
+    assert_eq!(game.get_pgn(), pgn);
+    assert_eq!(game.get_full_move_count_at(3), 32);
+}
+
+#[test]
+fn clock_times() {
+    use crate::clock::{Clock, ManualTime, TimeControl};
+    use std::sync::Arc;
+
+    let time = Arc::new(ManualTime::new());
+    let control = TimeControl::fischer(Duration::from_secs(3600), Duration::from_secs(2));
+    let mut game = GameState::init();
+    game.set_clock(Some(Clock::new(control, time.clone())));
+
+    let e4 = move_processor::parse_move("e4".to_string(), &mut game).unwrap();
+    game.perform_move(e4).unwrap();
+    game.get_annotations_mut(&[0]).unwrap().comment = "The center.".to_string();
+    time.advance(Duration::from_secs(75));
+    let e5 = move_processor::parse_move("e5".to_string(), &mut game).unwrap();
+    game.perform_move(e5).unwrap();
+
+    assert_eq!(
+        game.get_pgn(),
+        "1. e4 {[%clk 1:00:00] The center.} 1... e5 {[%clk 0:58:47]}"
+    );
+}
+
+#[test]
+fn errors() {
+    assert_eq!(
+        parse_pgn("(1. e4)").err(),
+        Some(PgnError::VariationWithoutMove)
+    );
+    assert_eq!(
+        parse_pgn("1. e4 (1. d4").err(),
+        Some(PgnError::UnbalancedVariation)
+    );
+    assert_eq!(
+        parse_pgn("1. e4 )").err(),
+        Some(PgnError::UnbalancedVariation)
+    );
+    assert_eq!(
+        parse_pgn("1. e4 { e5").err(),
+        Some(PgnError::UnterminatedComment)
+    );
+    assert_eq!(
+        parse_pgn("[FEN \"nope\"] 1. e4").err(),
+        Some(PgnError::InvalidFen)
+    );
+    assert_eq!(
+        parse_pgn("[FEN nope] 1. e4").err(),
+        Some(PgnError::InvalidTag)
+    );
+    assert_eq!(
+        parse_pgn("1. e5").err(),
+        Some(PgnError::IllegalMove("e5".to_string()))
+    );
+}
+
+#[test]
+fn tags() {
+    let mut game = parse_fen("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1".to_string()).unwrap();
+    game.set_tag("White", "lucy".to_string());
+    game.set_tag("Black", "Someone \"quoted\"".to_string());
+    game.set_tag("White", "pero".to_string());
+    assert_eq!(game.get_tag("White"), Some("pero"));
+
+    let e4 = move_processor::parse_move("e4".to_string(), &mut game).unwrap();
+    game.perform_move(e4).unwrap();
+    let pgn = game.get_pgn();
+    assert_eq!(
+        pgn,
+        "[White \"pero\"]\n[Black \"Someone \\\"quoted\\\"\"]\n[SetUp \"1\"]\n[FEN \"4k3/8/8/8/8/8/4P3/4K3 w - - 0 1\"]\n\n1. e4"
+    );
+    // the importer skips them
+    assert_eq!(parse_pgn(&pgn).unwrap().get_tags(), &[]);
+
+    // the result ends the movetext too
+    game.set_tag("Result", "1/2-1/2".to_string());
+    assert!(game.get_pgn().ends_with("[Result \"1/2-1/2\"]\n[SetUp \"1\"]\n[FEN \"4k3/8/8/8/8/8/4P3/4K3 w - - 0 1\"]\n\n1. e4 1/2-1/2"));
+    assert!(parse_pgn(&game.get_pgn()).is_ok());
+}
+
+#[test]
+fn time_control_tags() {
+    use crate::clock::Period;
+
+    let secs = Duration::from_secs;
+    assert_eq!(
+        time_control_tag(&TimeControl::fischer(secs(180), secs(2))),
+        "180+2"
+    );
+    assert_eq!(time_control_tag(&TimeControl::sudden_death(secs(60))), "60");
+    assert_eq!(
+        time_control_tag(&TimeControl::delay(secs(300), secs(5))),
+        "300d5"
+    );
+    assert_eq!(time_control_tag(&TimeControl::Hourglass(secs(60))), "*60");
+
+    let classical = TimeControl::Periods {
+        periods: vec![
+            Period {
+                moves: Some(40),
+                time: secs(5400),
+            },
+            Period {
+                moves: None,
+                time: secs(1800),
+            },
+        ],
+        bonus: TimeBonus::Fischer(secs(30)),
+    };
+    assert_eq!(time_control_tag(&classical), "40/5400:1800+30");
+}
+
+#[test]
+fn dates() {
+    assert_eq!(pgn_date(0), "1970.01.01");
+    assert_eq!(pgn_date(1_600_000_000), "2020.09.13");
+    assert_eq!(pgn_date(951_782_400), "2000.02.29");
+    assert_eq!(pgn_date(951_868_799), "2000.02.29");
+
+    assert_eq!(parse_pgn_date("2020.09.13"), Some(1_599_955_200));
+    assert_eq!(parse_pgn_date("2000-02-29"), Some(951_782_400));
+    assert_eq!(parse_pgn_date("1970.01.01"), Some(0));
+    assert_eq!(parse_pgn_date("2020.13.01"), None);
+    assert_eq!(parse_pgn_date("yesterday"), None);
+    assert_eq!(parse_pgn_date("2020.09"), None);
+
+    for secs in (0..4_000_000_000u64).step_by(86400 * 37) {
+        let date = pgn_date(secs);
+        assert_eq!(parse_pgn_date(&date), Some(secs - secs % 86400), "{}", date);
+    }
+}
+
+#[test]
+fn set_move_times() {
+    let mut game = parse_pgn("1. e4 e5").unwrap();
+    let time = MoveTime {
+        spent: Duration::from_secs(3),
+        remaining: Duration::from_secs(57),
+    };
+    game.set_move_time(1, Some(time));
+    assert_eq!(game.get_move_time(1), Some(time));
+    assert_eq!(game.get_pgn(), "1. e4 e5 {[%clk 0:00:57]}");
+}
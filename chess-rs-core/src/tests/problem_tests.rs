@@ -0,0 +1,194 @@
+use super::*;
+use crate::retrograde::DtmTables;
+
+fn board_from_fen(fen: &str) -> (Board, Option<Tile>) {
+    let mut game = parse_fen(fen.to_string()).unwrap();
+    let ep_square = game.en_passant_square;
+    (game.get_board().clone(), ep_square)
+}
+
+fn key_notations(solution: &ProblemSolution) -> Vec<String> {
+    solution.keys.iter().map(|k| k.notation.clone()).collect()
+}
+
+#[test]
+fn mate_in_one() {
+    let solution = solve_fen("k7/8/1K6/8/8/8/8/7R w - - 0 1", Stipulation::DirectMate(1)).unwrap();
+    assert_eq!(key_notations(&solution), vec!["Rh8#"]);
+    assert!(solution.is_sound());
+    assert_eq!(solution.to_string(), "1.Rh8#!");
+
+    // Rg8 and Rh8 both mate
+    let solution = solve_fen("k7/8/1K6/8/8/8/8/6RR w - - 0 1", Stipulation::DirectMate(1)).unwrap();
+    assert_eq!(key_notations(&solution), vec!["Rg8#", "Rh8#"]);
+    assert!(solution.is_cooked());
+    assert!(!solution.is_sound());
+
+    // stalemate is not mate
+    let solution = solve_fen("k7/8/8/1K6/8/8/8/1R6 w - - 0 1", Stipulation::DirectMate(1)).unwrap();
+    assert!(solution.keys.is_empty());
+    assert_eq!(solution.to_string(), "#1: no solution");
+}
+
+#[test]
+fn mate_in_two() {
+    let solution = solve_fen("k7/8/2K5/8/8/8/8/1R6 w - - 0 1", Stipulation::DirectMate(2)).unwrap();
+    assert_eq!(key_notations(&solution), vec!["Kc7"]);
+    assert_eq!(solution.keys[0].threats, vec!["Ra1#"]);
+    assert!(solution.is_sound());
+    assert_eq!(solution.to_string(), "1.Kc7! (2.Ra1#)\n  1...Ka7 2.Ra1#");
+
+    // Black is in check with White to move
+    assert_eq!(
+        solve_fen("k7/8/1K6/8/8/8/8/R6R w - - 0 1", Stipulation::DirectMate(2)),
+        None
+    );
+}
+
+#[test]
+fn duals() {
+    // after 1.Rg2 Kb8 both Rg8 and Rh8 mate
+    let solution = solve_fen("k7/8/1K6/8/8/8/8/6RR w - - 0 1", Stipulation::DirectMate(2)).unwrap();
+    assert!(solution
+        .duals()
+        .contains(&vec!["Rg2".to_string(), "Kb8".to_string()]));
+    assert!(!solution
+        .duals()
+        .contains(&vec!["Rb1".to_string(), "Kb8".to_string()]));
+
+    let the_move = Move::CastleShort;
+    let leaf = |notation: &str| SolutionNode {
+        the_move,
+        notation: notation.to_string(),
+        children: vec![],
+        threats: vec![],
+    };
+    let solution = ProblemSolution {
+        stipulation: Stipulation::DirectMate(2),
+        moves_first: ChessTeam::White,
+        keys: vec![SolutionNode {
+            the_move,
+            notation: "Qd7".to_string(),
+            children: vec![
+                SolutionNode {
+                    the_move,
+                    notation: "Kb8".to_string(),
+                    children: vec![leaf("Qc7#")],
+                    threats: vec![],
+                },
+                SolutionNode {
+                    the_move,
+                    notation: "Ka8".to_string(),
+                    children: vec![leaf("Qc8#"), leaf("Qa7#")],
+                    threats: vec![],
+                },
+            ],
+            threats: vec!["Qc7#".to_string()],
+        }],
+    };
+
+    assert_eq!(solution.duals(), vec![vec!["Qd7", "Ka8"]]);
+    assert!(!solution.is_sound());
+    assert_eq!(
+        solution.to_string(),
+        "1.Qd7! (2.Qc7#)\n  1...Kb8 2.Qc7#\n  1...Ka8\n    2.Qc8# (dual)\n    2.Qa7# (dual)"
+    );
+}
+
+// The keys of #n are the moves that the distance to mate tables say mate in n
+#[test]
+fn cross_check_with_tables() {
+    let mut tables = DtmTables::new();
+    tables.generate("KRvK").unwrap();
+
+    for fen in [
+        "k7/8/1K6/8/8/8/8/7R w - - 0 1",
+        "k7/8/2K5/8/8/8/8/1R6 w - - 0 1",
+        "2k5/8/2K5/8/8/8/8/7R w - - 0 1",
+        "8/8/8/8/8/1k6/8/1K1R4 w - - 0 1",
+        "4k3/8/3K4/8/8/8/8/R7 w - - 0 1",
+    ] {
+        let (board, ep_square) = board_from_fen(fen);
+
+        for n in 1..=2 {
+            let solution = solve(&board, ep_square, Stipulation::DirectMate(n));
+            let keys: Vec<Move> = solution.keys.iter().map(|k| k.the_move).collect();
+
+            let expected: Vec<Move> = board
+                .get_all_legal_moves(ep_square)
+                .into_iter()
+                .filter(|the_move| {
+                    let mut future_board = board.clone();
+                    future_board.apply_move(*the_move);
+                    let future = tables.solve(&future_board).unwrap();
+                    future.wdl == tablebase::Wdl::Loss && -future.dtm <= 2 * (n as i32 - 1)
+                })
+                .collect();
+
+            assert_eq!(keys, expected, "{} #{}", fen, n);
+        }
+    }
+}
+
+#[test]
+fn stipulations() {
+    assert_eq!(Stipulation::parse("#2"), Some(Stipulation::DirectMate(2)));
+    assert_eq!(Stipulation::parse("3"), Some(Stipulation::DirectMate(3)));
+    assert_eq!(Stipulation::parse("s#2"), Some(Stipulation::Selfmate(2)));
+    assert_eq!(Stipulation::parse("h#3"), Some(Stipulation::Helpmate(3)));
+    assert_eq!(Stipulation::parse("#0"), None);
+    assert_eq!(Stipulation::parse("x#2"), None);
+    assert_eq!(Stipulation::parse("mate"), None);
+    assert_eq!(Stipulation::DirectMate(2).to_string(), "#2");
+    assert_eq!(Stipulation::Helpmate(2).to_string(), "h#2");
+}
+
+#[test]
+fn selfmate() {
+    // 1.Rd1+ leaves Black nothing but Rxd1#
+    let solution = solve_fen(
+        "3r4/8/8/8/1N6/B2R4/6PP/k6K w - - 0 1",
+        Stipulation::Selfmate(1),
+    )
+    .unwrap();
+    assert_eq!(key_notations(&solution), vec!["Rd1+"]);
+    assert!(solution.is_sound());
+    assert_eq!(solution.to_string(), "1.Rd1+!\n  1...Rxd1#");
+
+    // White can't force it if the knight can take back
+    let solution = solve_fen(
+        "3r4/8/8/8/8/B1NR4/6PP/k6K w - - 0 1",
+        Stipulation::Selfmate(1),
+    )
+    .unwrap();
+    assert!(solution.keys.is_empty());
+
+    // a direct mate is not a selfmate
+    let solution = solve_fen("k7/8/1K6/8/8/8/8/7R w - - 0 1", Stipulation::Selfmate(1)).unwrap();
+    assert!(solution.keys.is_empty());
+}
+
+#[test]
+fn helpmate() {
+    let solution = solve_fen("k7/8/1K6/8/8/8/8/7R b - - 0 1", Stipulation::Helpmate(1)).unwrap();
+    assert_eq!(solution.moves_first, ChessTeam::Black);
+    assert_eq!(solution.lines(), vec![vec!["Kb8", "Rh8#"]]);
+    assert!(solution.is_sound());
+    assert_eq!(solution.to_string(), "1.Kb8 Rh8#");
+
+    // every line has exactly 2 moves of each team and ends in mate
+    let mut game = parse_fen("k7/8/2K5/8/8/8/8/7R b - - 0 1".to_string()).unwrap();
+    let solution = solve_game(&mut game, Stipulation::Helpmate(2));
+    assert!(solution.is_cooked());
+    assert!(solution.duals().is_empty());
+    assert!(!solution.is_sound());
+    for line in solution.lines() {
+        assert_eq!(line.len(), 4);
+        assert!(line[3].ends_with('#'));
+        assert!(!line[1].ends_with('#'));
+    }
+    assert!(solution
+        .to_string()
+        .lines()
+        .any(|l| l == "1.Ka7 Kc7 2.Ka8 Ra1#"));
+}
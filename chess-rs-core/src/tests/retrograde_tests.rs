@@ -0,0 +1,253 @@
+use super::*;
+use std::sync::OnceLock;
+
+// Generating is slow in debug builds, so all the tests share these
+fn tables() -> &'static DtmTables {
+    static TABLES: OnceLock<DtmTables> = OnceLock::new();
+    TABLES.get_or_init(|| {
+        let mut tables = DtmTables::new();
+        tables.generate("KQvK").unwrap();
+        tables.generate("KRvK").unwrap();
+        tables.generate("KPvK").unwrap();
+        tables
+    })
+}
+
+fn board_from_fen(fen: &str) -> Board {
+    parse_fen(fen.to_string()).unwrap().get_board().clone()
+}
+
+fn temp_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("chess-rs-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn materials() {
+    assert_eq!(Material::parse("KvKQ").unwrap().key(), "KvKQ");
+    assert_eq!(Material::parse("KNBvK").unwrap().key(), "KBNvK");
+    assert!(!Material::parse("KvKQ").unwrap().is_canonical());
+    assert!(Material::parse("KQvKR").unwrap().is_canonical());
+    assert_eq!(
+        Material::parse("KQvK").unwrap().flipped(),
+        Material::parse("KvKQ").unwrap()
+    );
+
+    assert_eq!(
+        Material::parse("KQK"),
+        Err(GeneratorError::InvalidMaterial("KQK".to_string()))
+    );
+    assert_eq!(
+        Material::parse("KQvKK"),
+        Err(GeneratorError::InvalidMaterial("KQvKK".to_string()))
+    );
+    assert_eq!(
+        Material::parse("KQRBvK"),
+        Err(GeneratorError::TooManyPieces("KQRBvK".to_string()))
+    );
+}
+
+#[test]
+fn longest_mates() {
+    // mate in 10 and mate in 16 moves
+    assert_eq!(tables().get("KQvK").unwrap().longest_mate(), 19);
+    assert_eq!(tables().get("KRvK").unwrap().longest_mate(), 31);
+
+    // KPvK needs the tables of everything the pawn can promote to
+    assert_eq!(
+        tables().keys(),
+        vec!["KBvK", "KNvK", "KPvK", "KQvK", "KRvK"]
+    );
+    assert_eq!(tables().get("KNvK").unwrap().longest_mate(), 0);
+}
+
+#[test]
+fn solve_positions() {
+    let tables = tables();
+
+    let solution = tables
+        .solve(&board_from_fen("k7/8/1K6/8/8/8/8/7Q w - - 0 1"))
+        .unwrap();
+    assert_eq!(solution.wdl, Wdl::Win);
+    assert_eq!(solution.dtm, 1);
+
+    // the same position, with the colours swapped
+    let solution = tables
+        .solve(&board_from_fen("7q/8/8/8/8/1k6/8/K7 b - - 0 1"))
+        .unwrap();
+    assert_eq!(solution.wdl, Wdl::Win);
+    assert_eq!(solution.dtm, 1);
+
+    // king in front of its pawn on the 6th rank wins
+    let solution = tables
+        .solve(&board_from_fen("4k3/8/4K3/4P3/8/8/8/8 b - - 0 1"))
+        .unwrap();
+    assert_eq!(solution.wdl, Wdl::Loss);
+    assert!(solution.dtm < 0);
+
+    // stalemate
+    let solution = tables
+        .solve(&board_from_fen("4k3/4P3/4K3/8/8/8/8/8 b - - 0 1"))
+        .unwrap();
+    assert_eq!(solution.wdl, Wdl::Draw);
+    assert_eq!(solution.best_move, None);
+
+    let solution = tables
+        .solve(&board_from_fen("4k3/4P3/4K3/8/8/8/8/8 w - - 0 1"))
+        .unwrap();
+    assert_eq!(solution.wdl, Wdl::Win);
+
+    // the king in the corner stops a rook pawn
+    let solution = tables
+        .solve(&board_from_fen("k7/8/8/8/8/8/P7/K7 w - - 0 1"))
+        .unwrap();
+    assert_eq!(solution.wdl, Wdl::Draw);
+    assert_eq!(solution.dtm, 0);
+
+    assert!(matches!(
+        tables.solve(&board_from_fen("4k3/8/8/8/8/8/8/R3K3 w Q - 0 1")),
+        Err(TablebaseError::CastlingRights)
+    ));
+    assert!(matches!(
+        tables.solve(&board_from_fen("4k3/8/8/8/8/8/8/B3K1N1 w - - 0 1")),
+        Err(TablebaseError::MissingTable(_))
+    ));
+}
+
+// Plays the best moves of the tables with the real move generator until mate
+#[test]
+fn play_to_mate() {
+    let tables = tables();
+
+    for fen in [
+        "8/8/8/3k4/8/8/8/R3K3 w - - 0 1",
+        "8/8/8/8/8/3k4/8/3K3Q b - - 0 1",
+        "8/8/8/8/2k5/8/4P3/4K3 w - - 0 1",
+    ] {
+        let mut board = board_from_fen(fen);
+        let mut solution = tables.solve(&board).unwrap();
+        assert_ne!(solution.wdl, Wdl::Draw, "{}", fen);
+
+        while let Some(the_move) = solution.best_move {
+            board.apply_move(the_move);
+            let next = tables.solve(&board).unwrap();
+            assert_eq!(next.dtm.abs(), solution.dtm.abs() - 1, "{}", fen);
+            assert_eq!(next.wdl, solution.wdl.flip(), "{}", fen);
+            solution = next;
+        }
+
+        assert_eq!(solution.wdl, Wdl::Loss);
+        assert!(board.get_all_legal_moves(None).is_empty());
+        assert!(board.is_team_in_check(board.whose_turn, None));
+    }
+}
+
+// The generator has its own move generator. It should agree with the real one.
+#[test]
+fn cross_check_move_generation() {
+    let tables = tables();
+    let material = Material::parse("KPvK").unwrap();
+    let generator = Generator::new(&material, tables);
+
+    let mut checked = 0;
+    for index in (0..material.table_size()).step_by(97) {
+        let pos = generator.view.position(index);
+        if !generator.view.is_legal(&pos) {
+            continue;
+        }
+
+        let mut board = Board {
+            whose_turn: pos.whose_turn,
+            piece_locations: HashMap::new(),
+            castling_rights: (false, false, false, false),
+        };
+        for (slot, (team, piece)) in material.pieces.iter().enumerate() {
+            board.piece_locations.insert(
+                tile_from_square(pos.squares[slot]),
+                TeamedChessPiece(*team, *piece),
+            );
+        }
+
+        assert_eq!(
+            generator.legal_moves(&pos).len(),
+            board.get_all_legal_moves(None).len(),
+            "{:?}",
+            pos
+        );
+        checked += 1;
+    }
+
+    assert!(checked > 1000);
+}
+
+#[test]
+fn table_files() {
+    let table = tables().get("KRvK").unwrap();
+
+    let mut buf = vec![];
+    table.write(&mut buf).unwrap();
+    assert!(buf.len() < table.values.len() / 8);
+
+    let read = DtmTable::read(&mut buf.as_slice()).unwrap();
+    assert_eq!(read.key(), "KRvK");
+    assert!(read.values == table.values);
+
+    // with pawns, only the files can be mirrored
+    let table = tables().get("KPvK").unwrap();
+    let mut pawn_buf = vec![];
+    table.write(&mut pawn_buf).unwrap();
+    let read = DtmTable::read(&mut pawn_buf.as_slice()).unwrap();
+    assert!(read.values == table.values);
+
+    buf[0] = b'X';
+    assert!(DtmTable::read(&mut buf.as_slice()).is_err());
+
+    // through a directory, and through the Tablebase interface
+    let dir = temp_dir("dtm");
+    let mut saved = DtmTables::new();
+    saved.generate("KRvK").unwrap();
+    saved.save(&dir).unwrap();
+    assert!(dir.join("KRvK.dtm").exists());
+
+    let opened = DtmTables::open(&dir).unwrap();
+    assert_eq!(opened.keys(), vec!["KRvK"]);
+    assert_eq!(opened.max_pieces(), 3);
+
+    let board = board_from_fen("k7/8/1K6/8/8/8/8/7R w - - 0 1");
+    assert_eq!(opened.probe_wdl(&board, None).unwrap(), Wdl::Win);
+    let best = tablebase::best_move(&opened, &board, None)
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        best.the_move,
+        opened.solve(&board).unwrap().best_move.unwrap()
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+// The longest bishop and knight mate is mate in 33. Slow, so it only runs with
+//  --ignored
+#[test]
+#[ignore]
+fn kbnk() {
+    let mut tables = DtmTables::new();
+    tables.generate("KBNvK").unwrap();
+    assert_eq!(tables.get("KBNvK").unwrap().longest_mate(), 65);
+
+    // the king is mated in the corner of the bishop's colour
+    let solution = tables
+        .solve(&board_from_fen("7k/4N3/7K/8/1B6/8/8/8 w - - 0 1"))
+        .unwrap();
+    assert_eq!(solution.wdl, Wdl::Win);
+    assert_eq!(solution.dtm, 1);
+
+    // and has to be driven there from the other one
+    let solution = tables
+        .solve(&board_from_fen("k7/8/2KB4/8/8/8/8/7N b - - 0 1"))
+        .unwrap();
+    assert_eq!(solution.wdl, Wdl::Loss);
+    assert!(solution.dtm < -1);
+}
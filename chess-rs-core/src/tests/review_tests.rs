@@ -0,0 +1,82 @@
+use super::*;
+use crate::engine;
+
+fn play(uci_moves: &[&str]) -> GameState {
+    let mut game = GameState::init();
+    for uci in uci_moves {
+        let ep_square = game.en_passant_square;
+        let the_move = engine::move_from_uci(game.get_board(), ep_square, uci).unwrap();
+        game.perform_move(the_move).unwrap();
+    }
+    game
+}
+
+fn depth(depth: u32) -> SearchLimits {
+    SearchLimits {
+        depth: Some(depth),
+        ..SearchLimits::default()
+    }
+}
+
+#[test]
+fn scholars_mate() {
+    // 1. e4 e5 2. Qh5 Nc6 3. Bc4 Nf6?? 4. Qxf7#
+    let game = play(&["e2e4", "e7e5", "d1h5", "b8c6", "f1c4", "g8f6", "h5f7"]);
+    let review = review_game(&game, &Engine::new(), &depth(3), |_, _| true).unwrap();
+
+    assert_eq!(review.moves.len(), 7);
+    assert_eq!(review.evals.len(), 8);
+
+    let blunder = &review.moves[5];
+    assert_eq!(blunder.notation, "Nf6");
+    assert_eq!(blunder.team, ChessTeam::Black);
+    assert_eq!(blunder.class, MoveClass::Blunder);
+    assert!(blunder.best_notation.is_some());
+    assert!(blunder.cp_loss >= 300);
+
+    let mate = &review.moves[6];
+    assert_eq!(mate.notation, "Qxf7#");
+    assert_eq!(mate.class, MoveClass::Best);
+    assert_eq!(mate.best_notation, None);
+
+    // Black is checkmated at the end
+    assert_eq!(review.evals[7], None);
+    assert_eq!(review.eval_graph()[7], MATE_CP);
+    assert_eq!(review.evals[6], Some(Score::Mate(1)));
+
+    assert_eq!(review.count(ChessTeam::Black, MoveClass::Blunder), 1);
+    assert!(
+        review.accuracy(ChessTeam::White).unwrap() > review.accuracy(ChessTeam::Black).unwrap()
+    );
+
+    let pgn = review.to_pgn();
+    assert!(pgn.starts_with("1. e4 e5 2. Qh5"), "{}", pgn);
+    assert!(pgn.contains("3. Bc4 Nf6 $4 { ("));
+    assert!(pgn.ends_with("} 4. Qxf7#"));
+}
+
+#[test]
+fn abandoned() {
+    let game = play(&["e2e4", "e7e5"]);
+    let mut calls = vec![];
+    let review = review_game(&game, &Engine::new(), &depth(1), |done, total| {
+        calls.push((done, total));
+        done < 2
+    });
+    assert_eq!(review, None);
+    assert_eq!(calls, vec![(1, 3), (2, 3)]);
+}
+
+#[test]
+fn accuracy() {
+    assert_eq!(win_percent(0), 50.);
+    assert!(win_percent(300) > 70. && win_percent(-300) < 30.);
+    assert!(move_accuracy(60., 60.) > 99.9);
+    assert!(move_accuracy(80., 20.) < 10.);
+
+    assert_eq!(MoveClass::from_cp_loss(0), MoveClass::Good);
+    assert_eq!(MoveClass::from_cp_loss(60), MoveClass::Inaccuracy);
+    assert_eq!(MoveClass::from_cp_loss(150), MoveClass::Mistake);
+    assert_eq!(MoveClass::from_cp_loss(1000), MoveClass::Blunder);
+    assert_eq!(MoveClass::Mistake.nag(), Some(2));
+}
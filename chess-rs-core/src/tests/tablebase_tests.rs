@@ -0,0 +1,306 @@
+use crate::retrograde::DtmTables;
+use crate::tablebase::*;
+
+// Says the team with more pieces wins. Good enough to test the code around the tables.
+struct MaterialTablebase;
+
+impl Tablebase for MaterialTablebase {
+    fn max_pieces(&self) -> usize {
+        5
+    }
+
+    fn probe_wdl(&self, board: &Board, _ep_square: Option<Tile>) -> Result<Wdl, TablebaseError> {
+        let ours = board.find_pieces_of_team(board.whose_turn).len();
+        let theirs = board
+            .find_pieces_of_team(board.whose_turn.the_other_one())
+            .len();
+
+        Ok(match ours.cmp(&theirs) {
+            std::cmp::Ordering::Greater => Wdl::Win,
+            std::cmp::Ordering::Less => Wdl::Loss,
+            std::cmp::Ordering::Equal => Wdl::Draw,
+        })
+    }
+
+    fn probe_dtz(&self, board: &Board, ep_square: Option<Tile>) -> Result<i32, TablebaseError> {
+        Ok(match self.probe_wdl(board, ep_square)? {
+            Wdl::Win => 10,
+            Wdl::Loss => -10,
+            _ => 0,
+        })
+    }
+}
+
+fn board_from_fen(fen: &str) -> (Board, Option<Tile>) {
+    let mut game = parse_fen(fen.to_string()).unwrap();
+    let ep_square = game.en_passant_square;
+    (game.get_board().clone(), ep_square)
+}
+
+// Real Syzygy tables for KQvK, KRvK, KPvK and the ones a pawn promotes to
+//  without mating material, see syzygy/README.md
+fn syzygy_dir() -> std::path::PathBuf {
+    std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src/tests/syzygy")
+}
+
+fn temp_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("chess-rs-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn material_keys() {
+    let (board, _) = board_from_fen("8/8/8/4k3/8/8/8/KQ6 w - - 0 1");
+    assert_eq!(material_key(&board), "KQvK");
+
+    let (board, _) = board_from_fen("8/8/2r5/4k3/1p6/8/6P1/KR6 b - - 0 1");
+    assert_eq!(material_key(&board), "KRPvKRP");
+
+    assert_eq!(flip_material_key("KRPvKR"), "KRvKRP");
+}
+
+#[test]
+fn best_move_mates() {
+    // Qc7 would be stalemate, there are several mates
+    let (board, ep_square) = board_from_fen("k7/3Q4/1K6/8/8/8/8/8 w - - 0 1");
+    let best = best_move(&MaterialTablebase, &board, ep_square)
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(best.wdl, Wdl::Win);
+    assert_eq!(best.dtz, 1);
+
+    let mut future_board = board.clone();
+    future_board.apply_move(best.the_move);
+    assert!(future_board.is_team_in_check(ChessTeam::Black, None));
+    assert!(future_board.get_all_legal_moves(None).is_empty());
+
+    let stalemate = rate_moves(&MaterialTablebase, &board, ep_square)
+        .unwrap()
+        .into_iter()
+        .find(|m| {
+            m.the_move
+                == Move::PieceMove {
+                    piece: ChessPiece::Queen,
+                    tile_from: Tile::D7,
+                    tile_to: Tile::C7,
+                    is_en_passant: false,
+                }
+        })
+        .unwrap();
+    assert_eq!(stalemate.wdl, Wdl::Draw);
+}
+
+#[test]
+fn best_move_takes_the_rook() {
+    // taking the rook wins, moving the knight away only draws
+    let (board, ep_square) = board_from_fen("7k/8/8/8/8/8/1r6/KN6 w - - 0 1");
+    let best = best_move(&MaterialTablebase, &board, ep_square)
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(
+        best.the_move,
+        Move::PieceMove {
+            piece: ChessPiece::King,
+            tile_from: Tile::A1,
+            tile_to: Tile::B2,
+            is_en_passant: false,
+        }
+    );
+    assert_eq!(best.wdl, Wdl::Win);
+    assert_eq!(best.dtz, 1);
+}
+
+#[test]
+fn adjudication() {
+    let mut game = parse_fen("8/8/8/4k3/8/8/8/KQ6 b - - 0 1".to_string()).unwrap();
+    assert_eq!(
+        adjudicate(&MaterialTablebase, &mut game),
+        Some(Adjudication::Win(ChessTeam::White))
+    );
+
+    // too many pieces for the tables
+    let mut game = GameState::init();
+    assert_eq!(adjudicate(&MaterialTablebase, &mut game), None);
+
+    // checkmate is not for the tablebase to decide
+    let mut game = parse_fen("k2Q4/8/1K6/8/8/8/8/8 b - - 0 1".to_string()).unwrap();
+    assert_eq!(adjudicate(&MaterialTablebase, &mut game), None);
+}
+
+#[test]
+fn syzygy_directory() {
+    let dir = temp_dir("syzygy");
+
+    std::fs::write(dir.join("KQvK.rtbw"), [0x71, 0xe8, 0x23, 0x5d, 0, 0]).unwrap();
+    std::fs::write(dir.join("KQvK.rtbz"), [0xd7, 0x66, 0x0c, 0xa5, 0, 0]).unwrap();
+    std::fs::write(dir.join("KRPvKR.rtbw"), [0x71, 0xe8, 0x23, 0x5d, 0, 0]).unwrap();
+    std::fs::write(dir.join("README.txt"), "not a table").unwrap();
+
+    let tb = SyzygyTablebase::open(&dir).unwrap();
+    assert_eq!(tb.table_count(), 2);
+    assert_eq!(tb.max_pieces(), 5);
+
+    // black has the queen: the same file is used with the colours swapped.
+    //   It is only the magic bytes, so it can't be read
+    let (board, ep_square) = board_from_fen("8/8/8/4k3/8/8/8/Kq6 w - - 0 1");
+    assert!(matches!(
+        tb.probe_wdl(&board, ep_square),
+        Err(TablebaseError::InvalidFile(_))
+    ));
+
+    let (board, ep_square) = board_from_fen("8/8/8/4k3/8/8/8/KR6 w - - 0 1");
+    assert_eq!(
+        tb.probe_wdl(&board, ep_square),
+        Err(TablebaseError::MissingTable("KRvK".to_string()))
+    );
+
+    let (board, ep_square) = board_from_fen("8/8/8/4k3/8/8/8/KRR5 w - - 0 1");
+    assert_eq!(
+        tb.probe_dtz(&board, ep_square),
+        Err(TablebaseError::MissingTable("KRRvK".to_string()))
+    );
+
+    // a table with the wrong magic bytes
+    std::fs::write(dir.join("KRvK.rtbw"), [0, 0, 0, 0]).unwrap();
+    assert!(matches!(
+        SyzygyTablebase::open(&dir),
+        Err(TablebaseError::InvalidFile(_))
+    ));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn syzygy_positions() {
+    let tb = SyzygyTablebase::open(syzygy_dir()).unwrap();
+    assert_eq!(tb.table_count(), 5);
+    assert_eq!(tb.max_pieces(), 3);
+
+    for (fen, wdl, dtz) in [
+        // from the tests of shakmaty-syzygy
+        ("8/8/8/2R5/1K6/8/5k2/8 w - - 0 1", Wdl::Win, 21),
+        ("8/5p2/6k1/K7/8/8/8/8 w - - 0 1", Wdl::Loss, -2),
+        ("8/8/8/2K5/5kp1/8/8/8 b - - 0 1", Wdl::Win, 1),
+        ("8/3k4/8/8/8/8/4P3/3K4 w - - 0 1", Wdl::Draw, 0),
+        // checkmated, and mate in 1
+        ("k7/1Q6/1K6/8/8/8/8/8 b - - 0 1", Wdl::Loss, -1),
+        ("k7/8/1K6/8/8/8/8/6Q1 w - - 0 1", Wdl::Win, 1),
+        // the king takes the queen
+        ("8/8/8/8/8/2k5/1Q6/7K b - - 0 1", Wdl::Draw, 0),
+    ] {
+        let (board, ep_square) = board_from_fen(fen);
+        assert_eq!(tb.probe_wdl(&board, ep_square), Ok(wdl), "{}", fen);
+        assert_eq!(tb.probe_dtz(&board, ep_square), Ok(dtz), "{}", fen);
+    }
+
+    let (board, ep_square) = board_from_fen("8/8/8/4k3/8/8/8/KR1q4 w - - 0 1");
+    assert_eq!(
+        tb.probe_wdl(&board, ep_square),
+        Err(TablebaseError::TooManyPieces(4))
+    );
+
+    // the best moves of the tables mate, as fast as the DTZ says
+    let (mut board, mut ep_square) = board_from_fen("8/8/8/3k4/8/8/8/R3K3 w - - 0 1");
+    let dtz = tb.probe_dtz(&board, ep_square).unwrap();
+    let mut moves = 0;
+    while let Some(best) = best_move(&tb, &board, ep_square).unwrap() {
+        board.apply_move(best.the_move);
+        ep_square = best.the_move.get_en_passant_square();
+        moves += 1;
+    }
+    assert!(board.is_team_in_check(ChessTeam::Black, None));
+    assert_eq!(moves, dtz);
+}
+
+// Every few positions of the tables against the retrograde generator. Without
+//  pawns the distance to zeroing is the distance to mate.
+#[test]
+fn syzygy_against_retrograde() {
+    let tb = SyzygyTablebase::open(syzygy_dir()).unwrap();
+    let mut dtm = DtmTables::new();
+    dtm.generate("KPvK").unwrap();
+
+    for piece in [ChessPiece::Queen, ChessPiece::Rook, ChessPiece::Pawn] {
+        let mut checked = 0;
+
+        for n in (0..2 * 2 * 64 * 64 * 64).step_by(61) {
+            let whose_turn = if n & 1 == 0 {
+                ChessTeam::White
+            } else {
+                ChessTeam::Black
+            };
+            let team = if n & 2 == 0 {
+                ChessTeam::White
+            } else {
+                ChessTeam::Black
+            };
+            let squares = [(n >> 2) & 63, (n >> 8) & 63, (n >> 14) & 63];
+
+            let tile = |square: usize| {
+                Tile::try_from(Coord {
+                    x: (square % 8) as i32,
+                    y: (square / 8) as i32,
+                })
+                .unwrap()
+            };
+            let mut piece_locations = HashMap::new();
+            for (square, piece) in squares.iter().zip([
+                TeamedChessPiece(ChessTeam::White, ChessPiece::King),
+                TeamedChessPiece(ChessTeam::Black, ChessPiece::King),
+                TeamedChessPiece(team, piece),
+            ]) {
+                piece_locations.insert(tile(*square), piece);
+            }
+            let board = Board {
+                whose_turn,
+                piece_locations,
+                castling_rights: (false, false, false, false),
+            };
+
+            let kings_apart = Coord::from(tile(squares[0]))
+                .distance(Coord::from(tile(squares[1])))
+                .magnitude()
+                .is_none_or(|d| d > 1);
+            let pawn_on_back_rank =
+                piece == ChessPiece::Pawn && (squares[2] < 8 || squares[2] >= 56);
+            if board.piece_locations.len() != 3
+                || !kings_apart
+                || pawn_on_back_rank
+                || board.is_team_in_check(whose_turn.the_other_one(), None)
+            {
+                continue;
+            }
+
+            let wdl = dtm.probe_wdl(&board, None).unwrap();
+            assert_eq!(
+                tb.probe_wdl(&board, None),
+                Ok(wdl),
+                "{:?}",
+                (whose_turn, team, squares)
+            );
+
+            let dtz = tb.probe_dtz(&board, None).unwrap();
+            if piece == ChessPiece::Pawn {
+                assert_eq!(
+                    dtz.signum(),
+                    wdl.value().signum(),
+                    "{:?}",
+                    (whose_turn, team, squares)
+                );
+            } else {
+                let expected = match dtm.probe_dtz(&board, None).unwrap() {
+                    0 if wdl == Wdl::Loss => -1,
+                    plies => plies,
+                };
+                assert_eq!(dtz, expected, "{:?}", (whose_turn, team, squares));
+            }
+            checked += 1;
+        }
+
+        assert!(checked > 2000);
+    }
+}
@@ -0,0 +1,770 @@
+#![warn(rust_2018_idioms)]
+
+// The messages between chess-rs-client and chess-rs-server, and how they go
+//  on the wire.
+//
+// Every message is a frame: its length as 4 big-endian bytes, then the message
+//  encoded with bincode. The client starts with Hello, and the server answers
+//  Welcome if it speaks the same protocol version, or an Error and closes.
+//  Then the client logs in, registers an account or plays as a guest, and
+//  joins the lobby to find an opponent. Or it goes back to the
+//  game it lost the connection to with Resume. It can also ask for the
+//  games being played with ListGames, and watch one of them with Spectate.
+//
+// In the lobby players post seeks: the game they want to play and who they
+//  want to play against. The server pairs two seeks that fit each other as
+//  soon as they are both there, and anyone can accept a seek they fit too.
+//  Players can also challenge someone in the lobby by name.
+//
+// Games between two accounts with a clock are rated. Every account has a
+//  rating for each RatingCategory, and anyone can ask for the ratings of a
+//  player, their history and the leaderboards.
+//
+// Finished games are kept by the server. FindGames looks for them, and GetPgn
+//  gets one to review.
+//
+// Players chat with their opponent and whoever watches, or with the lobby.
+//  Logged in clients can also send private messages to anyone by name. The
+//  server passes every line on as a ChatLine, to the one that said it too.
+//
+// Players in the lobby can make tournaments and join them. The server pairs
+//  the players of a tournament itself and sends their Standings whenever they
+//  change. Players of a tournament go back to the lobby after each game, and
+//  the connection stays open.
+//
+// The server takes WebSocket connections too, for browsers and bots. There a
+//  binary frame holds one message encoded with bincode, without the length,
+//  and a text frame holds one message as JSON, the way serde writes it.
+
+use bincode::Options;
+use chess_rs_core::clock::TimeControl;
+use chess_rs_core::{ChessTeam, GameState, Move, MoveError};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+// bump it on any change to Message
+pub const PROTOCOL_VERSION: u32 = 11;
+
+// frames bigger than this are an error, so a bad length can't eat the memory
+pub const MAX_FRAME_LEN: usize = 64 * 1024;
+
+// the longest chat line, in characters
+pub const MAX_CHAT_LEN: usize = 300;
+
+const LEN_SIZE: usize = 4;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Message {
+    //first message of the client, with its PROTOCOL_VERSION
+    Hello(u32),
+    //the server's answer to a Hello it can talk to
+    Welcome(u32),
+    //a new account, with its name and password. The client is logged in with it
+    Register(String, String),
+    Login(String, String),
+    //logging in again without the password
+    LoginWithToken(LoginToken),
+    //playing without an account, with a name the server makes up
+    PlayAsGuest,
+    //the name the client plays with. A login with a password gives a token to
+    //  log in again with
+    LoggedIn(String, Option<LoginToken>),
+    //entering the lobby, once logged in
+    JoinLobby,
+    //the answer to JoinLobby: the seeks in the lobby. Then the client gets
+    //  SeekAdded and SeekRemoved as seeks come and go
+    Lobby(Vec<Seek>),
+    //posting a seek. If it fits one in the lobby the game starts right away
+    Seek(SeekRequest),
+    SeekAdded(Seek),
+    SeekRemoved(u64),
+    CancelSeek(u64),
+    AcceptSeek(u64),
+    //challenging the player with that name
+    Challenge(String, SeekRequest),
+    //someone challenged the client
+    Challenged(Challenge),
+    AcceptChallenge(u64),
+    DeclineChallenge(u64),
+    //the player with that name declined the client's challenge
+    ChallengeDeclined(String),
+    //the game starts
+    GameStart(GameStart),
+    //back in the game of the session
+    Resume(SessionToken),
+    //the answer to Resume: the team and the game so far
+    Resumed(ChessTeam, GameSnapshot),
+    //the opponent lost the connection. It has this many seconds to come back
+    OpponentLeft(u32),
+    OpponentBack,
+    //the games being played right now
+    ListGames,
+    GameList(Vec<GameInfo>),
+    //watching the game with that id
+    Spectate(u64),
+    //the answer to Spectate: the game so far. Then the spectator gets the
+    //  moves, takebacks, clocks and result of the game as they happen
+    Spectating(GameSnapshot),
+    Move(Move),
+    //the server didn't take the client's last move
+    MoveRejected(MoveError),
+    //asking the opponent to take back the last move
+    TakebackRequest,
+    //the opponent's answer to a TakebackRequest. Only a decline is sent back
+    TakebackAnswer(bool),
+    //sent to both clients when a takeback is accepted: how many moves to undo
+    Takeback(u32),
+    //the clocks of the server after a move
+    ClockUpdate(ClockTimes),
+    Resign,
+    //the opponent gets it, and answers with AcceptDraw or DeclineDraw. Making
+    //  a move declines it too
+    OfferDraw,
+    AcceptDraw,
+    //sent back to the player that offered the draw
+    DeclineDraw,
+    //ending the game before it really started: before both sides moved. An
+    //  aborted game isn't rated
+    Abort,
+    //in an arena game, before the player's first move: half the time, and no
+    //  increment
+    Berserk,
+    //sent to everyone in the game, with a ClockUpdate
+    Berserked(ChessTeam),
+    //saying something to the match, or to the lobby
+    Chat(String),
+    //to the player with that name, wherever they are
+    PrivateChat(String, String),
+    //something someone said
+    ChatLine(ChatLine),
+    //sent to both clients when the game ends
+    GameOver(GameResult),
+    //after the GameOver of a rated game: the new rating, and how much it
+    //  changed
+    RatingChanged(RatingCategory, PlayerRating, i32),
+    //the ratings of the player with that name
+    GetRatings(String),
+    Ratings(String, Vec<(RatingCategory, PlayerRating)>),
+    //how the rating of the player went, oldest first
+    GetRatingHistory(String, RatingCategory),
+    RatingHistory(String, RatingCategory, Vec<RatingPoint>),
+    //the best players that aren't provisional
+    GetLeaderboard(RatingCategory),
+    Leaderboard(RatingCategory, Vec<(String, PlayerRating)>),
+    //finished games, newest first
+    FindGames(GameQuery),
+    FoundGames(Vec<PastGame>),
+    //a finished game in PGN, by its id
+    GetPgn(u64),
+    Pgn(u64, String),
+    //the tournaments that aren't over, or just ended
+    ListTournaments,
+    Tournaments(Vec<TournamentInfo>),
+    //the creator starts it when everyone is in
+    CreateTournament(TournamentSettings),
+    JoinTournament(u64),
+    //before the start it's leaving, then it's withdrawing
+    LeaveTournament(u64),
+    StartTournament(u64),
+    //sent to the lobby when a tournament is made, or changes
+    TournamentUpdated(TournamentInfo),
+    //the players of the tournament, best first. Sent to them when it changes
+    Standings(u64, Vec<Standing>),
+    //the last standings
+    TournamentOver(u64, Vec<Standing>),
+    //the server couldn't do what the client asked
+    Error(ProtocolError),
+}
+
+// a secret that logs in to an account, until it's used up by a new login
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct LoginToken(pub [u8; 16]);
+
+// a secret that only the player of the session knows
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SessionToken(pub [u8; 16]);
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub enum Variant {
+    #[default]
+    Standard,
+    //starting from the position of the FEN
+    FromPosition(String),
+}
+
+impl Variant {
+    // the game before the first move. None if the FEN isn't valid
+    pub fn starting_game(&self) -> Option<GameState> {
+        match self {
+            Variant::Standard => Some(GameState::init()),
+            Variant::FromPosition(fen) => chess_rs_core::parse_fen(fen.clone()),
+        }
+    }
+}
+
+// Ratings are kept apart for each speed, and for each variant whatever the
+//   speed
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum RatingCategory {
+    Bullet,
+    Blitz,
+    Rapid,
+    Classical,
+    FromPosition,
+}
+
+impl RatingCategory {
+    pub const ALL: [RatingCategory; 5] = [
+        RatingCategory::Bullet,
+        RatingCategory::Blitz,
+        RatingCategory::Rapid,
+        RatingCategory::Classical,
+        RatingCategory::FromPosition,
+    ];
+
+    // The category of games with the settings. None for games without a
+    //   clock (or with a broken one), they aren't rated
+    pub fn of(settings: &GameSettings) -> Option<RatingCategory> {
+        let duration = settings.time_control.as_ref()?.estimated_duration()?;
+        if let Variant::FromPosition(_) = settings.variant {
+            return Some(RatingCategory::FromPosition);
+        }
+
+        let minutes = |minutes: u64| Duration::from_secs(minutes * 60);
+        Some(if duration < minutes(3) {
+            RatingCategory::Bullet
+        } else if duration < minutes(8) {
+            RatingCategory::Blitz
+        } else if duration < minutes(25) {
+            RatingCategory::Rapid
+        } else {
+            RatingCategory::Classical
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RatingCategory::Bullet => "bullet",
+            RatingCategory::Blitz => "blitz",
+            RatingCategory::Rapid => "rapid",
+            RatingCategory::Classical => "classical",
+            RatingCategory::FromPosition => "from position",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<RatingCategory> {
+        RatingCategory::ALL
+            .iter()
+            .find(|category| category.name() == name)
+            .copied()
+    }
+}
+
+impl fmt::Display for RatingCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub struct PlayerRating {
+    pub rating: u32,
+    //how sure the rating is. Lower is surer
+    pub deviation: u32,
+    //not sure enough for the leaderboards, shown with a ?
+    pub provisional: bool,
+}
+
+impl fmt::Display for PlayerRating {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.provisional {
+            true => write!(f, "{}?", self.rating),
+            false => write!(f, "{}", self.rating),
+        }
+    }
+}
+
+// the rating after a game, and when it was played in seconds since 1970
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub struct RatingPoint {
+    pub time: u64,
+    pub rating: u32,
+}
+
+// What to look for in the finished games. Everything that is set has to fit
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub struct GameQuery {
+    //one of the players, in any case
+    pub player: Option<String>,
+    //when the game started, in seconds since 1970
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+    //the first moves of the game in SAN, like "e4 c5"
+    pub opening: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum ChatRoom {
+    Game,
+    Lobby,
+    //to the player with that name
+    Private(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ChatLine {
+    pub room: ChatRoom,
+    pub from: String,
+    pub text: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum TournamentKind {
+    //games as fast as the players finish them, for that long
+    Arena { minutes: u32 },
+    //players with the same score meet, without playing anyone twice
+    Swiss { rounds: u32 },
+    //everyone plays everyone once
+    RoundRobin,
+}
+
+impl fmt::Display for TournamentKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TournamentKind::Arena { minutes } => write!(f, "arena, {} minutes", minutes),
+            TournamentKind::Swiss { rounds } => write!(f, "Swiss, {} rounds", rounds),
+            TournamentKind::RoundRobin => write!(f, "round robin"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TournamentSettings {
+    pub name: String,
+    pub kind: TournamentKind,
+    pub game: GameSettings,
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub enum TournamentStatus {
+    //players can join
+    Open,
+    Running,
+    Finished,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TournamentInfo {
+    pub id: u64,
+    pub settings: TournamentSettings,
+    pub creator: String,
+    pub players: u32,
+    pub status: TournamentStatus,
+}
+
+// a player's place in a tournament
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Standing {
+    pub name: String,
+    //arena points, or else a point for a win and half for a draw
+    pub score: f32,
+    pub games: u32,
+    //the tie-breaks of Swiss and round robin tournaments: the scores of all
+    //  the opponents, and of the beaten ones plus half of the drawn ones
+    pub buchholz: f32,
+    pub sonneborn_berger: f32,
+    //arena: two wins in a row double the points, until a game isn't won
+    pub on_fire: bool,
+    pub withdrawn: bool,
+}
+
+// a finished game, without its moves
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PastGame {
+    pub id: u64,
+    //White first
+    pub players: [String; 2],
+    pub result: GameResult,
+    pub settings: GameSettings,
+    pub moves: u32,
+    //in seconds since 1970
+    pub started: u64,
+}
+
+// how a game is played
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub struct GameSettings {
+    pub variant: Variant,
+    //None for no clocks
+    pub time_control: Option<TimeControl>,
+}
+
+// the game a player wants, and who with
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub struct SeekRequest {
+    pub settings: GameSettings,
+    //None for either color
+    pub color: Option<ChessTeam>,
+    //the lowest and highest rating of the opponent. None for anyone
+    pub rating_range: Option<(u32, u32)>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Seek {
+    pub id: u64,
+    pub player: String,
+    pub rating: u32,
+    pub request: SeekRequest,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Challenge {
+    pub id: u64,
+    //the name of the challenger
+    pub from: String,
+    //the color is the challenger's, and the rating range means nothing
+    pub request: SeekRequest,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct GameStart {
+    pub team: ChessTeam,
+    //gets the client back in the game if the connection is lost
+    pub session: SessionToken,
+    pub settings: GameSettings,
+    //White first
+    pub players: [String; 2],
+    pub tournament: Option<TournamentGame>,
+}
+
+// what a game of a tournament is to its players
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TournamentGame {
+    pub id: u64,
+    pub name: String,
+    pub can_berserk: bool,
+}
+
+// everything needed to catch up with a game
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct GameSnapshot {
+    pub settings: GameSettings,
+    //White first
+    pub players: [String; 2],
+    pub moves: Vec<Move>,
+    pub clocks: Option<ClockTimes>,
+}
+
+// a game someone can watch
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct GameInfo {
+    pub id: u64,
+    //White first
+    pub players: [String; 2],
+    //moves made so far
+    pub moves: u32,
+    pub settings: GameSettings,
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub struct ClockTimes {
+    //time left of each team, White first
+    pub remaining: [Duration; 2],
+    pub running: Option<ChessTeam>,
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub enum EndReason {
+    Checkmate,
+    Timeout,
+    Resignation,
+    //stalemate, repetition, fifty moves or not enough material
+    DrawByRule,
+    DrawAgreed,
+    Aborted,
+    //the opponent left
+    Disconnection,
+}
+
+impl EndReason {
+    pub const ALL: [EndReason; 7] = [
+        EndReason::Checkmate,
+        EndReason::Timeout,
+        EndReason::Resignation,
+        EndReason::DrawByRule,
+        EndReason::DrawAgreed,
+        EndReason::Aborted,
+        EndReason::Disconnection,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EndReason::Checkmate => "checkmate",
+            EndReason::Timeout => "timeout",
+            EndReason::Resignation => "resignation",
+            EndReason::DrawByRule => "draw by rule",
+            EndReason::DrawAgreed => "draw agreed",
+            EndReason::Aborted => "aborted",
+            EndReason::Disconnection => "disconnection",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<EndReason> {
+        EndReason::ALL
+            .iter()
+            .find(|reason| reason.name() == name)
+            .copied()
+    }
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub struct GameResult {
+    //None for a draw or an aborted game
+    pub winner: Option<ChessTeam>,
+    pub reason: EndReason,
+}
+
+impl GameResult {
+    // the result in PGN: "1-0", "0-1", "1/2-1/2", or "*" if it was aborted
+    pub fn pgn(&self) -> &'static str {
+        match (self.winner, self.reason) {
+            (_, EndReason::Aborted) => "*",
+            (Some(ChessTeam::White), _) => "1-0",
+            (Some(ChessTeam::Black), _) => "0-1",
+            (None, _) => "1/2-1/2",
+        }
+    }
+}
+
+impl fmt::Display for GameResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self.reason {
+            EndReason::Checkmate => "checkmate",
+            EndReason::Timeout => "timeout",
+            EndReason::Resignation => "resignation",
+            EndReason::DrawByRule => "the rules",
+            EndReason::DrawAgreed => "agreement",
+            EndReason::Aborted => return write!(f, "Game aborted."),
+            EndReason::Disconnection => "disconnection",
+        };
+        match self.winner {
+            Some(team) => write!(f, "{} wins by {}.", team, reason),
+            None => write!(f, "Draw by {}.", reason),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum ProtocolError {
+    //the server speaks this version
+    VersionMismatch(u32),
+    //the message makes no sense right now, like a move before the game starts
+    UnexpectedMessage,
+    //there is no game to resume with that session, or it's over
+    UnknownSession,
+    //there is no game with that id, or it's over
+    UnknownGame,
+    //names are 3 to 20 letters, numbers and _
+    InvalidName,
+    //there is an account with that name, or someone is in the lobby with it
+    NameTaken,
+    //passwords are 8 to 128 characters
+    InvalidPassword,
+    WrongPassword,
+    //the login token is used up, or made up
+    UnknownLogin,
+    //the lobby is only for logged in clients
+    NotLoggedIn,
+    //there is no one with that name in the lobby
+    UnknownPlayer,
+    //the seek or challenge is gone, or the client doesn't fit it
+    UnknownSeek,
+    //the seek's FEN isn't valid
+    InvalidSeek,
+    //both sides have moved already
+    TooLateToAbort,
+    //chat lines are 1 to MAX_CHAT_LEN characters
+    InvalidChat,
+    //too many lines in a short time
+    ChatTooFast,
+    //there is no tournament with that id, or it's over
+    UnknownTournament,
+    //the tournament needs a name, and an arena a clock
+    InvalidTournament,
+    //it started already, or the client isn't the creator, or there aren't
+    //  two players
+    CantStartTournament,
+    //no periods, no time to start with, or more than clock::MAX_TIME
+    InvalidTimeControl,
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::VersionMismatch(version) => write!(
+                f,
+                "The server speaks protocol version {}, this is version {}.",
+                version, PROTOCOL_VERSION
+            ),
+            ProtocolError::UnexpectedMessage => write!(f, "The server didn't expect that message."),
+            ProtocolError::UnknownSession | ProtocolError::UnknownGame => {
+                write!(f, "That game is over.")
+            }
+            ProtocolError::InvalidName => write!(f, "Names are 3 to 20 letters, numbers and _."),
+            ProtocolError::NameTaken => write!(f, "That name is taken."),
+            ProtocolError::InvalidPassword => {
+                write!(f, "Passwords are 8 to 128 characters long.")
+            }
+            ProtocolError::WrongPassword => write!(f, "Wrong name or password."),
+            ProtocolError::UnknownLogin => write!(f, "The saved login expired."),
+            ProtocolError::NotLoggedIn => write!(f, "Log in first."),
+            ProtocolError::UnknownPlayer => write!(f, "There is no one with that name."),
+            ProtocolError::UnknownSeek => write!(f, "That game isn't available."),
+            ProtocolError::InvalidSeek => write!(f, "That position isn't valid."),
+            ProtocolError::InvalidChat => write!(
+                f,
+                "Chat messages are 1 to {} characters long.",
+                MAX_CHAT_LEN
+            ),
+            ProtocolError::ChatTooFast => write!(f, "Slow down a little."),
+            ProtocolError::UnknownTournament => write!(f, "That tournament is over."),
+            ProtocolError::InvalidTournament => {
+                write!(f, "Tournaments need a name, and arenas a clock.")
+            }
+            ProtocolError::CantStartTournament => {
+                write!(
+                    f,
+                    "Only its creator can start it, with two players or more."
+                )
+            }
+            ProtocolError::TooLateToAbort => {
+                write!(f, "The game can't be aborted after both sides moved.")
+            }
+            ProtocolError::InvalidTimeControl => write!(f, "That clock can't be used."),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum FrameError {
+    Io(io::Error),
+    //the length of a frame is over MAX_FRAME_LEN
+    TooLong(usize),
+    //the frame isn't a Message
+    Malformed,
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::Io(e) => write!(f, "{}", e),
+            FrameError::TooLong(len) => write!(f, "frame of {} bytes is too long", len),
+            FrameError::Malformed => write!(f, "malformed message"),
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+impl From<io::Error> for FrameError {
+    fn from(e: io::Error) -> Self {
+        FrameError::Io(e)
+    }
+}
+
+fn bincode_options() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .with_limit(MAX_FRAME_LEN as u64)
+}
+
+// the message as a whole frame, length included
+pub fn encode(message: &Message) -> Result<Vec<u8>, FrameError> {
+    let payload = encode_payload(message)?;
+    let mut frame = (payload.len() as u32).to_be_bytes().to_vec();
+    frame.extend(payload);
+    Ok(frame)
+}
+
+// Only the message, for transports that have frames of their own. A message
+//   over MAX_FRAME_LEN can't be read on the other end, so it's TooLong
+pub fn encode_payload(message: &Message) -> Result<Vec<u8>, FrameError> {
+    let payload = bincode_options()
+        .with_no_limit()
+        .serialize(message)
+        .map_err(|_| FrameError::Malformed)?;
+    match payload.len() > MAX_FRAME_LEN {
+        true => Err(FrameError::TooLong(payload.len())),
+        false => Ok(payload),
+    }
+}
+
+pub fn decode_payload(payload: &[u8]) -> Result<Message, FrameError> {
+    bincode_options()
+        .deserialize(payload)
+        .map_err(|_| FrameError::Malformed)
+}
+
+fn frame_len(len_bytes: [u8; LEN_SIZE]) -> Result<usize, FrameError> {
+    let len = u32::from_be_bytes(len_bytes) as usize;
+    match len > MAX_FRAME_LEN {
+        true => Err(FrameError::TooLong(len)),
+        false => Ok(len),
+    }
+}
+
+pub fn write_message(writer: &mut impl Write, message: &Message) -> io::Result<()> {
+    let frame = encode(message).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    writer.write_all(&frame)?;
+    writer.flush()
+}
+
+// blocks until a whole message is read
+pub fn read_message(reader: &mut impl Read) -> Result<Message, FrameError> {
+    let mut len_bytes = [0; LEN_SIZE];
+    reader.read_exact(&mut len_bytes)?;
+    let mut payload = vec![0; frame_len(len_bytes)?];
+    reader.read_exact(&mut payload)?;
+    decode_payload(&payload)
+}
+
+// Collects bytes from a non-blocking socket and gives out the messages as
+//   their frames are complete
+#[derive(Default)]
+pub struct FrameReader {
+    buffer: Vec<u8>,
+}
+
+impl FrameReader {
+    pub fn new() -> FrameReader {
+        FrameReader::default()
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    // the next message, or None if its frame isn't all here yet
+    pub fn next_message(&mut self) -> Result<Option<Message>, FrameError> {
+        if self.buffer.len() < LEN_SIZE {
+            return Ok(None);
+        }
+
+        let mut len_bytes = [0; LEN_SIZE];
+        len_bytes.copy_from_slice(&self.buffer[..LEN_SIZE]);
+        let len = frame_len(len_bytes)?;
+        if self.buffer.len() < LEN_SIZE + len {
+            return Ok(None);
+        }
+
+        let message = decode_payload(&self.buffer[LEN_SIZE..LEN_SIZE + len]);
+        self.buffer.drain(..LEN_SIZE + len);
+        message.map(Some)
+    }
+}
+
+#[cfg(test)]
+#[path = "./tests/protocol_tests.rs"]
+mod protocol_tests;
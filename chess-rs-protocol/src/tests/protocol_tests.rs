@@ -0,0 +1,256 @@
+use super::*;
+use chess_rs_core::{ChessPiece, Tile};
+
+fn some_messages() -> Vec<Message> {
+    vec![
+        Message::Hello(PROTOCOL_VERSION),
+        Message::Login("lucy".to_string(), "hunter22".to_string()),
+        Message::LoggedIn("lucy".to_string(), Some(LoginToken([3; 16]))),
+        Message::Seek(SeekRequest {
+            settings: GameSettings {
+                variant: Variant::FromPosition("8/8/4k3/8/8/8/3QK3/8 w - - 0 1".to_string()),
+                time_control: None,
+            },
+            color: Some(ChessTeam::White),
+            rating_range: Some((1200, 1800)),
+        }),
+        Message::GameStart(GameStart {
+            team: ChessTeam::Black,
+            session: SessionToken([7; 16]),
+            settings: GameSettings::default(),
+            players: ["pero".to_string(), "lucy".to_string()],
+            tournament: Some(TournamentGame {
+                id: 2,
+                name: "Friday blitz".to_string(),
+                can_berserk: true,
+            }),
+        }),
+        Message::Resumed(
+            ChessTeam::White,
+            GameSnapshot {
+                settings: GameSettings::default(),
+                players: ["lucy".to_string(), "pero".to_string()],
+                moves: vec![],
+                clocks: None,
+            },
+        ),
+        Message::Move(Move::PieceMove {
+            piece: ChessPiece::Knight,
+            tile_from: Tile::G1,
+            tile_to: Tile::F3,
+            is_en_passant: false,
+        }),
+        Message::MoveRejected(MoveError::NotYourTurn),
+        Message::GameList(vec![GameInfo {
+            id: 12,
+            players: ["lucy".to_string(), "pero".to_string()],
+            moves: 31,
+            settings: GameSettings {
+                variant: Variant::Standard,
+                time_control: Some(TimeControl::fischer(
+                    Duration::from_secs(180),
+                    Duration::from_secs(2),
+                )),
+            },
+        }]),
+        Message::ClockUpdate(ClockTimes {
+            remaining: [Duration::from_millis(61_500), Duration::from_secs(3)],
+            running: Some(ChessTeam::Black),
+        }),
+        Message::Chat("good luck, have fun".to_string()),
+        Message::ChatLine(ChatLine {
+            room: ChatRoom::Private("pero".to_string()),
+            from: "lucy".to_string(),
+            text: "rematch?".to_string(),
+        }),
+        Message::GameOver(GameResult {
+            winner: Some(ChessTeam::White),
+            reason: EndReason::Resignation,
+        }),
+        Message::RatingChanged(
+            RatingCategory::Blitz,
+            PlayerRating {
+                rating: 1512,
+                deviation: 80,
+                provisional: false,
+            },
+            -7,
+        ),
+        Message::RatingHistory(
+            "lucy".to_string(),
+            RatingCategory::Rapid,
+            vec![RatingPoint {
+                time: 1_600_000_000,
+                rating: 1480,
+            }],
+        ),
+        Message::FindGames(GameQuery {
+            player: Some("lucy".to_string()),
+            opening: Some("e4 c5".to_string()),
+            ..GameQuery::default()
+        }),
+        Message::FoundGames(vec![PastGame {
+            id: 3,
+            players: ["lucy".to_string(), "pero".to_string()],
+            result: GameResult {
+                winner: None,
+                reason: EndReason::DrawAgreed,
+            },
+            settings: GameSettings::default(),
+            moves: 40,
+            started: 1_600_000_000,
+        }]),
+        Message::CreateTournament(TournamentSettings {
+            name: "Friday blitz".to_string(),
+            kind: TournamentKind::Swiss { rounds: 5 },
+            game: GameSettings::default(),
+        }),
+        Message::Standings(
+            2,
+            vec![Standing {
+                name: "lucy".to_string(),
+                score: 2.5,
+                games: 3,
+                buchholz: 4.0,
+                sonneborn_berger: 3.25,
+                on_fire: false,
+                withdrawn: false,
+            }],
+        ),
+        Message::Error(ProtocolError::VersionMismatch(PROTOCOL_VERSION + 1)),
+    ]
+}
+
+#[test]
+fn results() {
+    let result = |winner, reason| GameResult { winner, reason };
+    assert_eq!(
+        result(Some(ChessTeam::White), EndReason::Checkmate).pgn(),
+        "1-0"
+    );
+    assert_eq!(
+        result(Some(ChessTeam::Black), EndReason::Timeout).pgn(),
+        "0-1"
+    );
+    assert_eq!(result(None, EndReason::DrawByRule).pgn(), "1/2-1/2");
+    assert_eq!(result(None, EndReason::Aborted).pgn(), "*");
+
+    for reason in EndReason::ALL.iter() {
+        assert_eq!(EndReason::from_name(reason.name()), Some(*reason));
+    }
+}
+
+#[test]
+fn rating_categories() {
+    let category = |variant, control| {
+        RatingCategory::of(&GameSettings {
+            variant,
+            time_control: control,
+        })
+    };
+    let mins = |minutes: u64| Duration::from_secs(minutes * 60);
+    let secs = Duration::from_secs;
+
+    assert_eq!(category(Variant::Standard, None), None);
+    assert_eq!(
+        category(
+            Variant::Standard,
+            Some(TimeControl::fischer(mins(1), secs(0)))
+        ),
+        Some(RatingCategory::Bullet)
+    );
+    // 3 minutes and 2 seconds a move is over 3 minutes in 40 moves
+    assert_eq!(
+        category(
+            Variant::Standard,
+            Some(TimeControl::fischer(mins(3), secs(2)))
+        ),
+        Some(RatingCategory::Blitz)
+    );
+    assert_eq!(
+        category(
+            Variant::Standard,
+            Some(TimeControl::fischer(mins(10), secs(5)))
+        ),
+        Some(RatingCategory::Rapid)
+    );
+    assert_eq!(
+        category(Variant::Standard, Some(TimeControl::sudden_death(mins(30)))),
+        Some(RatingCategory::Classical)
+    );
+    assert_eq!(
+        category(
+            Variant::FromPosition("8/8/4k3/8/8/8/3QK3/8 w - - 0 1".to_string()),
+            Some(TimeControl::fischer(mins(1), secs(0)))
+        ),
+        Some(RatingCategory::FromPosition)
+    );
+
+    for category in RatingCategory::ALL.iter() {
+        assert_eq!(RatingCategory::from_name(category.name()), Some(*category));
+    }
+}
+
+#[test]
+fn blocking_round_trip() {
+    let mut wire = vec![];
+    for message in some_messages() {
+        write_message(&mut wire, &message).unwrap();
+    }
+
+    let mut reader = &wire[..];
+    for message in some_messages() {
+        assert_eq!(read_message(&mut reader).unwrap(), message);
+    }
+    assert!(matches!(read_message(&mut reader), Err(FrameError::Io(_))));
+}
+
+#[test]
+fn frame_reader() {
+    let wire: Vec<u8> = some_messages()
+        .iter()
+        .flat_map(|message| encode(message).unwrap())
+        .collect();
+    let mut frames = FrameReader::new();
+    let mut received = vec![];
+
+    // the bytes come in small pieces
+    for bytes in wire.chunks(3) {
+        frames.push(bytes);
+        while let Some(message) = frames.next_message().unwrap() {
+            received.push(message);
+        }
+    }
+
+    assert_eq!(received, some_messages());
+}
+
+#[test]
+fn payloads() {
+    for message in some_messages() {
+        let frame = encode(&message).unwrap();
+        let payload = encode_payload(&message).unwrap();
+        assert_eq!(frame[LEN_SIZE..], payload[..]);
+        assert_eq!(decode_payload(&payload).unwrap(), message);
+    }
+    assert!(matches!(
+        decode_payload(&[255, 255, 255, 255]),
+        Err(FrameError::Malformed)
+    ));
+}
+
+#[test]
+fn bad_frames() {
+    let mut frames = FrameReader::new();
+    frames.push(&(MAX_FRAME_LEN as u32 + 1).to_be_bytes());
+    assert!(matches!(frames.next_message(), Err(FrameError::TooLong(_))));
+
+    let mut frames = FrameReader::new();
+    frames.push(&[0, 0, 0, 4, 255, 255, 255, 255]);
+    assert!(matches!(frames.next_message(), Err(FrameError::Malformed)));
+
+    // too long to send, instead of a frame no one can read
+    let too_long = Message::Chat("a".repeat(MAX_FRAME_LEN));
+    assert!(matches!(encode(&too_long), Err(FrameError::TooLong(_))));
+    assert!(write_message(&mut vec![], &too_long).is_err());
+}
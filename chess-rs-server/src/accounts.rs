@@ -0,0 +1,340 @@
+// The registered players, in a SQLite database.
+//
+// Passwords are hashed with Argon2 and a salt of their own. That is slow on
+//  purpose, so hash_password and verify_password don't need the database and
+//  the server calls them from another thread. A login with a password gives a
+//  login token, so the client can log in again without the password. Only the
+//  SHA-256 of the tokens is kept, and every token works once: logging in with
+//  it gives a new one.
+
+use crate::archive;
+use crate::ratings;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use chess_rs_protocol::{LoginToken, ProtocolError};
+use rusqlite::{params, Connection, ErrorCode, OptionalExtension};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const MIN_NAME_LEN: usize = 3;
+const MAX_NAME_LEN: usize = 20;
+const MIN_PASSWORD_LEN: usize = 8;
+const MAX_PASSWORD_LEN: usize = 128;
+
+// unused login tokens expire after this
+const TOKEN_LIFETIME: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Account {
+    pub id: i64,
+    //as it was registered, whatever the case of the login
+    pub name: String,
+}
+
+#[derive(Debug)]
+pub enum AccountError {
+    InvalidName,
+    InvalidPassword,
+    NameTaken,
+    //also for names without an account
+    WrongPassword,
+    UnknownToken,
+    Database(rusqlite::Error),
+    Hash(argon2::password_hash::Error),
+}
+
+impl AccountError {
+    // what to tell the client. None if it's the server's fault
+    pub fn to_protocol(&self) -> Option<ProtocolError> {
+        match self {
+            AccountError::InvalidName => Some(ProtocolError::InvalidName),
+            AccountError::InvalidPassword => Some(ProtocolError::InvalidPassword),
+            AccountError::NameTaken => Some(ProtocolError::NameTaken),
+            AccountError::WrongPassword => Some(ProtocolError::WrongPassword),
+            AccountError::UnknownToken => Some(ProtocolError::UnknownLogin),
+            AccountError::Database(_) | AccountError::Hash(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for AccountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccountError::Database(e) => write!(f, "database error: {}", e),
+            AccountError::Hash(e) => write!(f, "password hashing error: {}", e),
+            other => write!(f, "{:?}", other),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for AccountError {
+    fn from(e: rusqlite::Error) -> AccountError {
+        AccountError::Database(e)
+    }
+}
+
+impl From<argon2::password_hash::Error> for AccountError {
+    fn from(e: argon2::password_hash::Error) -> AccountError {
+        AccountError::Hash(e)
+    }
+}
+
+// 3 to 20 letters, numbers and _. Guests get names with a - so they can't
+//   take the name of an account
+pub fn valid_name(name: &str) -> bool {
+    (MIN_NAME_LEN..=MAX_NAME_LEN).contains(&name.len())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+pub fn valid_password(password: &str) -> bool {
+    (MIN_PASSWORD_LEN..=MAX_PASSWORD_LEN).contains(&password.chars().count())
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+// the hash to keep for the password of a new account
+pub fn hash_password(hasher: &Argon2<'_>, password: &str) -> Result<String, AccountError> {
+    if !valid_password(password) {
+        return Err(AccountError::InvalidPassword);
+    }
+
+    let mut salt = [0; 16];
+    getrandom::getrandom(&mut salt).expect("no random numbers for the salts");
+    let salt = SaltString::encode_b64(&salt)?;
+    Ok(hasher
+        .hash_password(password.as_bytes(), &salt)?
+        .to_string())
+}
+
+// WrongPassword if the password doesn't have that hash
+pub fn verify_password(
+    hasher: &Argon2<'_>,
+    password: &str,
+    hash: &str,
+) -> Result<(), AccountError> {
+    //the params of the hash are in it, so old hashes still work
+    let hash = PasswordHash::new(hash)?;
+    match hasher.verify_password(password.as_bytes(), &hash) {
+        Ok(()) => Ok(()),
+        Err(argon2::password_hash::Error::Password) => Err(AccountError::WrongPassword),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn token_hash(token: &LoginToken) -> Vec<u8> {
+    Sha256::digest(token.0).to_vec()
+}
+
+pub struct Accounts {
+    db: Connection,
+    hasher: Argon2<'static>,
+}
+
+impl Accounts {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Accounts, AccountError> {
+        Accounts::init(Connection::open(path)?)
+    }
+
+    // the accounts are gone with the server, for tests
+    pub fn in_memory() -> Result<Accounts, AccountError> {
+        Accounts::init(Connection::open_in_memory()?)
+    }
+
+    fn init(db: Connection) -> Result<Accounts, AccountError> {
+        db.execute_batch(
+            "CREATE TABLE IF NOT EXISTS accounts (
+                id INTEGER PRIMARY KEY,
+                name TEXT NOT NULL UNIQUE COLLATE NOCASE,
+                password_hash TEXT NOT NULL,
+                created INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS login_tokens (
+                token_hash BLOB PRIMARY KEY,
+                account INTEGER NOT NULL REFERENCES accounts(id),
+                created INTEGER NOT NULL
+            );",
+        )?;
+        ratings::create_tables(&db)?;
+        archive::create_tables(&db)?;
+
+        Ok(Accounts {
+            db,
+            hasher: Argon2::default(),
+        })
+    }
+
+    // Hashes new passwords as fast as Argon2 goes. Only for tests, the
+    //   hashes are easy to crack
+    pub fn set_cheap_hashing(&mut self) {
+        let params = Params::new(Params::MIN_M_COST, Params::MIN_T_COST, 1, None)
+            .expect("the smallest Argon2 params are valid");
+        self.hasher = Argon2::new(Algorithm::Argon2id, Version::V0x13, params);
+    }
+
+    // in memory and hashing fast, what the tests use
+    #[cfg(test)]
+    pub fn for_tests() -> Accounts {
+        let mut accounts = Accounts::in_memory().unwrap();
+        accounts.set_cheap_hashing();
+        accounts
+    }
+
+    // the database the accounts are in, for the tables that refer to them
+    pub fn db(&self) -> &Connection {
+        &self.db
+    }
+
+    // what hash_password and verify_password hash with
+    pub fn hasher(&self) -> Argon2<'static> {
+        self.hasher.clone()
+    }
+
+    pub fn register(&mut self, name: &str, password: &str) -> Result<Account, AccountError> {
+        if !valid_name(name) {
+            return Err(AccountError::InvalidName);
+        }
+        let hash = hash_password(&self.hasher, password)?;
+        self.insert(name, &hash)
+    }
+
+    // Makes the account, with the hash of its password from hash_password
+    pub fn insert(&mut self, name: &str, hash: &str) -> Result<Account, AccountError> {
+        if !valid_name(name) {
+            return Err(AccountError::InvalidName);
+        }
+
+        let inserted = self.db.execute(
+            "INSERT INTO accounts (name, password_hash, created) VALUES (?1, ?2, ?3)",
+            params![name, hash, now()],
+        );
+        match inserted {
+            Ok(_) => Ok(Account {
+                id: self.db.last_insert_rowid(),
+                name: name.to_string(),
+            }),
+            Err(rusqlite::Error::SqliteFailure(e, _))
+                if e.code == ErrorCode::ConstraintViolation =>
+            {
+                Err(AccountError::NameTaken)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // any case of the name logs in
+    pub fn login(&self, name: &str, password: &str) -> Result<Account, AccountError> {
+        let (account, hash) = self.password_hash(name)?;
+        verify_password(&self.hasher, password, &hash)?;
+        Ok(account)
+    }
+
+    // The account with the name, in any case, and the hash of its password.
+    //   WrongPassword if there is no such account
+    pub fn password_hash(&self, name: &str) -> Result<(Account, String), AccountError> {
+        let found = self
+            .db
+            .query_row(
+                "SELECT id, name, password_hash FROM accounts WHERE name = ?1",
+                params![name],
+                |row| {
+                    let account = Account {
+                        id: row.get(0)?,
+                        name: row.get(1)?,
+                    };
+                    Ok((account, row.get(2)?))
+                },
+            )
+            .optional()?;
+        found.ok_or(AccountError::WrongPassword)
+    }
+
+    pub fn new_token(&mut self, account: i64) -> Result<LoginToken, AccountError> {
+        let mut bytes = [0; 16];
+        getrandom::getrandom(&mut bytes).expect("no random numbers for the login tokens");
+        let token = LoginToken(bytes);
+
+        self.db.execute(
+            "INSERT INTO login_tokens (token_hash, account, created) VALUES (?1, ?2, ?3)",
+            params![token_hash(&token), account, now()],
+        )?;
+        Ok(token)
+    }
+
+    // Uses up the token. Returns its account and the token to use next time
+    pub fn login_with_token(
+        &mut self,
+        token: &LoginToken,
+    ) -> Result<(Account, LoginToken), AccountError> {
+        let oldest = now() - TOKEN_LIFETIME.as_secs() as i64;
+        self.db.execute(
+            "DELETE FROM login_tokens WHERE created < ?1",
+            params![oldest],
+        )?;
+
+        let hash = token_hash(token);
+        let account = self
+            .db
+            .query_row(
+                "SELECT accounts.id, accounts.name FROM login_tokens
+                    JOIN accounts ON accounts.id = login_tokens.account
+                    WHERE login_tokens.token_hash = ?1",
+                params![hash],
+                |row| {
+                    Ok(Account {
+                        id: row.get(0)?,
+                        name: row.get(1)?,
+                    })
+                },
+            )
+            .optional()?
+            .ok_or(AccountError::UnknownToken)?;
+
+        self.db.execute(
+            "DELETE FROM login_tokens WHERE token_hash = ?1",
+            params![hash],
+        )?;
+        let token = self.new_token(account.id)?;
+        Ok((account, token))
+    }
+
+    // the account with the name, in any case
+    pub fn find(&self, name: &str) -> Result<Option<Account>, AccountError> {
+        let account = self
+            .db
+            .query_row(
+                "SELECT id, name FROM accounts WHERE name = ?1",
+                params![name],
+                |row| {
+                    Ok(Account {
+                        id: row.get(0)?,
+                        name: row.get(1)?,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(account)
+    }
+
+    pub fn name(&self, id: i64) -> Result<Option<String>, AccountError> {
+        let name = self
+            .db
+            .query_row(
+                "SELECT name FROM accounts WHERE id = ?1",
+                params![id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(name)
+    }
+}
+
+#[cfg(test)]
+#[path = "./tests/accounts_tests.rs"]
+mod accounts_tests;
@@ -0,0 +1,336 @@
+// The finished games, in the database of the accounts.
+//
+// A game is kept with its moves twice: in UCI to play them again for the
+//  PGN, and in SAN to look for openings. "e4 c5" finds every game that
+//  started with those moves.
+
+use crate::chess_match::Match;
+use chess_rs_core::clock::MoveTime;
+use chess_rs_core::{self as chess, engine, pgn, GameState};
+use chess_rs_protocol::{EndReason, GameQuery, GameResult, GameSettings, PastGame, Variant};
+use rusqlite::{params, Connection, OptionalExtension, Row, ToSql};
+use std::time::Duration;
+
+// how many games a search finds, at most
+pub const MAX_FOUND: usize = 50;
+
+// what the archive keeps of a match
+pub struct Record {
+    //White first
+    pub players: [String; 2],
+    pub accounts: [Option<i64>; 2],
+    pub result: GameResult,
+    pub settings: GameSettings,
+    pub moves: Vec<chess::Move>,
+    //None without a clock
+    pub move_times: Option<Vec<MoveTime>>,
+    //in seconds since 1970
+    pub started: u64,
+    pub ended: u64,
+}
+
+impl Record {
+    // None if the match isn't over
+    pub fn of(
+        the_match: &Match,
+        accounts: [Option<i64>; 2],
+        started: u64,
+        ended: u64,
+    ) -> Option<Record> {
+        Some(Record {
+            players: the_match.players().clone(),
+            accounts,
+            result: the_match.get_result()?,
+            settings: the_match.settings().clone(),
+            moves: the_match.history(),
+            move_times: the_match.move_times(),
+            started,
+            ended,
+        })
+    }
+}
+
+pub fn create_tables(db: &Connection) -> rusqlite::Result<()> {
+    db.execute_batch(
+        "CREATE TABLE IF NOT EXISTS games (
+            id INTEGER PRIMARY KEY,
+            white TEXT NOT NULL COLLATE NOCASE,
+            black TEXT NOT NULL COLLATE NOCASE,
+            white_account INTEGER REFERENCES accounts(id),
+            black_account INTEGER REFERENCES accounts(id),
+            result TEXT NOT NULL,
+            reason TEXT NOT NULL,
+            variant TEXT NOT NULL,
+            start_fen TEXT NOT NULL,
+            time_control TEXT,
+            time_control_data BLOB,
+            moves TEXT NOT NULL,
+            san TEXT NOT NULL,
+            move_times TEXT,
+            started INTEGER NOT NULL,
+            ended INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS games_white ON games (white);
+        CREATE INDEX IF NOT EXISTS games_black ON games (black);
+        CREATE INDEX IF NOT EXISTS games_started ON games (started);",
+    )
+}
+
+// "1200:58800 3500:57300", the time spent and left after every move in ms
+fn write_move_times(times: &[MoveTime]) -> String {
+    let times: Vec<String> = times
+        .iter()
+        .map(|time| format!("{}:{}", time.spent.as_millis(), time.remaining.as_millis()))
+        .collect();
+    times.join(" ")
+}
+
+fn parse_move_times(text: &str) -> Option<Vec<MoveTime>> {
+    text.split_whitespace()
+        .map(|time| {
+            let mut parts = time.split(':');
+            let spent = parts.next()?.parse().ok()?;
+            let remaining = parts.next()?.parse().ok()?;
+            Some(MoveTime {
+                spent: Duration::from_millis(spent),
+                remaining: Duration::from_millis(remaining),
+            })
+        })
+        .collect()
+}
+
+// the moves and SAN lines have one space between moves
+fn normalize_moves(moves: &str) -> String {
+    moves.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// Keeps the game. Returns its id. Moves that aren't legal in the game's
+//   position can't happen, the match checked them
+pub fn store(db: &Connection, record: &Record) -> rusqlite::Result<u64> {
+    let mut game = record
+        .settings
+        .variant
+        .starting_game()
+        .unwrap_or_else(GameState::init);
+    let start_fen = game.get_fen();
+
+    let mut uci = vec![];
+    let mut san = vec![];
+    for the_move in record.moves.iter() {
+        let board = game.get_board().clone();
+        uci.push(engine::move_to_uci(&board, *the_move));
+        san.push(
+            board
+                .get_move_in_chess_notation(*the_move)
+                .replace(" e.p.", ""),
+        );
+        if game.perform_move(*the_move).is_err() {
+            break;
+        }
+    }
+
+    let variant = match record.settings.variant {
+        Variant::Standard => "standard",
+        Variant::FromPosition(_) => "from position",
+    };
+    let time_control = record.settings.time_control.as_ref();
+    let time_control_data = match time_control {
+        Some(control) => Some(
+            bincode::serialize(control).map_err(|e| rusqlite::Error::ToSqlConversionFailure(e))?,
+        ),
+        None => None,
+    };
+
+    db.execute(
+        "INSERT INTO games (white, black, white_account, black_account, result, reason,
+            variant, start_fen, time_control, time_control_data, moves, san, move_times,
+            started, ended)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+        params![
+            record.players[0],
+            record.players[1],
+            record.accounts[0],
+            record.accounts[1],
+            record.result.pgn(),
+            record.result.reason.name(),
+            variant,
+            start_fen,
+            time_control.map(pgn::time_control_tag),
+            time_control_data,
+            uci.join(" "),
+            san.join(" "),
+            record
+                .move_times
+                .as_ref()
+                .map(|times| write_move_times(times)),
+            record.started as i64,
+            record.ended as i64,
+        ],
+    )?;
+    Ok(db.last_insert_rowid() as u64)
+}
+
+fn result_of(result: &str, reason: &str) -> GameResult {
+    let winner = match result {
+        "1-0" => Some(chess::ChessTeam::White),
+        "0-1" => Some(chess::ChessTeam::Black),
+        _ => None,
+    };
+    GameResult {
+        winner,
+        reason: EndReason::from_name(reason).unwrap_or(EndReason::Aborted),
+    }
+}
+
+fn settings_of(variant: &str, start_fen: String, time_control: Option<Vec<u8>>) -> GameSettings {
+    let variant = match variant {
+        "from position" => Variant::FromPosition(start_fen),
+        _ => Variant::Standard,
+    };
+    let time_control = time_control.and_then(|data| bincode::deserialize(&data).ok());
+    GameSettings {
+        variant,
+        time_control,
+    }
+}
+
+fn past_game(row: &Row<'_>) -> rusqlite::Result<PastGame> {
+    let result: String = row.get("result")?;
+    let reason: String = row.get("reason")?;
+    let variant: String = row.get("variant")?;
+    let moves: String = row.get("moves")?;
+    let started: i64 = row.get("started")?;
+    let id: i64 = row.get("id")?;
+
+    Ok(PastGame {
+        id: id as u64,
+        players: [row.get("white")?, row.get("black")?],
+        result: result_of(&result, &reason),
+        settings: settings_of(
+            &variant,
+            row.get("start_fen")?,
+            row.get("time_control_data")?,
+        ),
+        moves: moves.split_whitespace().count() as u32,
+        started: started as u64,
+    })
+}
+
+// the games that fit the query, newest first
+pub fn find(db: &Connection, query: &GameQuery, max: usize) -> rusqlite::Result<Vec<PastGame>> {
+    let mut conditions = vec![];
+    let mut values: Vec<Box<dyn ToSql>> = vec![];
+
+    if let Some(player) = &query.player {
+        values.push(Box::new(player.trim().to_string()));
+        conditions.push(format!("(white = ?{0} OR black = ?{0})", values.len()));
+    }
+    if let Some(since) = query.since {
+        values.push(Box::new(since as i64));
+        conditions.push(format!("started >= ?{}", values.len()));
+    }
+    if let Some(until) = query.until {
+        values.push(Box::new(until as i64));
+        conditions.push(format!("started < ?{}", values.len()));
+    }
+    if let Some(opening) = &query.opening {
+        //the moves, and then more moves or none
+        values.push(Box::new(normalize_moves(opening)));
+        conditions.push(format!(
+            "(san = ?{0} OR substr(san, 1, length(?{0}) + 1) = ?{0} || ' ')",
+            values.len()
+        ));
+    }
+    values.push(Box::new(max as i64));
+
+    let filter = match conditions.is_empty() {
+        true => String::new(),
+        false => format!("WHERE {}", conditions.join(" AND ")),
+    };
+    let sql = format!(
+        "SELECT * FROM games {} ORDER BY started DESC, id DESC LIMIT ?{}",
+        filter,
+        values.len()
+    );
+
+    let mut statement = db.prepare(&sql)?;
+    let values: Vec<&dyn ToSql> = values.iter().map(|value| value.as_ref()).collect();
+    let games = statement.query_map(values.as_slice(), past_game)?;
+    games.collect()
+}
+
+// the PGN Termination tag. Aborted games have no result, so they didn't end
+fn termination(reason: EndReason) -> &'static str {
+    match reason {
+        EndReason::Checkmate
+        | EndReason::Resignation
+        | EndReason::DrawByRule
+        | EndReason::DrawAgreed => "normal",
+        EndReason::Timeout => "time forfeit",
+        EndReason::Disconnection => "abandoned",
+        EndReason::Aborted => "unterminated",
+    }
+}
+
+// The game in PGN, written by chess-rs-core with the Seven Tag Roster and
+//   the clock times. None if there is no game with the id
+pub fn pgn(db: &Connection, id: u64) -> rusqlite::Result<Option<String>> {
+    let found = db
+        .query_row(
+            "SELECT * FROM games WHERE id = ?1",
+            params![id as i64],
+            |row| {
+                let moves: String = row.get("moves")?;
+                let move_times: Option<String> = row.get("move_times")?;
+                let time_control: Option<String> = row.get("time_control")?;
+                Ok((past_game(row)?, moves, move_times, time_control))
+            },
+        )
+        .optional()?;
+    let (past_game, moves, move_times, time_control) = match found {
+        Some(found) => found,
+        None => return Ok(None),
+    };
+
+    let mut game = past_game
+        .settings
+        .variant
+        .starting_game()
+        .unwrap_or_else(GameState::init);
+    for uci in moves.split_whitespace() {
+        let en_passant = game.en_passant_square;
+        let the_move = match engine::move_from_uci(game.get_board(), en_passant, uci) {
+            Some(the_move) => the_move,
+            None => break,
+        };
+        if game.perform_move(the_move).is_err() {
+            break;
+        }
+    }
+    if let Some(times) = move_times.as_deref().and_then(parse_move_times) {
+        for (i, time) in times.into_iter().enumerate().take(game.move_count()) {
+            game.set_move_time(i, Some(time));
+        }
+    }
+
+    game.set_tag("Event", "chess-rs online game".to_string());
+    game.set_tag("Site", "chess-rs-server".to_string());
+    game.set_tag("Date", pgn::pgn_date(past_game.started));
+    game.set_tag("Round", "-".to_string());
+    game.set_tag("White", past_game.players[0].clone());
+    game.set_tag("Black", past_game.players[1].clone());
+    game.set_tag("Result", past_game.result.pgn().to_string());
+    game.set_tag(
+        "TimeControl",
+        time_control.unwrap_or_else(|| "-".to_string()),
+    );
+    game.set_tag(
+        "Termination",
+        termination(past_game.result.reason).to_string(),
+    );
+    Ok(Some(game.get_pgn()))
+}
+
+#[cfg(test)]
+#[path = "./tests/archive_tests.rs"]
+mod archive_tests;
@@ -0,0 +1,56 @@
+// What the server checks before it passes a chat line on: that it's not empty
+//  or too long, and that its connection isn't flooding everyone.
+
+use chess_rs_protocol::{ProtocolError, MAX_CHAT_LEN};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// a connection can send this many lines in CHAT_WINDOW, and then it waits
+pub const CHAT_BURST: usize = 5;
+pub const CHAT_WINDOW: Duration = Duration::from_secs(10);
+
+// The line as it's passed on: with the control characters (new lines too) as
+//   spaces, and then trimmed
+pub fn clean_line(text: &str) -> Result<String, ProtocolError> {
+    let text: String = text
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect();
+    let text = text.trim().to_string();
+    match text.chars().count() {
+        1..=MAX_CHAT_LEN => Ok(text),
+        _ => Err(ProtocolError::InvalidChat),
+    }
+}
+
+// when the last lines of a connection were sent
+pub struct ChatLimit {
+    sent: VecDeque<Instant>,
+}
+
+impl ChatLimit {
+    pub fn new() -> ChatLimit {
+        ChatLimit {
+            sent: VecDeque::new(),
+        }
+    }
+
+    // true if a line can go now. Only lines that go count
+    pub fn allow(&mut self, now: Instant) -> bool {
+        while let Some(first) = self.sent.front() {
+            match now.saturating_duration_since(*first) >= CHAT_WINDOW {
+                true => self.sent.pop_front(),
+                false => break,
+            };
+        }
+        if self.sent.len() >= CHAT_BURST {
+            return false;
+        }
+        self.sent.push_back(now);
+        true
+    }
+}
+
+#[cfg(test)]
+#[path = "./tests/chat_tests.rs"]
+mod chat_tests;
@@ -0,0 +1,453 @@
+// A game between two players, without the sockets.
+//
+// The server hands every message of a player to its match, and the match says
+//  what to send to whom: the players, or whoever is watching. Player 0 plays
+//  White.
+
+use chess::clock::{Clock, MoveTime, TimeSource};
+use chess_rs_core as chess;
+use chess_rs_protocol::{
+    ChatLine, ChatRoom, ClockTimes, EndReason, GameInfo, GameResult, GameSettings, GameSnapshot,
+    GameStart, Message, ProtocolError, SessionToken, TournamentGame,
+};
+use std::sync::Arc;
+
+pub fn player_team(player: usize) -> chess::ChessTeam {
+    match player {
+        0 => chess::ChessTeam::White,
+        _ => chess::ChessTeam::Black,
+    }
+}
+
+fn game_result(game: &mut chess::GameState, end_state: chess::GameEndState) -> GameResult {
+    let loser = game.whose_turn();
+    match end_state {
+        chess::GameEndState::Checkmate => GameResult {
+            winner: Some(loser.the_other_one()),
+            reason: EndReason::Checkmate,
+        },
+        chess::GameEndState::Timeout => GameResult {
+            winner: Some(loser.the_other_one()),
+            reason: EndReason::Timeout,
+        },
+        _ => GameResult {
+            winner: None,
+            reason: EndReason::DrawByRule,
+        },
+    }
+}
+
+pub struct Match {
+    settings: GameSettings,
+    //names of the players, White first
+    players: [String; 2],
+    //the server's game is the real one
+    game: chess::GameState,
+    //the player that asked for a takeback, waiting for the other one's answer
+    takeback_request: Option<usize>,
+    //the player that offered a draw. It stands until the other one answers or
+    //  moves
+    draw_offer: Option<usize>,
+    //None if it isn't a game of a tournament
+    tournament: Option<TournamentGame>,
+    result: Option<GameResult>,
+}
+
+// where a message goes
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum To {
+    Player(usize),
+    //everyone watching the match
+    Spectators,
+}
+
+// messages to send, and where each one goes
+pub type Outgoing = Vec<(To, Message)>;
+
+fn to_everyone(message: Message) -> Outgoing {
+    vec![
+        (To::Player(0), message.clone()),
+        (To::Player(1), message.clone()),
+        (To::Spectators, message),
+    ]
+}
+
+impl Match {
+    // The clock, if the settings have one, runs on the time of the source.
+    //   None if the variant's position isn't valid
+    pub fn new(
+        settings: GameSettings,
+        players: [String; 2],
+        source: Arc<dyn TimeSource>,
+    ) -> Option<Match> {
+        let mut game = settings.variant.starting_game()?;
+        if let Some(control) = &settings.time_control {
+            game.set_clock(Some(Clock::new(control.clone(), source)));
+        }
+        game.set_tag("White", players[0].clone());
+        game.set_tag("Black", players[1].clone());
+        Some(Match {
+            settings,
+            players,
+            game,
+            takeback_request: None,
+            draw_offer: None,
+            tournament: None,
+            result: None,
+        })
+    }
+
+    // before the start, so the players know
+    pub fn set_tournament(&mut self, tournament: TournamentGame) {
+        self.tournament = Some(tournament);
+    }
+
+    // the players that gave up half their time
+    pub fn berserked(&self) -> [bool; 2] {
+        let is_berserked = |player: usize| {
+            self.game
+                .get_clock()
+                .is_some_and(|clock| clock.is_berserked(player_team(player)))
+        };
+        [is_berserked(0), is_berserked(1)]
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.result.is_some()
+    }
+
+    pub fn settings(&self) -> &GameSettings {
+        &self.settings
+    }
+
+    pub fn get_result(&self) -> Option<GameResult> {
+        self.result
+    }
+
+    // the game so far, with the names of the players
+    pub fn pgn(&mut self) -> String {
+        self.game.get_pgn()
+    }
+
+    // every move so far, for a player that comes back
+    pub fn history(&self) -> Vec<chess::Move> {
+        (0..self.game.move_count())
+            .map(|i| self.game.get_move(i))
+            .collect()
+    }
+
+    pub fn players(&self) -> &[String; 2] {
+        &self.players
+    }
+
+    // how long every move took, if there is a clock
+    pub fn move_times(&self) -> Option<Vec<MoveTime>> {
+        self.game.get_clock()?;
+        Some(
+            (0..self.game.move_count())
+                .map(|i| {
+                    self.game.get_move_time(i).unwrap_or(MoveTime {
+                        spent: Default::default(),
+                        remaining: Default::default(),
+                    })
+                })
+                .collect(),
+        )
+    }
+
+    pub fn clock_times(&self) -> Option<ClockTimes> {
+        let clock = self.game.get_clock()?;
+        Some(ClockTimes {
+            remaining: [
+                clock.remaining(chess::ChessTeam::White),
+                clock.remaining(chess::ChessTeam::Black),
+            ],
+            running: clock.running(),
+        })
+    }
+
+    // the match in the list of games to watch
+    pub fn info(&self, id: u64) -> GameInfo {
+        GameInfo {
+            id,
+            players: self.players.clone(),
+            moves: self.game.move_count() as u32,
+            settings: self.settings.clone(),
+        }
+    }
+
+    // the game so far, for spectators and players that come back
+    pub fn snapshot(&self) -> GameSnapshot {
+        GameSnapshot {
+            settings: self.settings.clone(),
+            players: self.players.clone(),
+            moves: self.history(),
+            clocks: self.clock_times(),
+        }
+    }
+
+    // what each player gets when the match starts, with their sessions
+    pub fn start(&self, sessions: [SessionToken; 2]) -> Outgoing {
+        (0..2)
+            .map(|player| {
+                let start = GameStart {
+                    team: player_team(player),
+                    session: sessions[player],
+                    settings: self.settings.clone(),
+                    players: self.players.clone(),
+                    tournament: self.tournament.clone(),
+                };
+                (To::Player(player), Message::GameStart(start))
+            })
+            .collect()
+    }
+
+    fn end(&mut self, result: GameResult) -> Outgoing {
+        self.result = Some(result);
+        to_everyone(Message::GameOver(result))
+    }
+
+    fn clock_update(&self) -> Outgoing {
+        match self.clock_times() {
+            Some(times) => to_everyone(Message::ClockUpdate(times)),
+            None => vec![],
+        }
+    }
+
+    // Ends the match if a clock ran out. The clocks don't tell anyone, so the
+    //   server has to ask every now and then
+    pub fn check_time(&mut self) -> Outgoing {
+        let is_flagged = self.game.get_clock().and_then(|c| c.flagged()).is_some();
+        if self.is_over() || !is_flagged {
+            return vec![];
+        }
+
+        //a timeout, or a draw if the other side can't checkmate
+        let end_state = self.game.get_end_state();
+        let result = game_result(&mut self.game, end_state);
+        self.end(result)
+    }
+
+    // the player is gone. The other one wins
+    pub fn player_left(&mut self, player: usize) -> Outgoing {
+        if self.is_over() {
+            return vec![];
+        }
+
+        self.end(GameResult {
+            winner: Some(player_team(player).the_other_one()),
+            reason: EndReason::Disconnection,
+        })
+        .into_iter()
+        .filter(|(to, _)| *to != To::Player(player))
+        .collect()
+    }
+
+    pub fn handle_message(&mut self, player: usize, message: Message) -> Outgoing {
+        let opponent = 1 - player;
+        let unexpected = vec![(
+            To::Player(player),
+            Message::Error(ProtocolError::UnexpectedMessage),
+        )];
+
+        if self.is_over() {
+            return unexpected;
+        }
+
+        match message {
+            Message::Move(the_move) => {
+                let res = match self.game.whose_turn() == player_team(player) {
+                    true => self.game.perform_move(the_move),
+                    false => Err(chess::MoveError::NotYourTurn),
+                };
+
+                match res {
+                    Err(chess::MoveError::OutOfTime) => return self.check_time(),
+                    Err(e) => return vec![(To::Player(player), Message::MoveRejected(e))],
+                    Ok(_) => {}
+                }
+
+                //playing on answers a takeback request with a no, and the
+                //  opponent's draw offer. The opponent hears it like any no
+                let mut outgoing = vec![];
+                if self.takeback_request.take() == Some(opponent) {
+                    outgoing.push((To::Player(opponent), Message::TakebackAnswer(false)));
+                }
+                if self.draw_offer == Some(opponent) {
+                    self.draw_offer = None;
+                    outgoing.push((To::Player(opponent), Message::DeclineDraw));
+                }
+
+                outgoing.push((To::Player(opponent), Message::Move(the_move)));
+                outgoing.push((To::Spectators, Message::Move(the_move)));
+                outgoing.extend(self.clock_update());
+                let end_state = self.game.get_end_state();
+                if end_state != chess::GameEndState::Running {
+                    let result = game_result(&mut self.game, end_state);
+                    outgoing.extend(self.end(result));
+                }
+                outgoing
+            }
+            Message::TakebackRequest => {
+                let len = self.game.takeback_len(player_team(player));
+                if len == 0 || self.takeback_request.is_some() {
+                    return vec![];
+                }
+                self.takeback_request = Some(player);
+                vec![(To::Player(opponent), Message::TakebackRequest)]
+            }
+            Message::TakebackAnswer(accepted) => {
+                //only the other player can answer a request
+                if self.takeback_request != Some(opponent) {
+                    return vec![];
+                }
+                self.takeback_request = None;
+
+                if !accepted {
+                    return vec![(To::Player(opponent), Message::TakebackAnswer(false))];
+                }
+
+                let len = self.game.takeback_len(player_team(opponent));
+                self.draw_offer = None;
+                for _ in 0..len {
+                    self.game.undo_move();
+                }
+
+                //the clock of the team to move runs again
+                let team = self.game.whose_turn();
+                if let Some(clock) = self.game.get_clock_mut() {
+                    if clock.running().is_some() {
+                        clock.start(team);
+                    }
+                }
+
+                let mut outgoing = to_everyone(Message::Takeback(len as u32));
+                outgoing.extend(self.clock_update());
+                outgoing
+            }
+            Message::Resign => self.end(GameResult {
+                winner: Some(player_team(opponent)),
+                reason: EndReason::Resignation,
+            }),
+            //offering a draw back is taking the offer
+            Message::OfferDraw if self.draw_offer == Some(opponent) => self.end(GameResult {
+                winner: None,
+                reason: EndReason::DrawAgreed,
+            }),
+            Message::OfferDraw => {
+                if self.draw_offer.is_some() {
+                    return vec![];
+                }
+                self.draw_offer = Some(player);
+                vec![(To::Player(opponent), Message::OfferDraw)]
+            }
+            Message::AcceptDraw | Message::DeclineDraw if self.draw_offer != Some(opponent) => {
+                vec![]
+            }
+            Message::AcceptDraw => self.end(GameResult {
+                winner: None,
+                reason: EndReason::DrawAgreed,
+            }),
+            Message::DeclineDraw => {
+                self.draw_offer = None;
+                vec![(To::Player(opponent), Message::DeclineDraw)]
+            }
+            //only before both sides have moved
+            Message::Abort if self.game.move_count() >= 2 => vec![(
+                To::Player(player),
+                Message::Error(ProtocolError::TooLateToAbort),
+            )],
+            Message::Abort => self.end(GameResult {
+                winner: None,
+                reason: EndReason::Aborted,
+            }),
+            //only in arenas, and before the player's first move
+            Message::Berserk => {
+                let team = player_team(player);
+                let can_berserk = self.tournament.as_ref().is_some_and(|t| t.can_berserk);
+                let has_moved = match self.game.move_count() {
+                    0 => false,
+                    1 => self.game.whose_turn() != team,
+                    _ => true,
+                };
+                match self.game.get_clock_mut() {
+                    Some(clock) if can_berserk && !has_moved && !clock.is_berserked(team) => {
+                        clock.berserk(team)
+                    }
+                    _ => return vec![],
+                }
+
+                let mut outgoing = to_everyone(Message::Berserked(team));
+                outgoing.extend(self.clock_update());
+                outgoing
+            }
+            //the server checked it already
+            Message::Chat(text) => to_everyone(Message::ChatLine(ChatLine {
+                room: ChatRoom::Game,
+                from: self.players[player].clone(),
+                text,
+            })),
+            //only the server sends these, or they aren't for a match
+            Message::Hello(_)
+            | Message::Welcome(_)
+            | Message::Register(..)
+            | Message::Login(..)
+            | Message::LoginWithToken(_)
+            | Message::PlayAsGuest
+            | Message::LoggedIn(..)
+            | Message::JoinLobby
+            | Message::RatingChanged(..)
+            | Message::GetRatings(_)
+            | Message::Ratings(..)
+            | Message::GetRatingHistory(..)
+            | Message::RatingHistory(..)
+            | Message::GetLeaderboard(_)
+            | Message::Leaderboard(..)
+            | Message::FindGames(_)
+            | Message::FoundGames(_)
+            | Message::GetPgn(_)
+            | Message::Pgn(..)
+            | Message::Berserked(_)
+            | Message::ListTournaments
+            | Message::Tournaments(_)
+            | Message::CreateTournament(_)
+            | Message::JoinTournament(_)
+            | Message::LeaveTournament(_)
+            | Message::StartTournament(_)
+            | Message::TournamentUpdated(_)
+            | Message::Standings(..)
+            | Message::TournamentOver(..)
+            | Message::PrivateChat(..)
+            | Message::ChatLine(_)
+            | Message::Lobby(_)
+            | Message::Seek(_)
+            | Message::SeekAdded(_)
+            | Message::SeekRemoved(_)
+            | Message::CancelSeek(_)
+            | Message::AcceptSeek(_)
+            | Message::Challenge(..)
+            | Message::Challenged(_)
+            | Message::AcceptChallenge(_)
+            | Message::DeclineChallenge(_)
+            | Message::ChallengeDeclined(_)
+            | Message::GameStart(_)
+            | Message::Resume(_)
+            | Message::Resumed(..)
+            | Message::OpponentLeft(_)
+            | Message::OpponentBack
+            | Message::ListGames
+            | Message::GameList(_)
+            | Message::Spectate(_)
+            | Message::Spectating(..)
+            | Message::Takeback(_)
+            | Message::ClockUpdate(_)
+            | Message::MoveRejected(_)
+            | Message::GameOver(_)
+            | Message::Error(_) => unexpected,
+        }
+    }
+}
+
+#[cfg(test)]
+#[path = "./tests/chess_match_tests.rs"]
+mod chess_match_tests;
@@ -0,0 +1,151 @@
+// Glicko-2 ratings, from Mark Glickman's "Example of the Glicko-2 system".
+//
+// Every game is a rating period of its own, so ratings change right after
+//  each game like everywhere else online. No database and no clock here, just
+//  the numbers.
+
+use std::f64::consts::PI;
+
+// from Glicko-1 numbers to Glicko-2 ones
+const SCALE: f64 = 173.7178;
+
+// how much the volatility can change. Lower is slower
+const TAU: f64 = 0.5;
+
+// when the volatility is close enough
+const EPSILON: f64 = 0.000_001;
+
+// the deviation of someone that never played, and the highest one
+const MAX_DEVIATION: f64 = 350.0;
+
+// players with a deviation over this aren't on the leaderboards
+pub const PROVISIONAL_DEVIATION: f64 = 110.0;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Rating {
+    pub rating: f64,
+    pub deviation: f64,
+    pub volatility: f64,
+}
+
+impl Default for Rating {
+    fn default() -> Rating {
+        Rating {
+            rating: 1500.0,
+            deviation: MAX_DEVIATION,
+            volatility: 0.06,
+        }
+    }
+}
+
+impl Rating {
+    pub fn is_provisional(&self) -> bool {
+        self.deviation > PROVISIONAL_DEVIATION
+    }
+
+    fn mu(&self) -> f64 {
+        (self.rating - 1500.0) / SCALE
+    }
+
+    fn phi(&self) -> f64 {
+        self.deviation / SCALE
+    }
+}
+
+fn g(phi: f64) -> f64 {
+    1.0 / (1.0 + 3.0 * phi * phi / (PI * PI)).sqrt()
+}
+
+// the score mu is expected to get against an opponent
+fn expected(mu: f64, opponent_mu: f64, opponent_phi: f64) -> f64 {
+    1.0 / (1.0 + (-g(opponent_phi) * (mu - opponent_mu)).exp())
+}
+
+// step 5 of the paper, with the Illinois algorithm
+fn new_volatility(sigma: f64, phi: f64, v: f64, delta: f64) -> f64 {
+    let a = (sigma * sigma).ln();
+    let f = |x: f64| {
+        let ex = x.exp();
+        ex * (delta * delta - phi * phi - v - ex) / (2.0 * (phi * phi + v + ex).powi(2))
+            - (x - a) / (TAU * TAU)
+    };
+
+    let mut big_a = a;
+    let mut big_b = if delta * delta > phi * phi + v {
+        (delta * delta - phi * phi - v).ln()
+    } else {
+        let mut k = 1.0;
+        while f(a - k * TAU) < 0.0 {
+            k += 1.0;
+        }
+        a - k * TAU
+    };
+
+    let mut f_a = f(big_a);
+    let mut f_b = f(big_b);
+    while (big_b - big_a).abs() > EPSILON {
+        let big_c = big_a + (big_a - big_b) * f_a / (f_b - f_a);
+        let f_c = f(big_c);
+        if f_c * f_b <= 0.0 {
+            big_a = big_b;
+            f_a = f_b;
+        } else {
+            f_a /= 2.0;
+        }
+        big_b = big_c;
+        f_b = f_c;
+    }
+
+    (big_a / 2.0).exp()
+}
+
+// The rating after a period with the games against the opponents: their
+//   ratings, and the score (1 for a win, 0.5 for a draw and 0 for a loss)
+pub fn rate(player: Rating, games: &[(Rating, f64)]) -> Rating {
+    let mu = player.mu();
+    let phi = player.phi();
+    let sigma = player.volatility;
+
+    //someone that didn't play only gets less sure
+    if games.is_empty() {
+        let deviation = (phi * phi + sigma * sigma).sqrt() * SCALE;
+        return Rating {
+            deviation: deviation.min(MAX_DEVIATION),
+            ..player
+        };
+    }
+
+    let mut v_inverse = 0.0;
+    let mut improvement = 0.0;
+    for (opponent, score) in games {
+        let g_phi = g(opponent.phi());
+        let e = expected(mu, opponent.mu(), opponent.phi());
+        v_inverse += g_phi * g_phi * e * (1.0 - e);
+        improvement += g_phi * (score - e);
+    }
+    let v = 1.0 / v_inverse;
+    let delta = v * improvement;
+
+    let volatility = new_volatility(sigma, phi, v, delta);
+    let phi_star = (phi * phi + volatility * volatility).sqrt();
+    let new_phi = 1.0 / (1.0 / (phi_star * phi_star) + 1.0 / v).sqrt();
+    let new_mu = mu + new_phi * new_phi * improvement;
+
+    Rating {
+        rating: new_mu * SCALE + 1500.0,
+        deviation: (new_phi * SCALE).min(MAX_DEVIATION),
+        volatility,
+    }
+}
+
+// both players' ratings after a game between them. The score is White's
+pub fn rate_game(white: Rating, black: Rating, white_score: f64) -> (Rating, Rating) {
+    (
+        rate(white, &[(black, white_score)]),
+        rate(black, &[(white, 1.0 - white_score)]),
+    )
+}
+
+#[cfg(test)]
+#[path = "./tests/glicko_tests.rs"]
+mod glicko_tests;
@@ -0,0 +1,247 @@
+// The seeks and challenges of the players in the lobby, without the sockets.
+//
+// Players are the tokens of their connections. The lobby says who plays who
+//  and the server starts the matches.
+
+use chess_rs_core::ChessTeam;
+use chess_rs_protocol::{Challenge, GameSettings, Seek, SeekRequest};
+use mio::Token;
+use std::collections::HashMap;
+
+// open seeks a player can have. A new one takes the place of the oldest
+pub const MAX_SEEKS: usize = 3;
+
+// who a player is to the other ones
+#[derive(Clone, Debug, PartialEq)]
+pub struct Player {
+    pub name: String,
+    pub rating: u32,
+}
+
+// two players that should start a match
+#[derive(Debug, PartialEq)]
+pub struct Pairing {
+    pub white: Token,
+    pub black: Token,
+    pub settings: GameSettings,
+    //seeks that left the lobby with the players
+    pub removed_seeks: Vec<u64>,
+}
+
+pub enum SeekOutcome {
+    //no seek fits it yet. It waits in the lobby, with the id of the seek it
+    //  took the place of
+    Posted(Seek, Option<u64>),
+    Paired(Pairing),
+}
+
+fn in_range(rating: u32, range: Option<(u32, u32)>) -> bool {
+    match range {
+        Some((min, max)) => min <= rating && rating <= max,
+        None => true,
+    }
+}
+
+// the player with the rating would play the seek
+pub fn fits(seek: &Seek, rating: u32) -> bool {
+    in_range(rating, seek.request.rating_range)
+}
+
+// two seeks that could be the same game
+pub fn compatible(a: &Seek, b: &Seek) -> bool {
+    let colors_fit = match (a.request.color, b.request.color) {
+        (Some(a_color), Some(b_color)) => a_color != b_color,
+        _ => true,
+    };
+
+    a.request.settings == b.request.settings && colors_fit && fits(a, b.rating) && fits(b, a.rating)
+}
+
+// The colors of two players, White first. A player that doesn't mind gets
+//   what the other one doesn't want, or the coin decides.
+fn colors(a: Token, a_color: Option<ChessTeam>, b: Token, coin: bool) -> (Token, Token) {
+    match a_color {
+        Some(ChessTeam::White) => (a, b),
+        Some(ChessTeam::Black) => (b, a),
+        None if coin => (a, b),
+        None => (b, a),
+    }
+}
+
+#[derive(Default)]
+pub struct Lobby {
+    //oldest first, so matchmaking is first come first served
+    seeks: Vec<(Token, Seek)>,
+    //the challenger and the challenged player of every challenge
+    challenges: HashMap<u64, (Token, Token, Challenge)>,
+    next_id: u64,
+}
+
+impl Lobby {
+    pub fn new() -> Lobby {
+        Lobby::default()
+    }
+
+    fn new_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    pub fn seeks(&self) -> Vec<Seek> {
+        self.seeks.iter().map(|(_, seek)| seek.clone()).collect()
+    }
+
+    pub fn find(&self, seek_id: u64) -> Option<&Seek> {
+        self.seeks
+            .iter()
+            .map(|(_, seek)| seek)
+            .find(|seek| seek.id == seek_id)
+    }
+
+    // Posts the player's seek, or pairs it with the oldest one it fits. The
+    //   coin picks the colors if neither player minds
+    pub fn seek(
+        &mut self,
+        owner: Token,
+        player: Player,
+        request: SeekRequest,
+        coin: bool,
+    ) -> SeekOutcome {
+        let seek = Seek {
+            id: self.new_id(),
+            player: player.name,
+            rating: player.rating,
+            request,
+        };
+
+        let found = self
+            .seeks
+            .iter()
+            .find(|(other, other_seek)| *other != owner && compatible(&seek, other_seek))
+            .map(|(other, other_seek)| (*other, other_seek.request.color));
+        match found {
+            Some((other, other_color)) => {
+                let (white, black) = match seek.request.color {
+                    Some(_) => colors(owner, seek.request.color, other, coin),
+                    None => colors(other, other_color, owner, coin),
+                };
+                SeekOutcome::Paired(self.pair(white, black, seek.request.settings))
+            }
+            None => {
+                let own: Vec<u64> = self
+                    .seeks
+                    .iter()
+                    .filter(|(other, _)| *other == owner)
+                    .map(|(_, own_seek)| own_seek.id)
+                    .collect();
+                let replaced = match own.len() >= MAX_SEEKS {
+                    true => own.first().copied(),
+                    false => None,
+                };
+                if let Some(replaced) = replaced {
+                    self.seeks
+                        .retain(|(_, other_seek)| other_seek.id != replaced);
+                }
+                self.seeks.push((owner, seek.clone()));
+                SeekOutcome::Posted(seek, replaced)
+            }
+        }
+    }
+
+    // the player takes up a seek in the lobby
+    pub fn accept(
+        &mut self,
+        owner: Token,
+        rating: u32,
+        seek_id: u64,
+        coin: bool,
+    ) -> Option<Pairing> {
+        let (other, seek) = self
+            .seeks
+            .iter()
+            .find(|(other, seek)| seek.id == seek_id && *other != owner && fits(seek, rating))?
+            .clone();
+
+        let (white, black) = colors(other, seek.request.color, owner, coin);
+        Some(self.pair(white, black, seek.request.settings))
+    }
+
+    // true if the seek was the player's
+    pub fn cancel(&mut self, owner: Token, seek_id: u64) -> bool {
+        let len = self.seeks.len();
+        self.seeks
+            .retain(|(other, seek)| !(*other == owner && seek.id == seek_id));
+        self.seeks.len() != len
+    }
+
+    // the player left the lobby. Returns the seeks that went with it
+    pub fn remove_player(&mut self, player: Token) -> Vec<u64> {
+        self.challenges
+            .retain(|_, (from, to, _)| *from != player && *to != player);
+
+        let removed = self
+            .seeks
+            .iter()
+            .filter(|(owner, _)| *owner == player)
+            .map(|(_, seek)| seek.id)
+            .collect();
+        self.seeks.retain(|(owner, _)| *owner != player);
+        removed
+    }
+
+    fn pair(&mut self, white: Token, black: Token, settings: GameSettings) -> Pairing {
+        let mut removed_seeks = self.remove_player(white);
+        removed_seeks.extend(self.remove_player(black));
+        Pairing {
+            white,
+            black,
+            settings,
+            removed_seeks,
+        }
+    }
+
+    // the challenge to send to the challenged player
+    pub fn challenge(
+        &mut self,
+        from: Token,
+        from_name: String,
+        to: Token,
+        request: SeekRequest,
+    ) -> Challenge {
+        let challenge = Challenge {
+            id: self.new_id(),
+            from: from_name,
+            request,
+        };
+        self.challenges
+            .insert(challenge.id, (from, to, challenge.clone()));
+        challenge
+    }
+
+    // The challenged player's answer. Returns the challenger, and the pairing
+    //   if it was accepted. None if the challenge isn't for the player
+    pub fn answer(
+        &mut self,
+        player: Token,
+        challenge_id: u64,
+        accepted: bool,
+        coin: bool,
+    ) -> Option<(Token, Option<Pairing>)> {
+        match self.challenges.get(&challenge_id) {
+            Some((_, to, _)) if *to == player => {}
+            _ => return None,
+        }
+        let (from, _, challenge) = self.challenges.remove(&challenge_id)?;
+
+        if !accepted {
+            return Some((from, None));
+        }
+        let (white, black) = colors(from, challenge.request.color, player, coin);
+        let pairing = self.pair(white, black, challenge.request.settings);
+        Some((from, Some(pairing)))
+    }
+}
+
+#[cfg(test)]
+#[path = "./tests/lobby_tests.rs"]
+mod lobby_tests;
@@ -0,0 +1,92 @@
+// The thread that hashes passwords, so registering and logging in don't hold
+//  up the server's thread. The server hands it a job with the connection's
+//  token, and the thread wakes the server's poll when the job is done.
+//
+// The database stays with the server: it looks up the hash before a login and
+//  makes the account after a registration.
+
+use crate::accounts::{self, Account, AccountError};
+use argon2::Argon2;
+use mio::{Token, Waker};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+pub enum Job {
+    Register {
+        name: String,
+        password: String,
+    },
+    Login {
+        account: Account,
+        password: String,
+        hash: String,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Done {
+    //the account to make, with the hash of its password
+    Registered { name: String, hash: String },
+    LoggedIn(Account),
+}
+
+pub struct PasswordThread {
+    jobs: Sender<(Token, Argon2<'static>, Job)>,
+    done: Receiver<(Token, Result<Done, AccountError>)>,
+}
+
+fn run(hasher: &Argon2<'_>, job: Job) -> Result<Done, AccountError> {
+    match job {
+        Job::Register { name, password } => Ok(Done::Registered {
+            hash: accounts::hash_password(hasher, &password)?,
+            name,
+        }),
+        Job::Login {
+            account,
+            password,
+            hash,
+        } => {
+            accounts::verify_password(hasher, &password, &hash)?;
+            Ok(Done::LoggedIn(account))
+        }
+    }
+}
+
+impl PasswordThread {
+    // The thread stops with the PasswordThread. The waker is woken after
+    //   every job
+    pub fn spawn(waker: Waker) -> PasswordThread {
+        let (jobs, job_receiver) = mpsc::channel::<(Token, Argon2<'static>, Job)>();
+        let (done_sender, done) = mpsc::channel();
+
+        thread::Builder::new()
+            .name("passwords".to_string())
+            .spawn(move || {
+                for (token, hasher, job) in job_receiver {
+                    if done_sender.send((token, run(&hasher, job))).is_err() {
+                        return;
+                    }
+                    if let Err(e) = waker.wake() {
+                        println!("error at waking the server: {}", e);
+                    }
+                }
+            })
+            .expect("can't start the password thread");
+
+        PasswordThread { jobs, done }
+    }
+
+    pub fn start(&self, token: Token, hasher: Argon2<'static>, job: Job) {
+        //the thread only stops once this is dropped
+        let _ = self.jobs.send((token, hasher, job));
+    }
+
+    // the jobs that are done, in any order
+    pub fn done(&self) -> Vec<(Token, Result<Done, AccountError>)> {
+        self.done.try_iter().collect()
+    }
+}
+
+#[cfg(test)]
+#[path = "./tests/passwords_tests.rs"]
+mod passwords_tests;
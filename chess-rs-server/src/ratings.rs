@@ -0,0 +1,240 @@
+// The ratings of the accounts, one for each RatingCategory, with every rating
+//  they had after a game. The numbers come from glicko, this keeps them and
+//  makes them less sure for every RATING_PERIOD without a game.
+//
+// The tables are in the database of the accounts.
+
+use crate::glicko::{self, Rating};
+use chess_rs_protocol::{PlayerRating, RatingCategory, RatingPoint};
+use rusqlite::{params, Connection, OptionalExtension};
+
+// a week, in seconds. Games are rating periods of their own, this is for the
+//  time between them
+const RATING_PERIOD: u64 = 7 * 24 * 60 * 60;
+
+pub fn create_tables(db: &Connection) -> rusqlite::Result<()> {
+    db.execute_batch(
+        "CREATE TABLE IF NOT EXISTS ratings (
+            account INTEGER NOT NULL REFERENCES accounts(id),
+            category TEXT NOT NULL,
+            rating REAL NOT NULL,
+            deviation REAL NOT NULL,
+            volatility REAL NOT NULL,
+            games INTEGER NOT NULL,
+            PRIMARY KEY (account, category)
+        );
+        CREATE TABLE IF NOT EXISTS rating_history (
+            account INTEGER NOT NULL REFERENCES accounts(id),
+            category TEXT NOT NULL,
+            rating REAL NOT NULL,
+            deviation REAL NOT NULL,
+            time INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS rating_history_account
+            ON rating_history (account, category);",
+    )
+}
+
+// what the clients see of a rating
+pub fn player_rating(rating: Rating) -> PlayerRating {
+    PlayerRating {
+        rating: rating.rating.round().max(0.0) as u32,
+        deviation: rating.deviation.round() as u32,
+        provisional: rating.is_provisional(),
+    }
+}
+
+// The rating once the periods without games from the last game to time
+//   are rated. Until the deviation can't grow anymore
+fn after_inactivity(mut rating: Rating, last_game: u64, time: u64) -> Rating {
+    let periods = time.saturating_sub(last_game) / RATING_PERIOD;
+    for _ in 0..periods {
+        let next = glicko::rate(rating, &[]);
+        if next == rating {
+            break;
+        }
+        rating = next;
+    }
+    rating
+}
+
+// the time of the last game of the account in the category
+fn last_game(db: &Connection, account: i64, category: RatingCategory) -> rusqlite::Result<u64> {
+    let time: Option<i64> = db.query_row(
+        "SELECT MAX(time) FROM rating_history WHERE account = ?1 AND category = ?2",
+        params![account, category.name()],
+        |row| row.get(0),
+    )?;
+    Ok(time.unwrap_or(0) as u64)
+}
+
+// the rating of the account in the category at time (in seconds since
+//   1970). Accounts start with the default
+pub fn rating(
+    db: &Connection,
+    account: i64,
+    category: RatingCategory,
+    time: u64,
+) -> rusqlite::Result<Rating> {
+    let rating = db
+        .query_row(
+            "SELECT rating, deviation, volatility FROM ratings
+                WHERE account = ?1 AND category = ?2",
+            params![account, category.name()],
+            |row| {
+                Ok(Rating {
+                    rating: row.get(0)?,
+                    deviation: row.get(1)?,
+                    volatility: row.get(2)?,
+                })
+            },
+        )
+        .optional()?;
+    match rating {
+        Some(rating) => Ok(after_inactivity(
+            rating,
+            last_game(db, account, category)?,
+            time,
+        )),
+        None => Ok(Rating::default()),
+    }
+}
+
+// the categories the account played in
+pub fn ratings(
+    db: &Connection,
+    account: i64,
+    time: u64,
+) -> rusqlite::Result<Vec<(RatingCategory, Rating)>> {
+    let mut ratings = vec![];
+    for category in RatingCategory::ALL.iter() {
+        let has_played: bool = db.query_row(
+            "SELECT EXISTS (SELECT 1 FROM ratings WHERE account = ?1 AND category = ?2)",
+            params![account, category.name()],
+            |row| row.get(0),
+        )?;
+        if has_played {
+            ratings.push((*category, rating(db, account, *category, time)?));
+        }
+    }
+    Ok(ratings)
+}
+
+// Rates a game between the accounts, White first. Returns the ratings of
+//   both before and after it
+pub fn record_game(
+    db: &Connection,
+    accounts: [i64; 2],
+    category: RatingCategory,
+    white_score: f64,
+    time: u64,
+) -> rusqlite::Result<[(Rating, Rating); 2]> {
+    let before = [
+        rating(db, accounts[0], category, time)?,
+        rating(db, accounts[1], category, time)?,
+    ];
+    let (white, black) = glicko::rate_game(before[0], before[1], white_score);
+    let after = [white, black];
+
+    let transaction = db.unchecked_transaction()?;
+    for (account, rating) in accounts.iter().zip(after.iter()) {
+        transaction.execute(
+            "INSERT INTO ratings (account, category, rating, deviation, volatility, games)
+                VALUES (?1, ?2, ?3, ?4, ?5, 1)
+                ON CONFLICT (account, category) DO UPDATE SET
+                    rating = ?3, deviation = ?4, volatility = ?5, games = games + 1",
+            params![
+                account,
+                category.name(),
+                rating.rating,
+                rating.deviation,
+                rating.volatility
+            ],
+        )?;
+        transaction.execute(
+            "INSERT INTO rating_history (account, category, rating, deviation, time)
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                account,
+                category.name(),
+                rating.rating,
+                rating.deviation,
+                time as i64
+            ],
+        )?;
+    }
+    transaction.commit()?;
+
+    Ok([(before[0], after[0]), (before[1], after[1])])
+}
+
+// the rating after every game in the category, oldest first
+pub fn history(
+    db: &Connection,
+    account: i64,
+    category: RatingCategory,
+) -> rusqlite::Result<Vec<RatingPoint>> {
+    let mut statement = db.prepare(
+        "SELECT time, rating FROM rating_history
+            WHERE account = ?1 AND category = ?2 ORDER BY time, rowid",
+    )?;
+    let points = statement.query_map(params![account, category.name()], |row| {
+        let time: i64 = row.get(0)?;
+        let rating: f64 = row.get(1)?;
+        Ok(RatingPoint {
+            time: time as u64,
+            rating: rating.round() as u32,
+        })
+    })?;
+    points.collect()
+}
+
+// the best accounts in the category at time that aren't provisional, best
+//   first. Those that stopped playing drop out once they are provisional again
+pub fn leaderboard(
+    db: &Connection,
+    category: RatingCategory,
+    count: usize,
+    time: u64,
+) -> rusqlite::Result<Vec<(String, Rating)>> {
+    let mut statement = db.prepare(
+        "SELECT accounts.name, rating, deviation, volatility,
+                (SELECT MAX(time) FROM rating_history
+                    WHERE account = ratings.account AND category = ratings.category)
+            FROM ratings
+            JOIN accounts ON accounts.id = ratings.account
+            WHERE category = ?1 AND deviation <= ?2
+            ORDER BY rating DESC",
+    )?;
+    let rows = statement.query_map(
+        params![category.name(), glicko::PROVISIONAL_DEVIATION],
+        |row| {
+            let rating = Rating {
+                rating: row.get(1)?,
+                deviation: row.get(2)?,
+                volatility: row.get(3)?,
+            };
+            let last_game: Option<i64> = row.get(4)?;
+            Ok((
+                row.get(0)?,
+                after_inactivity(rating, last_game.unwrap_or(0) as u64, time),
+            ))
+        },
+    )?;
+
+    let mut best = vec![];
+    for row in rows {
+        let (name, rating) = row?;
+        if !rating.is_provisional() {
+            best.push((name, rating));
+        }
+        if best.len() == count {
+            break;
+        }
+    }
+    Ok(best)
+}
+
+#[cfg(test)]
+#[path = "./tests/ratings_tests.rs"]
+mod ratings_tests;
@@ -0,0 +1,1510 @@
+// The server: one thread waits on every socket with mio and hands whatever
+//  messages arrive to the matches.
+//
+// A connection says Hello, logs in (or plays as a guest), finds an opponent
+//  in the lobby and then plays its match. Passwords are hashed on the
+//  passwords thread, and the login finishes when it wakes the poll.
+//  When something goes wrong with a connection (it's gone, or it sends a
+//  bad frame) only that connection is closed. Its player has some time to
+//  come back with the session it got at the start of the match, or else its
+//  opponent wins.
+//
+// Anyone can also watch a match: spectators get everything the match sends to
+//  "To::Spectators" until it's over.
+//
+// Chat goes to the match or the lobby of the connection, and private messages
+//  to every connection logged in with the name. Each connection can only say
+//  so much so fast.
+//
+// Matches between two accounts with a clock are rated when they end, unless
+//  they are aborted. Every match that ends goes to the archive.
+//
+// Tournaments are made and joined in the lobby. The server pairs their players
+//  whenever it checks the clocks, starting the games of those that are in the
+//  lobby and scoring a forfeit for the others. After a game of a tournament
+//  the players go back to the lobby instead of being closed.
+//
+// The server can listen for WebSockets too. Their connections are the same as
+//  the others, only the messages are framed differently.
+
+use crate::accounts::{self, Account, AccountError, Accounts};
+use crate::archive::{self, Record};
+use crate::chat::{self, ChatLimit};
+use crate::chess_match::{self, Match, Outgoing, To};
+use crate::glicko::Rating;
+use crate::lobby::{Lobby, Pairing, Player, SeekOutcome};
+use crate::passwords::{self, PasswordThread};
+use crate::ratings;
+use crate::tournament::Tournament;
+use crate::websocket::WebSocketCodec;
+use chess_rs_core::{clock, ChessTeam};
+use chess_rs_protocol::{
+    self as protocol, ChatLine, ChatRoom, EndReason, FrameError, FrameReader, GameResult,
+    GameSettings, LoginToken, Message, ProtocolError, RatingCategory, SeekRequest, SessionToken,
+    TournamentGame, TournamentKind, TournamentSettings, TournamentStatus,
+};
+use mio::net::{TcpListener, TcpStream};
+use mio::{Events, Interest, Poll, Token, Waker};
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const LISTENER: Token = Token(0);
+const WEBSOCKET_LISTENER: Token = Token(1);
+//the passwords thread finished a job
+const PASSWORDS: Token = Token(2);
+
+// connections that don't say Hello in time are closed
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// how long a match waits for a player that lost the connection
+const RECONNECT_GRACE: Duration = Duration::from_secs(60);
+
+// how often the clocks and timeouts are checked, at least
+const TICK: Duration = Duration::from_millis(100);
+
+// how many players a leaderboard has
+const LEADERBOARD_LEN: usize = 20;
+
+// how many live games the list has, the newest ones
+const MAX_LISTED_GAMES: usize = 100;
+
+// in characters
+const MAX_TOURNAMENT_NAME_LEN: usize = 40;
+
+// how long finished tournaments stay in the list
+const FINISHED_TOURNAMENT_KEPT: Duration = Duration::from_secs(10 * 60);
+
+enum ConnectionState {
+    //waiting for the Hello, since then
+    Handshake(Instant),
+    //said Hello, and maybe logged in
+    Idle,
+    //looking for an opponent
+    Lobby { name: String },
+    Playing { match_id: usize, player: usize },
+    Spectating { match_id: usize },
+    //sending what's left, then it's closed
+    Closing,
+}
+
+// how the messages of a connection are put on the wire
+enum Codec {
+    Frames(FrameReader),
+    WebSocket(WebSocketCodec),
+}
+
+impl Codec {
+    fn push(&mut self, bytes: &[u8]) {
+        match self {
+            Codec::Frames(frames) => frames.push(bytes),
+            Codec::WebSocket(websocket) => websocket.push(bytes),
+        }
+    }
+
+    fn next_message(&mut self) -> Result<Option<Message>, Box<dyn Error>> {
+        match self {
+            Codec::Frames(frames) => Ok(frames.next_message()?),
+            Codec::WebSocket(websocket) => Ok(websocket.next_message()?),
+        }
+    }
+
+    fn encode(&mut self, message: &Message) -> Result<Vec<u8>, FrameError> {
+        match self {
+            Codec::Frames(_) => protocol::encode(message),
+            Codec::WebSocket(websocket) => websocket.encode(message),
+        }
+    }
+
+    // what the codec has to send by itself, like the answer to a WebSocket
+    //  handshake
+    fn take_outgoing(&mut self) -> Vec<u8> {
+        match self {
+            Codec::Frames(_) => vec![],
+            Codec::WebSocket(websocket) => websocket.take_outgoing(),
+        }
+    }
+}
+
+struct Connection {
+    stream: TcpStream,
+    addr: SocketAddr,
+    codec: Codec,
+    //bytes the socket didn't take yet
+    outgoing: Vec<u8>,
+    is_writable_registered: bool,
+    state: ConnectionState,
+    //who the client logged in as
+    identity: Option<Identity>,
+    //waiting for the passwords thread
+    is_logging_in: bool,
+    chat_limit: ChatLimit,
+}
+
+#[derive(Clone)]
+struct Identity {
+    name: String,
+    //None for guests
+    account: Option<i64>,
+}
+
+struct MatchEntry {
+    the_match: Match,
+    //None while the player is away
+    players: [Option<Token>; 2],
+    sessions: [SessionToken; 2],
+    //who the players logged in as, given back when they resume
+    identities: [Option<Identity>; 2],
+    //when each player lost the connection
+    left_at: [Option<Instant>; 2],
+    spectators: Vec<Token>,
+    //in seconds since 1970
+    started: u64,
+    //the tournament and its game, if it's part of one
+    tournament: Option<(u64, usize)>,
+}
+
+impl MatchEntry {
+    // the accounts of the players, if they aren't guests
+    fn accounts(&self) -> [Option<i64>; 2] {
+        let account = |player: usize| self.identities[player].as_ref().and_then(|i| i.account);
+        [account(0), account(1)]
+    }
+}
+
+pub struct Server {
+    poll: Poll,
+    listener: TcpListener,
+    websocket_listener: Option<TcpListener>,
+    connections: HashMap<Token, Connection>,
+    matches: HashMap<usize, MatchEntry>,
+    //the match and player of every session
+    sessions: HashMap<SessionToken, (usize, usize)>,
+    reconnect_grace: Duration,
+    lobby: Lobby,
+    tournaments: HashMap<u64, Tournament>,
+    accounts: Accounts,
+    passwords: PasswordThread,
+    //connections to close once the current event is handled
+    to_close: Vec<Token>,
+    //for tokens and match ids
+    next_id: usize,
+}
+
+impl Server {
+    pub fn bind(addr: SocketAddr, accounts: Accounts) -> io::Result<Server> {
+        let poll = Poll::new()?;
+        let mut listener = TcpListener::bind(addr)?;
+        poll.registry()
+            .register(&mut listener, LISTENER, Interest::READABLE)?;
+        let passwords = PasswordThread::spawn(Waker::new(poll.registry(), PASSWORDS)?);
+
+        Ok(Server {
+            poll,
+            listener,
+            websocket_listener: None,
+            connections: HashMap::new(),
+            matches: HashMap::new(),
+            sessions: HashMap::new(),
+            reconnect_grace: RECONNECT_GRACE,
+            lobby: Lobby::new(),
+            tournaments: HashMap::new(),
+            accounts,
+            passwords,
+            to_close: vec![],
+            next_id: 3,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    // listens for WebSockets too, and gives the address it got
+    pub fn bind_websocket(&mut self, addr: SocketAddr) -> io::Result<SocketAddr> {
+        let mut listener = TcpListener::bind(addr)?;
+        self.poll
+            .registry()
+            .register(&mut listener, WEBSOCKET_LISTENER, Interest::READABLE)?;
+        let addr = listener.local_addr()?;
+        self.websocket_listener = Some(listener);
+        Ok(addr)
+    }
+
+    pub fn set_reconnect_grace(&mut self, grace: Duration) {
+        self.reconnect_grace = grace;
+    }
+
+    fn new_id(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id - 1
+    }
+
+    pub fn run(&mut self) -> io::Result<()> {
+        let mut events = Events::with_capacity(1024);
+
+        loop {
+            match self.poll.poll(&mut events, Some(TICK)) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+
+            for event in events.iter() {
+                match event.token() {
+                    LISTENER | WEBSOCKET_LISTENER => self.accept(event.token()),
+                    PASSWORDS => self.finish_logins(),
+                    token => {
+                        if event.is_readable() {
+                            self.read(token);
+                        }
+                        if event.is_writable() {
+                            self.flush(token);
+                        }
+                    }
+                }
+                self.close_dead();
+            }
+
+            self.close_stale_handshakes();
+            self.expire_sessions();
+            self.check_clocks();
+            self.run_tournaments();
+            self.close_dead();
+        }
+    }
+
+    fn accept(&mut self, listener: Token) {
+        loop {
+            let accepted = match (listener, &self.websocket_listener) {
+                (WEBSOCKET_LISTENER, Some(websocket_listener)) => websocket_listener.accept(),
+                _ => self.listener.accept(),
+            };
+            let (mut stream, addr) = match accepted {
+                Ok(accepted) => accepted,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    println!("error at accepting a connection: {}", e);
+                    return;
+                }
+            };
+
+            let token = Token(self.new_id());
+            if let Err(e) = self
+                .poll
+                .registry()
+                .register(&mut stream, token, Interest::READABLE)
+            {
+                println!("error at registering {}: {}", addr, e);
+                continue;
+            }
+
+            println!("New connection: {}", addr);
+            self.connections.insert(
+                token,
+                Connection {
+                    stream,
+                    addr,
+                    codec: match listener {
+                        WEBSOCKET_LISTENER => Codec::WebSocket(WebSocketCodec::new()),
+                        _ => Codec::Frames(FrameReader::new()),
+                    },
+                    outgoing: vec![],
+                    is_writable_registered: false,
+                    state: ConnectionState::Handshake(Instant::now()),
+                    identity: None,
+                    is_logging_in: false,
+                    chat_limit: ChatLimit::new(),
+                },
+            );
+        }
+    }
+
+    fn read(&mut self, token: Token) {
+        let connection = match self.connections.get_mut(&token) {
+            Some(connection) => connection,
+            None => return,
+        };
+
+        let mut buffer = [0; 4096];
+        loop {
+            match connection.stream.read(&mut buffer) {
+                Ok(0) => {
+                    self.to_close.push(token);
+                    break;
+                }
+                Ok(len) => connection.codec.push(&buffer[..len]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    println!("error at reading from {}: {}", connection.addr, e);
+                    self.to_close.push(token);
+                    break;
+                }
+            }
+        }
+
+        let mut messages = vec![];
+        loop {
+            match connection.codec.next_message() {
+                Ok(Some(message)) => messages.push(message),
+                Ok(None) => break,
+                Err(e) => {
+                    println!("bad frame from {}: {}", connection.addr, e);
+                    self.to_close.push(token);
+                    break;
+                }
+            }
+        }
+
+        let outgoing = connection.codec.take_outgoing();
+        if !outgoing.is_empty() {
+            connection.outgoing.extend(outgoing);
+            self.flush(token);
+        }
+
+        for message in messages {
+            self.handle_message(token, message);
+        }
+    }
+
+    fn handle_message(&mut self, token: Token, message: Message) {
+        let state = match self.connections.get(&token) {
+            Some(connection) => &connection.state,
+            None => return,
+        };
+
+        match state {
+            ConnectionState::Handshake(_) => match message {
+                Message::Hello(protocol::PROTOCOL_VERSION) => {
+                    self.set_state(token, ConnectionState::Idle);
+                    self.send(token, &Message::Welcome(protocol::PROTOCOL_VERSION));
+                }
+                Message::Hello(_) => {
+                    let error = ProtocolError::VersionMismatch(protocol::PROTOCOL_VERSION);
+                    self.send(token, &Message::Error(error));
+                    self.close_after_sending(token);
+                }
+                _ => self.to_close.push(token),
+            },
+            ConnectionState::Idle => match message {
+                Message::Register(name, password) if self.can_log_in(token) => {
+                    self.register(token, name, password)
+                }
+                Message::Login(name, password) if self.can_log_in(token) => {
+                    match self.accounts.password_hash(&name) {
+                        Ok((account, hash)) => self.start_login(
+                            token,
+                            passwords::Job::Login {
+                                account,
+                                password,
+                                hash,
+                            },
+                        ),
+                        Err(e) => self.log_in(token, Err(e), None),
+                    }
+                }
+                Message::LoginWithToken(login_token) if self.can_log_in(token) => {
+                    match self.accounts.login_with_token(&login_token) {
+                        Ok((account, next)) => self.log_in(token, Ok(account), Some(next)),
+                        Err(e) => self.log_in(token, Err(e), None),
+                    }
+                }
+                Message::PlayAsGuest if self.can_log_in(token) => self.play_as_guest(token),
+                Message::JoinLobby => self.join_lobby(token),
+                Message::Resume(session) => self.resume(token, session),
+                Message::ListGames => self.list_games(token),
+                message @ Message::GetRatings(_)
+                | message @ Message::GetRatingHistory(..)
+                | message @ Message::GetLeaderboard(_) => self.answer_rating_query(token, message),
+                message @ Message::FindGames(_) | message @ Message::GetPgn(_) => {
+                    self.answer_archive_query(token, message)
+                }
+                Message::Spectate(id) => self.spectate(token, id as usize),
+                Message::PrivateChat(to, text) => self.private_chat(token, to, text),
+                _ => self.send(token, &Message::Error(ProtocolError::UnexpectedMessage)),
+            },
+            ConnectionState::Lobby { name } => {
+                let name = name.clone();
+                self.handle_lobby_message(token, name, message);
+            }
+            ConnectionState::Spectating { .. } => match message {
+                Message::PrivateChat(to, text) => self.private_chat(token, to, text),
+                _ => self.send(token, &Message::Error(ProtocolError::UnexpectedMessage)),
+            },
+            &ConnectionState::Playing { match_id, player } => {
+                let message = match message {
+                    Message::PrivateChat(to, text) => return self.private_chat(token, to, text),
+                    Message::Chat(text) => match self.check_chat(token, &text) {
+                        Some(text) => Message::Chat(text),
+                        None => return,
+                    },
+                    message => message,
+                };
+                let entry = match self.matches.get_mut(&match_id) {
+                    Some(entry) => entry,
+                    None => return,
+                };
+                let outgoing = entry.the_match.handle_message(player, message);
+                self.deliver(match_id, outgoing);
+            }
+            ConnectionState::Closing => {}
+        }
+    }
+
+    fn handle_lobby_message(&mut self, token: Token, name: String, message: Message) {
+        match message {
+            Message::Seek(request) => self.seek(token, name, request),
+            Message::CancelSeek(id) => {
+                if self.lobby.cancel(token, id) {
+                    self.send_to_lobby(&Message::SeekRemoved(id));
+                }
+            }
+            Message::AcceptSeek(id) => {
+                let rating = match self.lobby.find(id) {
+                    Some(seek) => self.lobby_rating(token, &seek.request.settings),
+                    None => 0,
+                };
+                match self.lobby.accept(token, rating, id, coin()) {
+                    Some(pairing) => self.start_match(pairing, None),
+                    None => self.send(token, &Message::Error(ProtocolError::UnknownSeek)),
+                }
+            }
+            Message::Challenge(opponent, request) => self.challenge(token, name, opponent, request),
+            Message::AcceptChallenge(id) => self.answer_challenge(token, name, id, true),
+            Message::DeclineChallenge(id) => self.answer_challenge(token, name, id, false),
+            Message::ListGames => self.list_games(token),
+            message @ Message::GetRatings(_)
+            | message @ Message::GetRatingHistory(..)
+            | message @ Message::GetLeaderboard(_) => self.answer_rating_query(token, message),
+            message @ Message::FindGames(_) | message @ Message::GetPgn(_) => {
+                self.answer_archive_query(token, message)
+            }
+            Message::Chat(text) => {
+                if let Some(text) = self.check_chat(token, &text) {
+                    let line = ChatLine {
+                        room: ChatRoom::Lobby,
+                        from: name,
+                        text,
+                    };
+                    self.send_to_lobby(&Message::ChatLine(line));
+                }
+            }
+            Message::PrivateChat(to, text) => self.private_chat(token, to, text),
+            Message::ListTournaments => {
+                let mut tournaments: Vec<_> = self.tournaments.values().map(|t| t.info()).collect();
+                tournaments.sort_by_key(|tournament| tournament.id);
+                self.send(token, &Message::Tournaments(tournaments));
+            }
+            Message::CreateTournament(settings) => self.create_tournament(token, name, settings),
+            Message::JoinTournament(id) => {
+                let rating = match self.tournaments.get(&id) {
+                    Some(tournament) => self.lobby_rating(token, &tournament.settings.game),
+                    None => 0,
+                };
+                match self.tournaments.get_mut(&id).map(|t| t.join(name, rating)) {
+                    Some(true) => self.tournament_changed(id),
+                    Some(false) => {}
+                    None => self.send(token, &Message::Error(ProtocolError::UnknownTournament)),
+                }
+            }
+            Message::LeaveTournament(id) => {
+                match self.tournaments.get_mut(&id).map(|t| t.leave(&name)) {
+                    Some(true) => self.tournament_changed(id),
+                    Some(false) => {}
+                    None => self.send(token, &Message::Error(ProtocolError::UnknownTournament)),
+                }
+            }
+            Message::StartTournament(id) => {
+                let now = Instant::now();
+                match self.tournaments.get_mut(&id).map(|t| t.start(&name, now)) {
+                    Some(true) => {
+                        self.tournament_changed(id);
+                        self.run_tournament(id, false);
+                    }
+                    Some(false) => {
+                        self.send(token, &Message::Error(ProtocolError::CantStartTournament))
+                    }
+                    None => self.send(token, &Message::Error(ProtocolError::UnknownTournament)),
+                }
+            }
+            _ => self.send(token, &Message::Error(ProtocolError::UnexpectedMessage)),
+        }
+    }
+
+    fn answer_rating_query(&mut self, token: Token, query: Message) {
+        let db = self.accounts.db();
+        let time = unix_time();
+        let answer = match query {
+            Message::GetRatings(name) => match self.accounts.find(&name) {
+                Ok(Some(account)) => ratings::ratings(db, account.id, time).map(|ratings| {
+                    let ratings = ratings
+                        .into_iter()
+                        .map(|(category, rating)| (category, ratings::player_rating(rating)))
+                        .collect();
+                    Message::Ratings(account.name, ratings)
+                }),
+                _ => Ok(Message::Error(ProtocolError::UnknownPlayer)),
+            },
+            Message::GetRatingHistory(name, category) => match self.accounts.find(&name) {
+                Ok(Some(account)) => ratings::history(db, account.id, category)
+                    .map(|history| Message::RatingHistory(account.name, category, history)),
+                _ => Ok(Message::Error(ProtocolError::UnknownPlayer)),
+            },
+            Message::GetLeaderboard(category) => {
+                ratings::leaderboard(db, category, LEADERBOARD_LEN, time).map(|best| {
+                    let best = best
+                        .into_iter()
+                        .map(|(name, rating)| (name, ratings::player_rating(rating)))
+                        .collect();
+                    Message::Leaderboard(category, best)
+                })
+            }
+            _ => return,
+        };
+
+        match answer {
+            Ok(answer) => self.send(token, &answer),
+            Err(e) => println!("error at reading the ratings: {}", e),
+        }
+    }
+
+    fn answer_archive_query(&mut self, token: Token, query: Message) {
+        let db = self.accounts.db();
+        let answer = match query {
+            Message::FindGames(query) => {
+                archive::find(db, &query, archive::MAX_FOUND).map(Message::FoundGames)
+            }
+            Message::GetPgn(id) => archive::pgn(db, id).map(|pgn| match pgn {
+                Some(pgn) => Message::Pgn(id, pgn),
+                None => Message::Error(ProtocolError::UnknownGame),
+            }),
+            _ => return,
+        };
+
+        match answer {
+            Ok(answer) => self.send(token, &answer),
+            Err(e) => println!("error at reading the archive: {}", e),
+        }
+    }
+
+    // The rating of the player for games with the settings, for the lobby.
+    //   Guests and unrated games get the default one
+    fn lobby_rating(&self, token: Token, settings: &GameSettings) -> u32 {
+        let rating = match (self.account(token), RatingCategory::of(settings)) {
+            (Some(account), Some(category)) => {
+                ratings::rating(self.accounts.db(), account, category, unix_time())
+                    .unwrap_or_default()
+            }
+            _ => Rating::default(),
+        };
+        ratings::player_rating(rating).rating
+    }
+
+    // not logged in, and not waiting for the passwords thread either
+    fn can_log_in(&self, token: Token) -> bool {
+        match self.connections.get(&token) {
+            Some(connection) => connection.identity.is_none() && !connection.is_logging_in,
+            None => false,
+        }
+    }
+
+    // Names are checked here, the password on the passwords thread
+    fn register(&mut self, token: Token, name: String, password: String) {
+        let found = match accounts::valid_name(&name) {
+            true => self.accounts.find(&name),
+            false => Err(AccountError::InvalidName),
+        };
+        match found {
+            Ok(None) => self.start_login(token, passwords::Job::Register { name, password }),
+            Ok(Some(_)) => self.log_in(token, Err(AccountError::NameTaken), None),
+            Err(e) => self.log_in(token, Err(e), None),
+        }
+    }
+
+    fn start_login(&mut self, token: Token, job: passwords::Job) {
+        if let Some(connection) = self.connections.get_mut(&token) {
+            connection.is_logging_in = true;
+            self.passwords.start(token, self.accounts.hasher(), job);
+        }
+    }
+
+    // The jobs of the passwords thread that are done. Accounts of clients
+    //   that left in the meantime aren't made
+    fn finish_logins(&mut self) {
+        for (token, done) in self.passwords.done() {
+            match self.connections.get_mut(&token) {
+                Some(connection) => connection.is_logging_in = false,
+                None => continue,
+            }
+
+            let logged_in = done.and_then(|done| match done {
+                passwords::Done::Registered { name, hash } => self.accounts.insert(&name, &hash),
+                passwords::Done::LoggedIn(account) => Ok(account),
+            });
+            self.log_in(token, logged_in, None);
+        }
+    }
+
+    // The account logged in, or why not. A login without a token to use next
+    //   time gets a new one
+    fn log_in(
+        &mut self,
+        token: Token,
+        logged_in: Result<Account, AccountError>,
+        next: Option<LoginToken>,
+    ) {
+        let logged_in = logged_in.and_then(|account| {
+            let next = match next {
+                Some(next) => next,
+                None => self.accounts.new_token(account.id)?,
+            };
+            Ok((account, next))
+        });
+
+        match logged_in {
+            Ok((account, next)) => {
+                if let Some(connection) = self.connections.get_mut(&token) {
+                    connection.identity = Some(Identity {
+                        name: account.name.clone(),
+                        account: Some(account.id),
+                    });
+                }
+                self.send(token, &Message::LoggedIn(account.name, Some(next)));
+            }
+            Err(e) => match e.to_protocol() {
+                Some(error) => self.send(token, &Message::Error(error)),
+                None => {
+                    println!("error at logging in: {}", e);
+                    self.to_close.push(token);
+                }
+            },
+        }
+    }
+
+    // a made up name that no one is using
+    fn play_as_guest(&mut self, token: Token) {
+        let name = loop {
+            let mut bytes = [0; 2];
+            getrandom::getrandom(&mut bytes).expect("no random numbers for the guests");
+            let name = format!("guest-{:02x}{:02x}", bytes[0], bytes[1]);
+            let is_taken = self.connections.values().any(|connection| {
+                matches!(&connection.identity, Some(identity) if identity.name == name)
+            });
+            if !is_taken {
+                break name;
+            }
+        };
+
+        if let Some(connection) = self.connections.get_mut(&token) {
+            connection.identity = Some(Identity {
+                name: name.clone(),
+                account: None,
+            });
+        }
+        self.send(token, &Message::LoggedIn(name, None));
+    }
+
+    // the connection in the lobby with that name
+    fn find_in_lobby(&self, name: &str) -> Option<Token> {
+        self.connections
+            .iter()
+            .find(|(_, connection)| match &connection.state {
+                ConnectionState::Lobby { name: other } => other == name,
+                _ => false,
+            })
+            .map(|(token, _)| *token)
+    }
+
+    fn send_to_lobby(&mut self, message: &Message) {
+        let tokens: Vec<Token> = self
+            .connections
+            .iter()
+            .filter(|(_, connection)| matches!(connection.state, ConnectionState::Lobby { .. }))
+            .map(|(token, _)| *token)
+            .collect();
+        for token in tokens {
+            self.send(token, message);
+        }
+    }
+
+    // The line to pass on, or None if it can't go. The client is told why, and
+    //   only lines that go count for the rate limit
+    fn check_chat(&mut self, token: Token, text: &str) -> Option<String> {
+        let connection = self.connections.get_mut(&token)?;
+        let checked = chat::clean_line(text).and_then(|text| {
+            match connection.chat_limit.allow(Instant::now()) {
+                true => Ok(text),
+                false => Err(ProtocolError::ChatTooFast),
+            }
+        });
+
+        match checked {
+            Ok(text) => Some(text),
+            Err(e) => {
+                self.send(token, &Message::Error(e));
+                None
+            }
+        }
+    }
+
+    // to every connection logged in with the name, and back to the sender
+    fn private_chat(&mut self, token: Token, to: String, text: String) {
+        let from = match self.connections.get(&token).map(|c| &c.identity) {
+            Some(Some(identity)) => identity.name.clone(),
+            _ => {
+                self.send(token, &Message::Error(ProtocolError::NotLoggedIn));
+                return;
+            }
+        };
+
+        let to = to.trim();
+        let mut recipients: Vec<(Token, String)> = self
+            .connections
+            .iter()
+            .filter(|(_, c)| !matches!(c.state, ConnectionState::Closing))
+            .filter_map(|(token, c)| match &c.identity {
+                Some(identity) if identity.name.eq_ignore_ascii_case(to) => {
+                    Some((*token, identity.name.clone()))
+                }
+                _ => None,
+            })
+            .collect();
+        let to = match recipients.first() {
+            Some((_, name)) => name.clone(),
+            None => {
+                self.send(token, &Message::Error(ProtocolError::UnknownPlayer));
+                return;
+            }
+        };
+        let text = match self.check_chat(token, &text) {
+            Some(text) => text,
+            None => return,
+        };
+
+        let line = Message::ChatLine(ChatLine {
+            room: ChatRoom::Private(to),
+            from,
+            text,
+        });
+        recipients.retain(|(recipient, _)| *recipient != token);
+        for (recipient, _) in recipients {
+            self.send(recipient, &line);
+        }
+        self.send(token, &line);
+    }
+
+    fn join_lobby(&mut self, token: Token) {
+        let name = match self.connections.get(&token).map(|c| &c.identity) {
+            Some(Some(identity)) => identity.name.clone(),
+            _ => {
+                self.send(token, &Message::Error(ProtocolError::NotLoggedIn));
+                return;
+            }
+        };
+        //the same account from somewhere else
+        if self.find_in_lobby(&name).is_some() {
+            self.send(token, &Message::Error(ProtocolError::NameTaken));
+            return;
+        }
+
+        self.set_state(token, ConnectionState::Lobby { name });
+        self.send(token, &Message::Lobby(self.lobby.seeks()));
+    }
+
+    fn seek(&mut self, token: Token, name: String, request: SeekRequest) {
+        if let Err(e) = check_settings(&request.settings) {
+            self.send(token, &Message::Error(e));
+            return;
+        }
+
+        let player = Player {
+            name,
+            rating: self.lobby_rating(token, &request.settings),
+        };
+        match self.lobby.seek(token, player, request, coin()) {
+            SeekOutcome::Posted(seek, replaced) => {
+                if let Some(replaced) = replaced {
+                    self.send_to_lobby(&Message::SeekRemoved(replaced));
+                }
+                self.send_to_lobby(&Message::SeekAdded(seek));
+            }
+            SeekOutcome::Paired(pairing) => self.start_match(pairing, None),
+        }
+    }
+
+    fn challenge(&mut self, token: Token, name: String, opponent: String, request: SeekRequest) {
+        let opponent = match self.find_in_lobby(&opponent) {
+            Some(opponent) if opponent != token => opponent,
+            _ => {
+                self.send(token, &Message::Error(ProtocolError::UnknownPlayer));
+                return;
+            }
+        };
+        if let Err(e) = check_settings(&request.settings) {
+            self.send(token, &Message::Error(e));
+            return;
+        }
+
+        let challenge = self.lobby.challenge(token, name, opponent, request);
+        self.send(opponent, &Message::Challenged(challenge));
+    }
+
+    fn answer_challenge(&mut self, token: Token, name: String, id: u64, accepted: bool) {
+        match self.lobby.answer(token, id, accepted, coin()) {
+            Some((_, Some(pairing))) => self.start_match(pairing, None),
+            Some((challenger, None)) => self.send(challenger, &Message::ChallengeDeclined(name)),
+            None => self.send(token, &Message::Error(ProtocolError::UnknownSeek)),
+        }
+    }
+
+    // The players of the pairing leave the lobby for their match. With the
+    //   tournament and its game, if it's one of a tournament
+    fn start_match(&mut self, pairing: Pairing, tournament: Option<(u64, usize)>) {
+        let players = [pairing.white, pairing.black];
+        let names = [self.lobby_name(players[0]), self.lobby_name(players[1])];
+        let identities = [self.identity(players[0]), self.identity(players[1])];
+        let source = Arc::new(clock::SystemTime::new());
+        let mut the_match = match Match::new(pairing.settings, names, source) {
+            Some(the_match) => the_match,
+            None => return,
+        };
+        if let Some(found) = tournament.and_then(|(id, _)| self.tournaments.get(&id)) {
+            the_match.set_tournament(TournamentGame {
+                id: found.id,
+                name: found.settings.name.clone(),
+                can_berserk: found.is_arena(),
+            });
+        }
+
+        let match_id = self.new_id();
+        let sessions = [new_session(), new_session()];
+        for (player, token) in players.iter().enumerate() {
+            self.set_state(*token, ConnectionState::Playing { match_id, player });
+            self.sessions.insert(sessions[player], (match_id, player));
+        }
+        for id in pairing.removed_seeks {
+            self.send_to_lobby(&Message::SeekRemoved(id));
+        }
+
+        let outgoing = the_match.start(sessions);
+        self.matches.insert(
+            match_id,
+            MatchEntry {
+                the_match,
+                players: [Some(players[0]), Some(players[1])],
+                sessions,
+                identities,
+                left_at: [None; 2],
+                spectators: vec![],
+                started: unix_time(),
+                tournament,
+            },
+        );
+        println!("match {} started", match_id);
+        self.deliver(match_id, outgoing);
+    }
+
+    fn create_tournament(&mut self, token: Token, name: String, settings: TournamentSettings) {
+        let settings = TournamentSettings {
+            name: settings.name.trim().to_string(),
+            ..settings
+        };
+        if settings
+            .game
+            .time_control
+            .as_ref()
+            .is_some_and(|c| !c.is_valid())
+        {
+            self.send(token, &Message::Error(ProtocolError::InvalidTimeControl));
+            return;
+        }
+        let has_name =
+            !settings.name.is_empty() && settings.name.chars().count() <= MAX_TOURNAMENT_NAME_LEN;
+        let is_valid = has_name
+            && settings.game.variant.starting_game().is_some()
+            && match settings.kind {
+                TournamentKind::Arena { minutes } => {
+                    minutes > 0 && settings.game.time_control.is_some()
+                }
+                TournamentKind::Swiss { rounds } => rounds > 0,
+                TournamentKind::RoundRobin => true,
+            };
+        if !is_valid {
+            self.send(token, &Message::Error(ProtocolError::InvalidTournament));
+            return;
+        }
+
+        let id = self.new_id() as u64;
+        let rating = self.lobby_rating(token, &settings.game);
+        let tournament = Tournament::new(id, settings, name, rating);
+        self.tournaments.insert(id, tournament);
+        self.tournament_changed(id);
+    }
+
+    // the lobby gets the news, and the players the standings
+    fn tournament_changed(&mut self, id: u64) {
+        let info = match self.tournaments.get(&id) {
+            Some(tournament) => tournament.info(),
+            None => return,
+        };
+        self.send_to_lobby(&Message::TournamentUpdated(info));
+        self.send_standings(id);
+    }
+
+    fn send_standings(&mut self, id: u64) {
+        if let Some(tournament) = self.tournaments.get(&id) {
+            let standings = tournament.standings();
+            self.send_to_tournament(id, &Message::Standings(id, standings));
+        }
+    }
+
+    // to every connection logged in as one of its players
+    fn send_to_tournament(&mut self, id: u64, message: &Message) {
+        let names = match self.tournaments.get(&id) {
+            Some(tournament) => tournament.names(),
+            None => return,
+        };
+        let tokens: Vec<Token> = self
+            .connections
+            .iter()
+            .filter(|(_, c)| !matches!(c.state, ConnectionState::Closing))
+            .filter(
+                |(_, c)| matches!(&c.identity, Some(identity) if names.contains(&identity.name)),
+            )
+            .map(|(token, _)| *token)
+            .collect();
+        for token in tokens {
+            self.send(token, message);
+        }
+    }
+
+    fn run_tournaments(&mut self) {
+        let now = Instant::now();
+        self.tournaments.retain(|_, tournament| {
+            tournament
+                .finished_at()
+                .is_none_or(|at| now.duration_since(at) < FINISHED_TOURNAMENT_KEPT)
+        });
+
+        let running: Vec<u64> = self
+            .tournaments
+            .values()
+            .filter(|tournament| tournament.status() == TournamentStatus::Running)
+            .map(|tournament| tournament.id)
+            .collect();
+        for id in running {
+            self.run_tournament(id, false);
+        }
+    }
+
+    // Starts the games the tournament wants now, and ends it when it's over.
+    //   The players get the standings if they changed, or has_changed says so
+    fn run_tournament(&mut self, id: u64, has_changed: bool) {
+        let waiting: Vec<String> = self
+            .connections
+            .values()
+            .filter_map(|connection| match &connection.state {
+                ConnectionState::Lobby { name } => Some(name.clone()),
+                _ => None,
+            })
+            .collect();
+        let now = Instant::now();
+        let (pairings, settings) = match self.tournaments.get_mut(&id) {
+            Some(tournament) => (
+                tournament.pairings(now, &waiting),
+                tournament.settings.game.clone(),
+            ),
+            None => return,
+        };
+        let has_changed = has_changed || !pairings.is_empty();
+
+        for pairing in pairings {
+            let players = [
+                self.find_in_lobby(&pairing.white),
+                self.find_in_lobby(&pairing.black),
+            ];
+            match players {
+                [Some(white), Some(black)] => {
+                    let mut removed_seeks = self.lobby.remove_player(white);
+                    removed_seeks.extend(self.lobby.remove_player(black));
+                    let game = Pairing {
+                        white,
+                        black,
+                        settings: settings.clone(),
+                        removed_seeks,
+                    };
+                    self.start_match(game, Some((id, pairing.game)));
+                }
+                //whoever isn't there loses
+                _ => {
+                    if let Some(tournament) = self.tournaments.get_mut(&id) {
+                        tournament
+                            .forfeit(pairing.game, [players[0].is_some(), players[1].is_some()]);
+                    }
+                }
+            }
+        }
+
+        let is_over = match self.tournaments.get(&id) {
+            Some(tournament) => tournament.is_over(now),
+            None => return,
+        };
+        if is_over {
+            self.finish_tournament(id);
+        } else if has_changed {
+            self.send_standings(id);
+        }
+    }
+
+    // the players get the final standings, and the lobby hears it's over
+    fn finish_tournament(&mut self, id: u64) {
+        let (info, standings) = match self.tournaments.get_mut(&id) {
+            Some(tournament) => {
+                tournament.finish(Instant::now());
+                (tournament.info(), tournament.standings())
+            }
+            None => return,
+        };
+        println!("tournament {} is over", id);
+        self.send_to_tournament(id, &Message::TournamentOver(id, standings));
+        self.send_to_lobby(&Message::TournamentUpdated(info));
+    }
+
+    // the result of the match, for its tournament
+    fn record_in_tournament(&mut self, match_id: usize) {
+        let entry = match self.matches.get(&match_id) {
+            Some(entry) => entry,
+            None => return,
+        };
+        let ((id, game), result) = match (entry.tournament, entry.the_match.get_result()) {
+            (Some(tournament), Some(result)) => (tournament, result),
+            _ => return,
+        };
+        let white_score = match result.reason {
+            EndReason::Aborted => None,
+            _ => Some(white_score(&result) as f32),
+        };
+        let berserked = entry.the_match.berserked();
+        if let Some(tournament) = self.tournaments.get_mut(&id) {
+            tournament.record(game, white_score, berserked);
+        }
+    }
+
+    fn lobby_name(&self, token: Token) -> String {
+        match self.connections.get(&token).map(|c| &c.state) {
+            Some(ConnectionState::Lobby { name }) => name.clone(),
+            _ => String::new(),
+        }
+    }
+
+    fn account(&self, token: Token) -> Option<i64> {
+        self.connections
+            .get(&token)
+            .and_then(|connection| connection.identity.as_ref())
+            .and_then(|identity| identity.account)
+    }
+
+    fn identity(&self, token: Token) -> Option<Identity> {
+        self.connections
+            .get(&token)
+            .and_then(|connection| connection.identity.clone())
+    }
+
+    // the connection takes the place of the session's player
+    fn resume(&mut self, token: Token, session: SessionToken) {
+        let (match_id, player) = match self.sessions.get(&session) {
+            Some(found) => *found,
+            None => {
+                self.send(token, &Message::Error(ProtocolError::UnknownSession));
+                return;
+            }
+        };
+        let entry = match self.matches.get_mut(&match_id) {
+            Some(entry) => entry,
+            None => return,
+        };
+
+        //the old connection may not know it's gone yet
+        let old = entry.players[player].replace(token);
+        entry.left_at[player] = None;
+        let opponent = entry.players[1 - player];
+        let snapshot = entry.the_match.snapshot();
+        let identity = entry.identities[player].clone();
+
+        if let Some(old) = old {
+            self.set_state(old, ConnectionState::Closing);
+            self.to_close.push(old);
+        }
+
+        println!("player {} is back in match {}", player, match_id);
+        self.set_state(token, ConnectionState::Playing { match_id, player });
+        //the login stays with the player, not the old connection
+        if let Some(connection) = self.connections.get_mut(&token) {
+            connection.identity = identity;
+        }
+        let team = chess_match::player_team(player);
+        self.send(token, &Message::Resumed(team, snapshot));
+        if let Some(opponent) = opponent {
+            self.send(opponent, &Message::OpponentBack);
+        }
+    }
+
+    fn list_games(&mut self, token: Token) {
+        let mut games: Vec<_> = self
+            .matches
+            .iter()
+            .map(|(match_id, entry)| entry.the_match.info(*match_id as u64))
+            .collect();
+        games.sort_by_key(|game| game.id);
+        games.drain(..games.len().saturating_sub(MAX_LISTED_GAMES));
+        self.send(token, &Message::GameList(games));
+    }
+
+    fn spectate(&mut self, token: Token, match_id: usize) {
+        let entry = match self.matches.get_mut(&match_id) {
+            Some(entry) => entry,
+            None => {
+                self.send(token, &Message::Error(ProtocolError::UnknownGame));
+                return;
+            }
+        };
+        entry.spectators.push(token);
+        let catch_up = Message::Spectating(entry.the_match.snapshot());
+
+        self.set_state(token, ConnectionState::Spectating { match_id });
+        self.send(token, &catch_up);
+    }
+
+    // the players whose time ran out lose
+    fn check_clocks(&mut self) {
+        let mut outgoing = vec![];
+        for (match_id, entry) in self.matches.iter_mut() {
+            outgoing.push((*match_id, entry.the_match.check_time()));
+        }
+
+        for (match_id, outgoing) in outgoing {
+            if !outgoing.is_empty() {
+                self.deliver(match_id, outgoing);
+            }
+        }
+    }
+
+    // the players that were away for too long lose
+    fn expire_sessions(&mut self) {
+        let grace = self.reconnect_grace;
+        let mut expired = vec![];
+        for (match_id, entry) in self.matches.iter() {
+            for player in 0..2 {
+                if let Some(left_at) = entry.left_at[player] {
+                    if left_at.elapsed() >= grace {
+                        expired.push((*match_id, player));
+                    }
+                }
+            }
+        }
+
+        for (match_id, player) in expired {
+            if let Some(entry) = self.matches.get_mut(&match_id) {
+                let outgoing = entry.the_match.player_left(player);
+                self.deliver(match_id, outgoing);
+            }
+        }
+    }
+
+    fn set_state(&mut self, token: Token, state: ConnectionState) {
+        if let Some(connection) = self.connections.get_mut(&token) {
+            connection.state = state;
+        }
+    }
+
+    // sends the match's messages, and closes it if it's over
+    fn deliver(&mut self, match_id: usize, outgoing: Outgoing) {
+        let entry = match self.matches.get(&match_id) {
+            Some(entry) => entry,
+            None => return,
+        };
+        let players = entry.players;
+        let sessions = entry.sessions;
+        let spectators = entry.spectators.clone();
+        let is_over = entry.the_match.is_over();
+        let tournament = entry.tournament;
+        let names = entry.the_match.players().clone();
+
+        for (to, message) in outgoing {
+            match to {
+                To::Player(player) => {
+                    if let Some(token) = players[player] {
+                        self.send(token, &message);
+                    }
+                }
+                To::Spectators => {
+                    for token in spectators.iter() {
+                        self.send(*token, &message);
+                    }
+                }
+            }
+        }
+
+        if is_over {
+            println!("match {} is over", match_id);
+            self.rate(match_id);
+            self.archive(match_id);
+            self.record_in_tournament(match_id);
+            self.matches.remove(&match_id);
+            for session in sessions.iter() {
+                self.sessions.remove(session);
+            }
+            for token in spectators.iter() {
+                self.close_after_sending(*token);
+            }
+
+            //players of a tournament go back to the lobby for their next game
+            for (player, token) in players.iter().enumerate() {
+                match (token, tournament) {
+                    (Some(token), Some(_)) => {
+                        let name = names[player].clone();
+                        self.set_state(*token, ConnectionState::Lobby { name });
+                        self.send(*token, &Message::Lobby(self.lobby.seeks()));
+                    }
+                    (Some(token), None) => self.close_after_sending(*token),
+                    (None, _) => {}
+                }
+            }
+            if let Some((id, _)) = tournament {
+                self.run_tournament(id, true);
+            }
+        }
+    }
+
+    // Updates the ratings of the players if the match was rated, and tells them.
+    //   Aborted matches don't count
+    fn rate(&mut self, match_id: usize) {
+        let entry = match self.matches.get(&match_id) {
+            Some(entry) => entry,
+            None => return,
+        };
+        let (accounts, category, result) = match (
+            entry.accounts(),
+            RatingCategory::of(entry.the_match.settings()),
+            entry.the_match.get_result(),
+        ) {
+            ([Some(white), Some(black)], Some(category), Some(result))
+                if result.reason != EndReason::Aborted =>
+            {
+                ([white, black], category, result)
+            }
+            _ => return,
+        };
+        let players = entry.players;
+
+        let white_score = white_score(&result);
+        let time = unix_time();
+        let changes =
+            match ratings::record_game(self.accounts.db(), accounts, category, white_score, time) {
+                Ok(changes) => changes,
+                Err(e) => {
+                    println!("error at rating match {}: {}", match_id, e);
+                    return;
+                }
+            };
+
+        for (player, (before, after)) in changes.iter().enumerate() {
+            if let Some(token) = players[player] {
+                let after = ratings::player_rating(*after);
+                let change = after.rating as i32 - ratings::player_rating(*before).rating as i32;
+                self.send(token, &Message::RatingChanged(category, after, change));
+            }
+        }
+    }
+
+    fn archive(&mut self, match_id: usize) {
+        let entry = match self.matches.get(&match_id) {
+            Some(entry) => entry,
+            None => return,
+        };
+        let record = match Record::of(
+            &entry.the_match,
+            entry.accounts(),
+            entry.started,
+            unix_time(),
+        ) {
+            Some(record) => record,
+            None => return,
+        };
+        if let Err(e) = archive::store(self.accounts.db(), &record) {
+            println!("error at archiving match {}: {}", match_id, e);
+        }
+    }
+
+    // a message that can't be encoded, like one that's too long, is dropped
+    fn send(&mut self, token: Token, message: &Message) {
+        if let Some(connection) = self.connections.get_mut(&token) {
+            match connection.codec.encode(message) {
+                Ok(bytes) => connection.outgoing.extend(bytes),
+                Err(e) => println!("message to {} not sent: {}", connection.addr, e),
+            }
+            self.flush(token);
+        }
+    }
+
+    fn close_after_sending(&mut self, token: Token) {
+        self.set_state(token, ConnectionState::Closing);
+        self.flush(token);
+    }
+
+    // writes what the socket takes, and waits for it to take the rest
+    fn flush(&mut self, token: Token) {
+        let connection = match self.connections.get_mut(&token) {
+            Some(connection) => connection,
+            None => return,
+        };
+
+        while !connection.outgoing.is_empty() {
+            match connection.stream.write(&connection.outgoing) {
+                Ok(0) => {
+                    self.to_close.push(token);
+                    return;
+                }
+                Ok(len) => {
+                    connection.outgoing.drain(..len);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => {
+                    self.to_close.push(token);
+                    return;
+                }
+            }
+        }
+
+        if connection.outgoing.is_empty() {
+            if let ConnectionState::Closing = connection.state {
+                self.to_close.push(token);
+                return;
+            }
+        }
+
+        let wants_writable = !connection.outgoing.is_empty();
+        if wants_writable != connection.is_writable_registered {
+            let interest = match wants_writable {
+                true => Interest::READABLE | Interest::WRITABLE,
+                false => Interest::READABLE,
+            };
+            connection.is_writable_registered = wants_writable;
+            if self
+                .poll
+                .registry()
+                .reregister(&mut connection.stream, token, interest)
+                .is_err()
+            {
+                self.to_close.push(token);
+            }
+        }
+    }
+
+    fn close_stale_handshakes(&mut self) {
+        for (token, connection) in self.connections.iter() {
+            if let ConnectionState::Handshake(since) = connection.state {
+                if since.elapsed() > HANDSHAKE_TIMEOUT {
+                    self.to_close.push(*token);
+                }
+            }
+        }
+    }
+
+    fn close_dead(&mut self) {
+        while let Some(token) = self.to_close.pop() {
+            self.close(token);
+        }
+    }
+
+    // Drops the connection. If it was playing, its match waits for it to come
+    //   back
+    fn close(&mut self, token: Token) {
+        let mut connection = match self.connections.remove(&token) {
+            Some(connection) => connection,
+            None => return,
+        };
+        let _ = self.poll.registry().deregister(&mut connection.stream);
+        println!("Connection closed: {}", connection.addr);
+
+        if let ConnectionState::Lobby { .. } = connection.state {
+            for id in self.lobby.remove_player(token) {
+                self.send_to_lobby(&Message::SeekRemoved(id));
+            }
+        }
+
+        if let ConnectionState::Spectating { match_id } = connection.state {
+            if let Some(entry) = self.matches.get_mut(&match_id) {
+                entry.spectators.retain(|spectator| *spectator != token);
+            }
+        }
+
+        //the match waits for the player to come back
+        if let ConnectionState::Playing { match_id, player } = connection.state {
+            let entry = match self.matches.get_mut(&match_id) {
+                Some(entry) => entry,
+                None => return,
+            };
+            if entry.players[player] != Some(token) {
+                return;
+            }
+            entry.players[player] = None;
+            entry.left_at[player] = Some(Instant::now());
+
+            if let Some(opponent) = entry.players[1 - player] {
+                let grace = self.reconnect_grace.as_secs() as u32;
+                self.send(opponent, &Message::OpponentLeft(grace));
+            }
+        }
+    }
+}
+
+// in seconds since 1970
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or(0)
+}
+
+// if a seek or a challenge can be played: a position that's valid and a clock
+//  that can run
+fn check_settings(settings: &GameSettings) -> Result<(), ProtocolError> {
+    if settings.variant.starting_game().is_none() {
+        return Err(ProtocolError::InvalidSeek);
+    }
+    match &settings.time_control {
+        Some(control) if !control.is_valid() => Err(ProtocolError::InvalidTimeControl),
+        _ => Ok(()),
+    }
+}
+
+fn white_score(result: &GameResult) -> f64 {
+    match result.winner {
+        Some(ChessTeam::White) => 1.0,
+        Some(ChessTeam::Black) => 0.0,
+        None => 0.5,
+    }
+}
+
+// heads or tails, for the colors
+fn coin() -> bool {
+    let mut byte = [0];
+    getrandom::getrandom(&mut byte).expect("no random numbers for the colors");
+    byte[0] & 1 == 1
+}
+
+fn new_session() -> SessionToken {
+    let mut bytes = [0; 16];
+    getrandom::getrandom(&mut bytes).expect("no random numbers for the sessions");
+    SessionToken(bytes)
+}
+
+#[cfg(test)]
+#[path = "./tests/server_tests.rs"]
+mod server_tests;
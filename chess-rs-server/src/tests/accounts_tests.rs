@@ -0,0 +1,106 @@
+use super::*;
+
+#[test]
+fn names_and_passwords() {
+    assert!(valid_name("lucy_2"));
+    assert!(!valid_name("lu"));
+    assert!(!valid_name("guest-1a2b"));
+    assert!(!valid_name("lucy pero"));
+    assert!(!valid_name(&"a".repeat(21)));
+    assert!(valid_password("hunter22"));
+    assert!(!valid_password("hunter2"));
+
+    let mut accounts = Accounts::for_tests();
+    assert!(matches!(
+        accounts.register("lu", "hunter22"),
+        Err(AccountError::InvalidName)
+    ));
+    assert!(matches!(
+        accounts.register("lucy", "short"),
+        Err(AccountError::InvalidPassword)
+    ));
+}
+
+#[test]
+fn register_and_login() {
+    let mut accounts = Accounts::for_tests();
+    let lucy = accounts.register("Lucy", "hunter22").unwrap();
+    assert_eq!(lucy.name, "Lucy");
+    assert_eq!(accounts.name(lucy.id).unwrap(), Some("Lucy".to_string()));
+    assert_eq!(accounts.find("lUCY").unwrap(), Some(lucy.clone()));
+    assert_eq!(accounts.find("pero").unwrap(), None);
+
+    // names don't care about case
+    assert!(matches!(
+        accounts.register("lucy", "password"),
+        Err(AccountError::NameTaken)
+    ));
+    assert_eq!(accounts.login("LUCY", "hunter22").unwrap(), lucy);
+
+    assert!(matches!(
+        accounts.login("lucy", "hunter23"),
+        Err(AccountError::WrongPassword)
+    ));
+    assert!(matches!(
+        accounts.login("nobody", "hunter22"),
+        Err(AccountError::WrongPassword)
+    ));
+
+    // the passwords aren't stored
+    let hash: String = accounts
+        .db()
+        .query_row("SELECT password_hash FROM accounts", [], |row| row.get(0))
+        .unwrap();
+    assert!(!hash.contains("hunter22"));
+    assert!(hash.starts_with("$argon2id$"));
+
+    // two accounts with the same password get different hashes
+    accounts.register("pero", "hunter22").unwrap();
+    let hashes: Vec<String> = accounts
+        .db()
+        .prepare("SELECT password_hash FROM accounts")
+        .unwrap()
+        .query_map([], |row| row.get(0))
+        .unwrap()
+        .map(|hash| hash.unwrap())
+        .collect();
+    assert_ne!(hashes[0], hashes[1]);
+}
+
+#[test]
+fn login_tokens() {
+    let mut accounts = Accounts::for_tests();
+    let lucy = accounts.register("lucy", "hunter22").unwrap();
+    let token = accounts.new_token(lucy.id).unwrap();
+
+    let (account, next) = accounts.login_with_token(&token).unwrap();
+    assert_eq!(account, lucy);
+    assert_ne!(next, token);
+
+    // tokens work once
+    assert!(matches!(
+        accounts.login_with_token(&token),
+        Err(AccountError::UnknownToken)
+    ));
+    assert!(matches!(
+        accounts.login_with_token(&LoginToken([0; 16])),
+        Err(AccountError::UnknownToken)
+    ));
+    assert_eq!(accounts.login_with_token(&next).unwrap().0, lucy);
+}
+
+#[test]
+fn accounts_stay_in_the_file() {
+    let path = std::env::temp_dir().join(format!("chess-rs-accounts-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let mut accounts = Accounts::open(&path).unwrap();
+    accounts.set_cheap_hashing();
+    let lucy = accounts.register("lucy", "hunter22").unwrap();
+    drop(accounts);
+
+    let accounts = Accounts::open(&path).unwrap();
+    assert_eq!(accounts.login("lucy", "hunter22").unwrap(), lucy);
+    drop(accounts);
+    std::fs::remove_file(&path).unwrap();
+}
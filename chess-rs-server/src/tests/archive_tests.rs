@@ -0,0 +1,171 @@
+use super::*;
+use crate::accounts::Accounts;
+use chess::clock::{ManualTime, TimeControl};
+use std::sync::Arc;
+
+// the moves from UCI notation, played from the start
+fn moves(uci: &str) -> Vec<chess::Move> {
+    let mut game = GameState::init();
+    uci.split_whitespace()
+        .map(|uci| {
+            let en_passant = game.en_passant_square;
+            let the_move = engine::move_from_uci(game.get_board(), en_passant, uci).unwrap();
+            game.perform_move(the_move).unwrap();
+            the_move
+        })
+        .collect()
+}
+
+fn record(white: &str, black: &str, uci: &str, started: u64) -> Record {
+    Record {
+        players: [white.to_string(), black.to_string()],
+        accounts: [None, None],
+        result: GameResult {
+            winner: None,
+            reason: EndReason::DrawAgreed,
+        },
+        settings: GameSettings::default(),
+        moves: moves(uci),
+        move_times: None,
+        started,
+        ended: started + 600,
+    }
+}
+
+#[test]
+fn store_and_export() {
+    let accounts = Accounts::for_tests();
+    let db = accounts.db();
+
+    let time = Arc::new(ManualTime::new());
+    let settings = GameSettings {
+        variant: Variant::Standard,
+        time_control: Some(TimeControl::fischer(
+            Duration::from_secs(180),
+            Duration::from_secs(2),
+        )),
+    };
+    let players = ["lucy".to_string(), "pero".to_string()];
+    let mut the_match = Match::new(settings.clone(), players, time.clone()).unwrap();
+    for (player, the_move) in moves("f2f3 e7e5 g2g4 d8h4").into_iter().enumerate() {
+        time.advance(Duration::from_secs(3));
+        the_match.handle_message(player % 2, chess_rs_protocol::Message::Move(the_move));
+    }
+    let record = Record::of(&the_match, [None, None], 1_600_000_000, 1_600_000_060).unwrap();
+    let id = store(db, &record).unwrap();
+
+    let found = find(db, &GameQuery::default(), MAX_FOUND).unwrap();
+    assert_eq!(
+        found,
+        vec![PastGame {
+            id,
+            players: ["lucy".to_string(), "pero".to_string()],
+            result: GameResult {
+                winner: Some(chess::ChessTeam::Black),
+                reason: EndReason::Checkmate,
+            },
+            settings,
+            moves: 4,
+            started: 1_600_000_000,
+        }]
+    );
+
+    let exported = pgn(db, id).unwrap().unwrap();
+    assert!(exported.starts_with("[Event \"chess-rs online game\"]\n"));
+    assert!(exported.contains("[Date \"2020.09.13\"]\n"));
+    assert!(exported.contains("[White \"lucy\"]\n[Black \"pero\"]\n[Result \"0-1\"]\n"));
+    assert!(exported.contains("[TimeControl \"180+2\"]\n[Termination \"normal\"]\n"));
+    assert!(exported.ends_with(
+        "1. f3 {[%clk 0:03:00]} 1... e5 {[%clk 0:02:59]} 2. g4 {[%clk 0:02:59]} 2... Qh4# {[%clk 0:02:58]} 0-1"
+    ));
+    assert_eq!(chess::pgn::parse_pgn(&exported).unwrap().move_count(), 4);
+
+    assert_eq!(pgn(db, id + 1).unwrap(), None);
+
+    assert_eq!(termination(EndReason::Timeout), "time forfeit");
+    assert_eq!(termination(EndReason::Disconnection), "abandoned");
+    assert_eq!(termination(EndReason::Aborted), "unterminated");
+}
+
+#[test]
+fn queries() {
+    let accounts = Accounts::for_tests();
+    let db = accounts.db();
+    let day = 86400;
+
+    let sicilian = store(db, &record("lucy", "pero", "e2e4 c7c5 g1f3", day)).unwrap();
+    let french = store(db, &record("pero", "ana", "e2e4 e7e6", 2 * day)).unwrap();
+    let english = store(db, &record("ana", "Lucy", "c2c4", 3 * day)).unwrap();
+    let ids = |query: GameQuery| -> Vec<u64> {
+        find(db, &query, MAX_FOUND)
+            .unwrap()
+            .iter()
+            .map(|game| game.id)
+            .collect()
+    };
+
+    // newest first
+    assert_eq!(ids(GameQuery::default()), vec![english, french, sicilian]);
+    assert_eq!(
+        ids(GameQuery {
+            player: Some("LUCY".to_string()),
+            ..GameQuery::default()
+        }),
+        vec![english, sicilian]
+    );
+    assert_eq!(
+        ids(GameQuery {
+            since: Some(2 * day),
+            until: Some(3 * day),
+            ..GameQuery::default()
+        }),
+        vec![french]
+    );
+    assert_eq!(
+        ids(GameQuery {
+            opening: Some("e4".to_string()),
+            ..GameQuery::default()
+        }),
+        vec![french, sicilian]
+    );
+    assert_eq!(
+        ids(GameQuery {
+            opening: Some(" e4  c5 ".to_string()),
+            player: Some("pero".to_string()),
+            ..GameQuery::default()
+        }),
+        vec![sicilian]
+    );
+    // whole moves only
+    assert_eq!(
+        ids(GameQuery {
+            opening: Some("c".to_string()),
+            ..GameQuery::default()
+        }),
+        Vec::<u64>::new()
+    );
+    assert_eq!(find(db, &GameQuery::default(), 1).unwrap().len(), 1);
+}
+
+#[test]
+fn custom_positions() {
+    let accounts = Accounts::for_tests();
+    let db = accounts.db();
+
+    let fen = "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1".to_string();
+    let mut game = chess::parse_fen(fen.clone()).unwrap();
+    let en_passant = game.en_passant_square;
+    let e4 = engine::move_from_uci(game.get_board(), en_passant, "e2e4").unwrap();
+
+    let mut record = record("lucy", "pero", "", 0);
+    record.settings.variant = Variant::FromPosition(fen.clone());
+    record.moves = vec![e4];
+    let id = store(db, &record).unwrap();
+
+    let found = find(db, &GameQuery::default(), MAX_FOUND).unwrap();
+    assert_eq!(found[0].settings.variant, Variant::FromPosition(fen));
+    assert!(pgn(db, id)
+        .unwrap()
+        .unwrap()
+        .ends_with("[SetUp \"1\"]\n[FEN \"4k3/8/8/8/8/8/4P3/4K3 w - - 0 1\"]\n\n1. e4 1/2-1/2"));
+}
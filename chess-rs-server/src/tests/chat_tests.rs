@@ -0,0 +1,37 @@
+use super::*;
+
+#[test]
+fn lines() {
+    assert_eq!(clean_line("  hi there \n"), Ok("hi there".to_string()));
+    assert_eq!(clean_line("gg\nwp"), Ok("gg wp".to_string()));
+    assert_eq!(clean_line(""), Err(ProtocolError::InvalidChat));
+    assert_eq!(clean_line(" \t "), Err(ProtocolError::InvalidChat));
+    assert_eq!(clean_line("\u{7}"), Err(ProtocolError::InvalidChat));
+    assert_eq!(clean_line("\u{0}hi"), Ok("hi".to_string()));
+
+    // characters, not bytes
+    let longest = "ž".repeat(MAX_CHAT_LEN);
+    assert_eq!(clean_line(&longest), Ok(longest.clone()));
+    assert_eq!(
+        clean_line(&(longest + "ž")),
+        Err(ProtocolError::InvalidChat)
+    );
+}
+
+#[test]
+fn rate_limit() {
+    let mut limit = ChatLimit::new();
+    let start = Instant::now();
+    let second = Duration::from_secs(1);
+
+    for i in 0..CHAT_BURST as u32 {
+        assert!(limit.allow(start + second * i));
+    }
+    assert!(!limit.allow(start + second * 5));
+    assert!(!limit.allow(start + second * 9));
+
+    // the first line is out of the window
+    assert!(limit.allow(start + CHAT_WINDOW));
+    assert!(!limit.allow(start + CHAT_WINDOW));
+    assert!(limit.allow(start + CHAT_WINDOW + second));
+}
@@ -0,0 +1,428 @@
+use super::*;
+use chess::clock::{ManualTime, TimeControl};
+use chess::engine;
+use chess_rs_protocol::Variant;
+use std::time::Duration;
+
+fn names() -> [String; 2] {
+    ["lucy".to_string(), "pero".to_string()]
+}
+
+fn new_match() -> Match {
+    Match::new(
+        GameSettings::default(),
+        names(),
+        Arc::new(ManualTime::new()),
+    )
+    .unwrap()
+}
+
+// the move in the match's game, from UCI notation
+fn uci(the_match: &mut Match, uci: &str) -> chess::Move {
+    let ep_square = the_match.game.en_passant_square;
+    engine::move_from_uci(the_match.game.get_board(), ep_square, uci).unwrap()
+}
+
+fn play(the_match: &mut Match, player: usize, uci_move: &str) -> Outgoing {
+    let the_move = uci(the_match, uci_move);
+    the_match.handle_message(player, Message::Move(the_move))
+}
+
+#[test]
+fn moves() {
+    let mut the_match = new_match();
+    let sessions = [SessionToken([0; 16]), SessionToken([1; 16])];
+    assert_eq!(
+        the_match.start(sessions),
+        vec![
+            (
+                To::Player(0),
+                Message::GameStart(GameStart {
+                    team: chess::ChessTeam::White,
+                    session: sessions[0],
+                    settings: GameSettings::default(),
+                    players: names(),
+                    tournament: None,
+                })
+            ),
+            (
+                To::Player(1),
+                Message::GameStart(GameStart {
+                    team: chess::ChessTeam::Black,
+                    session: sessions[1],
+                    settings: GameSettings::default(),
+                    players: names(),
+                    tournament: None,
+                })
+            ),
+        ]
+    );
+
+    let e4 = uci(&mut the_match, "e2e4");
+    assert_eq!(
+        the_match.handle_message(1, Message::Move(e4)),
+        vec![(
+            To::Player(1),
+            Message::MoveRejected(chess::MoveError::NotYourTurn)
+        )]
+    );
+    assert_eq!(
+        play(&mut the_match, 0, "e2e4"),
+        vec![
+            (To::Player(1), Message::Move(e4)),
+            (To::Spectators, Message::Move(e4)),
+        ]
+    );
+    assert_eq!(the_match.history(), vec![e4]);
+
+    // a white pawn can't be moved by black
+    let e5 = chess::Move::PieceMove {
+        piece: chess::ChessPiece::Pawn,
+        tile_from: chess::Tile::E4,
+        tile_to: chess::Tile::E5,
+        is_en_passant: false,
+    };
+    assert_eq!(
+        the_match.handle_message(1, Message::Move(e5)),
+        vec![(
+            To::Player(1),
+            Message::MoveRejected(chess::MoveError::TileFromIsEnemyPiece)
+        )]
+    );
+    assert_eq!(
+        the_match.handle_message(1, Message::JoinLobby),
+        vec![(
+            To::Player(1),
+            Message::Error(ProtocolError::UnexpectedMessage)
+        )]
+    );
+}
+
+#[test]
+fn checkmate() {
+    let mut the_match = new_match();
+    play(&mut the_match, 0, "f2f3");
+    play(&mut the_match, 1, "e7e5");
+    play(&mut the_match, 0, "g2g4");
+    let outgoing = play(&mut the_match, 1, "d8h4");
+
+    let result = GameResult {
+        winner: Some(chess::ChessTeam::Black),
+        reason: EndReason::Checkmate,
+    };
+    assert_eq!(
+        &outgoing[2..],
+        &[
+            (To::Player(0), Message::GameOver(result)),
+            (To::Player(1), Message::GameOver(result)),
+            (To::Spectators, Message::GameOver(result)),
+        ]
+    );
+    assert_eq!(the_match.get_result(), Some(result));
+    assert!(the_match.player_left(0).is_empty());
+
+    // the PGN has the names of the players
+    let pgn = the_match.pgn();
+    assert!(pgn.contains("[White \"lucy\"]\n[Black \"pero\"]"));
+    assert!(pgn.contains("1. f3 e5 2. g4 Qh4#"));
+}
+
+#[test]
+fn takeback() {
+    let mut the_match = new_match();
+    assert!(the_match
+        .handle_message(0, Message::TakebackRequest)
+        .is_empty());
+
+    play(&mut the_match, 0, "e2e4");
+    play(&mut the_match, 1, "e7e5");
+    play(&mut the_match, 0, "g1f3");
+
+    // declined
+    assert_eq!(
+        the_match.handle_message(1, Message::TakebackRequest),
+        vec![(To::Player(0), Message::TakebackRequest)]
+    );
+    assert!(the_match
+        .handle_message(1, Message::TakebackAnswer(true))
+        .is_empty());
+    assert_eq!(
+        the_match.handle_message(0, Message::TakebackAnswer(false)),
+        vec![(To::Player(1), Message::TakebackAnswer(false))]
+    );
+
+    // accepted: black takes back e5, and the knight move after it
+    the_match.handle_message(1, Message::TakebackRequest);
+    assert_eq!(
+        the_match.handle_message(0, Message::TakebackAnswer(true)),
+        vec![
+            (To::Player(0), Message::Takeback(2)),
+            (To::Player(1), Message::Takeback(2)),
+            (To::Spectators, Message::Takeback(2)),
+        ]
+    );
+    assert_eq!(the_match.game.move_count(), 1);
+
+    // playing on is a no
+    the_match.handle_message(0, Message::TakebackRequest);
+    let outgoing = play(&mut the_match, 1, "e7e5");
+    assert_eq!(outgoing[0], (To::Player(0), Message::TakebackAnswer(false)));
+    assert!(the_match
+        .handle_message(1, Message::TakebackAnswer(true))
+        .is_empty());
+}
+
+#[test]
+fn resign() {
+    let mut the_match = new_match();
+    play(&mut the_match, 0, "e2e4");
+    let result = GameResult {
+        winner: Some(chess::ChessTeam::Black),
+        reason: EndReason::Resignation,
+    };
+    assert_eq!(
+        the_match.handle_message(0, Message::Resign),
+        to_everyone(Message::GameOver(result))
+    );
+    assert_eq!(the_match.get_result(), Some(result));
+}
+
+#[test]
+fn draw_offers() {
+    let mut the_match = new_match();
+    let draw = GameResult {
+        winner: None,
+        reason: EndReason::DrawAgreed,
+    };
+
+    // nothing to answer
+    assert!(the_match.handle_message(1, Message::AcceptDraw).is_empty());
+
+    // declined
+    assert_eq!(
+        the_match.handle_message(0, Message::OfferDraw),
+        vec![(To::Player(1), Message::OfferDraw)]
+    );
+    assert!(the_match.handle_message(0, Message::OfferDraw).is_empty());
+    assert!(the_match.handle_message(0, Message::AcceptDraw).is_empty());
+    assert_eq!(
+        the_match.handle_message(1, Message::DeclineDraw),
+        vec![(To::Player(0), Message::DeclineDraw)]
+    );
+    assert!(the_match.handle_message(1, Message::AcceptDraw).is_empty());
+
+    // the offer stands while its player moves, and the answer is a move
+    the_match.handle_message(0, Message::OfferDraw);
+    assert!(!play(&mut the_match, 0, "e2e4").contains(&(To::Player(1), Message::DeclineDraw)));
+    assert!(play(&mut the_match, 1, "e7e5").contains(&(To::Player(0), Message::DeclineDraw)));
+    assert!(the_match.handle_message(1, Message::AcceptDraw).is_empty());
+
+    // accepted
+    the_match.handle_message(1, Message::OfferDraw);
+    assert_eq!(
+        the_match.handle_message(0, Message::AcceptDraw),
+        to_everyone(Message::GameOver(draw))
+    );
+
+    // two offers at once
+    let mut the_match = new_match();
+    the_match.handle_message(1, Message::OfferDraw);
+    assert_eq!(
+        the_match.handle_message(0, Message::OfferDraw),
+        to_everyone(Message::GameOver(draw))
+    );
+}
+
+#[test]
+fn abort() {
+    let aborted = GameResult {
+        winner: None,
+        reason: EndReason::Aborted,
+    };
+    let mut the_match = new_match();
+    play(&mut the_match, 0, "e2e4");
+    assert_eq!(
+        the_match.handle_message(1, Message::Abort),
+        to_everyone(Message::GameOver(aborted))
+    );
+
+    let mut the_match = new_match();
+    play(&mut the_match, 0, "e2e4");
+    play(&mut the_match, 1, "e7e5");
+    assert_eq!(
+        the_match.handle_message(0, Message::Abort),
+        vec![(To::Player(0), Message::Error(ProtocolError::TooLateToAbort))]
+    );
+    assert!(!the_match.is_over());
+}
+
+#[test]
+fn chat() {
+    let mut the_match = new_match();
+    let line = ChatLine {
+        room: ChatRoom::Game,
+        from: "pero".to_string(),
+        text: "gl hf".to_string(),
+    };
+    assert_eq!(
+        the_match.handle_message(1, Message::Chat("gl hf".to_string())),
+        to_everyone(Message::ChatLine(line))
+    );
+}
+
+#[test]
+fn player_left() {
+    let mut the_match = new_match();
+    let result = GameResult {
+        winner: Some(chess::ChessTeam::White),
+        reason: EndReason::Disconnection,
+    };
+    assert_eq!(
+        the_match.player_left(1),
+        vec![
+            (To::Player(0), Message::GameOver(result)),
+            (To::Spectators, Message::GameOver(result)),
+        ]
+    );
+    assert!(the_match.is_over());
+    assert_eq!(
+        the_match.handle_message(0, Message::Resign),
+        vec![(
+            To::Player(0),
+            Message::Error(ProtocolError::UnexpectedMessage)
+        )]
+    );
+}
+
+#[test]
+fn spectate() {
+    let mut the_match = new_match();
+    play(&mut the_match, 0, "e2e4");
+    assert_eq!(
+        the_match.snapshot(),
+        GameSnapshot {
+            settings: GameSettings::default(),
+            players: names(),
+            moves: the_match.history(),
+            clocks: None,
+        }
+    );
+    assert_eq!(the_match.info(3).moves, 1);
+
+    let e5 = uci(&mut the_match, "e7e5");
+    assert_eq!(
+        play(&mut the_match, 1, "e7e5"),
+        vec![
+            (To::Player(0), Message::Move(e5)),
+            (To::Spectators, Message::Move(e5)),
+        ]
+    );
+}
+
+#[test]
+fn clocks() {
+    let time = Arc::new(ManualTime::new());
+    let control = TimeControl::fischer(Duration::from_secs(60), Duration::from_secs(1));
+    let settings = GameSettings {
+        variant: Variant::Standard,
+        time_control: Some(control),
+    };
+    let mut the_match = Match::new(settings.clone(), names(), time.clone()).unwrap();
+    assert_eq!(the_match.info(0).settings, settings);
+
+    play(&mut the_match, 0, "e2e4");
+    time.advance(Duration::from_secs(5));
+    let times = ClockTimes {
+        remaining: [Duration::from_secs(60), Duration::from_secs(56)],
+        running: Some(chess::ChessTeam::White),
+    };
+    let outgoing = play(&mut the_match, 1, "e7e5");
+    assert_eq!(
+        outgoing[2..3],
+        [(To::Player(0), Message::ClockUpdate(times))]
+    );
+    assert!(the_match.check_time().is_empty());
+
+    // white doesn't move in time
+    time.advance(Duration::from_secs(60));
+    let result = GameResult {
+        winner: Some(chess::ChessTeam::Black),
+        reason: EndReason::Timeout,
+    };
+    assert_eq!(
+        the_match.check_time()[0],
+        (To::Player(0), Message::GameOver(result))
+    );
+    assert_eq!(the_match.get_result(), Some(result));
+    assert!(the_match.check_time().is_empty());
+}
+
+#[test]
+fn berserk() {
+    let control = TimeControl::fischer(Duration::from_secs(60), Duration::from_secs(1));
+    let settings = GameSettings {
+        variant: Variant::Standard,
+        time_control: Some(control),
+    };
+    let new_match = || Match::new(settings.clone(), names(), Arc::new(ManualTime::new())).unwrap();
+    let arena = TournamentGame {
+        id: 1,
+        name: "Arena".to_string(),
+        can_berserk: true,
+    };
+
+    // not outside of arenas
+    let mut the_match = new_match();
+    assert!(the_match.handle_message(0, Message::Berserk).is_empty());
+    let mut the_match = new_match();
+    the_match.set_tournament(TournamentGame {
+        can_berserk: false,
+        ..arena.clone()
+    });
+    assert!(the_match.handle_message(0, Message::Berserk).is_empty());
+
+    let mut the_match = new_match();
+    the_match.set_tournament(arena.clone());
+    let sessions = [SessionToken([0; 16]), SessionToken([1; 16])];
+    match &the_match.start(sessions)[0].1 {
+        Message::GameStart(start) => assert_eq!(start.tournament, Some(arena)),
+        message => panic!("expected the game to start, got {:?}", message),
+    }
+
+    let times = ClockTimes {
+        remaining: [Duration::from_secs(30), Duration::from_secs(60)],
+        running: None,
+    };
+    let mut berserked = to_everyone(Message::Berserked(chess::ChessTeam::White));
+    berserked.extend(to_everyone(Message::ClockUpdate(times)));
+    assert_eq!(the_match.handle_message(0, Message::Berserk), berserked);
+    assert!(the_match.handle_message(0, Message::Berserk).is_empty());
+    assert_eq!(the_match.berserked(), [true, false]);
+
+    // only before the player's first move
+    play(&mut the_match, 0, "e2e4");
+    assert_eq!(
+        the_match.handle_message(1, Message::Berserk)[0].1,
+        Message::Berserked(chess::ChessTeam::Black)
+    );
+    play(&mut the_match, 1, "e7e5");
+    assert_eq!(the_match.berserked(), [true, true]);
+}
+
+#[test]
+fn from_position() {
+    let settings = GameSettings {
+        variant: Variant::FromPosition("8/8/4k3/8/8/8/3QK3/8 b - - 0 1".to_string()),
+        time_control: None,
+    };
+    let mut the_match = Match::new(settings, names(), Arc::new(ManualTime::new())).unwrap();
+    // black moves first
+    assert!(!play(&mut the_match, 1, "e6e5").is_empty());
+    assert_eq!(the_match.history().len(), 1);
+
+    let settings = GameSettings {
+        variant: Variant::FromPosition("not a position".to_string()),
+        time_control: None,
+    };
+    assert!(Match::new(settings, names(), Arc::new(ManualTime::new())).is_none());
+}
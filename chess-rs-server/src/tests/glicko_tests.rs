@@ -0,0 +1,72 @@
+use super::*;
+
+fn rating(rating: f64, deviation: f64) -> Rating {
+    Rating {
+        rating,
+        deviation,
+        volatility: 0.06,
+    }
+}
+
+fn close(a: f64, b: f64, precision: f64) -> bool {
+    (a - b).abs() < precision
+}
+
+// the example in Glickman's paper
+#[test]
+fn paper_example() {
+    let player = rating(1500.0, 200.0);
+    let games = [
+        (rating(1400.0, 30.0), 1.0),
+        (rating(1550.0, 100.0), 0.0),
+        (rating(1700.0, 300.0), 0.0),
+    ];
+
+    let after = rate(player, &games);
+    assert!(close(after.rating, 1464.06, 0.01), "{:?}", after);
+    assert!(close(after.deviation, 151.52, 0.01), "{:?}", after);
+    assert!(close(after.volatility, 0.05999, 0.00001), "{:?}", after);
+}
+
+#[test]
+fn games() {
+    let new = Rating::default();
+    assert!(new.is_provisional());
+
+    // a win takes from the loser what the winner gets, between equals
+    let (white, black) = rate_game(new, new, 1.0);
+    assert!(white.rating > 1500.0);
+    assert!(close(white.rating - 1500.0, 1500.0 - black.rating, 0.001));
+    assert!(white.deviation < new.deviation);
+
+    // a draw between equals changes nothing but how sure the ratings are
+    let (white, black) = rate_game(new, new, 0.5);
+    assert!(close(white.rating, 1500.0, 0.001));
+    assert!(close(black.rating, 1500.0, 0.001));
+
+    // beating someone much better is worth more than beating someone worse
+    let strong = rating(1900.0, 60.0);
+    let weak = rating(1100.0, 60.0);
+    let player = rating(1500.0, 60.0);
+    let upset = rate(player, &[(strong, 1.0)]).rating - 1500.0;
+    let expected = rate(player, &[(weak, 1.0)]).rating - 1500.0;
+    assert!(upset > expected * 5.0);
+
+    // after enough games the rating isn't provisional anymore
+    let mut rating = new;
+    for _ in 0..20 {
+        rating = rate(rating, &[(player, 0.5)]);
+    }
+    assert!(!rating.is_provisional());
+}
+
+#[test]
+fn no_games() {
+    let player = rating(1700.0, 100.0);
+    let after = rate(player, &[]);
+    assert_eq!(after.rating, 1700.0);
+    assert!(after.deviation > 100.0);
+
+    let unsure = rate(rating(1700.0, 349.9), &[]);
+    assert_eq!(unsure.deviation, 350.0);
+}
@@ -0,0 +1,184 @@
+use super::*;
+use chess_rs_core::clock::TimeControl;
+use chess_rs_protocol::Variant;
+use std::time::Duration;
+
+fn player(name: &str, rating: u32) -> Player {
+    Player {
+        name: name.to_string(),
+        rating,
+    }
+}
+
+fn blitz() -> GameSettings {
+    GameSettings {
+        variant: Variant::Standard,
+        time_control: Some(TimeControl::fischer(
+            Duration::from_secs(300),
+            Duration::from_secs(3),
+        )),
+    }
+}
+
+fn request(color: Option<ChessTeam>, rating_range: Option<(u32, u32)>) -> SeekRequest {
+    SeekRequest {
+        settings: blitz(),
+        color,
+        rating_range,
+    }
+}
+
+fn posted(outcome: SeekOutcome) -> Seek {
+    match outcome {
+        SeekOutcome::Posted(seek, _) => seek,
+        SeekOutcome::Paired(pairing) => panic!("expected a posted seek, got {:?}", pairing),
+    }
+}
+
+fn paired(outcome: SeekOutcome) -> Pairing {
+    match outcome {
+        SeekOutcome::Posted(seek, _) => panic!("expected a pairing, got {:?}", seek),
+        SeekOutcome::Paired(pairing) => pairing,
+    }
+}
+
+#[test]
+fn matchmaking() {
+    let mut lobby = Lobby::new();
+    let (a, b, c) = (Token(1), Token(2), Token(3));
+
+    // different games don't pair
+    let a_seek = posted(lobby.seek(a, player("a", 1500), request(None, None), true));
+    let other_game = SeekRequest {
+        settings: GameSettings::default(),
+        ..request(None, None)
+    };
+    posted(lobby.seek(b, player("b", 1500), other_game, true));
+    // a player doesn't play itself
+    posted(lobby.seek(a, player("a", 1500), request(None, None), true));
+    assert_eq!(lobby.seeks().len(), 3);
+
+    // c wants black, so a gets white. Every seek of both players is gone
+    let pairing = paired(lobby.seek(
+        c,
+        player("c", 1500),
+        request(Some(ChessTeam::Black), None),
+        false,
+    ));
+    assert_eq!((pairing.white, pairing.black), (a, c));
+    assert_eq!(pairing.settings, blitz());
+    assert_eq!(pairing.removed_seeks.len(), 2);
+    assert!(pairing.removed_seeks.contains(&a_seek.id));
+    assert_eq!(lobby.seeks().len(), 1);
+}
+
+#[test]
+fn ratings_and_colors() {
+    let mut lobby = Lobby::new();
+    let (a, b) = (Token(1), Token(2));
+
+    posted(lobby.seek(
+        a,
+        player("a", 1500),
+        request(Some(ChessTeam::White), Some((1400, 1600))),
+        true,
+    ));
+    // too strong for a
+    posted(lobby.seek(b, player("b", 1700), request(None, None), true));
+    // a wants white too
+    posted(lobby.seek(
+        b,
+        player("b", 1500),
+        request(Some(ChessTeam::White), None),
+        true,
+    ));
+    // a is too weak for b
+    posted(lobby.seek(
+        b,
+        player("b", 1500),
+        request(Some(ChessTeam::Black), Some((1600, 2000))),
+        true,
+    ));
+
+    let pairing = paired(lobby.seek(
+        b,
+        player("b", 1500),
+        request(Some(ChessTeam::Black), None),
+        true,
+    ));
+    assert_eq!((pairing.white, pairing.black), (a, b));
+    assert!(lobby.seeks().is_empty());
+}
+
+#[test]
+fn accept_and_cancel() {
+    let mut lobby = Lobby::new();
+    let (a, b) = (Token(1), Token(2));
+
+    let seek = posted(lobby.seek(
+        a,
+        player("a", 1500),
+        request(None, Some((1000, 1600))),
+        true,
+    ));
+    assert!(!lobby.cancel(b, seek.id));
+    assert_eq!(lobby.accept(a, 1500, seek.id, true), None);
+    assert_eq!(lobby.accept(b, 1700, seek.id, true), None);
+
+    // the coin gives white to the seek's owner
+    let pairing = lobby.accept(b, 1500, seek.id, true).unwrap();
+    assert_eq!((pairing.white, pairing.black), (a, b));
+    assert_eq!(pairing.removed_seeks, vec![seek.id]);
+
+    let seek = posted(lobby.seek(a, player("a", 1500), request(None, None), true));
+    assert!(lobby.cancel(a, seek.id));
+    assert!(lobby.seeks().is_empty());
+    assert_eq!(lobby.accept(b, 1500, seek.id, true), None);
+}
+
+#[test]
+fn seeks_per_player() {
+    let mut lobby = Lobby::new();
+    let a = Token(1);
+
+    let first = posted(lobby.seek(a, player("a", 1500), request(None, None), true));
+    for _ in 1..MAX_SEEKS {
+        posted(lobby.seek(a, player("a", 1500), request(None, None), true));
+    }
+    assert_eq!(lobby.seeks().len(), MAX_SEEKS);
+
+    // one more takes the place of the oldest
+    match lobby.seek(a, player("a", 1500), request(None, None), true) {
+        SeekOutcome::Posted(seek, replaced) => {
+            assert_eq!(replaced, Some(first.id));
+            assert_eq!(lobby.seeks().last(), Some(&seek));
+        }
+        SeekOutcome::Paired(pairing) => panic!("expected a posted seek, got {:?}", pairing),
+    }
+    assert_eq!(lobby.seeks().len(), MAX_SEEKS);
+    assert_eq!(lobby.find(first.id), None);
+}
+
+#[test]
+fn challenges() {
+    let mut lobby = Lobby::new();
+    let (a, b, c) = (Token(1), Token(2), Token(3));
+
+    let challenge = lobby.challenge(a, "a".to_string(), b, request(Some(ChessTeam::Black), None));
+    assert_eq!(challenge.from, "a");
+    // only b can answer
+    assert_eq!(lobby.answer(c, challenge.id, true, true), None);
+    assert_eq!(lobby.answer(b, challenge.id, false, true), Some((a, None)));
+    assert_eq!(lobby.answer(b, challenge.id, true, true), None);
+
+    let challenge = lobby.challenge(a, "a".to_string(), b, request(Some(ChessTeam::Black), None));
+    let (from, pairing) = lobby.answer(b, challenge.id, true, true).unwrap();
+    assert_eq!(from, a);
+    let pairing = pairing.unwrap();
+    assert_eq!((pairing.white, pairing.black), (b, a));
+
+    // challenges go away with their players
+    let challenge = lobby.challenge(a, "a".to_string(), b, request(None, None));
+    lobby.remove_player(a);
+    assert_eq!(lobby.answer(b, challenge.id, true, true), None);
+}
@@ -0,0 +1,64 @@
+use super::*;
+use crate::accounts::Accounts;
+use mio::{Events, Poll};
+use std::time::Duration;
+
+const WAKER: Token = Token(7);
+
+// the next job that is done, once the thread wakes the poll
+fn wait(poll: &mut Poll, passwords: &PasswordThread) -> (Token, Result<Done, AccountError>) {
+    let mut events = Events::with_capacity(8);
+    loop {
+        if let Ok(done) = passwords.done.try_recv() {
+            return done;
+        }
+        poll.poll(&mut events, Some(Duration::from_secs(5)))
+            .unwrap();
+        assert!(events.iter().any(|e| e.token() == WAKER), "not woken");
+    }
+}
+
+#[test]
+fn hash_and_verify() {
+    let mut accounts = Accounts::for_tests();
+    let mut poll = Poll::new().unwrap();
+    let passwords = PasswordThread::spawn(Waker::new(poll.registry(), WAKER).unwrap());
+
+    let register = Job::Register {
+        name: "lucy".to_string(),
+        password: "hunter22".to_string(),
+    };
+    passwords.start(Token(10), accounts.hasher(), register);
+    let hash = match wait(&mut poll, &passwords) {
+        (Token(10), Ok(Done::Registered { name, hash })) if name == "lucy" => hash,
+        (token, done) => panic!("expected lucy's hash, got {:?} {:?}", token, done),
+    };
+    let account = accounts.insert("lucy", &hash).unwrap();
+
+    let login = |password: &str| Job::Login {
+        account: account.clone(),
+        password: password.to_string(),
+        hash: hash.clone(),
+    };
+    passwords.start(Token(11), accounts.hasher(), login("hunter22"));
+    let (token, done) = wait(&mut poll, &passwords);
+    assert_eq!(token, Token(11));
+    assert_eq!(done.unwrap(), Done::LoggedIn(account.clone()));
+
+    passwords.start(Token(12), accounts.hasher(), login("hunter23"));
+    assert!(matches!(
+        wait(&mut poll, &passwords),
+        (Token(12), Err(AccountError::WrongPassword))
+    ));
+
+    let short = Job::Register {
+        name: "pero".to_string(),
+        password: "short".to_string(),
+    };
+    passwords.start(Token(13), accounts.hasher(), short);
+    assert!(matches!(
+        wait(&mut poll, &passwords),
+        (Token(13), Err(AccountError::InvalidPassword))
+    ));
+    assert!(passwords.done().is_empty());
+}
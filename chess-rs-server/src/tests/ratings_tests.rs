@@ -0,0 +1,120 @@
+use super::*;
+use crate::accounts::Accounts;
+
+#[test]
+fn rated_games() {
+    let mut accounts = Accounts::for_tests();
+    let lucy = accounts.register("lucy", "hunter22").unwrap().id;
+    let pero = accounts.register("pero", "hunter22").unwrap().id;
+    let db = accounts.db();
+
+    assert_eq!(
+        rating(db, lucy, RatingCategory::Blitz, 300).unwrap(),
+        Rating::default()
+    );
+    assert!(ratings(db, lucy, 300).unwrap().is_empty());
+
+    let [(lucy_before, lucy_after), (pero_before, pero_after)] =
+        record_game(db, [lucy, pero], RatingCategory::Blitz, 1.0, 100).unwrap();
+    assert_eq!(lucy_before, Rating::default());
+    assert_eq!(pero_before, Rating::default());
+    assert!(lucy_after.rating > 1500.0);
+    assert!(pero_after.rating < 1500.0);
+    assert_eq!(
+        rating(db, lucy, RatingCategory::Blitz, 300).unwrap(),
+        lucy_after
+    );
+
+    // the other categories don't change
+    assert_eq!(
+        rating(db, lucy, RatingCategory::Bullet, 300).unwrap(),
+        Rating::default()
+    );
+    assert_eq!(
+        ratings(db, pero, 300).unwrap(),
+        vec![(RatingCategory::Blitz, pero_after)]
+    );
+
+    // the second game starts from the ratings after the first
+    let [(before, _), _] = record_game(db, [lucy, pero], RatingCategory::Blitz, 0.5, 200).unwrap();
+    assert_eq!(before, lucy_after);
+    let history = history(db, lucy, RatingCategory::Blitz).unwrap();
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[0].time, 100);
+    assert_eq!(history[0].rating, player_rating(lucy_after).rating);
+    assert!(history[1].rating < history[0].rating);
+}
+
+#[test]
+fn leaderboards() {
+    let mut accounts = Accounts::for_tests();
+    let ids: Vec<i64> = ["lucy", "pero", "newbie"]
+        .iter()
+        .map(|name| accounts.register(name, "hunter22").unwrap().id)
+        .collect();
+    let db = accounts.db();
+
+    // lucy beats pero most of the time, until neither is provisional
+    for time in 0..30 {
+        let score = if time % 3 == 0 { 0.0 } else { 1.0 };
+        record_game(db, [ids[0], ids[1]], RatingCategory::Rapid, score, time).unwrap();
+    }
+    record_game(db, [ids[2], ids[1]], RatingCategory::Rapid, 1.0, 30).unwrap();
+
+    let best = leaderboard(db, RatingCategory::Rapid, 10, 300).unwrap();
+    let names: Vec<&str> = best.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, vec!["lucy", "pero"]);
+    assert!(best[0].1.rating > best[1].1.rating);
+    assert!(!player_rating(best[0].1).provisional);
+    assert_eq!(
+        leaderboard(db, RatingCategory::Rapid, 1, 300)
+            .unwrap()
+            .len(),
+        1
+    );
+    assert!(leaderboard(db, RatingCategory::Blitz, 10, 300)
+        .unwrap()
+        .is_empty());
+
+    let newbie = player_rating(rating(db, ids[2], RatingCategory::Rapid, 300).unwrap());
+    assert!(newbie.provisional);
+    assert_eq!(newbie.to_string(), format!("{}?", newbie.rating));
+}
+
+#[test]
+fn inactivity() {
+    let mut accounts = Accounts::for_tests();
+    let lucy = accounts.register("lucy", "hunter22").unwrap().id;
+    let pero = accounts.register("pero", "hunter22").unwrap().id;
+    let db = accounts.db();
+    for time in 0..30 {
+        let score = if time % 3 == 0 { 0.0 } else { 1.0 };
+        record_game(db, [lucy, pero], RatingCategory::Rapid, score, time).unwrap();
+    }
+    let [(_, active), _] = record_game(db, [lucy, pero], RatingCategory::Rapid, 1.0, 30).unwrap();
+    assert_eq!(rating(db, lucy, RatingCategory::Rapid, 30).unwrap(), active);
+    assert_eq!(
+        leaderboard(db, RatingCategory::Rapid, 10, 30)
+            .unwrap()
+            .len(),
+        2
+    );
+
+    // less sure after a month without games, but with the same rating
+    let month = 30 + 4 * RATING_PERIOD;
+    let idle = rating(db, lucy, RatingCategory::Rapid, month).unwrap();
+    assert!(idle.deviation > active.deviation);
+    assert_eq!(idle.rating, active.rating);
+    let [(before, _), _] =
+        record_game(db, [lucy, pero], RatingCategory::Rapid, 1.0, month).unwrap();
+    assert_eq!(before, idle);
+
+    // and provisional again after years, off the leaderboard
+    let years = month + 1000 * RATING_PERIOD;
+    assert!(rating(db, lucy, RatingCategory::Rapid, years)
+        .unwrap()
+        .is_provisional());
+    assert!(leaderboard(db, RatingCategory::Rapid, 10, years)
+        .unwrap()
+        .is_empty());
+}
@@ -0,0 +1,946 @@
+use super::*;
+use chess_rs_core::{ChessPiece, ChessTeam, Move, Tile};
+use chess_rs_protocol::{ChatLine, ChatRoom, EndReason, GameResult, GameSettings};
+use std::io::Write;
+use std::net::TcpStream as StdTcpStream;
+use std::thread;
+
+fn start_server(reconnect_grace: Duration) -> SocketAddr {
+    let accounts = Accounts::for_tests();
+    let mut server = Server::bind("127.0.0.1:0".parse().unwrap(), accounts).unwrap();
+    server.set_reconnect_grace(reconnect_grace);
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || server.run().unwrap());
+    addr
+}
+
+fn connect(addr: SocketAddr) -> StdTcpStream {
+    let mut stream = StdTcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    protocol::write_message(&mut stream, &Message::Hello(protocol::PROTOCOL_VERSION)).unwrap();
+    assert_eq!(
+        protocol::read_message(&mut stream).unwrap(),
+        Message::Welcome(protocol::PROTOCOL_VERSION)
+    );
+    stream
+}
+
+fn register(name: &str) -> Message {
+    Message::Register(name.to_string(), "password".to_string())
+}
+
+// logged in to the account with that name, that is made if it's not there
+fn log_in(addr: SocketAddr, name: &str) -> StdTcpStream {
+    let mut stream = connect(addr);
+    protocol::write_message(&mut stream, &register(name)).unwrap();
+    if let Message::Error(ProtocolError::NameTaken) = protocol::read_message(&mut stream).unwrap() {
+        let login = Message::Login(name.to_string(), "password".to_string());
+        protocol::write_message(&mut stream, &login).unwrap();
+        match protocol::read_message(&mut stream).unwrap() {
+            Message::LoggedIn(..) => {}
+            message => panic!("expected to log in, got {:?}", message),
+        }
+    }
+    stream
+}
+
+fn join_lobby(addr: SocketAddr, name: &str) -> StdTcpStream {
+    let mut stream = log_in(addr, name);
+    protocol::write_message(&mut stream, &Message::JoinLobby).unwrap();
+    match protocol::read_message(&mut stream).unwrap() {
+        Message::Lobby(_) => stream,
+        message => panic!("expected the lobby, got {:?}", message),
+    }
+}
+
+fn seek(color: ChessTeam) -> Message {
+    Message::Seek(SeekRequest {
+        color: Some(color),
+        ..SeekRequest::default()
+    })
+}
+
+fn game_start(stream: &mut StdTcpStream) -> protocol::GameStart {
+    match protocol::read_message(stream).unwrap() {
+        Message::GameStart(start) => start,
+        message => panic!("expected the game to start, got {:?}", message),
+    }
+}
+
+// two players in a match, White first. With their sessions
+fn start_match(addr: SocketAddr) -> (StdTcpStream, StdTcpStream, [SessionToken; 2]) {
+    let mut white = join_lobby(addr, "white");
+    protocol::write_message(&mut white, &seek(ChessTeam::White)).unwrap();
+    assert!(matches!(
+        protocol::read_message(&mut white).unwrap(),
+        Message::SeekAdded(_)
+    ));
+
+    let mut black = join_lobby(addr, "black");
+    protocol::write_message(&mut black, &seek(ChessTeam::Black)).unwrap();
+    let white_start = game_start(&mut white);
+    let black_start = game_start(&mut black);
+    assert_eq!(white_start.team, ChessTeam::White);
+    assert_eq!(
+        black_start.players,
+        ["white".to_string(), "black".to_string()]
+    );
+    (white, black, [white_start.session, black_start.session])
+}
+
+fn pawn_move(tile_from: Tile, tile_to: Tile) -> Message {
+    Message::Move(Move::PieceMove {
+        piece: ChessPiece::Pawn,
+        tile_from,
+        tile_to,
+        is_en_passant: false,
+    })
+}
+
+#[test]
+fn plays_a_match() {
+    let addr = start_server(RECONNECT_GRACE);
+    let (mut white, mut black, _) = start_match(addr);
+
+    // out of turn
+    protocol::write_message(&mut black, &pawn_move(Tile::E7, Tile::E5)).unwrap();
+    assert_eq!(
+        protocol::read_message(&mut black).unwrap(),
+        Message::MoveRejected(chess_rs_core::MoveError::NotYourTurn)
+    );
+
+    let e4 = pawn_move(Tile::E2, Tile::E4);
+    protocol::write_message(&mut white, &e4).unwrap();
+    assert_eq!(protocol::read_message(&mut black).unwrap(), e4);
+
+    // another match runs next to this one
+    let (mut white_2, mut black_2, _) = start_match(addr);
+    let d4 = pawn_move(Tile::D2, Tile::D4);
+    protocol::write_message(&mut white_2, &d4).unwrap();
+    assert_eq!(protocol::read_message(&mut black_2).unwrap(), d4);
+}
+
+#[test]
+fn disconnection_ends_only_that_match() {
+    let addr = start_server(Duration::from_secs(0));
+    let (white, mut black, _) = start_match(addr);
+    let (mut white_2, mut black_2, _) = start_match(addr);
+
+    drop(white);
+    assert_eq!(
+        protocol::read_message(&mut black).unwrap(),
+        Message::OpponentLeft(0)
+    );
+    assert_eq!(
+        protocol::read_message(&mut black).unwrap(),
+        Message::GameOver(GameResult {
+            winner: Some(ChessTeam::Black),
+            reason: EndReason::Disconnection,
+        })
+    );
+
+    // a bad frame is like leaving
+    black_2.write_all(&[0, 0, 0, 2, 255, 255]).unwrap();
+    assert_eq!(
+        protocol::read_message(&mut white_2).unwrap(),
+        Message::OpponentLeft(0)
+    );
+    assert_eq!(
+        protocol::read_message(&mut white_2).unwrap(),
+        Message::GameOver(GameResult {
+            winner: Some(ChessTeam::White),
+            reason: EndReason::Disconnection,
+        })
+    );
+
+    // the server still takes new players
+    start_match(addr);
+}
+
+#[test]
+fn version_mismatch() {
+    let addr = start_server(RECONNECT_GRACE);
+    let mut stream = StdTcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+
+    protocol::write_message(&mut stream, &Message::Hello(protocol::PROTOCOL_VERSION + 1)).unwrap();
+    assert_eq!(
+        protocol::read_message(&mut stream).unwrap(),
+        Message::Error(ProtocolError::VersionMismatch(protocol::PROTOCOL_VERSION))
+    );
+}
+
+#[test]
+fn reconnection() {
+    let addr = start_server(RECONNECT_GRACE);
+    let (mut white, mut black, sessions) = start_match(addr);
+
+    let e4 = pawn_move(Tile::E2, Tile::E4);
+    protocol::write_message(&mut white, &e4).unwrap();
+    assert_eq!(protocol::read_message(&mut black).unwrap(), e4);
+
+    drop(black);
+    assert_eq!(
+        protocol::read_message(&mut white).unwrap(),
+        Message::OpponentLeft(RECONNECT_GRACE.as_secs() as u32)
+    );
+
+    // a made up session doesn't work
+    let mut black = connect(addr);
+    protocol::write_message(&mut black, &Message::Resume(SessionToken([0; 16]))).unwrap();
+    assert_eq!(
+        protocol::read_message(&mut black).unwrap(),
+        Message::Error(ProtocolError::UnknownSession)
+    );
+
+    protocol::write_message(&mut black, &Message::Resume(sessions[1])).unwrap();
+    let e4_move = match e4 {
+        Message::Move(the_move) => the_move,
+        _ => unreachable!(),
+    };
+    match protocol::read_message(&mut black).unwrap() {
+        Message::Resumed(ChessTeam::Black, snapshot) => assert_eq!(snapshot.moves, vec![e4_move]),
+        message => panic!("expected to be back in the game, got {:?}", message),
+    }
+    assert_eq!(
+        protocol::read_message(&mut white).unwrap(),
+        Message::OpponentBack
+    );
+
+    // the game goes on
+    let e5 = pawn_move(Tile::E7, Tile::E5);
+    protocol::write_message(&mut black, &e5).unwrap();
+    assert_eq!(protocol::read_message(&mut white).unwrap(), e5);
+
+    // and black is still logged in
+    let private = Message::PrivateChat("white".to_string(), "back".to_string());
+    protocol::write_message(&mut black, &private).unwrap();
+    let line = chat_line(ChatRoom::Private("white".to_string()), "black", "back");
+    assert_eq!(protocol::read_message(&mut white).unwrap(), line);
+    assert_eq!(protocol::read_message(&mut black).unwrap(), line);
+}
+
+#[test]
+fn spectating() {
+    let addr = start_server(RECONNECT_GRACE);
+    let (mut white, mut black, _) = start_match(addr);
+    let e4 = pawn_move(Tile::E2, Tile::E4);
+    protocol::write_message(&mut white, &e4).unwrap();
+    assert_eq!(protocol::read_message(&mut black).unwrap(), e4);
+
+    let mut spectator = connect(addr);
+    protocol::write_message(&mut spectator, &Message::Spectate(12345)).unwrap();
+    assert_eq!(
+        protocol::read_message(&mut spectator).unwrap(),
+        Message::Error(ProtocolError::UnknownGame)
+    );
+
+    protocol::write_message(&mut spectator, &Message::ListGames).unwrap();
+    let game = match protocol::read_message(&mut spectator).unwrap() {
+        Message::GameList(games) if games.len() == 1 => games[0].clone(),
+        message => panic!("expected one game, got {:?}", message),
+    };
+    assert_eq!(game.moves, 1);
+
+    // it gets the moves made before, then the live ones
+    protocol::write_message(&mut spectator, &Message::Spectate(game.id)).unwrap();
+    match protocol::read_message(&mut spectator).unwrap() {
+        Message::Spectating(snapshot) => {
+            assert_eq!(snapshot.players, ["white".to_string(), "black".to_string()]);
+            assert_eq!(snapshot.moves.len(), 1);
+        }
+        message => panic!("expected the game so far, got {:?}", message),
+    }
+    let e5 = pawn_move(Tile::E7, Tile::E5);
+    protocol::write_message(&mut black, &e5).unwrap();
+    assert_eq!(protocol::read_message(&mut spectator).unwrap(), e5);
+
+    // spectators can't play
+    protocol::write_message(&mut spectator, &pawn_move(Tile::D2, Tile::D4)).unwrap();
+    assert_eq!(
+        protocol::read_message(&mut spectator).unwrap(),
+        Message::Error(ProtocolError::UnexpectedMessage)
+    );
+    assert_eq!(protocol::read_message(&mut white).unwrap(), e5);
+
+    drop(white);
+    drop(black);
+    drop(spectator);
+    // the match goes on without its spectator
+    start_match(addr);
+}
+
+#[test]
+fn lobby() {
+    let addr = start_server(RECONNECT_GRACE);
+    let mut lucy = join_lobby(addr, "lucy");
+
+    // an account is in the lobby once
+    let mut other = log_in(addr, "lucy");
+    protocol::write_message(&mut other, &Message::JoinLobby).unwrap();
+    assert_eq!(
+        protocol::read_message(&mut other).unwrap(),
+        Message::Error(ProtocolError::NameTaken)
+    );
+
+    // the seeks of the lobby, and the new ones
+    protocol::write_message(&mut lucy, &Message::Seek(SeekRequest::default())).unwrap();
+    let lucy_seek = match protocol::read_message(&mut lucy).unwrap() {
+        Message::SeekAdded(seek) => seek,
+        message => panic!("expected the seek, got {:?}", message),
+    };
+    let mut pero = log_in(addr, "pero");
+    protocol::write_message(&mut pero, &Message::JoinLobby).unwrap();
+    assert_eq!(
+        protocol::read_message(&mut pero).unwrap(),
+        Message::Lobby(vec![lucy_seek.clone()])
+    );
+
+    // a declined challenge
+    let blitz = SeekRequest {
+        settings: GameSettings {
+            variant: protocol::Variant::Standard,
+            time_control: Some(chess_rs_core::clock::TimeControl::fischer(
+                Duration::from_secs(180),
+                Duration::from_secs(2),
+            )),
+        },
+        color: Some(ChessTeam::Black),
+        rating_range: None,
+    };
+    let challenge = Message::Challenge("lucy".to_string(), blitz.clone());
+    protocol::write_message(&mut pero, &challenge).unwrap();
+    let challenge = match protocol::read_message(&mut lucy).unwrap() {
+        Message::Challenged(challenge) => challenge,
+        message => panic!("expected a challenge, got {:?}", message),
+    };
+    assert_eq!(challenge.from, "pero");
+    protocol::write_message(&mut lucy, &Message::DeclineChallenge(challenge.id)).unwrap();
+    assert_eq!(
+        protocol::read_message(&mut pero).unwrap(),
+        Message::ChallengeDeclined("lucy".to_string())
+    );
+
+    protocol::write_message(&mut pero, &Message::Challenge("nobody".to_string(), blitz)).unwrap();
+    assert_eq!(
+        protocol::read_message(&mut pero).unwrap(),
+        Message::Error(ProtocolError::UnknownPlayer)
+    );
+
+    // clocks that can't run
+    let no_periods = SeekRequest {
+        settings: GameSettings {
+            variant: protocol::Variant::Standard,
+            time_control: Some(chess_rs_core::clock::TimeControl::Periods {
+                periods: vec![],
+                bonus: chess_rs_core::clock::TimeBonus::None,
+            }),
+        },
+        ..SeekRequest::default()
+    };
+    protocol::write_message(&mut pero, &Message::Seek(no_periods)).unwrap();
+    assert_eq!(
+        protocol::read_message(&mut pero).unwrap(),
+        Message::Error(ProtocolError::InvalidTimeControl)
+    );
+    let huge_bonus = SeekRequest {
+        settings: GameSettings {
+            variant: protocol::Variant::Standard,
+            time_control: Some(chess_rs_core::clock::TimeControl::fischer(
+                Duration::from_secs(180),
+                Duration::MAX,
+            )),
+        },
+        ..SeekRequest::default()
+    };
+    let challenge = Message::Challenge("lucy".to_string(), huge_bonus);
+    protocol::write_message(&mut pero, &challenge).unwrap();
+    assert_eq!(
+        protocol::read_message(&mut pero).unwrap(),
+        Message::Error(ProtocolError::InvalidTimeControl)
+    );
+
+    // taking up lucy's seek takes it out of the lobby
+    let mut watcher = join_lobby(addr, "watcher");
+    protocol::write_message(&mut pero, &Message::AcceptSeek(lucy_seek.id)).unwrap();
+    game_start(&mut lucy);
+    game_start(&mut pero);
+    assert_eq!(
+        protocol::read_message(&mut watcher).unwrap(),
+        Message::SeekRemoved(lucy_seek.id)
+    );
+}
+
+#[test]
+fn accounts() {
+    let addr = start_server(RECONNECT_GRACE);
+    let mut lucy = connect(addr);
+    protocol::write_message(&mut lucy, &Message::JoinLobby).unwrap();
+    assert_eq!(
+        protocol::read_message(&mut lucy).unwrap(),
+        Message::Error(ProtocolError::NotLoggedIn)
+    );
+
+    protocol::write_message(&mut lucy, &register("lu")).unwrap();
+    assert_eq!(
+        protocol::read_message(&mut lucy).unwrap(),
+        Message::Error(ProtocolError::InvalidName)
+    );
+    let short = Message::Register("lucy".to_string(), "short".to_string());
+    protocol::write_message(&mut lucy, &short).unwrap();
+    assert_eq!(
+        protocol::read_message(&mut lucy).unwrap(),
+        Message::Error(ProtocolError::InvalidPassword)
+    );
+
+    protocol::write_message(&mut lucy, &register("lucy")).unwrap();
+    let token = match protocol::read_message(&mut lucy).unwrap() {
+        Message::LoggedIn(name, Some(token)) if name == "lucy" => token,
+        message => panic!("expected to log in, got {:?}", message),
+    };
+    // once is enough
+    protocol::write_message(&mut lucy, &Message::PlayAsGuest).unwrap();
+    assert_eq!(
+        protocol::read_message(&mut lucy).unwrap(),
+        Message::Error(ProtocolError::UnexpectedMessage)
+    );
+
+    let mut other = connect(addr);
+    protocol::write_message(&mut other, &register("LUCY")).unwrap();
+    assert_eq!(
+        protocol::read_message(&mut other).unwrap(),
+        Message::Error(ProtocolError::NameTaken)
+    );
+    let wrong = Message::Login("lucy".to_string(), "passwort".to_string());
+    protocol::write_message(&mut other, &wrong).unwrap();
+    assert_eq!(
+        protocol::read_message(&mut other).unwrap(),
+        Message::Error(ProtocolError::WrongPassword)
+    );
+
+    // the token logs in once, and gives a new one
+    protocol::write_message(&mut other, &Message::LoginWithToken(token)).unwrap();
+    match protocol::read_message(&mut other).unwrap() {
+        Message::LoggedIn(name, Some(next)) => {
+            assert_eq!(name, "lucy");
+            assert_ne!(next, token);
+        }
+        message => panic!("expected to log in, got {:?}", message),
+    }
+    let mut again = connect(addr);
+    protocol::write_message(&mut again, &Message::LoginWithToken(token)).unwrap();
+    assert_eq!(
+        protocol::read_message(&mut again).unwrap(),
+        Message::Error(ProtocolError::UnknownLogin)
+    );
+
+    // guests play with a made up name
+    protocol::write_message(&mut again, &Message::PlayAsGuest).unwrap();
+    let guest = match protocol::read_message(&mut again).unwrap() {
+        Message::LoggedIn(name, None) => name,
+        message => panic!("expected a guest name, got {:?}", message),
+    };
+    assert!(guest.starts_with("guest-"));
+    protocol::write_message(&mut again, &Message::JoinLobby).unwrap();
+    assert!(matches!(
+        protocol::read_message(&mut again).unwrap(),
+        Message::Lobby(_)
+    ));
+}
+
+// the first message that isn't a move or a clock update
+fn skip_moves(stream: &mut StdTcpStream) -> Message {
+    loop {
+        match protocol::read_message(stream).unwrap() {
+            Message::Move(_) | Message::ClockUpdate(_) => {}
+            message => return message,
+        }
+    }
+}
+
+// the move gets to the opponent before the next one is made
+fn play_move(player: &mut StdTcpStream, opponent: &mut StdTcpStream, the_move: Message) {
+    protocol::write_message(player, &the_move).unwrap();
+    loop {
+        match protocol::read_message(opponent).unwrap() {
+            Message::ClockUpdate(_) => {}
+            message => {
+                assert_eq!(message, the_move);
+                return;
+            }
+        }
+    }
+}
+
+#[test]
+fn ratings() {
+    let addr = start_server(RECONNECT_GRACE);
+    let blitz = GameSettings {
+        variant: protocol::Variant::Standard,
+        time_control: Some(chess_rs_core::clock::TimeControl::fischer(
+            Duration::from_secs(180),
+            Duration::from_secs(2),
+        )),
+    };
+    let blitz_seek = |color| {
+        Message::Seek(SeekRequest {
+            settings: blitz.clone(),
+            color: Some(color),
+            rating_range: None,
+        })
+    };
+
+    let mut lucy = join_lobby(addr, "lucy");
+    protocol::write_message(&mut lucy, &blitz_seek(ChessTeam::White)).unwrap();
+    match protocol::read_message(&mut lucy).unwrap() {
+        Message::SeekAdded(seek) => assert_eq!(seek.rating, 1500),
+        message => panic!("expected the seek, got {:?}", message),
+    }
+    let mut pero = join_lobby(addr, "pero");
+    protocol::write_message(&mut pero, &blitz_seek(ChessTeam::Black)).unwrap();
+    game_start(&mut lucy);
+    game_start(&mut pero);
+
+    // fool's mate
+    let queen_mate = Message::Move(Move::PieceMove {
+        piece: ChessPiece::Queen,
+        tile_from: Tile::D8,
+        tile_to: Tile::H4,
+        is_en_passant: false,
+    });
+    play_move(&mut lucy, &mut pero, pawn_move(Tile::F2, Tile::F3));
+    play_move(&mut pero, &mut lucy, pawn_move(Tile::E7, Tile::E5));
+    play_move(&mut lucy, &mut pero, pawn_move(Tile::G2, Tile::G4));
+    protocol::write_message(&mut pero, &queen_mate).unwrap();
+
+    for (stream, is_winner) in [(&mut lucy, false), (&mut pero, true)].iter_mut() {
+        assert!(matches!(skip_moves(stream), Message::GameOver(_)));
+        match protocol::read_message(stream).unwrap() {
+            Message::RatingChanged(protocol::RatingCategory::Blitz, rating, change) => {
+                assert_eq!(*is_winner, change > 0);
+                assert_eq!(rating.rating as i32, 1500 + change);
+                assert!(rating.provisional);
+            }
+            message => panic!("expected the new rating, got {:?}", message),
+        }
+    }
+
+    let mut other = connect(addr);
+    protocol::write_message(&mut other, &Message::GetRatings("PERO".to_string())).unwrap();
+    let pero_rating = match protocol::read_message(&mut other).unwrap() {
+        Message::Ratings(name, ratings) => {
+            assert_eq!(name, "pero");
+            assert_eq!(ratings.len(), 1);
+            assert_eq!(ratings[0].0, protocol::RatingCategory::Blitz);
+            ratings[0].1
+        }
+        message => panic!("expected the ratings, got {:?}", message),
+    };
+    assert!(pero_rating.rating > 1500);
+
+    let history = Message::GetRatingHistory("pero".to_string(), protocol::RatingCategory::Blitz);
+    protocol::write_message(&mut other, &history).unwrap();
+    match protocol::read_message(&mut other).unwrap() {
+        Message::RatingHistory(_, _, points) => {
+            assert_eq!(points.len(), 1);
+            assert_eq!(points[0].rating, pero_rating.rating);
+        }
+        message => panic!("expected the rating history, got {:?}", message),
+    }
+
+    // one game isn't enough for the leaderboard
+    let leaderboard = Message::GetLeaderboard(protocol::RatingCategory::Blitz);
+    protocol::write_message(&mut other, &leaderboard).unwrap();
+    assert_eq!(
+        protocol::read_message(&mut other).unwrap(),
+        Message::Leaderboard(protocol::RatingCategory::Blitz, vec![])
+    );
+
+    protocol::write_message(&mut other, &Message::GetRatings("nobody".to_string())).unwrap();
+    assert_eq!(
+        protocol::read_message(&mut other).unwrap(),
+        Message::Error(ProtocolError::UnknownPlayer)
+    );
+
+    // pero's seeks show the new rating
+    let mut pero = join_lobby(addr, "pero");
+    protocol::write_message(&mut pero, &blitz_seek(ChessTeam::White)).unwrap();
+    match protocol::read_message(&mut pero).unwrap() {
+        Message::SeekAdded(seek) => assert_eq!(seek.rating, pero_rating.rating),
+        message => panic!("expected the seek, got {:?}", message),
+    }
+}
+
+#[test]
+fn archive() {
+    let addr = start_server(RECONNECT_GRACE);
+    let (mut white, mut black, _) = start_match(addr);
+
+    // fool's mate, without a clock
+    play_move(&mut white, &mut black, pawn_move(Tile::F2, Tile::F3));
+    play_move(&mut black, &mut white, pawn_move(Tile::E7, Tile::E5));
+    play_move(&mut white, &mut black, pawn_move(Tile::G2, Tile::G4));
+    let queen_mate = Message::Move(Move::PieceMove {
+        piece: ChessPiece::Queen,
+        tile_from: Tile::D8,
+        tile_to: Tile::H4,
+        is_en_passant: false,
+    });
+    protocol::write_message(&mut black, &queen_mate).unwrap();
+    assert!(matches!(skip_moves(&mut white), Message::GameOver(_)));
+
+    let mut other = connect(addr);
+    let query = protocol::GameQuery {
+        player: Some("black".to_string()),
+        opening: Some("f3 e5".to_string()),
+        ..protocol::GameQuery::default()
+    };
+    protocol::write_message(&mut other, &Message::FindGames(query)).unwrap();
+    let id = match protocol::read_message(&mut other).unwrap() {
+        Message::FoundGames(games) => {
+            assert_eq!(games.len(), 1);
+            assert_eq!(games[0].players, ["white".to_string(), "black".to_string()]);
+            assert_eq!(games[0].result.winner, Some(ChessTeam::Black));
+            assert_eq!(games[0].moves, 4);
+            games[0].id
+        }
+        message => panic!("expected the games, got {:?}", message),
+    };
+
+    protocol::write_message(&mut other, &Message::GetPgn(id)).unwrap();
+    match protocol::read_message(&mut other).unwrap() {
+        Message::Pgn(pgn_id, pgn) => {
+            assert_eq!(pgn_id, id);
+            assert!(pgn.contains("[White \"white\"]\n[Black \"black\"]\n"));
+            assert!(pgn.ends_with("1. f3 e5 2. g4 Qh4# 0-1"));
+        }
+        message => panic!("expected the PGN, got {:?}", message),
+    }
+
+    protocol::write_message(&mut other, &Message::GetPgn(id + 1)).unwrap();
+    assert_eq!(
+        protocol::read_message(&mut other).unwrap(),
+        Message::Error(ProtocolError::UnknownGame)
+    );
+}
+
+#[test]
+fn aborted_games_arent_rated() {
+    let addr = start_server(RECONNECT_GRACE);
+    let blitz_seek = |color| {
+        Message::Seek(SeekRequest {
+            settings: GameSettings {
+                variant: protocol::Variant::Standard,
+                time_control: Some(chess_rs_core::clock::TimeControl::fischer(
+                    Duration::from_secs(180),
+                    Duration::from_secs(2),
+                )),
+            },
+            color: Some(color),
+            rating_range: None,
+        })
+    };
+
+    let mut lucy = join_lobby(addr, "lucy");
+    protocol::write_message(&mut lucy, &blitz_seek(ChessTeam::White)).unwrap();
+    protocol::read_message(&mut lucy).unwrap();
+    let mut pero = join_lobby(addr, "pero");
+    protocol::write_message(&mut pero, &blitz_seek(ChessTeam::Black)).unwrap();
+    game_start(&mut lucy);
+    game_start(&mut pero);
+
+    play_move(&mut lucy, &mut pero, pawn_move(Tile::E2, Tile::E4));
+    protocol::write_message(&mut pero, &Message::Abort).unwrap();
+    let aborted = GameResult {
+        winner: None,
+        reason: EndReason::Aborted,
+    };
+    for stream in [&mut lucy, &mut pero].iter_mut() {
+        assert_eq!(skip_moves(stream), Message::GameOver(aborted));
+        // closed without a new rating
+        assert!(protocol::read_message(stream).is_err());
+    }
+
+    let mut other = connect(addr);
+    protocol::write_message(&mut other, &Message::GetRatings("lucy".to_string())).unwrap();
+    assert_eq!(
+        protocol::read_message(&mut other).unwrap(),
+        Message::Ratings("lucy".to_string(), vec![])
+    );
+    let query = protocol::GameQuery::default();
+    protocol::write_message(&mut other, &Message::FindGames(query)).unwrap();
+    match protocol::read_message(&mut other).unwrap() {
+        Message::FoundGames(games) => assert_eq!(games[0].result, aborted),
+        message => panic!("expected the games, got {:?}", message),
+    }
+}
+
+fn chat_line(room: ChatRoom, from: &str, text: &str) -> Message {
+    Message::ChatLine(ChatLine {
+        room,
+        from: from.to_string(),
+        text: text.to_string(),
+    })
+}
+
+#[test]
+fn chat() {
+    let addr = start_server(RECONNECT_GRACE);
+    let (mut white, mut black, _) = start_match(addr);
+    protocol::write_message(&mut white, &Message::Chat(" good luck ".to_string())).unwrap();
+    let line = chat_line(ChatRoom::Game, "white", "good luck");
+    assert_eq!(protocol::read_message(&mut white).unwrap(), line);
+    assert_eq!(protocol::read_message(&mut black).unwrap(), line);
+
+    let mut lucy = join_lobby(addr, "lucy");
+    let mut pero = join_lobby(addr, "pero");
+    protocol::write_message(&mut lucy, &Message::Chat("hi all".to_string())).unwrap();
+    let line = chat_line(ChatRoom::Lobby, "lucy", "hi all");
+    assert_eq!(protocol::read_message(&mut lucy).unwrap(), line);
+    assert_eq!(protocol::read_message(&mut pero).unwrap(), line);
+
+    // to lucy, from her opponent's game
+    let private = Message::PrivateChat("LUCY".to_string(), "psst".to_string());
+    protocol::write_message(&mut black, &private).unwrap();
+    let line = chat_line(ChatRoom::Private("lucy".to_string()), "black", "psst");
+    assert_eq!(protocol::read_message(&mut lucy).unwrap(), line);
+    assert_eq!(protocol::read_message(&mut black).unwrap(), line);
+
+    let private = Message::PrivateChat("nobody".to_string(), "psst".to_string());
+    protocol::write_message(&mut lucy, &private).unwrap();
+    assert_eq!(
+        protocol::read_message(&mut lucy).unwrap(),
+        Message::Error(ProtocolError::UnknownPlayer)
+    );
+    protocol::write_message(&mut lucy, &Message::Chat(" ".to_string())).unwrap();
+    assert_eq!(
+        protocol::read_message(&mut lucy).unwrap(),
+        Message::Error(ProtocolError::InvalidChat)
+    );
+
+    // lucy said one line already
+    for _ in 1..chat::CHAT_BURST {
+        protocol::write_message(&mut lucy, &Message::Chat("spam".to_string())).unwrap();
+        protocol::read_message(&mut lucy).unwrap();
+    }
+    protocol::write_message(&mut lucy, &Message::Chat("spam".to_string())).unwrap();
+    assert_eq!(
+        protocol::read_message(&mut lucy).unwrap(),
+        Message::Error(ProtocolError::ChatTooFast)
+    );
+}
+
+#[test]
+fn tournament() {
+    let addr = start_server(RECONNECT_GRACE);
+    let mut lucy = join_lobby(addr, "lucy");
+    let settings = protocol::TournamentSettings {
+        name: " Friday Swiss ".to_string(),
+        kind: protocol::TournamentKind::Swiss { rounds: 1 },
+        game: GameSettings::default(),
+    };
+    let no_name = protocol::TournamentSettings {
+        name: " ".to_string(),
+        ..settings.clone()
+    };
+    protocol::write_message(&mut lucy, &Message::CreateTournament(no_name)).unwrap();
+    assert_eq!(
+        protocol::read_message(&mut lucy).unwrap(),
+        Message::Error(ProtocolError::InvalidTournament)
+    );
+    let no_time = protocol::TournamentSettings {
+        game: GameSettings {
+            time_control: Some(chess_rs_core::clock::TimeControl::Hourglass(
+                Duration::from_secs(0),
+            )),
+            ..GameSettings::default()
+        },
+        ..settings.clone()
+    };
+    protocol::write_message(&mut lucy, &Message::CreateTournament(no_time)).unwrap();
+    assert_eq!(
+        protocol::read_message(&mut lucy).unwrap(),
+        Message::Error(ProtocolError::InvalidTimeControl)
+    );
+    protocol::write_message(&mut lucy, &Message::CreateTournament(settings)).unwrap();
+    let info = match protocol::read_message(&mut lucy).unwrap() {
+        Message::TournamentUpdated(info) => info,
+        message => panic!("expected the tournament, got {:?}", message),
+    };
+    assert_eq!(info.settings.name, "Friday Swiss");
+    assert_eq!((info.players, info.status), (1, TournamentStatus::Open));
+    assert!(matches!(
+        protocol::read_message(&mut lucy).unwrap(),
+        Message::Standings(..)
+    ));
+
+    let mut pero = join_lobby(addr, "pero");
+    protocol::write_message(&mut pero, &Message::ListTournaments).unwrap();
+    assert_eq!(
+        protocol::read_message(&mut pero).unwrap(),
+        Message::Tournaments(vec![info.clone()])
+    );
+    protocol::write_message(&mut pero, &Message::JoinTournament(info.id)).unwrap();
+    for stream in [&mut lucy, &mut pero].iter_mut() {
+        match protocol::read_message(stream).unwrap() {
+            Message::TournamentUpdated(info) => assert_eq!(info.players, 2),
+            message => panic!("expected the tournament, got {:?}", message),
+        }
+        assert!(matches!(
+            protocol::read_message(stream).unwrap(),
+            Message::Standings(..)
+        ));
+    }
+
+    protocol::write_message(&mut pero, &Message::StartTournament(info.id)).unwrap();
+    assert_eq!(
+        protocol::read_message(&mut pero).unwrap(),
+        Message::Error(ProtocolError::CantStartTournament)
+    );
+    protocol::write_message(&mut lucy, &Message::StartTournament(info.id)).unwrap();
+    let game = protocol::TournamentGame {
+        id: info.id,
+        name: "Friday Swiss".to_string(),
+        can_berserk: false,
+    };
+    for stream in [&mut lucy, &mut pero].iter_mut() {
+        match protocol::read_message(stream).unwrap() {
+            Message::TournamentUpdated(info) => assert_eq!(info.status, TournamentStatus::Running),
+            message => panic!("expected the tournament, got {:?}", message),
+        }
+        assert!(matches!(
+            protocol::read_message(stream).unwrap(),
+            Message::Standings(..)
+        ));
+        assert_eq!(game_start(stream).tournament, Some(game.clone()));
+        assert!(matches!(
+            protocol::read_message(stream).unwrap(),
+            Message::Standings(..)
+        ));
+    }
+
+    // back in the lobby after the game, with the final standings
+    protocol::write_message(&mut pero, &Message::Resign).unwrap();
+    for stream in [&mut lucy, &mut pero].iter_mut() {
+        assert!(matches!(
+            protocol::read_message(stream).unwrap(),
+            Message::GameOver(_)
+        ));
+        assert_eq!(
+            protocol::read_message(stream).unwrap(),
+            Message::Lobby(vec![])
+        );
+        match protocol::read_message(stream).unwrap() {
+            Message::TournamentOver(id, standings) => {
+                assert_eq!(id, info.id);
+                assert_eq!(standings[0].name, "lucy");
+                assert_eq!(standings[0].score, 1.);
+            }
+            message => panic!("expected the standings, got {:?}", message),
+        }
+        match protocol::read_message(stream).unwrap() {
+            Message::TournamentUpdated(info) => assert_eq!(info.status, TournamentStatus::Finished),
+            message => panic!("expected the tournament, got {:?}", message),
+        }
+    }
+    // it's still listed for a while
+    protocol::write_message(&mut lucy, &Message::ListTournaments).unwrap();
+    match protocol::read_message(&mut lucy).unwrap() {
+        Message::Tournaments(found) => {
+            assert_eq!(found.len(), 1);
+            assert_eq!(found[0].status, TournamentStatus::Finished);
+        }
+        message => panic!("expected the tournaments, got {:?}", message),
+    }
+}
+
+type WebSocketClient = tungstenite::WebSocket<StdTcpStream>;
+
+fn connect_websocket(addr: SocketAddr) -> WebSocketClient {
+    let stream = StdTcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let url = format!("ws://{}/", addr);
+    tungstenite::client(url.as_str(), stream).unwrap().0
+}
+
+// as JSON, like a browser would
+fn send_json(websocket: &mut WebSocketClient, message: &Message) {
+    let text = serde_json::to_string(message).unwrap();
+    websocket.send(tungstenite::Message::Text(text)).unwrap();
+}
+
+fn read_json(websocket: &mut WebSocketClient) -> Message {
+    match websocket.read().unwrap() {
+        tungstenite::Message::Text(text) => serde_json::from_str(&text).unwrap(),
+        frame => panic!("expected text, got {:?}", frame),
+    }
+}
+
+#[test]
+fn websockets() {
+    let accounts = Accounts::for_tests();
+    let mut server = Server::bind("127.0.0.1:0".parse().unwrap(), accounts).unwrap();
+    let addr = server.local_addr().unwrap();
+    let websocket_addr = server
+        .bind_websocket("127.0.0.1:0".parse().unwrap())
+        .unwrap();
+    thread::spawn(move || server.run().unwrap());
+
+    // binary frames hold the same bincode as the TCP frames
+    let mut binary = connect_websocket(websocket_addr);
+    let hello = protocol::encode_payload(&Message::Hello(protocol::PROTOCOL_VERSION)).unwrap();
+    binary.send(tungstenite::Message::Binary(hello)).unwrap();
+    match binary.read().unwrap() {
+        tungstenite::Message::Binary(payload) => assert_eq!(
+            protocol::decode_payload(&payload).unwrap(),
+            Message::Welcome(protocol::PROTOCOL_VERSION)
+        ),
+        frame => panic!("expected binary, got {:?}", frame),
+    }
+
+    // a guest on a WebSocket plays someone on TCP
+    let mut guest = connect_websocket(websocket_addr);
+    send_json(&mut guest, &Message::Hello(protocol::PROTOCOL_VERSION));
+    assert_eq!(
+        read_json(&mut guest),
+        Message::Welcome(protocol::PROTOCOL_VERSION)
+    );
+    send_json(&mut guest, &Message::PlayAsGuest);
+    assert!(matches!(read_json(&mut guest), Message::LoggedIn(_, None)));
+    send_json(&mut guest, &Message::JoinLobby);
+    assert!(matches!(read_json(&mut guest), Message::Lobby(_)));
+    send_json(&mut guest, &seek(ChessTeam::White));
+    assert!(matches!(read_json(&mut guest), Message::SeekAdded(_)));
+
+    let mut pero = join_lobby(addr, "pero");
+    protocol::write_message(&mut pero, &seek(ChessTeam::Black)).unwrap();
+    assert_eq!(game_start(&mut pero).team, ChessTeam::Black);
+    let start = loop {
+        if let Message::GameStart(start) = read_json(&mut guest) {
+            break start;
+        }
+    };
+    assert_eq!(start.team, ChessTeam::White);
+
+    let e4 = pawn_move(Tile::E2, Tile::E4);
+    send_json(&mut guest, &e4);
+    assert_eq!(protocol::read_message(&mut pero).unwrap(), e4);
+    let e5 = pawn_move(Tile::E7, Tile::E5);
+    protocol::write_message(&mut pero, &e5).unwrap();
+    assert_eq!(read_json(&mut guest), e5);
+
+    // not a message
+    guest
+        .send(tungstenite::Message::Text("e4".to_string()))
+        .unwrap();
+    assert!(!matches!(
+        guest.read(),
+        Ok(tungstenite::Message::Text(_)) | Ok(tungstenite::Message::Binary(_))
+    ));
+}
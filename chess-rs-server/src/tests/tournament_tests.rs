@@ -0,0 +1,288 @@
+use super::*;
+use chess_rs_protocol::GameSettings;
+
+fn tournament(kind: TournamentKind, players: &[(&str, u32)]) -> Tournament {
+    let settings = TournamentSettings {
+        name: "Test".to_string(),
+        kind,
+        game: GameSettings::default(),
+    };
+    let (creator, rating) = players[0];
+    let mut tournament = Tournament::new(1, settings, creator.to_string(), rating);
+    for (name, rating) in players[1..].iter() {
+        assert!(tournament.join(name.to_string(), *rating));
+    }
+    tournament
+}
+
+fn names(players: &[&str]) -> Vec<String> {
+    players.iter().map(|name| name.to_string()).collect()
+}
+
+fn pairs(pairings: &[TournamentPairing]) -> Vec<(&str, &str)> {
+    pairings
+        .iter()
+        .map(|p| (p.white.as_str(), p.black.as_str()))
+        .collect()
+}
+
+fn win(tournament: &mut Tournament, pairing: &TournamentPairing, winner: &str) {
+    let score = if pairing.white == winner { 1. } else { 0. };
+    tournament.record(pairing.game, Some(score), [false; 2]);
+}
+
+fn score(tournament: &Tournament, name: &str) -> Standing {
+    tournament
+        .standings()
+        .into_iter()
+        .find(|standing| standing.name == name)
+        .unwrap()
+}
+
+#[test]
+fn joining() {
+    let now = Instant::now();
+    let mut swiss = tournament(TournamentKind::Swiss { rounds: 3 }, &[("a", 1500)]);
+    assert!(!swiss.join("a".to_string(), 1500));
+    assert_eq!(swiss.info().players, 1);
+
+    // only the creator starts it, and not alone
+    assert!(!swiss.start("a", now));
+    assert!(swiss.join("b".to_string(), 1500));
+    assert!(!swiss.start("b", now));
+    assert!(swiss.leave("b"));
+    assert!(!swiss.leave("b"));
+    assert_eq!(swiss.names(), names(&["a"]));
+
+    swiss.join("b".to_string(), 1500);
+    swiss.join("c".to_string(), 1500);
+    assert!(swiss.start("a", now));
+    assert_eq!(swiss.status(), TournamentStatus::Running);
+    assert!(!swiss.start("a", now));
+
+    // too late for a Swiss, and leaving only withdraws
+    assert!(!swiss.join("d".to_string(), 1500));
+    assert!(swiss.leave("c"));
+    assert_eq!(swiss.info().players, 2);
+    assert!(score(&swiss, "c").withdrawn);
+
+    // arenas take players any time, and back
+    let mut arena = tournament(TournamentKind::Arena { minutes: 10 }, &[("a", 0), ("b", 0)]);
+    arena.start("a", now);
+    assert!(arena.join("c".to_string(), 1500));
+    assert!(arena.leave("c"));
+    assert!(arena.join("c".to_string(), 1500));
+    assert!(!score(&arena, "c").withdrawn);
+}
+
+#[test]
+fn arena() {
+    let now = Instant::now();
+    let mut arena = tournament(
+        TournamentKind::Arena { minutes: 10 },
+        &[("a", 1500), ("b", 1500), ("c", 1500)],
+    );
+    assert!(arena.pairings(now, &names(&["a", "b", "c"])).is_empty());
+    arena.start("a", now);
+
+    // only the ones that are waiting
+    assert!(arena.pairings(now, &names(&["a"])).is_empty());
+    let first = arena.pairings(now, &names(&["a", "b"]));
+    assert_eq!(pairs(&first), vec![("a", "b")]);
+    assert!(arena.pairings(now, &names(&["a", "b"])).is_empty());
+    win(&mut arena, &first[0], "a");
+
+    // close scores, but not the last opponent again
+    let second = arena.pairings(now, &names(&["a", "b", "c"]));
+    assert_eq!(pairs(&second), vec![("c", "a")]);
+    win(&mut arena, &second[0], "a");
+    assert!(score(&arena, "a").on_fire);
+    assert_eq!(score(&arena, "a").score, 4.);
+
+    // doubled, and a point for berserking
+    let third = arena.pairings(now, &names(&["a", "b"]));
+    assert_eq!(pairs(&third), vec![("b", "a")]);
+    arena.record(third[0].game, Some(0.), [true, true]);
+    assert_eq!(score(&arena, "a").score, 9.);
+    assert_eq!(score(&arena, "a").games, 3);
+
+    // a draw is doubled too, and ends the streak
+    let fourth = arena.pairings(now, &names(&["a", "c"]));
+    arena.record(fourth[0].game, Some(0.5), [false; 2]);
+    assert_eq!(score(&arena, "a").score, 11.);
+    assert!(!score(&arena, "a").on_fire);
+    assert_eq!(score(&arena, "c").score, 1.);
+
+    let standings = arena.standings();
+    assert_eq!(standings[0].name, "a");
+    assert_eq!(standings[2].score, 0.);
+
+    // no new games once the time is up
+    let later = now + Duration::from_secs(10 * 60);
+    assert!(!arena.is_over(now));
+    let last = arena.pairings(now, &names(&["a", "b"]));
+    assert!(!arena.is_over(later));
+    arena.record(last[0].game, None, [false; 2]);
+    assert!(arena.pairings(later, &names(&["a", "b"])).is_empty());
+    assert!(arena.is_over(later));
+}
+
+#[test]
+fn swiss() {
+    let now = Instant::now();
+    let mut swiss = tournament(
+        TournamentKind::Swiss { rounds: 4 },
+        &[("a", 2000), ("b", 1900), ("c", 1800), ("d", 1700)],
+    );
+    swiss.start("a", now);
+
+    // the top half meets the bottom half
+    let first = swiss.pairings(now, &[]);
+    assert_eq!(pairs(&first), vec![("a", "c"), ("b", "d")]);
+    win(&mut swiss, &first[0], "a");
+    assert!(swiss.pairings(now, &[]).is_empty());
+    win(&mut swiss, &first[1], "d");
+
+    // by score, and the colors switch
+    let second = swiss.pairings(now, &[]);
+    assert_eq!(pairs(&second), vec![("d", "a"), ("c", "b")]);
+    win(&mut swiss, &second[0], "a");
+    win(&mut swiss, &second[1], "b");
+
+    // no one meets twice
+    let third = swiss.pairings(now, &[]);
+    assert_eq!(pairs(&third), vec![("a", "b"), ("c", "d")]);
+    win(&mut swiss, &third[0], "a");
+    swiss.forfeit(third[1].game, [false, true]);
+    assert_eq!(score(&swiss, "d").score, 2.);
+    assert_eq!(score(&swiss, "c").score, 0.);
+
+    // everyone met, so they meet again rather than not play
+    let fourth = swiss.pairings(now, &[]);
+    assert_eq!(fourth.len(), 2);
+    assert!(!swiss.is_over(now));
+    for pairing in fourth.iter() {
+        swiss.record(pairing.game, Some(0.5), [false; 2]);
+    }
+    assert!(swiss.is_over(now));
+    assert!(swiss.pairings(now, &[]).is_empty());
+
+    let standings = swiss.standings();
+    assert_eq!(standings[0].name, "a");
+    assert_eq!(standings[0].score, 3.5);
+    assert_eq!(standings[0].games, 4);
+}
+
+#[test]
+fn byes() {
+    let now = Instant::now();
+    let mut swiss = tournament(
+        TournamentKind::Swiss { rounds: 3 },
+        &[("a", 2000), ("b", 1900), ("c", 1800)],
+    );
+    swiss.start("a", now);
+
+    // the lowest one gets the bye, and a point
+    let first = swiss.pairings(now, &[]);
+    assert_eq!(pairs(&first), vec![("a", "b")]);
+    assert_eq!(score(&swiss, "c").score, 1.);
+    assert_eq!(score(&swiss, "c").games, 0);
+    win(&mut swiss, &first[0], "a");
+
+    // but only once
+    let second = swiss.pairings(now, &[]);
+    assert_eq!(pairs(&second), vec![("c", "a")]);
+    assert_eq!(score(&swiss, "b").score, 1.);
+    win(&mut swiss, &second[0], "c");
+
+    // withdrawn players aren't paired, and the tournament is over without
+    //  two players
+    swiss.leave("b");
+    swiss.leave("c");
+    assert!(swiss.pairings(now, &[]).is_empty());
+    assert!(swiss.is_over(now));
+}
+
+#[test]
+fn round_robin() {
+    for players in 2..8 {
+        let rounds = round_robin_schedule(players);
+        assert_eq!(rounds.len(), players - 1 + players % 2);
+
+        let mut met = vec![];
+        for round in rounds.iter() {
+            assert_eq!(round.len(), players / 2);
+            for (white, black) in round.iter() {
+                met.push((*white.min(black), *white.max(black)));
+            }
+        }
+        met.sort_unstable();
+        met.dedup();
+        assert_eq!(met.len(), players * (players - 1) / 2);
+    }
+
+    let now = Instant::now();
+    let mut round_robin = tournament(
+        TournamentKind::RoundRobin,
+        &[("a", 1500), ("b", 1600), ("c", 1500), ("d", 1500)],
+    );
+    round_robin.start("a", now);
+
+    // a beats everyone, b beats c, d beats c and b draws d
+    let white_score = |white: &str, black: &str| match (white, black) {
+        ("a", _) | ("b", "c") | ("d", "c") => 1.,
+        (_, "a") | ("c", "b") | ("c", "d") => 0.,
+        _ => 0.5,
+    };
+    for _ in 0..3 {
+        let round = round_robin.pairings(now, &[]);
+        assert_eq!(round.len(), 2);
+        for pairing in round.iter() {
+            let score = white_score(&pairing.white, &pairing.black);
+            round_robin.record(pairing.game, Some(score), [false; 2]);
+        }
+    }
+    assert!(round_robin.is_over(now));
+    assert!(round_robin.pairings(now, &[]).is_empty());
+
+    let standings = round_robin.standings();
+    let order: Vec<&str> = standings.iter().map(|s| s.name.as_str()).collect();
+    assert_eq!(order, vec!["a", "b", "d", "c"]);
+    assert_eq!(standings[0].sonneborn_berger, 3.);
+    assert_eq!(standings[0].buchholz, 3.);
+    assert_eq!(standings[1].score, 1.5);
+    assert_eq!(standings[1].sonneborn_berger, 0.75);
+    assert_eq!(standings[1].buchholz, 4.5);
+    assert_eq!(standings[3].buchholz, 6.);
+}
+
+#[test]
+fn round_robin_withdrawals() {
+    let now = Instant::now();
+    let players = [("a", 1500), ("b", 1500), ("c", 1500), ("d", 1500)];
+    let mut round_robin = tournament(TournamentKind::RoundRobin, &players);
+    round_robin.start("a", now);
+
+    let round = round_robin.pairings(now, &[]);
+    assert_eq!(round.len(), 2);
+    for pairing in round.iter() {
+        round_robin.record(pairing.game, Some(0.5), [false; 2]);
+    }
+
+    // d doesn't play anymore, and loses the games it had left
+    assert!(round_robin.leave("d"));
+    for _ in 0..2 {
+        let round = round_robin.pairings(now, &[]);
+        assert_eq!(round.len(), 1);
+        assert!(round.iter().all(|p| p.white != "d" && p.black != "d"));
+        round_robin.record(round[0].game, Some(0.5), [false; 2]);
+    }
+    assert!(round_robin.is_over(now));
+
+    let d = score(&round_robin, "d");
+    assert_eq!(d.score, 0.5);
+    assert_eq!(d.games, 3);
+    assert!(d.withdrawn);
+    let points: f32 = round_robin.standings().iter().map(|s| s.score).sum();
+    assert_eq!(points, 6.);
+}
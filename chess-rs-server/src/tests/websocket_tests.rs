@@ -0,0 +1,132 @@
+use super::*;
+use chess_rs_protocol::PROTOCOL_VERSION;
+use tungstenite::protocol::Role;
+
+// the example of RFC 6455
+const REQUEST: &str = "GET /chess HTTP/1.1\r\n\
+    Host: localhost\r\n\
+    Upgrade: websocket\r\n\
+    Connection: Upgrade\r\n\
+    Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+    Sec-WebSocket-Version: 13\r\n\r\n";
+
+fn open() -> (WebSocketCodec, WebSocket<Buffers>) {
+    let mut codec = WebSocketCodec::new();
+    // the request comes in pieces
+    for bytes in REQUEST.as_bytes().chunks(10) {
+        codec.push(bytes);
+        assert!(codec.next_message().unwrap().is_none());
+    }
+
+    let response = String::from_utf8(codec.take_outgoing()).unwrap();
+    assert!(response.starts_with("HTTP/1.1 101"));
+    assert!(response.contains("s3pPLMBiTxaQ9kYGzzhZRbK+xOo="));
+
+    let client = WebSocket::from_raw_socket(Buffers::default(), Role::Client, None);
+    (codec, client)
+}
+
+fn send(client: &mut WebSocket<Buffers>, codec: &mut WebSocketCodec, frame: tungstenite::Message) {
+    client.send(frame).unwrap();
+    let bytes = std::mem::take(&mut client.get_mut().outgoing);
+    for piece in bytes.chunks(5) {
+        codec.push(piece);
+    }
+}
+
+fn receive(client: &mut WebSocket<Buffers>, bytes: Vec<u8>) -> tungstenite::Message {
+    client.get_mut().incoming.extend(bytes);
+    client.read().unwrap()
+}
+
+#[test]
+fn binary_and_json() {
+    let (mut codec, mut client) = open();
+
+    let hello = protocol::encode_payload(&Message::Hello(PROTOCOL_VERSION)).unwrap();
+    send(&mut client, &mut codec, tungstenite::Message::Binary(hello));
+    assert_eq!(
+        codec.next_message().unwrap(),
+        Some(Message::Hello(PROTOCOL_VERSION))
+    );
+    assert!(codec.next_message().unwrap().is_none());
+
+    let welcome = Message::Welcome(PROTOCOL_VERSION);
+    match receive(&mut client, codec.encode(&welcome).unwrap()) {
+        tungstenite::Message::Binary(payload) => {
+            assert_eq!(protocol::decode_payload(&payload).unwrap(), welcome)
+        }
+        frame => panic!("not binary: {:?}", frame),
+    }
+
+    // text in, text out
+    let hello = format!("{{\"Hello\":{}}}", PROTOCOL_VERSION);
+    send(&mut client, &mut codec, tungstenite::Message::Text(hello));
+    assert_eq!(
+        codec.next_message().unwrap(),
+        Some(Message::Hello(PROTOCOL_VERSION))
+    );
+    match receive(&mut client, codec.encode(&welcome).unwrap()) {
+        tungstenite::Message::Text(text) => {
+            assert_eq!(text, format!("{{\"Welcome\":{}}}", PROTOCOL_VERSION))
+        }
+        frame => panic!("not text: {:?}", frame),
+    }
+
+    // pings are answered without a message
+    send(
+        &mut client,
+        &mut codec,
+        tungstenite::Message::Ping(vec![1, 2]),
+    );
+    assert!(codec.next_message().unwrap().is_none());
+    assert_eq!(
+        receive(&mut client, codec.take_outgoing()),
+        tungstenite::Message::Pong(vec![1, 2])
+    );
+}
+
+#[test]
+fn bad_websockets() {
+    let mut codec = WebSocketCodec::new();
+    codec.push(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(matches!(
+        codec.next_message(),
+        Err(WebSocketError::Handshake(_))
+    ));
+
+    let mut codec = WebSocketCodec::new();
+    codec.push(&[b'a'; MAX_REQUEST_LEN + 1]);
+    assert!(matches!(
+        codec.next_message(),
+        Err(WebSocketError::Handshake(_))
+    ));
+
+    let (mut codec, mut client) = open();
+    let text = tungstenite::Message::Text("e4".to_string());
+    send(&mut client, &mut codec, text);
+    assert!(matches!(
+        codec.next_message(),
+        Err(WebSocketError::Malformed)
+    ));
+    let binary = tungstenite::Message::Binary(vec![255; 4]);
+    send(&mut client, &mut codec, binary);
+    assert!(matches!(
+        codec.next_message(),
+        Err(WebSocketError::Malformed)
+    ));
+
+    let too_long = tungstenite::Message::Binary(vec![0; MAX_FRAME_LEN + 1]);
+    send(&mut client, &mut codec, too_long);
+    assert!(matches!(
+        codec.next_message(),
+        Err(WebSocketError::WebSocket(_))
+    ));
+
+    let (mut codec, mut client) = open();
+    send(&mut client, &mut codec, tungstenite::Message::Close(None));
+    assert!(matches!(
+        codec.next_message(),
+        Err(WebSocketError::WebSocket(_))
+    ));
+}
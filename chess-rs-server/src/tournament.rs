@@ -0,0 +1,632 @@
+// Tournaments, without the sockets: who plays who, and the standings.
+//
+// Players are their names. Every now and then the server asks for the games
+//  to start, starts their matches (or scores a forfeit for the players that
+//  aren't in the lobby) and records how they end.
+//
+// Arenas pair whoever is waiting, close scores first, until the time is up.
+//  A win is 2 points and a draw 1. After two wins in a row the points are
+//  doubled until a game isn't won, and a berserked win gets a point more.
+// Swiss tournaments pair their rounds like a simple Dutch system: in each
+//  score group the top half meets the bottom half, players that can't be
+//  paired there float down, and no one meets anyone twice. The player that
+//  had fewer whites gets white. With an odd number of players, the lowest one
+//  that didn't have a bye yet gets one, and a point for it.
+// Round robins play the rounds of the Berger tables. The games of withdrawn
+//  players aren't played, their opponents win them.
+
+use chess_rs_protocol::{
+    Standing, TournamentInfo, TournamentKind, TournamentSettings, TournamentStatus,
+};
+use std::cmp::Ordering;
+use std::time::{Duration, Instant};
+
+// how many tries the Swiss pairing gets before it lets players meet again
+const PAIRING_BUDGET: u32 = 100_000;
+
+struct Entrant {
+    name: String,
+    rating: u32,
+    withdrawn: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Outcome {
+    Playing,
+    //White first
+    Scored([f32; 2]),
+    //no one gets anything, but they met
+    Aborted,
+}
+
+// Players are indices into the entrants
+struct Game {
+    white: usize,
+    //None for a bye
+    black: Option<usize>,
+    outcome: Outcome,
+    berserked: [bool; 2],
+}
+
+// a game the server should start now
+#[derive(Clone, Debug, PartialEq)]
+pub struct TournamentPairing {
+    pub game: usize,
+    pub white: String,
+    pub black: String,
+}
+
+pub struct Tournament {
+    pub id: u64,
+    pub settings: TournamentSettings,
+    pub creator: String,
+    status: TournamentStatus,
+    entrants: Vec<Entrant>,
+    games: Vec<Game>,
+    //Swiss and round robin: the rounds started so far
+    round: usize,
+    //round robin: every round, made at the start
+    schedule: Vec<Vec<(usize, usize)>>,
+    //arena: no new games after this
+    ends_at: Option<Instant>,
+    finished_at: Option<Instant>,
+}
+
+// Pairs the players, best first. Each one gets the player half its score
+//   group below it, or the next one that fits. None if they can't all be
+//   paired without meeting twice
+fn swiss_pairs(
+    ranked: &[usize],
+    scores: &[f32],
+    met: &dyn Fn(usize, usize) -> bool,
+    budget: &mut u32,
+) -> Option<Vec<(usize, usize)>> {
+    let (first, rest) = match ranked.split_first() {
+        Some(split) => split,
+        None => return Some(vec![]),
+    };
+    if *budget == 0 {
+        return None;
+    }
+    *budget -= 1;
+
+    let group_len = rest
+        .iter()
+        .take_while(|player| scores[**player] == scores[*first])
+        .count();
+    let middle = group_len.div_ceil(2).saturating_sub(1);
+    let order = (middle..group_len)
+        .chain(0..middle)
+        .chain(group_len..rest.len());
+
+    for i in order {
+        let opponent = rest[i];
+        if met(*first, opponent) {
+            continue;
+        }
+        let others: Vec<usize> = rest
+            .iter()
+            .enumerate()
+            .filter(|(j, _)| *j != i)
+            .map(|(_, player)| *player)
+            .collect();
+        if let Some(mut pairs) = swiss_pairs(&others, scores, met, budget) {
+            pairs.insert(0, (*first, opponent));
+            return Some(pairs);
+        }
+    }
+    None
+}
+
+// The rounds of the Berger tables: the first seat stays, the others turn
+//   around it. With an odd number of players someone sits out every round
+pub fn round_robin_schedule(players: usize) -> Vec<Vec<(usize, usize)>> {
+    let mut seats: Vec<Option<usize>> = (0..players).map(Some).collect();
+    if seats.len() % 2 == 1 {
+        seats.push(None);
+    }
+    let len = seats.len();
+
+    (0..len.saturating_sub(1))
+        .map(|round| {
+            let pairs = (0..len / 2)
+                .filter_map(|i| match (seats[i], seats[len - 1 - i]) {
+                    (Some(a), Some(b)) if (round + i) % 2 == 0 => Some((a, b)),
+                    (Some(a), Some(b)) => Some((b, a)),
+                    _ => None,
+                })
+                .collect();
+            seats[1..].rotate_right(1);
+            pairs
+        })
+        .collect()
+}
+
+impl Tournament {
+    // the creator is in it from the start
+    pub fn new(id: u64, settings: TournamentSettings, creator: String, rating: u32) -> Tournament {
+        let mut tournament = Tournament {
+            id,
+            settings,
+            creator: creator.clone(),
+            status: TournamentStatus::Open,
+            entrants: vec![],
+            games: vec![],
+            round: 0,
+            schedule: vec![],
+            ends_at: None,
+            finished_at: None,
+        };
+        tournament.join(creator, rating);
+        tournament
+    }
+
+    pub fn info(&self) -> TournamentInfo {
+        TournamentInfo {
+            id: self.id,
+            settings: self.settings.clone(),
+            creator: self.creator.clone(),
+            players: self.entrants.iter().filter(|e| !e.withdrawn).count() as u32,
+            status: self.status,
+        }
+    }
+
+    pub fn status(&self) -> TournamentStatus {
+        self.status
+    }
+
+    pub fn is_arena(&self) -> bool {
+        matches!(self.settings.kind, TournamentKind::Arena { .. })
+    }
+
+    // everyone that played or plays in it
+    pub fn names(&self) -> Vec<String> {
+        self.entrants.iter().map(|e| e.name.clone()).collect()
+    }
+
+    fn entrant(&self, name: &str) -> Option<usize> {
+        self.entrants.iter().position(|e| e.name == name)
+    }
+
+    // Before the start, or any time in an arena. True if the player wasn't in
+    //   it, or is back
+    pub fn join(&mut self, name: String, rating: u32) -> bool {
+        let can_join = match self.status {
+            TournamentStatus::Open => true,
+            TournamentStatus::Running => self.is_arena(),
+            TournamentStatus::Finished => false,
+        };
+        if !can_join {
+            return false;
+        }
+
+        match self.entrant(&name) {
+            Some(i) if self.entrants[i].withdrawn => {
+                self.entrants[i].withdrawn = false;
+                true
+            }
+            Some(_) => false,
+            None => {
+                self.entrants.push(Entrant {
+                    name,
+                    rating,
+                    withdrawn: false,
+                });
+                true
+            }
+        }
+    }
+
+    // Leaving before the start, or else withdrawing: the player isn't paired
+    //   anymore but keeps the score. True if something changed
+    pub fn leave(&mut self, name: &str) -> bool {
+        let i = match self.entrant(name) {
+            Some(i) => i,
+            None => return false,
+        };
+        match self.status {
+            TournamentStatus::Open => {
+                self.entrants.remove(i);
+                true
+            }
+            TournamentStatus::Running if !self.entrants[i].withdrawn => {
+                self.entrants[i].withdrawn = true;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // only the creator starts it, with two players or more
+    pub fn start(&mut self, name: &str, now: Instant) -> bool {
+        if self.status != TournamentStatus::Open || name != self.creator || self.entrants.len() < 2
+        {
+            return false;
+        }
+
+        self.status = TournamentStatus::Running;
+        match self.settings.kind {
+            TournamentKind::Arena { minutes } => {
+                self.ends_at = Some(now + Duration::from_secs(minutes as u64 * 60));
+            }
+            TournamentKind::RoundRobin => {
+                self.schedule = round_robin_schedule(self.entrants.len());
+            }
+            TournamentKind::Swiss { .. } => {}
+        }
+        true
+    }
+
+    fn is_playing(&self, player: usize) -> bool {
+        self.games.iter().any(|game| {
+            game.outcome == Outcome::Playing && (game.white == player || game.black == Some(player))
+        })
+    }
+
+    fn has_games_running(&self) -> bool {
+        self.games
+            .iter()
+            .any(|game| game.outcome == Outcome::Playing)
+    }
+
+    // the players still in it
+    fn active(&self) -> Vec<usize> {
+        (0..self.entrants.len())
+            .filter(|i| !self.entrants[*i].withdrawn)
+            .collect()
+    }
+
+    // The games to start now. In an arena only players that are waiting get
+    //   paired, the others are paired anyway and the server scores a forfeit
+    //   if they aren't there
+    pub fn pairings(&mut self, now: Instant, waiting: &[String]) -> Vec<TournamentPairing> {
+        if self.status != TournamentStatus::Running {
+            return vec![];
+        }
+
+        let pairs = match self.settings.kind {
+            TournamentKind::Arena { .. } => self.arena_pairs(now, waiting),
+            TournamentKind::Swiss { rounds } => match self.has_games_running() {
+                false if self.round < rounds as usize && self.active().len() >= 2 => {
+                    self.round += 1;
+                    self.swiss_round()
+                }
+                _ => vec![],
+            },
+            TournamentKind::RoundRobin => match self.has_games_running() {
+                false if self.round < self.schedule.len() => {
+                    self.round += 1;
+                    self.round_robin_round()
+                }
+                _ => vec![],
+            },
+        };
+
+        pairs
+            .into_iter()
+            .map(|(white, black)| {
+                self.games.push(Game {
+                    white,
+                    black: Some(black),
+                    outcome: Outcome::Playing,
+                    berserked: [false; 2],
+                });
+                TournamentPairing {
+                    game: self.games.len() - 1,
+                    white: self.entrants[white].name.clone(),
+                    black: self.entrants[black].name.clone(),
+                }
+            })
+            .collect()
+    }
+
+    fn arena_pairs(&self, now: Instant, waiting: &[String]) -> Vec<(usize, usize)> {
+        if self.ends_at.is_none_or(|ends_at| now >= ends_at) {
+            return vec![];
+        }
+
+        let mut players: Vec<usize> = self
+            .active()
+            .into_iter()
+            .filter(|i| !self.is_playing(*i) && waiting.contains(&self.entrants[*i].name))
+            .collect();
+        let scores = self.scores();
+        players.sort_by(|a, b| self.compare(*a, *b, &scores, &[0.; 0], &[0.; 0]));
+
+        //close scores, but not the last opponent again if someone else is there
+        let mut pairs = vec![];
+        while players.len() >= 2 {
+            let first = players.remove(0);
+            let last_opponent = self.last_opponent(first);
+            let i = (0..players.len())
+                .find(|i| Some(players[*i]) != last_opponent)
+                .unwrap_or(0);
+            let opponent = players.remove(i);
+            pairs.push(self.colors(first, opponent));
+        }
+        pairs
+    }
+
+    fn swiss_round(&mut self) -> Vec<(usize, usize)> {
+        let scores = self.scores();
+        let mut ranked = self.active();
+        ranked.sort_by(|a, b| {
+            scores[*b]
+                .partial_cmp(&scores[*a])
+                .unwrap_or(Ordering::Equal)
+                .then(self.entrants[*b].rating.cmp(&self.entrants[*a].rating))
+        });
+
+        let met = |a: usize, b: usize| self.have_met(a, b);
+        let mut budget = PAIRING_BUDGET;
+
+        //the bye goes to the lowest player without one that lets the others pair
+        let mut bye = None;
+        let mut pairs = None;
+        if ranked.len() % 2 == 1 {
+            let has_bye = |player: usize| {
+                self.games
+                    .iter()
+                    .any(|game| game.white == player && game.black.is_none())
+            };
+            for candidate in ranked.iter().rev().filter(|p| !has_bye(**p)) {
+                let others: Vec<usize> =
+                    ranked.iter().copied().filter(|p| p != candidate).collect();
+                if let Some(found) = swiss_pairs(&others, &scores, &met, &mut budget) {
+                    bye = Some(*candidate);
+                    pairs = Some(found);
+                    break;
+                }
+            }
+            if pairs.is_none() {
+                bye = ranked.pop();
+            }
+        } else {
+            pairs = swiss_pairs(&ranked, &scores, &met, &mut budget);
+        }
+
+        //too many rounds for everyone to meet someone new
+        let pairs = pairs.unwrap_or_else(|| {
+            let mut budget = PAIRING_BUDGET;
+            swiss_pairs(&ranked, &scores, &|_, _| false, &mut budget).unwrap_or_default()
+        });
+        let pairs = pairs.into_iter().map(|(a, b)| self.colors(a, b)).collect();
+
+        if let Some(player) = bye {
+            self.games.push(Game {
+                white: player,
+                black: None,
+                outcome: Outcome::Scored([1., 0.]),
+                berserked: [false; 2],
+            });
+        }
+        pairs
+    }
+
+    // The pairs of the round that get played. The others are scored now
+    fn round_robin_round(&mut self) -> Vec<(usize, usize)> {
+        let mut pairs = vec![];
+        for (white, black) in self.schedule[self.round - 1].clone() {
+            let is_there = [white, black].map(|player| !self.entrants[player].withdrawn);
+            match is_there {
+                [true, true] => pairs.push((white, black)),
+                _ => {
+                    self.games.push(Game {
+                        white,
+                        black: Some(black),
+                        outcome: Outcome::Playing,
+                        berserked: [false; 2],
+                    });
+                    self.forfeit(self.games.len() - 1, is_there);
+                }
+            }
+        }
+        pairs
+    }
+
+    fn have_met(&self, a: usize, b: usize) -> bool {
+        self.games.iter().any(|game| {
+            (game.white == a && game.black == Some(b)) || (game.white == b && game.black == Some(a))
+        })
+    }
+
+    fn last_opponent(&self, player: usize) -> Option<usize> {
+        self.games.iter().rev().find_map(|game| match game.black {
+            Some(black) if game.white == player => Some(black),
+            Some(black) if black == player => Some(game.white),
+            _ => None,
+        })
+    }
+
+    // The one that had fewer whites gets white, or the one that had black
+    //   last time
+    fn colors(&self, a: usize, b: usize) -> (usize, usize) {
+        let balance = |player: usize| -> i32 {
+            self.games
+                .iter()
+                .map(|game| match game.black {
+                    Some(_) if game.white == player => 1,
+                    Some(black) if black == player => -1,
+                    _ => 0,
+                })
+                .sum()
+        };
+        let had_white_last = |player: usize| {
+            self.games.iter().rev().find_map(|game| match game.black {
+                Some(_) if game.white == player => Some(true),
+                Some(black) if black == player => Some(false),
+                _ => None,
+            })
+        };
+
+        match balance(a).cmp(&balance(b)) {
+            Ordering::Less => (a, b),
+            Ordering::Greater => (b, a),
+            Ordering::Equal => match had_white_last(a) {
+                Some(true) => (b, a),
+                _ => (a, b),
+            },
+        }
+    }
+
+    // A game the server started ended. None if it was aborted
+    pub fn record(&mut self, game: usize, white_score: Option<f32>, berserked: [bool; 2]) {
+        if let Some(game) = self.games.get_mut(game) {
+            game.outcome = match white_score {
+                Some(score) => Outcome::Scored([score, 1. - score]),
+                None => Outcome::Aborted,
+            };
+            game.berserked = berserked;
+        }
+    }
+
+    // the players that weren't there for the game lose it
+    pub fn forfeit(&mut self, game: usize, is_there: [bool; 2]) {
+        let score = |there: bool| if there { 1. } else { 0. };
+        if let Some(game) = self.games.get_mut(game) {
+            game.outcome = Outcome::Scored([score(is_there[0]), score(is_there[1])]);
+        }
+    }
+
+    // its games are played, or its time is up
+    pub fn is_over(&self, now: Instant) -> bool {
+        if self.status != TournamentStatus::Running || self.has_games_running() {
+            return false;
+        }
+        match self.settings.kind {
+            TournamentKind::Arena { .. } => self.ends_at.is_none_or(|ends_at| now >= ends_at),
+            TournamentKind::Swiss { rounds } => {
+                self.round >= rounds as usize || self.active().len() < 2
+            }
+            TournamentKind::RoundRobin => self.round >= self.schedule.len(),
+        }
+    }
+
+    pub fn finish(&mut self, now: Instant) {
+        self.status = TournamentStatus::Finished;
+        self.finished_at = Some(now);
+    }
+
+    pub fn finished_at(&self) -> Option<Instant> {
+        self.finished_at
+    }
+
+    // the finished games of the player, with its side and its opponent
+    fn games_of(&self, player: usize) -> impl Iterator<Item = (f32, Option<usize>, bool)> + '_ {
+        self.games.iter().filter_map(move |game| {
+            let scores = match game.outcome {
+                Outcome::Scored(scores) => scores,
+                _ => return None,
+            };
+            match game.black {
+                _ if game.white == player => Some((scores[0], game.black, game.berserked[0])),
+                Some(black) if black == player => {
+                    Some((scores[1], Some(game.white), game.berserked[1]))
+                }
+                _ => None,
+            }
+        })
+    }
+
+    // arena points, and if the player is on a streak
+    fn arena_score(&self, player: usize) -> (f32, bool) {
+        let mut points = 0.;
+        let mut wins_in_a_row = 0;
+        for (score, _, berserked) in self.games_of(player) {
+            let on_fire = wins_in_a_row >= 2;
+            let base = match score {
+                s if s >= 1. => 2.,
+                s if s > 0. => 1.,
+                _ => 0.,
+            };
+            points += if on_fire { base * 2. } else { base };
+            if score >= 1. {
+                wins_in_a_row += 1;
+                if berserked {
+                    points += 1.;
+                }
+            } else {
+                wins_in_a_row = 0;
+            }
+        }
+        (points, wins_in_a_row >= 2)
+    }
+
+    fn scores(&self) -> Vec<f32> {
+        (0..self.entrants.len())
+            .map(|player| match self.is_arena() {
+                true => self.arena_score(player).0,
+                false => self.games_of(player).map(|(score, _, _)| score).sum(),
+            })
+            .collect()
+    }
+
+    // the scores of the opponents, and of the beaten ones plus half the drawn
+    fn tie_breaks(&self, player: usize, scores: &[f32]) -> (f32, f32) {
+        let mut buchholz = 0.;
+        let mut sonneborn_berger = 0.;
+        for (score, opponent, _) in self.games_of(player) {
+            if let Some(opponent) = opponent {
+                buchholz += scores[opponent];
+                sonneborn_berger += score * scores[opponent];
+            }
+        }
+        (buchholz, sonneborn_berger)
+    }
+
+    fn compare(
+        &self,
+        a: usize,
+        b: usize,
+        scores: &[f32],
+        buchholz: &[f32],
+        sonneborn_berger: &[f32],
+    ) -> Ordering {
+        let by = |values: &[f32]| match values.is_empty() {
+            true => Ordering::Equal,
+            false => values[b].partial_cmp(&values[a]).unwrap_or(Ordering::Equal),
+        };
+        let tie_breaks = match self.settings.kind {
+            TournamentKind::RoundRobin => by(sonneborn_berger).then(by(buchholz)),
+            _ => by(buchholz).then(by(sonneborn_berger)),
+        };
+        by(scores)
+            .then(tie_breaks)
+            .then(self.entrants[b].rating.cmp(&self.entrants[a].rating))
+            .then(self.entrants[a].name.cmp(&self.entrants[b].name))
+    }
+
+    // best first
+    pub fn standings(&self) -> Vec<Standing> {
+        let scores = self.scores();
+        let (buchholz, sonneborn_berger): (Vec<f32>, Vec<f32>) = (0..self.entrants.len())
+            .map(|player| match self.is_arena() {
+                true => (0., 0.),
+                false => self.tie_breaks(player, &scores),
+            })
+            .unzip();
+
+        let mut order: Vec<usize> = (0..self.entrants.len()).collect();
+        order.sort_by(|a, b| self.compare(*a, *b, &scores, &buchholz, &sonneborn_berger));
+
+        order
+            .into_iter()
+            .map(|player| Standing {
+                name: self.entrants[player].name.clone(),
+                score: scores[player],
+                games: self
+                    .games_of(player)
+                    .filter(|(_, opponent, _)| opponent.is_some())
+                    .count() as u32,
+                buchholz: buchholz[player],
+                sonneborn_berger: sonneborn_berger[player],
+                on_fire: self.is_arena() && self.arena_score(player).1,
+                withdrawn: self.entrants[player].withdrawn,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+#[path = "./tests/tournament_tests.rs"]
+mod tournament_tests;
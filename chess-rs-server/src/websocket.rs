@@ -0,0 +1,184 @@
+// WebSocket connections, for browsers and bots that don't want to speak the
+//  length-prefixed frames. The socket stays the server's: the codec is given
+//  the bytes that arrive and hands back the bytes to send, so tungstenite
+//  never waits on anything.
+//
+// A binary frame holds one message encoded with bincode, and a text frame
+//  one message as JSON. The server answers with the kind the client used
+//  last.
+
+use chess_rs_protocol::{self as protocol, FrameError, Message, MAX_FRAME_LEN};
+use std::fmt;
+use std::io::{self, Read, Write};
+use tungstenite::protocol::WebSocketConfig;
+use tungstenite::WebSocket;
+
+// the longest HTTP request that opens a WebSocket
+const MAX_REQUEST_LEN: usize = 8 * 1024;
+
+// what tungstenite reads from and writes to, in place of the socket
+#[derive(Default)]
+struct Buffers {
+    incoming: Vec<u8>,
+    outgoing: Vec<u8>,
+}
+
+impl Read for Buffers {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.incoming.is_empty() {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        let len = buf.len().min(self.incoming.len());
+        buf[..len].copy_from_slice(&self.incoming[..len]);
+        self.incoming.drain(..len);
+        Ok(len)
+    }
+}
+
+impl Write for Buffers {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.outgoing.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+enum State {
+    //the HTTP request so far
+    Handshake(Vec<u8>),
+    Open(Box<WebSocket<Buffers>>),
+}
+
+#[derive(Debug)]
+pub enum WebSocketError {
+    //the request didn't open a WebSocket
+    Handshake(String),
+    WebSocket(Box<tungstenite::Error>),
+    //a frame that isn't a message
+    Malformed,
+}
+
+impl fmt::Display for WebSocketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WebSocketError::Handshake(e) => write!(f, "bad WebSocket handshake: {}", e),
+            WebSocketError::WebSocket(e) => write!(f, "{}", e),
+            WebSocketError::Malformed => write!(f, "malformed message"),
+        }
+    }
+}
+
+impl std::error::Error for WebSocketError {}
+
+fn config() -> WebSocketConfig {
+    WebSocketConfig {
+        max_message_size: Some(MAX_FRAME_LEN),
+        max_frame_size: Some(MAX_FRAME_LEN),
+        ..WebSocketConfig::default()
+    }
+}
+
+pub struct WebSocketCodec {
+    state: State,
+    //if the client sent text frames last
+    is_json: bool,
+}
+
+impl WebSocketCodec {
+    pub fn new() -> WebSocketCodec {
+        WebSocketCodec {
+            state: State::Handshake(vec![]),
+            is_json: false,
+        }
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        match &mut self.state {
+            State::Handshake(request) => request.extend_from_slice(bytes),
+            State::Open(socket) => socket.get_mut().incoming.extend_from_slice(bytes),
+        }
+    }
+
+    // the next message, or None if its frame isn't all here yet
+    pub fn next_message(&mut self) -> Result<Option<Message>, WebSocketError> {
+        if let State::Handshake(request) = &mut self.state {
+            if !request.windows(4).any(|end| end == b"\r\n\r\n") {
+                return match request.len() > MAX_REQUEST_LEN {
+                    true => Err(WebSocketError::Handshake("too long".to_string())),
+                    false => Ok(None),
+                };
+            }
+
+            let buffers = Buffers {
+                incoming: std::mem::take(request),
+                outgoing: vec![],
+            };
+            let socket = tungstenite::accept_with_config(buffers, Some(config()))
+                .map_err(|e| WebSocketError::Handshake(e.to_string()))?;
+            self.state = State::Open(Box::new(socket));
+        }
+
+        let socket = match &mut self.state {
+            State::Open(socket) => socket,
+            State::Handshake(_) => return Ok(None),
+        };
+        loop {
+            match socket.read() {
+                Ok(tungstenite::Message::Binary(payload)) => {
+                    self.is_json = false;
+                    return protocol::decode_payload(&payload)
+                        .map(Some)
+                        .map_err(|_| WebSocketError::Malformed);
+                }
+                Ok(tungstenite::Message::Text(text)) => {
+                    self.is_json = true;
+                    return serde_json::from_str(&text)
+                        .map(Some)
+                        .map_err(|_| WebSocketError::Malformed);
+                }
+                //tungstenite answers the pings and the close by itself
+                Ok(_) => continue,
+                Err(tungstenite::Error::Io(e)) if e.kind() == io::ErrorKind::WouldBlock => {
+                    return Ok(None)
+                }
+                Err(e) => return Err(WebSocketError::WebSocket(Box::new(e))),
+            }
+        }
+    }
+
+    // The bytes of the message in a frame, and whatever else is waiting to
+    //  be sent. Text frames are held to MAX_FRAME_LEN like binary ones
+    pub fn encode(&mut self, message: &Message) -> Result<Vec<u8>, FrameError> {
+        if let State::Open(socket) = &mut self.state {
+            let frame = match self.is_json {
+                true => {
+                    let text = serde_json::to_string(message).map_err(|_| FrameError::Malformed)?;
+                    if text.len() > MAX_FRAME_LEN {
+                        return Err(FrameError::TooLong(text.len()));
+                    }
+                    tungstenite::Message::Text(text)
+                }
+                false => tungstenite::Message::Binary(protocol::encode_payload(message)?),
+            };
+            //a closed WebSocket takes nothing, and is closed by the server soon
+            let _ = socket.send(frame);
+        }
+        Ok(self.take_outgoing())
+    }
+
+    // the bytes tungstenite wrote by itself: the answer to the handshake,
+    //  pongs...
+    pub fn take_outgoing(&mut self) -> Vec<u8> {
+        match &mut self.state {
+            State::Open(socket) => std::mem::take(&mut socket.get_mut().outgoing),
+            State::Handshake(_) => vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+#[path = "./tests/websocket_tests.rs"]
+mod websocket_tests;